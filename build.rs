@@ -15,5 +15,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         &["../fr-pipewire-registry/proto/pipewire.proto"],
         &["../fr-pipewire-registry/"],
     )?;
+    tonic_build::configure().compile(&["proto/builder.proto"], &["proto/"])?;
     Ok(())
 }
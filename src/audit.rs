@@ -0,0 +1,87 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::daemon::Reconciled;
+use crate::plan::Link;
+
+/// One line of the audit log.
+#[derive(Debug, Serialize)]
+struct AuditEvent<'a> {
+    /// Unix time in milliseconds the daemon noticed the change at
+    at: u128,
+    /// `added` or `removed`
+    change: &'static str,
+    link: &'a Link,
+    /// Whether the daemon made the change itself
+    builder: bool,
+}
+
+/// Appends every change to the managed links the daemon sees to a JSON
+/// lines file. Links that changed between two rounds were changed by
+/// someone else, the ones a round created or removed by the builder.
+pub struct AuditLog {
+    file: File,
+    /// Managed links after the last round, none before the first one
+    links: Option<BTreeSet<Link>>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<AuditLog, Box<dyn std::error::Error>> {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(AuditLog { file, links: None })
+    }
+
+    /// Logs what changed since the last round and what `reconciled` did.
+    pub fn round_finished(
+        &mut self,
+        reconciled: &Reconciled,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(previous) = self.links.take() {
+            for link in reconciled.found_links.difference(&previous) {
+                self.append("added", link, false)?;
+            }
+            for link in previous.difference(&reconciled.found_links) {
+                self.append("removed", link, false)?;
+            }
+        }
+        let mut links = reconciled.found_links.clone();
+        for link in &reconciled.removed_links {
+            self.append("removed", link, true)?;
+            links.remove(link);
+        }
+        for link in &reconciled.created_links {
+            self.append("added", link, true)?;
+            links.insert(link.clone());
+        }
+        self.links = Some(links);
+        Ok(())
+    }
+
+    fn append(
+        &mut self,
+        change: &'static str,
+        link: &Link,
+        builder: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        let event = AuditEvent {
+            at,
+            change,
+            link,
+            builder,
+        };
+        writeln!(self.file, "{}", serde_json::to_string(&event)?)?;
+        Ok(())
+    }
+}
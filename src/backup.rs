@@ -0,0 +1,101 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::builder;
+use crate::clients::Clients;
+use crate::live;
+use crate::plan::Link;
+use crate::report::Reporter;
+use crate::state::{Checkpoints, Stage};
+use crate::topology::Topology;
+
+static RESTORE_ON_FAILURE: AtomicBool = AtomicBool::new(false);
+
+/// Makes builds capture the managed links before they change anything and
+/// put them back if they fail.
+pub fn enable_restore_on_failure() {
+    RESTORE_ON_FAILURE.store(true, Ordering::Relaxed);
+}
+
+pub fn restores_on_failure() -> bool {
+    RESTORE_ON_FAILURE.load(Ordering::Relaxed)
+}
+
+/// Links touching the nodes the builder manages, as they were before a
+/// build.
+#[derive(Debug, Clone, Default)]
+pub struct GraphBackup {
+    pub links: BTreeSet<Link>,
+}
+
+pub async fn capture(
+    topology: &Topology,
+    checkpoints: &Checkpoints,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<GraphBackup, Box<dyn std::error::Error>> {
+    reporter.start_stage("Capturing links before the build");
+    let live_state = live::read_live_state(clients, reporter)
+        .await?
+        .with_checkpoints(checkpoints);
+    let links: BTreeSet<Link> = live_state
+        .managed_live_links(&topology.looper)
+        .into_iter()
+        .map(|(_, link)| link)
+        .collect();
+    reporter.log_info(&format!("Captured {} links", links.len()));
+    Ok(GraphBackup { links })
+}
+
+/// Puts the managed links back the way `backup` found them: links added
+/// since are removed, links gone since are created again. Channel strips
+/// and output stages the failed build created stay, only the wiring is
+/// restored.
+pub async fn restore(
+    backup: &GraphBackup,
+    topology: &Topology,
+    checkpoints: &mut Checkpoints,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    reporter.start_stage("Restoring links captured before the build");
+    let live_state = live::read_live_state(clients, reporter)
+        .await?
+        .with_checkpoints(checkpoints);
+    let managed_links = live_state.managed_live_links(&topology.looper);
+
+    let added_links: Vec<(u32, Link)> = managed_links
+        .iter()
+        .filter(|(_, link)| !backup.links.contains(link))
+        .cloned()
+        .collect();
+    builder::delete_links(&added_links, &topology.protected_nodes, clients, reporter).await?;
+    let removed: Vec<Link> = added_links.into_iter().map(|(_, link)| link).collect();
+    checkpoints.forget_links(&removed)?;
+
+    let live_links: BTreeSet<&Link> = managed_links.iter().map(|(_, link)| link).collect();
+    let missing_links: Vec<Link> = backup
+        .links
+        .iter()
+        .filter(|link| !live_links.contains(link))
+        .cloned()
+        .collect();
+    builder::create_links(
+        &missing_links,
+        &[],
+        &topology.protected_nodes,
+        clients,
+        reporter,
+    )
+    .await?;
+    // The wiring is undone, a resumed build has to wire again
+    for stage in [Stage::Inputs, Stage::Groups, Stage::OutputStageWired] {
+        checkpoints.reset(stage)?;
+    }
+    reporter.log_info(&format!(
+        "Removed {} links, recreated {}",
+        removed.len(),
+        missing_links.len()
+    ));
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::clients::Clients;
+use crate::diff;
+use crate::live;
+use crate::model::Input;
+use crate::naming::naming;
+use crate::plan;
+use crate::report::Reporter;
+use crate::state::Checkpoints;
+use crate::topology::{ChannelStripType, Topology};
+
+/// Plugins of an output stage: two basic channel strips and the cross fader
+/// between them.
+const OUTPUT_STAGE_PLUGINS: usize = 2 * strip_plugins(ChannelStripType::Basic) + 1;
+
+/// What the rig can host. A build that would go over a limit is refused
+/// before it changes anything, instead of failing halfway through.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Budget {
+    /// Most plugin instances mod-host runs
+    pub max_plugins: Option<usize>,
+    /// Most nodes in the pipewire graph
+    pub max_nodes: Option<usize>,
+    /// Most loops the loopers play at once
+    pub max_loops: Option<usize>,
+}
+
+impl Budget {
+    fn is_limited(&self) -> bool {
+        self.max_plugins.is_some() || self.max_nodes.is_some() || self.max_loops.is_some()
+    }
+}
+
+/// Plugins the factory puts into a channel strip of `channel_strip_type`.
+const fn strip_plugins(channel_strip_type: ChannelStripType) -> usize {
+    match channel_strip_type {
+        ChannelStripType::Basic => 2,
+        ChannelStripType::CrossFaded => 3,
+    }
+}
+
+/// What the rig holds once the build is done, the live graph plus what the
+/// build adds to it.
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    plugins: usize,
+    nodes: usize,
+    loops: usize,
+}
+
+/// Estimates the usage after building `topology` for `inputs`. Every new
+/// plugin is a new pipewire node, the loopers' nodes are there already.
+fn estimate(
+    topology: &Topology,
+    inputs: &[Input],
+    live: &live::LiveState,
+    checkpoints: &Checkpoints,
+    reporter: &Reporter,
+) -> Usage {
+    let strips = inputs
+        .iter()
+        .map(|i| {
+            (
+                naming().input_strip(&i.name),
+                topology.input_config(&i.name).channel_strip_type,
+            )
+        })
+        .chain(
+            topology
+                .groups
+                .iter()
+                .map(|g| (naming().group_strip(&g.name), g.channel_strip_type)),
+        )
+        .chain(topology.aux_buses.iter().map(|a| {
+            (
+                naming().aux_bus_strip(&a.name),
+                ChannelStripType::CrossFaded,
+            )
+        }))
+        .chain(
+            topology
+                .cue
+                .iter()
+                .map(|c| (naming().cue_strip(&c.name), ChannelStripType::CrossFaded)),
+        );
+    let strip_plugins: usize = strips
+        .filter(|(name, _)| !live.channel_strips.iter().any(|c| c.name == *name))
+        .map(|(_, channel_strip_type)| strip_plugins(channel_strip_type))
+        .sum();
+    let output_stage_plugins =
+        diff::output_stage_diff(topology, live).to_create.len() * OUTPUT_STAGE_PLUGINS;
+    let chain_plugins: usize = topology
+        .inputs
+        .iter()
+        .filter(|i| !checkpoints.plugin_chains().contains_key(&i.name))
+        .map(|i| i.plugins.len())
+        .sum();
+    let talkback_plugins = topology
+        .talkback
+        .iter()
+        .filter(|t| t.duck_plugin.is_some())
+        .filter(|t| !checkpoints.plugin_chains().contains_key(&t.input))
+        .count();
+    // Outputs whose limiter or mono sum from an earlier build isn't live get
+    // a new one
+    let missing_output_plugins = |recorded: &BTreeMap<String, u32>, outputs: BTreeSet<String>| {
+        outputs
+            .iter()
+            .filter(|output| {
+                !recorded
+                    .get(*output)
+                    .is_some_and(|id| live.plugins.iter().any(|p| p.id == *id))
+            })
+            .count()
+    };
+    let limiter_plugins = if topology.limiter.is_some() {
+        missing_output_plugins(
+            checkpoints.limiters(),
+            live.output_stage_output_names(topology),
+        )
+    } else {
+        0
+    };
+    let mono_sum_plugins = missing_output_plugins(
+        checkpoints.mono_sums(),
+        live.mono_sum_output_names(topology),
+    );
+    let new_plugins = strip_plugins
+        + output_stage_plugins
+        + chain_plugins
+        + talkback_plugins
+        + limiter_plugins
+        + mono_sum_plugins;
+
+    Usage {
+        plugins: live.plugins.len() + new_plugins,
+        nodes: live.nodes.len() + new_plugins,
+        loops: plan::looper_inputs(topology, inputs, checkpoints.loop_numbers(), reporter).len(),
+    }
+}
+
+/// Refuses to build when the estimated usage goes over the topology's
+/// budget. Nothing is read from the services without a limit.
+pub async fn check(
+    topology: &Topology,
+    inputs: &[Input],
+    checkpoints: &Checkpoints,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let budget = topology.budget;
+    if !budget.is_limited() {
+        return Ok(());
+    }
+    reporter.start_stage("Checking the resource budget");
+    let live = live::read_live_state(clients, reporter).await?;
+    let usage = estimate(topology, inputs, &live, checkpoints, reporter);
+    reporter.log_info(&format!(
+        "Build needs {} plugins, {} nodes and {} loops",
+        usage.plugins, usage.nodes, usage.loops
+    ));
+
+    let over: Vec<String> = [
+        ("plugins", usage.plugins, budget.max_plugins),
+        ("nodes", usage.nodes, budget.max_nodes),
+        ("loops", usage.loops, budget.max_loops),
+    ]
+    .into_iter()
+    .filter_map(|(what, used, limit)| {
+        let limit = limit?;
+        (used > limit).then(|| format!("{used} {what}, the budget allows {limit}"))
+    })
+    .collect();
+    if over.is_empty() {
+        return Ok(());
+    }
+    Err(format!("The rig can't host this topology: {}", over.join("; ")).into())
+}
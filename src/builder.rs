@@ -1,771 +1,1182 @@
-use clap::error::Result;
-use fr_logging::Logger;
-use tonic::{transport::Channel, Request};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
+use clap::error::Result;
+use tracing::{field, info_span, Instrument};
+
+use crate::clients::Clients;
+use crate::discovery::DiscoveredInput;
+use crate::live::LiveState;
+use crate::model::{ChannelStrip, Input, Looper, Output, OutputStage};
+use crate::naming::naming;
+use crate::ownership;
+use crate::plan::{self, ConnectionPlan, Link, ParameterSetting};
 use crate::pmx::{
-    factory::{
-        channel_strip::{PmxChannelStrip, PmxChannelStripType},
-        output_stage::PmxOutputStage,
-        pmx_factory_client::PmxFactoryClient,
-        CreateChannelStripRequest, CreateOutputStageRequest,
-    },
-    input::{PmxInput, PmxInputType},
-    looper::PmxLooper,
-    output::PmxOutput,
-    pipewire::{
-        node::ListNode, pipewire_client::PipewireClient, port::ListPort, CreateLinkByNameRequest,
-        ListNodesRequest, ListPortsRequest,
-    },
-    pmx_registry_client::PmxRegistryClient,
-    EmptyRequest, RegisterLooperRequest,
+    factory::{CreateChannelStripRequest, CreateChannelStripsRequest, CreateOutputStageRequest},
+    mod_host::{AddPluginRequest, UpdateParameterRequest},
+    pipewire::{node::ListNode, port::ListPort},
+    EmptyRequest, RegisterInputRequest, RegisterLinkRequest, RegisterLooperRequest,
+    UnregisterLinkRequest, UnregisterLooperRequest,
+};
+use crate::protection;
+use crate::report::Reporter;
+use crate::rpc::{self, Service};
+use crate::shutdown;
+use crate::telemetry;
+use crate::topology::{
+    ChainPluginConfig, ChannelStripType, LimiterConfig, MonoSumConfig, TalkbackConfig, Topology,
 };
 
+/// How often the graph is listed while waiting for a node to show up.
+const NODE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Cleared once the factory answers `CreateChannelStrips` as unimplemented,
+/// strips are created one by one from then on.
+static FACTORY_BATCHES: AtomicBool = AtomicBool::new(true);
+
 pub async fn get_inputs(
-    mut client: PmxRegistryClient<Channel>,
-    logger: &Logger,
-) -> std::result::Result<Vec<PmxInput>, Box<dyn std::error::Error>> {
-    logger.log_info("Reading inputs from registry");
-    let request = Request::new(EmptyRequest {});
-    let response = client.list_inputs(request).await?;
-    Ok(response.into_inner().inputs)
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<Vec<Input>, Box<dyn std::error::Error>> {
+    reporter.log_info("Reading inputs from registry");
+    let request = EmptyRequest {};
+    let response = rpc::call(
+        Service::Registry,
+        "ListInputs",
+        &clients.registry,
+        &request,
+        |mut client, request| async move { client.list_inputs(request).await },
+    )
+    .await?;
+    Ok(response.inputs.into_iter().map(Input::from).collect())
+}
+
+pub async fn register_inputs(
+    inputs: &[DiscoveredInput],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    reporter.start_counted_stage("Registering discovered inputs", inputs.len());
+    for input in inputs {
+        reporter.step();
+        reporter.log_info(&format!("Registering input {}", input.name));
+        let request = RegisterInputRequest {
+            name: input.name.clone(),
+            input_type: input.input_type.to_pmx() as i32,
+            left_port_path: input.left_port_path.clone(),
+            right_port_path: input.right_port_path.clone(),
+            group_channel_strip_name: input.group_channel_strip_name.clone(),
+        };
+        rpc::call(
+            Service::Registry,
+            "RegisterInput",
+            &clients.registry,
+            &request,
+            |mut client, request| async move { client.register_input(request).await },
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Channel strip named `name` that is already in the registry, the one with
+/// the lowest id if there are several. A strip of another type can't be
+/// reused and isn't replaced either, that is left to the user.
+fn adoptable_strip<'a>(
+    existing: &'a [ChannelStrip],
+    name: &str,
+    channel_strip_type: ChannelStripType,
+) -> std::result::Result<Option<&'a ChannelStrip>, Box<dyn std::error::Error>> {
+    let Some(strip) = existing
+        .iter()
+        .filter(|s| s.name == name)
+        .min_by_key(|s| s.id)
+    else {
+        return Ok(None);
+    };
+    if strip.channel_strip_type() != channel_strip_type {
+        return Err(format!(
+            "Channel strip {name} exists as {:?}, the topology wants {channel_strip_type:?}",
+            strip.channel_strip_type()
+        )
+        .into());
+    }
+    Ok(Some(strip))
 }
 
+/// Creates a channel strip for every input, adopting the ones `existing`
+/// already holds so a rerun after a failed build only wires them.
 pub async fn build_channel_strips(
-    input_channels: &Vec<PmxInput>,
-    mut client: PmxFactoryClient<Channel>,
-    logger: &Logger,
-) -> std::result::Result<Vec<PmxChannelStrip>, Box<dyn std::error::Error>> {
-    logger.log_info("Creating channel strips");
+    topology: &Topology,
+    input_channels: &Vec<Input>,
+    existing: &[ChannelStrip],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<Vec<ChannelStrip>, Box<dyn std::error::Error>> {
+    reporter.start_counted_stage("Creating channel strips", input_channels.len());
     let mut channel_strips = Vec::new();
+    let mut requests = Vec::new();
     for channel in input_channels {
-        let request = Request::new(CreateChannelStripRequest {
-            name: channel.name.clone(),
-            channel_type: PmxChannelStripType::CrossFaded as i32,
+        let channel_strip_type = topology.input_config(&channel.name).channel_strip_type;
+        let name = naming().input_strip(&channel.name);
+        if let Some(strip) = adoptable_strip(existing, &name, channel_strip_type)? {
+            reporter.step();
+            reporter.channel_strip_adopted(&strip.name, strip.id);
+            channel_strips.push(strip.clone());
+            continue;
+        }
+        requests.push(CreateChannelStripRequest {
+            name,
+            channel_type: channel_strip_type.to_pmx() as i32,
         });
-        let response = client.create_channel_strip(request).await?;
-        let channel_strip = response.into_inner();
-        channel_strips.push(channel_strip);
+    }
+
+    if requests.len() > 1 && FACTORY_BATCHES.load(Ordering::Relaxed) {
+        requests =
+            create_channel_strip_batch(requests, &mut channel_strips, clients, reporter).await?;
+    }
+    if requests.is_empty() {
+        return Ok(channel_strips);
+    }
+    let factory = clients.factory().await?;
+    for chunk in requests.chunks(topology.concurrency.max(1)) {
+        let calls: Vec<_> = chunk
+            .iter()
+            .cloned()
+            .map(|request| {
+                let factory = factory.clone();
+                tokio::spawn(async move {
+                    rpc::call(
+                        Service::Factory,
+                        "CreateChannelStrip",
+                        &factory,
+                        &request,
+                        |mut client, request| async move {
+                            client.create_channel_strip(request).await
+                        },
+                    )
+                    .await
+                })
+            })
+            .collect();
+        // Every call of the chunk is awaited before failing so no strip it
+        // created goes unreported.
+        let mut failure = None;
+        for call in calls {
+            reporter.step();
+            match call.await? {
+                Ok(channel_strip) => {
+                    reporter.channel_strip_created(&channel_strip.name, channel_strip.id);
+                    channel_strips.push(ChannelStrip::from(channel_strip));
+                }
+                Err(error) => {
+                    failure.get_or_insert(error);
+                }
+            }
+        }
+        if let Some(error) = failure {
+            return Err(error.into());
+        }
     }
     Ok(channel_strips)
 }
 
-pub async fn build_output_stage(
-    mut client: PmxFactoryClient<Channel>,
-    logger: &Logger,
-) -> PmxOutputStage {
-    logger.log_info("Creating output stage");
-    let request = Request::new(CreateOutputStageRequest {
-        name: String::from("Output Stage"),
-    });
-    let response = client.create_output_stage(request);
-    response.await.unwrap().into_inner()
+/// Creates the strips of `requests` in one `CreateChannelStrips` call and
+/// adds them to `channel_strips`. Returns the requests still to be sent one
+/// by one: the ones the factory couldn't create, or all of them if it
+/// doesn't know the call.
+async fn create_channel_strip_batch(
+    requests: Vec<CreateChannelStripRequest>,
+    channel_strips: &mut Vec<ChannelStrip>,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<Vec<CreateChannelStripRequest>, Box<dyn std::error::Error>> {
+    let request = CreateChannelStripsRequest {
+        channel_strips: requests.clone(),
+    };
+    let response = rpc::call(
+        Service::Factory,
+        "CreateChannelStrips",
+        &clients.factory().await?,
+        &request,
+        |mut client, request| async move { client.create_channel_strips(request).await },
+    )
+    .await;
+    let response = match response {
+        Ok(response) => response,
+        Err(error) if error.is_unimplemented() => {
+            reporter.log_info("Factory can't create strips in batches, creating them one by one");
+            FACTORY_BATCHES.store(false, Ordering::Relaxed);
+            return Ok(requests);
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    let mut failed = Vec::new();
+    for (index, request) in requests.into_iter().enumerate() {
+        let result = response.results.get(index);
+        match result.and_then(|r| r.channel_strip.clone()) {
+            Some(channel_strip) => {
+                reporter.step();
+                reporter.channel_strip_created(&channel_strip.name, channel_strip.id);
+                channel_strips.push(ChannelStrip::from(channel_strip));
+            }
+            None => {
+                reporter.log_info(&format!(
+                    "Factory couldn't create channel strip {} in the batch, trying again alone: {}",
+                    request.name,
+                    result.map_or("no result", |r| r.error.as_str())
+                ));
+                failed.push(request);
+            }
+        }
+    }
+    Ok(failed)
 }
 
-pub struct GroupChannelStrips {
-    pub drums: PmxChannelStrip,
-    pub bass: PmxChannelStrip,
-    pub melody: PmxChannelStrip,
-    pub atmos: PmxChannelStrip,
+pub async fn build_output_stages(
+    topology: &Topology,
+    existing: &[OutputStage],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<Vec<OutputStage>, Box<dyn std::error::Error>> {
+    reporter.start_counted_stage("Creating output stages", topology.output_stages.len());
+    let mut output_stages = Vec::new();
+    for output_stage_config in &topology.output_stages {
+        reporter.step();
+        let name = naming().output_stage(&output_stage_config.name);
+        if let Some(output_stage) = existing
+            .iter()
+            .filter(|o| o.name == name)
+            .min_by_key(|o| o.id)
+        {
+            reporter.output_stage_adopted(&output_stage.name, output_stage.id);
+            output_stages.push(output_stage.clone());
+            continue;
+        }
+        let request = CreateOutputStageRequest { name };
+        let output_stage = rpc::call(
+            Service::Factory,
+            "CreateOutputStage",
+            &clients.factory().await?,
+            &request,
+            |mut client, request| async move { client.create_output_stage(request).await },
+        )
+        .await?;
+        reporter.output_stage_created(&output_stage.name, output_stage.id);
+        output_stages.push(OutputStage::from(output_stage));
+    }
+    Ok(output_stages)
 }
 
 pub async fn build_group_channel_strips(
-    client: PmxFactoryClient<Channel>,
-    logger: &Logger,
-) -> GroupChannelStrips {
-    logger.log_info("Building group channels");
-    let drums_channel =
-        build_group_channel_strip(String::from("Drums"), client.clone(), logger).await;
-    let bass_channel =
-        build_group_channel_strip(String::from("Bass"), client.clone(), logger).await;
-    let melody_channel =
-        build_group_channel_strip(String::from("Melody"), client.clone(), logger).await;
-    let atmos_channel =
-        build_group_channel_strip(String::from("Atmos"), client.clone(), logger).await;
-    GroupChannelStrips {
-        drums: drums_channel,
-        bass: bass_channel,
-        melody: melody_channel,
-        atmos: atmos_channel,
+    topology: &Topology,
+    existing: &[ChannelStrip],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<Vec<ChannelStrip>, Box<dyn std::error::Error>> {
+    reporter.start_counted_stage("Building group channels", topology.groups.len());
+    let mut strips = Vec::new();
+    for group in &topology.groups {
+        reporter.step();
+        strips.push(
+            build_group_channel_strip(
+                naming().group_strip(&group.name),
+                group.channel_strip_type,
+                existing,
+                clients,
+                reporter,
+            )
+            .await?,
+        );
+    }
+    Ok(strips)
+}
+
+pub async fn build_aux_bus_channel_strips(
+    topology: &Topology,
+    existing: &[ChannelStrip],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<Vec<ChannelStrip>, Box<dyn std::error::Error>> {
+    reporter.start_counted_stage("Building aux bus channels", topology.aux_buses.len());
+    let mut strips = Vec::new();
+    for aux_bus in &topology.aux_buses {
+        reporter.step();
+        strips.push(
+            build_group_channel_strip(
+                naming().aux_bus_strip(&aux_bus.name),
+                ChannelStripType::CrossFaded,
+                existing,
+                clients,
+                reporter,
+            )
+            .await?,
+        );
     }
+    Ok(strips)
+}
+
+pub async fn build_cue_channel_strip(
+    topology: &Topology,
+    existing: &[ChannelStrip],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<Option<ChannelStrip>, Box<dyn std::error::Error>> {
+    let Some(cue) = topology.cue.as_ref() else {
+        return Ok(None);
+    };
+    reporter.start_stage("Building cue channel");
+    let strip = build_group_channel_strip(
+        naming().cue_strip(&cue.name),
+        ChannelStripType::CrossFaded,
+        existing,
+        clients,
+        reporter,
+    )
+    .await?;
+    Ok(Some(strip))
 }
 
 async fn build_group_channel_strip(
     name: String,
-    mut client: PmxFactoryClient<Channel>,
-    logger: &Logger,
-) -> PmxChannelStrip {
-    logger.log_info(&format!("Creating group channel strip {name}"));
-    let request = Request::new(CreateChannelStripRequest {
+    channel_strip_type: ChannelStripType,
+    existing: &[ChannelStrip],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<ChannelStrip, Box<dyn std::error::Error>> {
+    if let Some(strip) = adoptable_strip(existing, &name, channel_strip_type)? {
+        reporter.channel_strip_adopted(&strip.name, strip.id);
+        return Ok(strip.clone());
+    }
+    reporter.log_info(&format!("Creating group channel strip {name}"));
+    let request = CreateChannelStripRequest {
         name,
-        channel_type: PmxChannelStripType::CrossFaded as i32,
-    });
-    let response = client.create_channel_strip(request).await.unwrap();
-    response.into_inner()
+        channel_type: channel_strip_type.to_pmx() as i32,
+    };
+    let channel_strip = rpc::call(
+        Service::Factory,
+        "CreateChannelStrip",
+        &clients.factory().await?,
+        &request,
+        |mut client, request| async move { client.create_channel_strip(request).await },
+    )
+    .await?;
+    reporter.channel_strip_created(&channel_strip.name, channel_strip.id);
+    Ok(ChannelStrip::from(channel_strip))
 }
 
 pub async fn get_all_channel_strips(
-    mut registry_client: PmxRegistryClient<Channel>,
-) -> Vec<crate::pmx::channel_strip::PmxChannelStrip> {
-    let request = Request::new(EmptyRequest {});
-    let response = registry_client.list_channel_strips(request).await.unwrap();
-    response.into_inner().channel_strips
+    clients: &Clients,
+) -> std::result::Result<Vec<ChannelStrip>, Box<dyn std::error::Error>> {
+    let request = EmptyRequest {};
+    let response = rpc::call(
+        Service::Registry,
+        "ListChannelStrips",
+        &clients.registry,
+        &request,
+        |mut client, request| async move { client.list_channel_strips(request).await },
+    )
+    .await?;
+    Ok(response
+        .channel_strips
+        .into_iter()
+        .map(ChannelStrip::from)
+        .collect())
 }
 
 pub async fn get_all_outputs(
-    mut registry_client: PmxRegistryClient<Channel>,
-) -> Vec<crate::pmx::output::PmxOutput> {
-    let request = Request::new(EmptyRequest {});
-    let response = registry_client.list_outputs(request).await.unwrap();
-    response.into_inner().outputs
-}
-
-pub async fn connect_output_stage_to_outputs(
-    output_stage: &PmxOutputStage,
-    output_channels: &Vec<PmxOutput>,
-    ports: &[ListPort],
-    nodes: &[ListNode],
-    plugins: &[crate::pmx::plugin::PmxPlugin],
-    mut pipewire_client: PipewireClient<Channel>,
-    logger: &Logger,
-) {
-    let cross_fader_plugin = plugins
-        .iter()
-        .find(|p| p.id == output_stage.cross_fader_plugin_id);
-
-    if let Some(cross_fader_plugin) = cross_fader_plugin {
-        for output_channel in output_channels {
-            if let (Some(left_path), Some(right_path)) = (
-                output_channel.left_port_path.clone(),
-                output_channel.right_port_path.clone(),
-            ) {
-                let left_port = ports.iter().find(|p| p.path == left_path);
-                let right_port = ports.iter().find(|p| p.path == right_path);
-                if let (Some(left_port), Some(right_port)) = (left_port, right_port) {
-                    let left_node = nodes.iter().find(|n| n.object_serial == left_port.node_id);
-                    let right_node = nodes.iter().find(|n| n.object_serial == right_port.node_id);
-
-                    if let (Some(left_node), Some(right_node)) = (left_node, right_node) {
-                        logger.log_info(&format!(
-                            "Connecting {}:{} -> {}:{}",
-                            cross_fader_plugin.name.clone(),
-                            0,
-                            left_node.name.clone(),
-                            left_port.id,
-                        ));
-
-                        let request = Request::new(CreateLinkByNameRequest {
-                            output_port_id: 0,
-                            input_port_id: left_port.id,
-                            output_node_name: cross_fader_plugin.name.clone(),
-                            input_node_name: left_node.name.clone(),
-                        });
-
-                        pipewire_client.create_link_by_name(request).await.unwrap();
-
-                        logger.log_info(&format!(
-                            "Connecting {}:{} -> {}:{}",
-                            cross_fader_plugin.name.clone(),
-                            0,
-                            right_node.name.clone(),
-                            right_port.id,
-                        ));
-
-                        let request = Request::new(CreateLinkByNameRequest {
-                            output_port_id: 0,
-                            input_port_id: right_port.id,
-                            output_node_name: cross_fader_plugin.name.clone(),
-                            input_node_name: right_node.name.clone(),
-                        });
-
-                        pipewire_client.create_link_by_name(request).await.unwrap();
-                    } else {
-                        logger.log_info(&format!(
-                            "Couldn't find nodes for ports: {:?}, {:?}",
-                            left_port, right_port
-                        ));
-                    }
-                } else {
-                    logger.log_info(&format!(
-                        "Couldn't find ports for paths: {}, {}",
-                        left_path, right_path
-                    ));
-                }
-            } else {
-                logger.log_info(&format!(
-                    "Channel doesn't have both path filled {:?}",
-                    output_channel
-                ));
-            }
-        }
-    }
+    clients: &Clients,
+) -> std::result::Result<Vec<Output>, Box<dyn std::error::Error>> {
+    let request = EmptyRequest {};
+    let response = rpc::call(
+        Service::Registry,
+        "ListOutputs",
+        &clients.registry,
+        &request,
+        |mut client, request| async move { client.list_outputs(request).await },
+    )
+    .await?;
+    Ok(response.outputs.into_iter().map(Output::from).collect())
 }
 
-pub async fn connect_group_channel_strips_to_output_stage_channels(
-    group_channel_strips: &GroupChannelStrips,
-    output_stage: &PmxOutputStage,
-    plugins: &[crate::pmx::plugin::PmxPlugin],
-    channel_strips: &[crate::pmx::channel_strip::PmxChannelStrip],
-    pipewire_client: PipewireClient<Channel>,
-    logger: &Logger,
-) {
-    let left_channel_strip = channel_strips
-        .iter()
-        .find(|c| c.id == output_stage.left_channel_strip_id);
-
-    let right_channel_strip = channel_strips
-        .iter()
-        .find(|c| c.id == output_stage.right_channel_strip_id);
-
-    if let (Some(left_channel_strip), Some(right_channel_strip)) =
-        (left_channel_strip, right_channel_strip)
-    {
-        let left_plugin = plugins
-            .iter()
-            .find(|p| p.id == left_channel_strip.saturator_plugin_id);
+pub async fn get_loopers(
+    clients: &Clients,
+) -> std::result::Result<Vec<Looper>, Box<dyn std::error::Error>> {
+    let request = EmptyRequest {};
+    let response = rpc::call(
+        Service::Registry,
+        "ListLoopers",
+        &clients.registry,
+        &request,
+        |mut client, request| async move { client.list_loopers(request).await },
+    )
+    .await?;
+    Ok(response.loopers.into_iter().map(Looper::from).collect())
+}
 
-        let right_plugin = plugins
-            .iter()
-            .find(|p| p.id == right_channel_strip.saturator_plugin_id);
+pub async fn get_output_stages(
+    clients: &Clients,
+) -> std::result::Result<Vec<OutputStage>, Box<dyn std::error::Error>> {
+    let request = EmptyRequest {};
+    let response = rpc::call(
+        Service::Registry,
+        "ListOutputStages",
+        &clients.registry,
+        &request,
+        |mut client, request| async move { client.list_output_stages(request).await },
+    )
+    .await?;
+    Ok(response
+        .output_stages
+        .into_iter()
+        .map(OutputStage::from)
+        .collect())
+}
 
-        if let (Some(left_plugin), Some(right_plugin)) = (left_plugin, right_plugin) {
-            let drum_gain_plugin = plugins
-                .iter()
-                .find(|p| p.id == group_channel_strips.drums.gain_plugin_id);
-            let bass_gain_plugin = plugins
-                .iter()
-                .find(|p| p.id == group_channel_strips.bass.gain_plugin_id);
-            let melody_gain_plugin = plugins
-                .iter()
-                .find(|p| p.id == group_channel_strips.melody.gain_plugin_id);
-            let atmos_gain_plugin = plugins
-                .iter()
-                .find(|p| p.id == group_channel_strips.atmos.gain_plugin_id);
-
-            if let Some(drum_gain_plugin) = drum_gain_plugin {
-                connect_plugins(
-                    drum_gain_plugin,
-                    left_plugin,
-                    &[(0, 0), (1, 1)],
-                    pipewire_client.clone(),
-                    logger,
-                )
-                .await;
+/// Creates `links`, with the port numbers of `plugins` resolved against the
+/// ports pipewire currently reports for them.
+pub async fn create_links(
+    links: &[Link],
+    plugins: &[crate::pmx::plugin::PmxPlugin],
+    protected_nodes: &[String],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    if links.is_empty() {
+        return Ok(());
+    }
 
-                connect_plugins(
-                    drum_gain_plugin,
-                    right_plugin,
-                    &[(0, 0), (1, 1)],
-                    pipewire_client.clone(),
-                    logger,
-                )
-                .await;
-            } else {
-                logger.log_info("Couldn't find drum gain plugin");
+    let mut graph = GraphView::read(clients).await?;
+    for link in links {
+        stop_if_shutting_down()?;
+        if let Err(error) = create_link(
+            link,
+            plugins,
+            protected_nodes,
+            &mut graph,
+            clients,
+            reporter,
+        )
+        .await
+        {
+            if reporter.aborts_on_failure() {
+                return Err(error);
             }
+        }
+    }
+    Ok(())
+}
 
-            if let Some(bass_gain_plugin) = bass_gain_plugin {
-                connect_plugins(
-                    bass_gain_plugin,
-                    left_plugin,
-                    &[(0, 0), (1, 1)],
-                    pipewire_client.clone(),
-                    logger,
-                )
-                .await;
-
-                connect_plugins(
-                    bass_gain_plugin,
-                    right_plugin,
-                    &[(0, 0), (1, 1)],
-                    pipewire_client.clone(),
-                    logger,
-                )
-                .await;
-            } else {
-                logger.log_info("Couldn't find bass gain plugin");
-            }
+/// Nodes and ports links are created against, read again while a link
+/// waits for its nodes.
+struct GraphView {
+    nodes: Vec<ListNode>,
+    ports: Vec<ListPort>,
+    /// Nodes that never showed up for an earlier link, they aren't waited
+    /// for again
+    timed_out: BTreeSet<String>,
+}
 
-            if let Some(melody_gain_plugin) = melody_gain_plugin {
-                connect_plugins(
-                    melody_gain_plugin,
-                    left_plugin,
-                    &[(0, 0), (1, 1)],
-                    pipewire_client.clone(),
-                    logger,
-                )
-                .await;
+impl GraphView {
+    async fn read(clients: &Clients) -> std::result::Result<GraphView, Box<dyn std::error::Error>> {
+        Ok(GraphView {
+            nodes: get_nodes(clients).await?,
+            ports: get_ports(clients).await?,
+            timed_out: BTreeSet::new(),
+        })
+    }
 
-                connect_plugins(
-                    melody_gain_plugin,
-                    right_plugin,
-                    &[(0, 0), (1, 1)],
-                    pipewire_client.clone(),
-                    logger,
-                )
-                .await;
-            } else {
-                logger.log_info("Couldn't find melody gain plugin");
+    /// Waits for both nodes of `link`, the error names the node missing.
+    async fn wait_for_endpoints(
+        &mut self,
+        link: &Link,
+        clients: &Clients,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        for name in [&link.output_node_name, &link.input_node_name] {
+            if self.timed_out.contains(name) {
+                return Err(format!("Node {name} isn't in the graph").into());
             }
-
-            if let Some(atmos_gain_plugin) = atmos_gain_plugin {
-                connect_plugins(
-                    atmos_gain_plugin,
-                    left_plugin,
-                    &[(0, 0), (1, 1)],
-                    pipewire_client.clone(),
-                    logger,
-                )
-                .await;
-
-                connect_plugins(
-                    atmos_gain_plugin,
-                    right_plugin,
-                    &[(0, 0), (1, 1)],
-                    pipewire_client.clone(),
-                    logger,
-                )
-                .await;
-            } else {
-                logger.log_info("Couldn't find atmos gain plugin");
+            if let Err(error) = wait_for_node(name, &mut self.nodes, &mut self.ports, clients).await
+            {
+                self.timed_out.insert(name.clone());
+                return Err(error);
             }
-        } else {
-            logger.log_info(&format!(
-                "Couldn't find a plugin left: {:?}, right: {:?}",
-                left_plugin, right_plugin
-            ));
         }
-    } else {
-        logger.log_info(&format!(
-            "Couldn't find a channel strip left: {:?}, right: {:?}",
-            left_channel_strip, right_channel_strip
-        ));
+        Ok(())
     }
 }
 
-async fn connect_plugins(
-    output_plugin: &crate::pmx::plugin::PmxPlugin,
-    input_plugin: &crate::pmx::plugin::PmxPlugin,
-    connections: &[(u32, u32)],
-    mut pipewire_client: PipewireClient<Channel>,
-    logger: &Logger,
-) {
-    for connection in connections {
-        logger.log_info(&format!(
-            "Connecting {}:{} -> {}:{}",
-            output_plugin.name, connection.0, input_plugin.name, connection.1
-        ));
-        let request = Request::new(CreateLinkByNameRequest {
-            output_port_id: connection.0,
-            input_port_id: connection.1,
-            output_node_name: output_plugin.name.clone(),
-            input_node_name: input_plugin.name.clone(),
-        });
-        pipewire_client.create_link_by_name(request).await.unwrap();
+/// Waits until the node `name` is in the graph with its ports. Plugin nodes
+/// the factory just created take a moment to show up in pipewire.
+async fn wait_for_node(
+    name: &str,
+    nodes: &mut Vec<ListNode>,
+    ports: &mut Vec<ListPort>,
+    clients: &Clients,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let timeout = rpc::node_ready_timeout();
+    let deadline = Instant::now() + timeout;
+    loop {
+        let ready = nodes
+            .iter()
+            .filter(|n| n.name == name)
+            .any(|n| ports.iter().any(|p| p.node_id == n.object_serial));
+        if ready {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "Node {name} didn't show up in the graph within {} seconds",
+                timeout.as_secs()
+            )
+            .into());
+        }
+        stop_if_shutting_down()?;
+        tokio::time::sleep(NODE_POLL_INTERVAL).await;
+        *nodes = get_nodes(clients).await?;
+        *ports = get_ports(clients).await?;
+    }
+}
+
+/// Fails once a shutdown was requested. The pw-cli and JACK backends don't
+/// call the services, so links stop here rather than in `rpc::call`.
+fn stop_if_shutting_down() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    if shutdown::is_requested() {
+        return Err("The build is shutting down".into());
     }
+    Ok(())
 }
 
-pub async fn connect_channel_strips_to_group_channel_strips(
-    input_channels: &[PmxInput],
-    channel_strips: &[PmxChannelStrip],
-    group_channel_strips: &GroupChannelStrips,
+/// Connects `link` unless it touches one of the `protected_nodes`, once both
+/// its nodes are in `graph`. Port numbers of `plugins` are resolved after
+/// waiting, the ports of plugin nodes are only known once they show up.
+/// Returns whether the link was created, protected links are reported as
+/// skipped.
+async fn create_link(
+    link: &Link,
     plugins: &[crate::pmx::plugin::PmxPlugin],
-    pipewire_client: PipewireClient<Channel>,
-    logger: &Logger,
-) {
-    for input_channel in input_channels {
-        let group_name = &input_channel.group_channel_strip_name;
-
-        let mut group_channel_strip = None;
-        if group_name == "Drums" {
-            group_channel_strip = Some(&group_channel_strips.drums)
-        } else if group_name == "Bass" {
-            group_channel_strip = Some(&group_channel_strips.bass)
-        } else if group_name == "Melody" {
-            group_channel_strip = Some(&group_channel_strips.melody)
-        } else if group_name == "Atmos" {
-            group_channel_strip = Some(&group_channel_strips.atmos)
+    protected_nodes: &[String],
+    graph: &mut GraphView,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<bool, Box<dyn std::error::Error>> {
+    if !protection::allows(link, protected_nodes, reporter) {
+        return Ok(false);
+    }
+    if let Err(error) = graph.wait_for_endpoints(link, clients).await {
+        reporter.link_failed(link, &error.to_string());
+        return Err(error);
+    }
+    let link = &plan::resolve_plugin_ports(vec![link.clone()], plugins, &graph.ports, &graph.nodes)
+        .remove(0);
+    reporter.log_info(&format!("Connecting {link}"));
+    let span = info_span!(
+        parent: &telemetry::stage_span(),
+        "link",
+        link = %link,
+        error = field::Empty,
+    );
+    let response = clients
+        .graph
+        .connect(link, &graph.nodes)
+        .instrument(span.clone())
+        .await;
+    match &response {
+        Ok(_) => {
+            reporter.link_created(link);
+            register_link(link, clients, reporter)
+                .instrument(span.clone())
+                .await;
         }
+        Err(error) => {
+            span.record("error", field::display(error));
+            reporter.link_failed(link, &error.to_string());
+        }
+    }
+    response.map(|_| true)
+}
 
-        let channel_strip = channel_strips.iter().find(|c| c.name == input_channel.name);
-
-        if let Some((group_channel_strip, input_channel_strip)) =
-            group_channel_strip.zip(channel_strip)
-        {
-            let group_channel_plugin = plugins
-                .iter()
-                .find(|p| p.id == group_channel_strip.saturator_plugin_id);
-            let input_channel_plugin = plugins
-                .iter()
-                .find(|p| p.id == input_channel_strip.gain_plugin_id);
-
-            if let Some((group_channel_plugin, input_channel_plugin)) =
-                group_channel_plugin.zip(input_channel_plugin)
-            {
-                logger.log_info(&format!(
-                    "Connecting {}:{} -> {}:{}",
-                    input_channel_plugin.name, 0, group_channel_plugin.name, 0
-                ));
-                let request = Request::new(CreateLinkByNameRequest {
-                    output_port_id: 0,
-                    input_port_id: 0,
-                    output_node_name: input_channel_plugin.name.clone(),
-                    input_node_name: group_channel_plugin.name.clone(),
-                });
-                pipewire_client
-                    .clone()
-                    .create_link_by_name(request)
-                    .await
-                    .unwrap();
-
-                logger.log_info(&format!(
-                    "Connecting {}:{} -> {}:{}",
-                    input_channel_plugin.name, 1, group_channel_plugin.name, 1
-                ));
-                let request = Request::new(CreateLinkByNameRequest {
-                    output_port_id: 1,
-                    input_port_id: 1,
-                    output_node_name: input_channel_plugin.name.clone(),
-                    input_node_name: group_channel_plugin.name.clone(),
-                });
-                pipewire_client
-                    .clone()
-                    .create_link_by_name(request)
-                    .await
-                    .unwrap();
-            } else {
-                logger.log_info("Couldn't find plugin");
-                continue;
+/// Creates every link of `plan` that isn't in `existing` yet. A failed link
+/// stops the plan if the failure policy says so, otherwise it is only
+/// reported and counted in the summary.
+pub async fn execute_plan(
+    plan: &ConnectionPlan,
+    existing: &BTreeSet<Link>,
+    protected_nodes: &[String],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let mut graph = GraphView::read(clients).await?;
+    reporter.start_counted_stage("Connecting links", plan.len());
+    let started = Instant::now();
+    let mut created = 0;
+    let mut protected = 0;
+    let mut failed = 0;
+    let mut links = plan.links().iter().peekable();
+    while let Some(link) = links.next() {
+        stop_if_shutting_down()?;
+        reporter.step();
+        if !existing.contains(link) {
+            match create_link(link, &[], protected_nodes, &mut graph, clients, reporter).await {
+                Ok(true) => created += 1,
+                Ok(false) => protected += 1,
+                Err(error) if reporter.aborts_on_failure() => {
+                    return Err(format!("Couldn't connect {link}: {error}").into());
+                }
+                Err(_) => failed += 1,
             }
-        } else {
-            logger.log_info(&format!(
-                "Couldn't find group channel {} for input channel {}",
-                group_name, input_channel.name
+        }
+        let priority = plan.priority(link);
+        let class_done = links
+            .peek()
+            .map_or(true, |next| plan.priority(next) != priority);
+        if plan.is_prioritized() && class_done {
+            reporter.log_info(&format!(
+                "Priority {priority} links ready after {:.1} seconds",
+                started.elapsed().as_secs_f64()
             ));
-            continue;
         }
     }
+    reporter.log_info(&format!(
+        "{} links planned: {created} created, {} already connected, {protected} protected, {failed} failed",
+        plan.len(),
+        plan.len() - created - protected - failed,
+    ));
+    Ok(())
 }
 
-pub async fn connect_inputs_to_channel_strips(
-    input_channels: &Vec<PmxInput>,
-    channel_strips: &Vec<PmxChannelStrip>,
-    plugins: &[crate::pmx::plugin::PmxPlugin],
-    ports: &[ListPort],
-    nodes: &[ListNode],
-    pipewire_client: PipewireClient<Channel>,
-    logger: &Logger,
+/// Removes the links between the plugins an insert splits, so the chain runs
+/// through the hardware instead.
+pub async fn disconnect_insert_bypasses(
+    topology: &Topology,
+    live_state: &LiveState,
+    clients: &Clients,
+    reporter: &Reporter,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
-    logger.log_info("Connecting inputs to channel strips");
-
-    logger.log_info(&format!(
-        "Found {} ports and {} nodes",
-        ports.len(),
-        nodes.len()
-    ));
-
-    let pairs = std::iter::zip(input_channels, channel_strips);
-
-    for (input, channel) in pairs {
-        logger.log_info(&format!(
-            "Connecting input {} to channel {}",
-            input.name, channel.name
-        ));
-
-        if input.input_type == PmxInputType::None as i32 {
-            logger.log_info("Input type is None, nothing to do");
+    reporter.start_counted_stage("Disconnecting insert bypasses", topology.inserts.len());
+    for insert in &topology.inserts {
+        reporter.step();
+        let Some(channel_strip) = live_state.find_channel_strip(&insert.channel_strip) else {
+            reporter.skipped(&format!(
+                "Couldn't find channel strip {} for its insert",
+                insert.channel_strip
+            ));
             continue;
         };
+        let bypass_links = plan::insert_bypass_links(
+            insert,
+            channel_strip,
+            &live_state.plugins,
+            &live_state.links,
+            &live_state.nodes,
+        );
+        delete_links(&bypass_links, &topology.protected_nodes, clients, reporter).await?;
+    }
+    Ok(())
+}
 
-        let left_port = ports
-            .iter()
-            .find(|p| p.path == input.left_port_path.clone().unwrap());
-
-        let plugin = plugins
-            .iter()
-            .find(|p| p.id == channel.cross_fader_plugin_id.unwrap());
-
-        match (left_port, plugin) {
-            (Some(port), Some(plugin)) => {
-                if let Some(node) = nodes.iter().find(|n| n.object_serial == port.node_id) {
-                    let mut pipewire_client = pipewire_client.clone();
-
-                    logger.log_info(&format!(
-                        "Connecting {}:{} -> {}:{}",
-                        node.name.clone(),
-                        port.id,
-                        plugin.name.clone(),
-                        0
-                    ));
-
-                    let request = Request::new(CreateLinkByNameRequest {
-                        output_port_id: port.id,
-                        input_port_id: 0,
-                        output_node_name: node.name.clone(),
-                        input_node_name: plugin.name.clone(),
-                    });
-                    pipewire_client.create_link_by_name(request).await?;
-                } else {
-                    logger.log_info("Couldn't find node for port");
-                }
-            }
-            (None, None) => {
-                logger.log_info("Can't connect, port and plugin not found");
-            }
-            (None, Some(_)) => {
-                logger.log_info("Can't connect, port not found");
-            }
-            (Some(_), None) => {
-                logger.log_info("Can't connect, plugin not found");
-            }
+/// Removes the factory's link from saturator to gain of every channel strip
+/// whose extra plugins are live, so the signal runs through them instead.
+pub async fn disconnect_plugin_chain_bypasses(
+    topology: &Topology,
+    live_state: &LiveState,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    reporter.start_counted_stage(
+        "Disconnecting plugin chain bypasses",
+        live_state.plugin_chains.len(),
+    );
+    for (input_name, plugin_ids) in &live_state.plugin_chains {
+        reporter.step();
+        let Some(channel_strip) = live_state.find_channel_strip(input_name) else {
+            continue;
         };
+        let bypass_links = plan::plugin_chain_bypass_links(
+            channel_strip,
+            plugin_ids,
+            &live_state.plugins,
+            &live_state.links,
+            &live_state.nodes,
+        );
+        delete_links(&bypass_links, &topology.protected_nodes, clients, reporter).await?;
+    }
+    Ok(())
+}
 
-        if input.input_type == PmxInputType::StereoInput as i32 {
-            let right_port = ports
-                .iter()
-                .find(|p| p.path == input.right_port_path.clone().unwrap());
-
-            match (right_port, plugin) {
-                (Some(port), Some(plugin)) => {
-                    if let Some(node) = nodes.iter().find(|n| n.object_serial == port.node_id) {
-                        logger.log_info(&format!(
-                            "Connecting {}:{} -> {}:{}",
-                            node.name.clone(),
-                            port.id,
-                            plugin.name.clone(),
-                            0
-                        ));
-
-                        let mut pipewire_client = pipewire_client.clone();
-                        let request = Request::new(CreateLinkByNameRequest {
-                            output_port_id: port.id,
-                            input_port_id: 1,
-                            output_node_name: node.name.clone(),
-                            input_node_name: plugin.name.clone(),
-                        });
-                        pipewire_client.create_link_by_name(request).await?;
-                    }
-                }
-                (None, None) => (),
-                (None, Some(_)) => (),
-                (Some(_), None) => (),
+/// Adds the extra plugins of every input's chain through mod-host. Chains
+/// recorded by an earlier build are kept as long as all their plugins are
+/// still live. Returns the chains that were added.
+pub async fn add_plugin_chains(
+    topology: &Topology,
+    inputs: &[Input],
+    plugin_chains: &BTreeMap<String, Vec<u32>>,
+    plugins: &[crate::pmx::plugin::PmxPlugin],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<Vec<(String, Vec<u32>)>, Box<dyn std::error::Error>> {
+    let chained: Vec<(&Input, Vec<ChainPluginConfig>)> = inputs
+        .iter()
+        .map(|input| (input, topology.input_config(&input.name).plugins))
+        .filter(|(_, configs)| !configs.is_empty())
+        .collect();
+    reporter.start_counted_stage("Adding plugin chains", chained.len());
+    let mut added = Vec::new();
+    for (input, configs) in chained {
+        reporter.step();
+        let live = plugin_chains.get(&input.name).is_some_and(|plugin_ids| {
+            plugin_ids.len() == configs.len()
+                && plugin_ids
+                    .iter()
+                    .all(|id| plugins.iter().any(|p| p.id == *id))
+        });
+        if live {
+            reporter.log_info(&format!("Plugin chain of {} is already live", input.name));
+            continue;
+        }
+
+        let mut plugin_ids = Vec::new();
+        for config in &configs {
+            let request = AddPluginRequest {
+                plugin_uri: config.uri.clone(),
             };
+            let response = rpc::call(
+                Service::ModHost,
+                "AddPlugin",
+                &clients.mod_host().await?,
+                &request,
+                |mut client, request| async move { client.add_plugin(request).await },
+            )
+            .await?;
+            reporter.log_info(&format!(
+                "Added {} as plugin {} to the chain of {}",
+                config.uri, response.plugin_id, input.name
+            ));
+            plugin_ids.push(response.plugin_id);
         }
+        added.push((input.name.clone(), plugin_ids));
     }
-
-    Ok(())
+    Ok(added)
 }
 
-pub async fn get_nodes(
-    mut pipewire_client: PipewireClient<Channel>,
-) -> std::result::Result<Vec<super::pmx::pipewire::node::ListNode>, Box<dyn std::error::Error>> {
-    let nodes_request = Request::new(ListNodesRequest {});
-    let nodes_response = pipewire_client.list_nodes(nodes_request).await?;
-    Ok(nodes_response.into_inner().nodes)
-}
+/// Adds the duck plugin of the talkback through mod-host, unless the one an
+/// earlier build added is still live. Returns the plugin id if it was added.
+pub async fn add_talkback_plugin(
+    talkback: &TalkbackConfig,
+    plugin_chains: &BTreeMap<String, Vec<u32>>,
+    plugins: &[crate::pmx::plugin::PmxPlugin],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<Option<u32>, Box<dyn std::error::Error>> {
+    let Some(duck_plugin) = &talkback.duck_plugin else {
+        return Ok(None);
+    };
+    reporter.start_stage("Adding talkback plugin");
+    let live = plugin_chains
+        .get(&talkback.input)
+        .and_then(|ids| ids.first())
+        .is_some_and(|id| plugins.iter().any(|p| p.id == *id));
+    if live {
+        reporter.log_info("Talkback plugin is already live");
+        return Ok(None);
+    }
 
-pub async fn get_plugins(
-    mut registry_client: PmxRegistryClient<Channel>,
-) -> std::result::Result<Vec<super::pmx::plugin::PmxPlugin>, Box<dyn std::error::Error>> {
-    let plugin_request = Request::new(EmptyRequest {});
-    let plugin_response = registry_client.list_plugins(plugin_request).await?;
-    Ok(plugin_response.into_inner().plugins)
+    let request = AddPluginRequest {
+        plugin_uri: duck_plugin.clone(),
+    };
+    let response = rpc::call(
+        Service::ModHost,
+        "AddPlugin",
+        &clients.mod_host().await?,
+        &request,
+        |mut client, request| async move { client.add_plugin(request).await },
+    )
+    .await?;
+    reporter.log_info(&format!(
+        "Added {duck_plugin} as plugin {} for talkback {}",
+        response.plugin_id, talkback.input
+    ));
+    Ok(Some(response.plugin_id))
 }
 
-pub async fn get_ports(
-    mut pipewire_client: PipewireClient<Channel>,
-) -> std::result::Result<Vec<super::pmx::pipewire::port::ListPort>, Box<dyn std::error::Error>> {
-    let port_request = Request::new(ListPortsRequest {
-        node_id_filter: None,
-    });
-    let port_response = pipewire_client.list_ports(port_request).await?;
-    Ok(port_response.into_inner().ports)
+/// Adds a limiter through mod-host for each of `outputs` whose limiter from
+/// an earlier build isn't live and sets the ceiling of all of them. Returns
+/// the limiter of every output done, with the error that stopped the rest if
+/// one did, so the state file keeps the limiters already added either way.
+pub async fn add_output_limiters(
+    limiter: &LimiterConfig,
+    outputs: &BTreeSet<String>,
+    existing: &BTreeMap<String, u32>,
+    plugins: &[crate::pmx::plugin::PmxPlugin],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> (
+    BTreeMap<String, u32>,
+    std::result::Result<(), Box<dyn std::error::Error>>,
+) {
+    reporter.start_counted_stage("Adding output limiters", outputs.len());
+    add_output_plugins(
+        &limiter.plugin,
+        &limiter.ceiling_parameter,
+        limiter.ceiling,
+        outputs,
+        existing,
+        plugins,
+        clients,
+        reporter,
+    )
+    .await
 }
 
-pub async fn register_loopers_for_input_channels(
-    input_channels: &[PmxInput],
-    registry_client: PmxRegistryClient<Channel>,
-) -> Vec<PmxLooper> {
-    let mut result = Vec::new();
-    for (index, _channel) in input_channels.iter().enumerate() {
-        let looper = register_looper(index as u32, registry_client.clone())
-            .await
-            .unwrap();
-        result.push(looper);
-    }
-    result
+/// Adds a mono sum plugin through mod-host for each of `outputs` whose mono
+/// sum from an earlier build isn't live and pads all of them. Returns the
+/// mono sum of every output done, with the error that stopped the rest if
+/// one did.
+pub async fn add_mono_sums(
+    mono_sum: &MonoSumConfig,
+    outputs: &BTreeSet<String>,
+    existing: &BTreeMap<String, u32>,
+    plugins: &[crate::pmx::plugin::PmxPlugin],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> (
+    BTreeMap<String, u32>,
+    std::result::Result<(), Box<dyn std::error::Error>>,
+) {
+    reporter.start_counted_stage("Adding output mono sums", outputs.len());
+    add_output_plugins(
+        &mono_sum.plugin,
+        &mono_sum.gain_parameter,
+        mono_sum.gain,
+        outputs,
+        existing,
+        plugins,
+        clients,
+        reporter,
+    )
+    .await
 }
 
-pub async fn connect_loopers_to_channel_strips(
-    loopers: &[PmxLooper],
-    channel_strips: &Vec<PmxChannelStrip>,
+/// Adds `plugin_uri` for each of `outputs` unless `existing` holds a live
+/// one and sets `parameter` of every one to `value`.
+#[allow(clippy::too_many_arguments)]
+async fn add_output_plugins(
+    plugin_uri: &str,
+    parameter: &str,
+    value: f32,
+    outputs: &BTreeSet<String>,
+    existing: &BTreeMap<String, u32>,
     plugins: &[crate::pmx::plugin::PmxPlugin],
-    pipewire_client: PipewireClient<Channel>,
-    logger: &Logger,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> (
+    BTreeMap<String, u32>,
+    std::result::Result<(), Box<dyn std::error::Error>>,
 ) {
-    for (looper, channel_strip) in std::iter::zip(loopers, channel_strips) {
-        connect_looper_to_channel_strip(
-            looper,
-            channel_strip,
-            plugins,
-            pipewire_client.clone(),
-            logger,
+    let mut output_plugins = BTreeMap::new();
+    let result = add_output_plugins_into(
+        &mut output_plugins,
+        plugin_uri,
+        parameter,
+        value,
+        outputs,
+        existing,
+        plugins,
+        clients,
+        reporter,
+    )
+    .await;
+    (output_plugins, result)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn add_output_plugins_into(
+    output_plugins: &mut BTreeMap<String, u32>,
+    plugin_uri: &str,
+    parameter: &str,
+    value: f32,
+    outputs: &BTreeSet<String>,
+    existing: &BTreeMap<String, u32>,
+    plugins: &[crate::pmx::plugin::PmxPlugin],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    for output in outputs {
+        reporter.step();
+        let live = existing
+            .get(output)
+            .copied()
+            .filter(|id| plugins.iter().any(|p| p.id == *id));
+        let plugin_id = match live {
+            Some(plugin_id) => plugin_id,
+            None => {
+                let request = AddPluginRequest {
+                    plugin_uri: String::from(plugin_uri),
+                };
+                let response = rpc::call(
+                    Service::ModHost,
+                    "AddPlugin",
+                    &clients.mod_host().await?,
+                    &request,
+                    |mut client, request| async move { client.add_plugin(request).await },
+                )
+                .await?;
+                reporter.log_info(&format!(
+                    "Added {plugin_uri} as plugin {} for output {output}",
+                    response.plugin_id
+                ));
+                response.plugin_id
+            }
+        };
+        output_plugins.insert(output.clone(), plugin_id);
+        let request = UpdateParameterRequest {
+            plugin_instance_id: plugin_id,
+            parameter_symbol: String::from(parameter),
+            value,
+        };
+        rpc::call(
+            Service::ModHost,
+            "UpdateParameter",
+            &clients.mod_host().await?,
+            &request,
+            |mut client, request| async move { client.update_parameter(request).await },
         )
-        .await
-        .unwrap();
+        .await?;
     }
+    Ok(())
 }
 
-async fn connect_looper_to_channel_strip(
-    looper: &PmxLooper,
-    channel_strip: &PmxChannelStrip,
-    plugins: &[crate::pmx::plugin::PmxPlugin],
-    mut pipewire_client: PipewireClient<Channel>,
-    logger: &Logger,
+pub async fn delete_links(
+    links: &[(u32, Link)],
+    protected_nodes: &[String],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    for (id, link) in links {
+        stop_if_shutting_down()?;
+        delete_link(*id, link, protected_nodes, clients, reporter).await?;
+    }
+    Ok(())
+}
+
+/// Disconnects the live link `id` unless it touches one of the
+/// `protected_nodes`, protected links are reported as skipped.
+async fn delete_link(
+    id: u32,
+    link: &Link,
+    protected_nodes: &[String],
+    clients: &Clients,
+    reporter: &Reporter,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
-    if channel_strip.channel_type() == PmxChannelStripType::Basic {
-        logger.log_info("Channel strip type is Basic, nothing to do!");
+    if !protection::allows(link, protected_nodes, reporter) {
         return Ok(());
     }
+    reporter.log_info(&format!("Disconnecting {link}"));
+    clients.graph.disconnect(id, link).await?;
+    reporter.link_removed(link);
+    unregister_link(link, clients, reporter).await;
+    Ok(())
+}
 
-    if let Some(plugin) = plugins
-        .iter()
-        .find(|p| p.id == channel_strip.cross_fader_plugin_id.unwrap())
-    {
-        logger.log_info(&format!(
-            "Connecting {}:{} -> {}:{}",
-            String::from("sooperlooper"),
-            looper.loop_number + 2,
-            plugin.name.clone(),
-            2
+/// Tells the registry about a link the builder created, so the UI and the
+/// controller see the routing without reading pipewire. The link stays when
+/// the registry can't take it, it is only logged.
+async fn register_link(link: &Link, clients: &Clients, reporter: &Reporter) {
+    let request = RegisterLinkRequest {
+        output_node_name: link.output_node_name.clone(),
+        output_port_id: link.output_port_id,
+        input_node_name: link.input_node_name.clone(),
+        input_port_id: link.input_port_id,
+        run_id: String::from(ownership::run_id()),
+    };
+    let response = rpc::call(
+        Service::Registry,
+        "RegisterLink",
+        &clients.registry,
+        &request,
+        |mut client, request| async move { client.register_link(request).await },
+    )
+    .await;
+    if let Err(error) = response {
+        reporter.log_info(&format!(
+            "Couldn't register {link} with the registry: {error}"
         ));
+    }
+}
 
-        let request = Request::new(CreateLinkByNameRequest {
-            output_port_id: looper.loop_number + 2,
-            input_port_id: 2,
-            output_node_name: String::from("sooperlooper"),
-            input_node_name: plugin.name.clone(),
-        });
-        pipewire_client.create_link_by_name(request).await?;
-
-        logger.log_info(&format!(
-            "Connecting {}:{} -> {}:{}",
-            String::from("sooperlooper"),
-            looper.loop_number + 3,
-            plugin.name.clone(),
-            3
+/// Tells the registry a link the builder managed is gone.
+async fn unregister_link(link: &Link, clients: &Clients, reporter: &Reporter) {
+    let request = UnregisterLinkRequest {
+        output_node_name: link.output_node_name.clone(),
+        output_port_id: link.output_port_id,
+        input_node_name: link.input_node_name.clone(),
+        input_port_id: link.input_port_id,
+    };
+    let response = rpc::call(
+        Service::Registry,
+        "UnregisterLink",
+        &clients.registry,
+        &request,
+        |mut client, request| async move { client.unregister_link(request).await },
+    )
+    .await;
+    if let Err(error) = response {
+        reporter.log_info(&format!(
+            "Couldn't unregister {link} from the registry: {error}"
         ));
+    }
+}
 
-        let request = Request::new(CreateLinkByNameRequest {
-            output_port_id: looper.loop_number + 3,
-            input_port_id: 3,
-            output_node_name: String::from("sooperlooper"),
-            input_node_name: plugin.name.clone(),
-        });
-        pipewire_client.create_link_by_name(request).await?;
-    };
+pub async fn get_links(
+    clients: &Clients,
+) -> std::result::Result<Vec<super::pmx::pipewire::link::ListLink>, Box<dyn std::error::Error>> {
+    clients.graph.links().await
+}
 
-    Ok(())
+pub async fn get_nodes(
+    clients: &Clients,
+) -> std::result::Result<Vec<super::pmx::pipewire::node::ListNode>, Box<dyn std::error::Error>> {
+    clients.graph.nodes().await
 }
 
-pub async fn connect_loopers_to_inputs(
-    inputs: &[PmxInput],
-    loopers: &[PmxLooper],
-    nodes: &[ListNode],
-    ports: &[ListPort],
-    pipewire_client: PipewireClient<Channel>,
-    logger: &Logger,
-) {
-    let channel_and_looper_pairs = std::iter::zip(inputs, loopers);
-    for (channel, looper) in channel_and_looper_pairs {
-        connect_looper_to_input(
-            channel,
-            looper,
-            ports,
-            nodes,
-            pipewire_client.clone(),
-            logger,
-        )
-        .await;
-    }
+pub async fn get_plugins(
+    clients: &Clients,
+) -> std::result::Result<Vec<super::pmx::plugin::PmxPlugin>, Box<dyn std::error::Error>> {
+    let plugin_request = EmptyRequest {};
+    let plugin_response = rpc::call(
+        Service::Registry,
+        "ListPlugins",
+        &clients.registry,
+        &plugin_request,
+        |mut client, request| async move { client.list_plugins(request).await },
+    )
+    .await?;
+    Ok(plugin_response.plugins)
 }
 
-pub async fn connect_looper_to_input(
-    input: &PmxInput,
-    looper: &PmxLooper,
-    ports: &[ListPort],
-    nodes: &[ListNode],
-    mut pipewire_client: PipewireClient<Channel>,
-    logger: &Logger,
-) {
-    logger.log_info(&format!(
-        "Connecting input {} to looper {}",
-        input.name, looper.loop_number,
-    ));
+pub async fn get_ports(
+    clients: &Clients,
+) -> std::result::Result<Vec<super::pmx::pipewire::port::ListPort>, Box<dyn std::error::Error>> {
+    clients.graph.ports().await
+}
 
-    if input.input_type() == PmxInputType::None {
-        logger.log_info("Input type is None, nothing to do");
-        return;
+/// Registers the loopers of `looper_inputs`, loop numbers the registry
+/// already has a looper for are reused.
+pub async fn register_loopers_for_input_channels(
+    looper_inputs: &[(u32, &Input)],
+    existing: &[Looper],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<Vec<Looper>, Box<dyn std::error::Error>> {
+    reporter.start_counted_stage("Registering loopers", looper_inputs.len());
+    let mut result = Vec::new();
+    for (loop_number, channel) in looper_inputs {
+        reporter.step();
+        if existing.iter().any(|l| l.loop_number == *loop_number) {
+            reporter.log_info(&format!(
+                "Looper {loop_number} of input {} already registered, reusing it",
+                channel.name
+            ));
+            continue;
+        }
+        let looper = register_looper(*loop_number, clients).await?;
+        reporter.looper_registered(looper.loop_number);
+        result.push(looper);
     }
+    Ok(result)
+}
 
-    if let Some(port) = ports
+/// Unregisters the loopers earlier builds `assigned` to inputs that no
+/// longer get one, removed inputs or inputs with the looper turned off.
+/// Loopers the builder didn't register are left alone. Returns the inputs
+/// whose looper is gone.
+pub async fn unregister_orphaned_loopers(
+    looper_inputs: &[(u32, &Input)],
+    assigned: &BTreeMap<String, u32>,
+    existing: &[Looper],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<Vec<String>, Box<dyn std::error::Error>> {
+    let orphaned: Vec<(&String, u32)> = assigned
         .iter()
-        .find(|p| p.path == input.left_port_path.clone().unwrap())
-    {
-        if let Some(node) = nodes.iter().find(|n| n.object_serial == port.node_id) {
-            logger.log_info(&format!(
-                "Connecting {}:{} -> {}:{}",
-                node.name.clone(),
-                0,
-                String::from("sooperlooper"),
-                looper.loop_number + 2,
+        .filter(|(input_name, loop_number)| {
+            !looper_inputs
+                .iter()
+                .any(|(n, input)| input.name == **input_name || n == *loop_number)
+        })
+        .map(|(input_name, loop_number)| (input_name, *loop_number))
+        .collect();
+    let mut forgotten = Vec::new();
+    for (input_name, loop_number) in orphaned {
+        if existing.iter().any(|l| l.loop_number == loop_number) {
+            reporter.log_info(&format!(
+                "Unregistering looper {loop_number}, input {input_name} no longer has one"
             ));
-
-            let request = Request::new(CreateLinkByNameRequest {
-                output_port_id: 2 * looper.loop_number + 2,
-                input_port_id: 0,
-                output_node_name: String::from("sooperlooper"),
-                input_node_name: node.name.clone(),
-            });
-            pipewire_client.create_link_by_name(request).await.unwrap();
+            let request = UnregisterLooperRequest { loop_number };
+            rpc::call(
+                Service::Registry,
+                "UnregisterLooper",
+                &clients.registry,
+                &request,
+                |mut client, request| async move { client.unregister_looper(request).await },
+            )
+            .await?;
         }
+        forgotten.push(input_name.clone());
     }
+    Ok(forgotten)
+}
 
-    if input.input_type() == PmxInputType::MonoInput {
-        return;
+/// Sets plugin parameters through the mod-host proxy.
+pub async fn apply_parameters(
+    settings: &[ParameterSetting],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    reporter.start_counted_stage("Applying plugin parameters", settings.len());
+    for setting in settings {
+        reporter.step();
+        reporter.log_info(&format!("Setting {setting}"));
+        set_parameter(setting, clients).await?;
     }
+    Ok(())
+}
 
-    if let Some(port) = ports
-        .iter()
-        .find(|p| p.path == input.right_port_path.clone().unwrap())
-    {
-        if let Some(node) = nodes.iter().find(|n| n.object_serial == port.node_id) {
-            logger.log_info(&format!(
-                "Connecting {}:{} -> {}:{}",
-                node.name.clone(),
-                1,
-                String::from("sooperlooper"),
-                looper.loop_number + 3,
-            ));
-
-            let request = Request::new(CreateLinkByNameRequest {
-                output_port_id: 2 * looper.loop_number + 3,
-                input_port_id: 1,
-                output_node_name: String::from("sooperlooper"),
-                input_node_name: node.name.clone(),
-            });
-            pipewire_client.create_link_by_name(request).await.unwrap();
-        }
-    }
+pub async fn set_parameter(
+    setting: &ParameterSetting,
+    clients: &Clients,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let request = UpdateParameterRequest {
+        plugin_instance_id: setting.plugin_id,
+        parameter_symbol: String::from(setting.symbol),
+        value: setting.value,
+    };
+    rpc::call(
+        Service::ModHost,
+        "UpdateParameter",
+        &clients.mod_host().await?,
+        &request,
+        |mut client, request| async move { client.update_parameter(request).await },
+    )
+    .await?;
+    Ok(())
 }
 
-async fn register_looper(
+pub async fn register_looper(
     loop_number: u32,
-    mut registry_client: PmxRegistryClient<Channel>,
-) -> Result<PmxLooper, Box<dyn std::error::Error>> {
-    let looper_request = Request::new(RegisterLooperRequest { loop_number });
-    Ok(registry_client
-        .register_looper(looper_request)
-        .await
-        .unwrap()
-        .into_inner())
+    clients: &Clients,
+) -> Result<Looper, Box<dyn std::error::Error>> {
+    let looper_request = RegisterLooperRequest { loop_number };
+    Ok(Looper::from(
+        rpc::call(
+            Service::Registry,
+            "RegisterLooper",
+            &clients.registry,
+            &looper_request,
+            |mut client, request| async move { client.register_looper(request).await },
+        )
+        .await?,
+    ))
 }
@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clients::Clients;
+use crate::model::Input;
+use crate::naming::naming;
+use crate::plan::{ParameterSetting, StripPlugins, GAIN_PARAMETER};
+use crate::pmx::pipewire::PortLevel;
+use crate::report::Reporter;
+use crate::topology::{PluginRole, Topology};
+
+/// Levels below this are taken for a silent input, nothing to calibrate on.
+const SILENCE_DBFS: f32 = -90.0;
+
+/// Gain staging of the inputs from their measured levels, run after wiring.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CalibrationConfig {
+    /// Seconds each input is measured for. Has to stay below the pipewire
+    /// timeout, all inputs are measured in one call.
+    pub seconds: u64,
+    /// Level in dBFS the inputs are brought to
+    pub target_dbfs: f32,
+    pub level: LevelKind,
+    /// Most gain in dB calibration sets, up or down
+    pub max_gain: f32,
+    /// Measure inputs again that the state file already has a gain for
+    pub remeasure: bool,
+}
+
+/// Which measured level is brought to the target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LevelKind {
+    #[default]
+    Rms,
+    Peak,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        CalibrationConfig {
+            seconds: 5,
+            target_dbfs: -18.0,
+            level: LevelKind::default(),
+            max_gain: 24.0,
+            remeasure: false,
+        }
+    }
+}
+
+impl CalibrationConfig {
+    /// Gain bringing `level` to the target, none for a silent input.
+    pub fn gain_for(&self, level: &PortLevel) -> Option<f32> {
+        let measured = match self.level {
+            LevelKind::Rms => level.rms_dbfs,
+            LevelKind::Peak => level.peak_dbfs,
+        };
+        (measured > SILENCE_DBFS)
+            .then(|| (self.target_dbfs - measured).clamp(-self.max_gain, self.max_gain))
+    }
+}
+
+fn port_paths(input: &Input) -> Vec<String> {
+    input
+        .left_port_path
+        .iter()
+        .chain(input.right_port_path.iter())
+        .cloned()
+        .collect()
+}
+
+/// Measures the inputs without a calibrated gain, or all of them when
+/// remeasuring. Returns the gain of each input that had a signal, the louder
+/// port decides for stereo inputs.
+pub async fn measure_inputs(
+    config: &CalibrationConfig,
+    inputs: &[Input],
+    calibrated_gains: &BTreeMap<String, f32>,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<BTreeMap<String, f32>, Box<dyn std::error::Error>> {
+    let measured: Vec<&Input> = inputs
+        .iter()
+        .filter(|input| config.remeasure || !calibrated_gains.contains_key(&input.name))
+        .filter(|input| !port_paths(input).is_empty())
+        .collect();
+    if measured.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    reporter.start_stage("Measuring input levels");
+    reporter.log_info(&format!(
+        "Measuring {} inputs for {} seconds",
+        measured.len(),
+        config.seconds
+    ));
+    let paths: Vec<String> = measured.iter().flat_map(|i| port_paths(i)).collect();
+    let levels = clients
+        .graph
+        .measure_levels(&paths, Duration::from_secs(config.seconds))
+        .await?;
+
+    let mut gains = BTreeMap::new();
+    for input in measured {
+        let input_paths = port_paths(input);
+        let gain = levels
+            .iter()
+            .filter(|level| input_paths.contains(&level.port_path))
+            .filter_map(|level| config.gain_for(level))
+            // The smallest gain belongs to the loudest port
+            .reduce(f32::min);
+        match gain {
+            Some(gain) => {
+                reporter.log_info(&format!("Calibrated {} to {gain:.1} dB", input.name));
+                gains.insert(input.name.clone(), gain);
+            }
+            None => reporter.skipped(&format!("No signal on {}, not calibrating it", input.name)),
+        }
+    }
+    Ok(gains)
+}
+
+/// Gain settings for the calibrated inputs. Strips with a preset gain keep
+/// the preset.
+pub fn gain_settings<S: StripPlugins>(
+    topology: &Topology,
+    calibrated_gains: &BTreeMap<String, f32>,
+    channel_strips: &[S],
+) -> Vec<ParameterSetting> {
+    calibrated_gains
+        .iter()
+        .filter(|(input_name, _)| {
+            !topology
+                .presets
+                .iter()
+                .any(|p| p.channel_strip == **input_name && p.gain.is_some())
+        })
+        .filter_map(|(input_name, gain)| {
+            let channel_strip = channel_strips
+                .iter()
+                .find(|s| naming().matches(s.strip_name(), input_name))?;
+            Some(ParameterSetting {
+                channel_strip: input_name.clone(),
+                plugin_id: channel_strip.plugin_id(PluginRole::Gain)?,
+                symbol: GAIN_PARAMETER,
+                value: *gain,
+            })
+        })
+        .collect()
+}
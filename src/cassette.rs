@@ -0,0 +1,200 @@
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tonic::{Code, Status};
+
+use crate::ownership;
+use crate::rpc::Service;
+
+/// One call to a service with the encoded request and what the service
+/// answered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub service: String,
+    pub rpc: String,
+    /// Protobuf encoded request, hex
+    pub request: String,
+    /// Protobuf encoded response, hex, none if the call failed
+    pub response: Option<String>,
+    pub status_code: i32,
+    pub status_message: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    /// Run id the recorded links were tagged with
+    #[serde(default)]
+    run_id: Option<String>,
+    interactions: Vec<Interaction>,
+}
+
+/// Service interactions recorded during a build, or loaded to answer the
+/// calls of a build without any service running.
+#[derive(Debug)]
+pub struct Cassette {
+    replaying: bool,
+    run_id: Option<String>,
+    interactions: Mutex<Vec<Interaction>>,
+    /// Interactions already replayed, each answers one call only
+    used: Mutex<Vec<bool>>,
+}
+
+impl Cassette {
+    pub fn recording() -> Cassette {
+        Cassette {
+            replaying: false,
+            run_id: None,
+            interactions: Mutex::new(Vec::new()),
+            used: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Cassette, Box<dyn std::error::Error>> {
+        let file: CassetteFile = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let used = vec![false; file.interactions.len()];
+        Ok(Cassette {
+            replaying: true,
+            run_id: file.run_id,
+            interactions: Mutex::new(file.interactions),
+            used: Mutex::new(used),
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let file = CassetteFile {
+            run_id: Some(String::from(ownership::run_id())),
+            interactions: self.interactions.lock().unwrap().clone(),
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+
+    /// Run id of the recorded build, none for cassettes recorded before
+    /// links were tagged.
+    pub fn run_id(&self) -> Option<&str> {
+        self.run_id.as_deref()
+    }
+
+    /// Recorded interactions no call has asked for during the replay.
+    pub fn unused(&self) -> Vec<Interaction> {
+        let used = self.used.lock().unwrap();
+        self.interactions
+            .lock()
+            .unwrap()
+            .iter()
+            .zip(used.iter())
+            .filter(|(_, used)| !**used)
+            .map(|(interaction, _)| interaction.clone())
+            .collect()
+    }
+
+    /// Answer to the first unused interaction with the same service, rpc
+    /// and request. Calls the recording never made fail, so a change in
+    /// the wiring shows up as a failed replay.
+    fn answer(&self, service: Service, rpc: &str, request: &[u8]) -> Result<Vec<u8>, Status> {
+        let service = service.to_string();
+        let request = to_hex(request);
+        let interactions = self.interactions.lock().unwrap();
+        let mut used = self.used.lock().unwrap();
+        let Some(index) = (0..interactions.len()).find(|&index| {
+            let interaction = &interactions[index];
+            !used[index]
+                && interaction.service == service
+                && interaction.rpc == rpc
+                && interaction.request == request
+        }) else {
+            return Err(Status::failed_precondition(format!(
+                "cassette has no answer for {rpc} on {service} with this request"
+            )));
+        };
+        used[index] = true;
+        let interaction = &interactions[index];
+        match &interaction.response {
+            Some(response) => from_hex(response).ok_or_else(|| {
+                Status::data_loss(format!("cassette response of {rpc} isn't valid hex"))
+            }),
+            None => Err(Status::new(
+                Code::from(interaction.status_code),
+                interaction.status_message.clone(),
+            )),
+        }
+    }
+
+    fn record(
+        &self,
+        service: Service,
+        rpc: &str,
+        request: &[u8],
+        response: Result<&[u8], &Status>,
+    ) {
+        let (response, status_code, status_message) = match response {
+            Ok(response) => (Some(to_hex(response)), Code::Ok as i32, String::new()),
+            Err(status) => (None, status.code() as i32, String::from(status.message())),
+        };
+        self.interactions.lock().unwrap().push(Interaction {
+            service: service.to_string(),
+            rpc: String::from(rpc),
+            request: to_hex(request),
+            response,
+            status_code,
+            status_message,
+        });
+    }
+}
+
+tokio::task_local! {
+    static CASSETTE: Arc<Cassette>;
+}
+
+/// Runs `future` with every service call recorded into or replayed from
+/// `cassette`.
+pub async fn scope<F: Future>(cassette: Option<Arc<Cassette>>, future: F) -> F::Output {
+    match cassette {
+        Some(cassette) => CASSETTE.scope(cassette, future).await,
+        None => future.await,
+    }
+}
+
+/// Whether calls are answered from a cassette, so no service has to run.
+pub fn is_replaying() -> bool {
+    CASSETTE
+        .try_with(|cassette| cassette.replaying)
+        .unwrap_or(false)
+}
+
+/// The recorded answer when replaying, none otherwise.
+pub fn replay(service: Service, rpc: &str, request: &[u8]) -> Option<Result<Vec<u8>, Status>> {
+    CASSETTE
+        .try_with(|cassette| {
+            cassette
+                .replaying
+                .then(|| cassette.answer(service, rpc, request))
+        })
+        .ok()
+        .flatten()
+}
+
+/// Adds the call to the cassette when recording.
+pub fn record(service: Service, rpc: &str, request: &[u8], response: Result<&[u8], &Status>) {
+    let _ = CASSETTE.try_with(|cassette| {
+        if !cassette.replaying {
+            cassette.record(service, rpc, request, response);
+        }
+    });
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,100 @@
+//! Flakiness injected into the service calls, for exercising the retries,
+//! restores and reconciling against the mock services or a test rig. Only
+//! built with the `chaos` feature.
+
+#[cfg(feature = "chaos")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "chaos")]
+use std::sync::OnceLock;
+use std::time::Duration;
+#[cfg(feature = "chaos")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tonic::Status;
+
+#[cfg(feature = "chaos")]
+#[derive(Debug, Clone, Copy)]
+struct Chaos {
+    /// Percentage of calls that fail
+    failure_percent: u8,
+    /// Longest a call is held back for
+    max_delay: Duration,
+}
+
+#[cfg(feature = "chaos")]
+static CHAOS: OnceLock<Chaos> = OnceLock::new();
+#[cfg(feature = "chaos")]
+static RANDOM: AtomicU64 = AtomicU64::new(0);
+
+/// Makes `failure_percent` of the following calls fail and holds each one
+/// back for up to `max_delay`.
+#[cfg(feature = "chaos")]
+pub fn enable(failure_percent: u8, max_delay: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    // Xorshift never leaves zero
+    RANDOM.store(seed | 1, Ordering::Relaxed);
+    let _ = CHAOS.set(Chaos {
+        failure_percent,
+        max_delay,
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "chaos"))]
+pub fn enable(
+    _failure_percent: u8,
+    _max_delay: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err("the builder was built without chaos mode".into())
+}
+
+/// Xorshift, good enough for picking the calls to disturb.
+#[cfg(feature = "chaos")]
+fn random() -> u64 {
+    let step = |mut x: u64| {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x
+    };
+    let previous = RANDOM
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(step(x)))
+        .unwrap();
+    step(previous)
+}
+
+/// Status a call fails with instead of being sent, if chaos picks it. Half
+/// the failures look like an unreachable service and are retried, the
+/// others fail the call right away.
+#[cfg(feature = "chaos")]
+pub fn failure() -> Option<Status> {
+    let chaos = CHAOS.get()?;
+    if random() % 100 >= u64::from(chaos.failure_percent) {
+        return None;
+    }
+    Some(if random() % 2 == 0 {
+        Status::unavailable("failed by chaos mode")
+    } else {
+        Status::internal("failed by chaos mode")
+    })
+}
+
+#[cfg(not(feature = "chaos"))]
+pub fn failure() -> Option<Status> {
+    None
+}
+
+/// Holds a call back for a random time up to the configured delay.
+#[cfg(feature = "chaos")]
+pub async fn delay() {
+    let Some(chaos) = CHAOS.get().filter(|c| !c.max_delay.is_zero()) else {
+        return;
+    };
+    let max_delay = chaos.max_delay.as_millis() as u64;
+    tokio::time::sleep(Duration::from_millis(random() % (max_delay + 1))).await;
+}
+
+#[cfg(not(feature = "chaos"))]
+pub async fn delay() {}
@@ -0,0 +1,298 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+
+use crate::daemon;
+use crate::graph::GraphFormat;
+use crate::report::FailurePolicy;
+use crate::script::ScriptFormat;
+use crate::state::Stage;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "fr-pmx-builder",
+    version,
+    about = "Builds the PMX mixer graph",
+    arg_required_else_help = true
+)]
+pub struct Cli {
+    /// Topology file describing the desired mixer layout
+    #[arg(long, global = true, env = "PMX_BUILDER_TOPOLOGY")]
+    pub topology: Option<PathBuf>,
+
+    /// Per venue topology merged over the topology file, its values win
+    #[arg(long, global = true, env = "PMX_BUILDER_OVERLAY")]
+    pub overlay: Option<PathBuf>,
+
+    /// Venue file mapping the topology's outputs to the ports of this venue
+    #[arg(long, global = true, env = "PMX_BUILDER_VENUE")]
+    pub venue: Option<PathBuf>,
+
+    /// Write a JSON report of every action the build took to this file
+    #[arg(long, global = true)]
+    pub report_json: Option<PathBuf>,
+
+    /// Serve prometheus metrics on this address while the builder runs
+    #[arg(long, global = true)]
+    pub metrics_address: Option<SocketAddr>,
+
+    /// Only print failures, no progress display or log lines
+    #[arg(long, short, global = true)]
+    pub quiet: bool,
+
+    /// What a failed connection does: stop the build, carry on, or carry on
+    /// and exit non-zero at the end
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub on_failure: FailurePolicy,
+
+    /// Same as `--on-failure strict`
+    #[arg(long, global = true, conflicts_with = "on_failure")]
+    pub strict: bool,
+
+    /// Profile from the topology file to apply
+    #[arg(long, global = true, env = "PMX_BUILDER_PROFILE")]
+    pub profile: Option<String>,
+
+    /// File the completed build stages are recorded in
+    #[arg(long, global = true, default_value = "fr-pmx-builder.state.json")]
+    pub state_file: PathBuf,
+
+    /// Directory the reports of the last builds are kept in
+    #[arg(long, global = true, default_value = "fr-pmx-builder.history")]
+    pub history_dir: PathBuf,
+
+    /// Number of builds the history keeps
+    #[arg(long, global = true, default_value_t = 20)]
+    pub history_keep: usize,
+
+    /// Record how long every RPC and stage took and write them to this file
+    /// as folded stacks for flamegraph tools
+    #[arg(long, global = true)]
+    pub timings: Option<PathBuf>,
+
+    /// Export a trace of every stage, RPC and link to the OTLP collector at
+    /// this endpoint, like `http://localhost:4317`. Needs the `otlp` feature.
+    #[arg(long, global = true)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Fail this percentage of service calls on purpose, to try the retries
+    /// and restores. Needs the `chaos` feature.
+    #[arg(long, global = true, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub chaos: Option<u8>,
+
+    /// Hold every service call back for a random time up to this long, like
+    /// `500ms`, when chaos mode is on
+    #[arg(long, global = true, default_value = "0s", value_parser = daemon::parse_duration, requires = "chaos")]
+    pub chaos_delay: Duration,
+
+    /// Record every service call and its answer to this cassette file
+    #[arg(long, global = true, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Answer every service call from a recorded cassette instead of the
+    /// services, calls it didn't record fail
+    #[arg(long, global = true)]
+    pub replay: Option<PathBuf>,
+
+    /// Skip the stages an interrupted build already completed
+    #[arg(long, global = true)]
+    pub resume: bool,
+
+    /// Capture the managed links before building and put them back if the
+    /// build fails, removing whatever it connected
+    #[arg(long, global = true)]
+    pub restore_on_failure: bool,
+
+    /// Mute the output stages while wiring and bring them up only once the
+    /// build verified, so a mis-wired link can't blast the speakers
+    #[arg(long, global = true)]
+    pub safe: bool,
+
+    /// Ask before deleting each link, channel strip or plugin in teardown,
+    /// gc and stale link removal
+    #[arg(long, global = true)]
+    pub interactive: bool,
+
+    /// Delete without asking, overrides `--interactive` and gc's prompt
+    #[arg(long, global = true)]
+    pub yes: bool,
+
+    /// Run only these build stages, like `--only inputs,loopers`. The
+    /// inputs, groups and output_stage_wired stages are wired together.
+    #[arg(long, value_enum, value_delimiter = ',', conflicts_with = "skip")]
+    pub only: Vec<Stage>,
+
+    /// Leave these build stages out, like `--skip output-stage`
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub skip: Vec<Stage>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Create channel strips and wire the complete mixer graph
+    Build,
+    /// Print the links the wiring would make, marking the ones already live
+    Plan,
+    /// List the last builds with their outcome and configuration hash
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+    },
+    /// Compare the desired topology with the live registry and pipewire state
+    Diff,
+    /// Remove every link the builder manages from pipewire
+    Teardown,
+    /// Delete channel strips and plugins no input, group or output stage
+    /// uses any more, or whose pipewire nodes are gone. Asks for each one
+    /// unless `--yes` is given
+    Gc,
+    /// Check the live graph against the topology, exits non-zero on mismatch
+    Verify,
+    /// Check every service and port path without changing anything
+    Doctor,
+    /// Rewire the channel strip, looper and group routing of one input
+    RebuildInput {
+        /// Name of the input in the registry
+        name: String,
+    },
+    /// Build the chain of every input registered while the watch runs
+    Watch {
+        /// Seconds between two looks at the registry
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+    },
+    /// Repair drift between the topology and the live links periodically
+    Daemon {
+        /// Time between two reconcile rounds, like 30s, 5m or 1h
+        #[arg(long, default_value = "30s", value_parser = daemon::parse_duration)]
+        interval: Duration,
+        /// Random extra delay of up to this long added to every interval
+        #[arg(long, default_value = "0s", value_parser = daemon::parse_duration)]
+        jitter: Duration,
+        /// Serve the outcome of the last reconcile round as JSON on /status
+        #[arg(long)]
+        status_address: Option<SocketAddr>,
+        /// Append every added or removed managed link to this JSON lines
+        /// file, marked with whether the daemon made the change
+        #[arg(long)]
+        audit_log: Option<PathBuf>,
+    },
+    /// Export the managed part of the live graph
+    #[command(name = "export-graph", alias = "graph")]
+    Graph {
+        /// Format the graph is written in
+        #[arg(long, value_enum, default_value_t)]
+        format: GraphFormat,
+        /// Write the graph to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Write the link plan as a script that makes the links without the PMX
+    /// services
+    ExportScript {
+        /// Kind of script written
+        #[arg(long, value_enum, default_value_t)]
+        format: ScriptFormat,
+        /// Write the script to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Save the live registry state and every link to a snapshot file
+    Snapshot {
+        /// File the snapshot is written to
+        path: PathBuf,
+    },
+    /// Recreate whatever part of a snapshot is missing from the live graph
+    Restore {
+        /// Snapshot file written by the snapshot command
+        path: PathBuf,
+    },
+    /// Write the live connections the topology doesn't make as a CSV patch
+    /// list
+    ExportPatches {
+        /// Write the patch list to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Make the connections of a CSV patch list that aren't live yet
+    ApplyPatches {
+        /// Patch list with output_node,output_port,input_node,input_port rows
+        path: PathBuf,
+    },
+    /// Interactive console showing the wiring, to retry links and re-run stages
+    Tui,
+    /// Run the builder as a gRPC service
+    Serve {
+        /// Address the pmx.builder service listens on
+        #[arg(long, default_value = "127.0.0.1:50070")]
+        address: SocketAddr,
+    },
+    /// Print the completion script for a shell
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print the manual page in roff format
+    Manpage,
+    /// Inspect the configuration the builder runs with
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+impl Command {
+    /// Whether the command changes the graph or the state file, and so has
+    /// to hold the state file's lock while it runs.
+    pub fn changes_graph(&self) -> bool {
+        matches!(
+            self,
+            Command::Build
+                | Command::Gc
+                | Command::Teardown
+                | Command::RebuildInput { .. }
+                | Command::Watch { .. }
+                | Command::Daemon { .. }
+                | Command::Restore { .. }
+                | Command::ApplyPatches { .. }
+                | Command::Tui
+                | Command::Serve { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum HistoryCommand {
+    /// Print the full report of one build
+    Show {
+        /// Run id as `history` lists it
+        run_id: String,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the topology a build uses, with the overlay, venue, environment
+    /// overrides and profile applied, and the service URLs
+    Dump,
+}
+
+/// Writes the completion script of `shell` to stdout.
+pub fn print_completions(shell: Shell) -> Result<(), Box<dyn std::error::Error>> {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+    Ok(())
+}
+
+/// Writes the manual page to stdout.
+pub fn print_manpage() -> Result<(), Box<dyn std::error::Error>> {
+    clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?;
+    Ok(())
+}
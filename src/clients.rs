@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use tokio::sync::OnceCell;
+
+use crate::config;
+use crate::connection::{self, AuthChannel, ServiceConnection};
+use crate::connector::{self, Connector};
+use crate::pmx::{
+    factory::pmx_factory_client::PmxFactoryClient,
+    mod_host::mod_host_proxy_client::ModHostProxyClient, pmx_registry_client::PmxRegistryClient,
+};
+use crate::report::Reporter;
+use crate::topology::Topology;
+
+/// Where a service is reached, for connecting to it on first use.
+#[derive(Debug, Clone)]
+struct LazyService {
+    url: String,
+    config: ServiceConnection,
+}
+
+/// Clients of every service a build talks to. Clones are cheap and share
+/// the connections, so the handle is passed around instead of the single
+/// clients. Calls go through `rpc::call` for timeouts, retries and the
+/// cassette.
+///
+/// The registry and the graph are connected right away, every command
+/// needs them. The factory and mod-host are connected on first use, most
+/// commands never call them and shouldn't fail when they are down.
+#[derive(Clone)]
+pub struct Clients {
+    pub registry: PmxRegistryClient<AuthChannel>,
+    pub graph: Connector,
+    factory_service: Option<LazyService>,
+    factory: Arc<OnceCell<PmxFactoryClient<AuthChannel>>>,
+    mod_host_service: Option<LazyService>,
+    mod_host: Arc<OnceCell<ModHostProxyClient<AuthChannel>>>,
+}
+
+impl Clients {
+    /// Connects to the services at the configured URLs.
+    pub async fn connect(
+        topology: &Topology,
+        reporter: &Reporter,
+    ) -> Result<Clients, Box<dyn std::error::Error>> {
+        let service_urls = config::service_urls();
+        let registry = connection::registry_client(
+            service_urls.pmx_registry_url,
+            &topology.connections.registry,
+        )
+        .await?;
+        let (graph, fallback) = connector::connect(
+            topology.graph_backend,
+            service_urls.pipewire_registry_url,
+            &topology.connections.pipewire,
+        )
+        .await?;
+        if let Some(reason) = fallback {
+            reporter.graph_fallback(&reason);
+        }
+        Ok(Clients::new(registry, graph)
+            .with_factory(
+                service_urls.pmx_factory_url,
+                topology.connections.factory.clone(),
+            )
+            .with_mod_host(
+                service_urls.pmx_mod_host_proxy_url,
+                topology.connections.mod_host.clone(),
+            ))
+    }
+
+    /// Clients of an already connected registry and graph. Calls to the
+    /// factory or mod-host fail until they are set.
+    pub fn new(registry: PmxRegistryClient<AuthChannel>, graph: Connector) -> Clients {
+        Clients {
+            registry,
+            graph,
+            factory_service: None,
+            factory: Arc::new(OnceCell::new()),
+            mod_host_service: None,
+            mod_host: Arc::new(OnceCell::new()),
+        }
+    }
+
+    pub fn with_factory(mut self, url: String, config: ServiceConnection) -> Clients {
+        self.factory_service = Some(LazyService { url, config });
+        self.factory = Arc::new(OnceCell::new());
+        self
+    }
+
+    /// Uses an already connected factory client.
+    pub fn with_factory_client(mut self, client: PmxFactoryClient<AuthChannel>) -> Clients {
+        self.factory = Arc::new(OnceCell::new_with(Some(client)));
+        self
+    }
+
+    pub fn with_mod_host(mut self, url: String, config: ServiceConnection) -> Clients {
+        self.mod_host_service = Some(LazyService { url, config });
+        self.mod_host = Arc::new(OnceCell::new());
+        self
+    }
+
+    /// Uses an already connected mod-host client.
+    pub fn with_mod_host_client(mut self, client: ModHostProxyClient<AuthChannel>) -> Clients {
+        self.mod_host = Arc::new(OnceCell::new_with(Some(client)));
+        self
+    }
+
+    pub async fn factory(
+        &self,
+    ) -> Result<PmxFactoryClient<AuthChannel>, Box<dyn std::error::Error>> {
+        let service = &self.factory_service;
+        self.factory
+            .get_or_try_init(|| async {
+                let service = service.as_ref().ok_or("no pmx factory configured")?;
+                connection::factory_client(service.url.clone(), &service.config).await
+            })
+            .await
+            .cloned()
+    }
+
+    pub async fn mod_host(
+        &self,
+    ) -> Result<ModHostProxyClient<AuthChannel>, Box<dyn std::error::Error>> {
+        let service = &self.mod_host_service;
+        self.mod_host
+            .get_or_try_init(|| async {
+                let service = service.as_ref().ok_or("no mod-host proxy configured")?;
+                connection::mod_host_client(service.url.clone(), &service.config).await
+            })
+            .await
+            .cloned()
+    }
+}
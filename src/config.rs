@@ -0,0 +1,155 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::topology::Topology;
+
+/// Environment variables overriding the service URLs, over the ones
+/// `fr_pmx_config_lib` reads.
+const REGISTRY_URL: &str = "PMX_BUILDER_REGISTRY_URL";
+const FACTORY_URL: &str = "PMX_BUILDER_FACTORY_URL";
+const MOD_HOST_PROXY_URL: &str = "PMX_BUILDER_MOD_HOST_PROXY_URL";
+const PIPEWIRE_REGISTRY_URL: &str = "PMX_BUILDER_PIPEWIRE_REGISTRY_URL";
+
+/// URLs of the services the builder talks to.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceUrls {
+    pub pmx_registry_url: String,
+    pub pmx_factory_url: String,
+    pub pmx_mod_host_proxy_url: String,
+    pub pipewire_registry_url: String,
+}
+
+/// Service URLs of the PMX config with the `PMX_BUILDER_*_URL` environment
+/// variables applied.
+pub fn service_urls() -> ServiceUrls {
+    let configured = fr_pmx_config_lib::read_service_urls();
+    let url = |variable: &str, configured: String| std::env::var(variable).unwrap_or(configured);
+    ServiceUrls {
+        pmx_registry_url: url(REGISTRY_URL, configured.pmx_registry_url),
+        pmx_factory_url: url(FACTORY_URL, configured.pmx_factory_url),
+        pmx_mod_host_proxy_url: url(MOD_HOST_PROXY_URL, configured.pmx_mod_host_proxy_url),
+        pipewire_registry_url: url(PIPEWIRE_REGISTRY_URL, configured.pipewire_registry_url),
+    }
+}
+
+/// The topology file with the overlay merged over it. Tables are merged key
+/// by key, any other value in the overlay replaces the topology's, lists
+/// included. Empty if neither file is given.
+pub fn read_layers(
+    path: Option<&Path>,
+    overlay: Option<&Path>,
+) -> Result<toml::Table, Box<dyn std::error::Error>> {
+    let mut merged = toml::Table::new();
+    for path in path.into_iter().chain(overlay) {
+        let layer: toml::Table = toml::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        merge(&mut merged, layer);
+    }
+    Ok(merged)
+}
+
+fn merge(base: &mut toml::Table, layer: toml::Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(layer)) => merge(base, layer),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Environment variables overriding the service timeouts in seconds, the
+/// reconnect policy and the concurrency of the topology.
+const TIMEOUT_REGISTRY: &str = "PMX_BUILDER_TIMEOUT_REGISTRY";
+const TIMEOUT_FACTORY: &str = "PMX_BUILDER_TIMEOUT_FACTORY";
+const TIMEOUT_PIPEWIRE: &str = "PMX_BUILDER_TIMEOUT_PIPEWIRE";
+const TIMEOUT_MOD_HOST: &str = "PMX_BUILDER_TIMEOUT_MOD_HOST";
+const TIMEOUT_NODE_READY: &str = "PMX_BUILDER_TIMEOUT_NODE_READY";
+const RECONNECT_ATTEMPTS: &str = "PMX_BUILDER_RECONNECT_ATTEMPTS";
+const RECONNECT_BACKOFF: &str = "PMX_BUILDER_RECONNECT_BACKOFF";
+const RECONNECT_MAX_BACKOFF: &str = "PMX_BUILDER_RECONNECT_MAX_BACKOFF";
+const CONCURRENCY: &str = "PMX_BUILDER_CONCURRENCY";
+
+/// Value of the environment variable `variable` if it is set.
+fn env_override<T: FromStr>(variable: &str) -> Result<Option<T>, Box<dyn std::error::Error>>
+where
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(variable) {
+        Ok(value) => Ok(Some(
+            value
+                .trim()
+                .parse()
+                .map_err(|e| format!("{variable}={value}: {e}"))?,
+        )),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Applies the `PMX_BUILDER_TIMEOUT_*`, `PMX_BUILDER_RECONNECT_*` and
+/// `PMX_BUILDER_CONCURRENCY` environment variables over the topology.
+pub fn apply_environment(topology: &mut Topology) -> Result<(), Box<dyn std::error::Error>> {
+    let timeouts = &mut topology.timeouts;
+    for (variable, timeout) in [
+        (TIMEOUT_REGISTRY, &mut timeouts.registry),
+        (TIMEOUT_FACTORY, &mut timeouts.factory),
+        (TIMEOUT_PIPEWIRE, &mut timeouts.pipewire),
+        (TIMEOUT_MOD_HOST, &mut timeouts.mod_host),
+        (TIMEOUT_NODE_READY, &mut timeouts.node_ready),
+    ] {
+        if let Some(seconds) = env_override(variable)? {
+            *timeout = seconds;
+        }
+    }
+    let reconnect = &mut topology.reconnect;
+    if let Some(attempts) = env_override(RECONNECT_ATTEMPTS)? {
+        reconnect.attempts = attempts;
+    }
+    if let Some(backoff) = env_override(RECONNECT_BACKOFF)? {
+        reconnect.backoff = backoff;
+    }
+    if let Some(max_backoff) = env_override(RECONNECT_MAX_BACKOFF)? {
+        reconnect.max_backoff = max_backoff;
+    }
+    if let Some(concurrency) = env_override(CONCURRENCY)? {
+        topology.concurrency = concurrency;
+    }
+    Ok(())
+}
+
+/// Prints the topology a build would use, with the overlay, the venue, the
+/// environment overrides and the profile applied, and the service URLs.
+pub fn dump(
+    topology: &Topology,
+    path: Option<&Path>,
+    overlay: Option<&Path>,
+    venue: Option<&Path>,
+    profile: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sources: Vec<String> = path
+        .into_iter()
+        .chain(overlay)
+        .chain(venue)
+        .map(|p| p.display().to_string())
+        .collect();
+    if sources.is_empty() {
+        println!("# Built in default topology");
+    } else {
+        println!("# Layered from {}", sources.join(", "));
+    }
+    if let Some(profile) = profile {
+        println!("# Profile {profile} applied");
+    }
+    println!("{}", toml::to_string_pretty(topology)?);
+
+    let mut services = toml::Table::new();
+    services.insert(
+        String::from("services"),
+        toml::Value::try_from(service_urls())?,
+    );
+    print!("{}", toml::to_string_pretty(&services)?);
+    Ok(())
+}
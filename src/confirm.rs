@@ -0,0 +1,50 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+/// Set once `all` was answered, nothing is asked after that.
+static APPROVED_ALL: AtomicBool = AtomicBool::new(false);
+
+/// Makes teardown, gc and stale link removal ask before deleting anything.
+pub fn enable() {
+    INTERACTIVE.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    INTERACTIVE.load(Ordering::Relaxed)
+}
+
+/// Asks whether to delete `what`. `y` deletes it, `a` deletes it and
+/// everything after it without asking again, anything else keeps it.
+/// Everything is deleted without asking unless interactive.
+pub fn approve(what: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    if !is_enabled() || APPROVED_ALL.load(Ordering::Relaxed) {
+        return Ok(true);
+    }
+    eprint!("Delete {what}? [y/N/all] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(true),
+        "a" | "all" => {
+            APPROVED_ALL.store(true, Ordering::Relaxed);
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// The `items` approved for deletion, each asked for as `describe` puts it.
+pub fn approved<T>(
+    items: Vec<T>,
+    describe: impl Fn(&T) -> String,
+) -> Result<Vec<T>, Box<dyn std::error::Error>> {
+    let mut approved = Vec::new();
+    for item in items {
+        if approve(&describe(&item))? {
+            approved.push(item);
+        }
+    }
+    Ok(approved)
+}
@@ -0,0 +1,166 @@
+use std::path::PathBuf;
+
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use tokio::net::UnixStream;
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    service::{interceptor::InterceptedService, Interceptor},
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, Uri},
+    Request, Status,
+};
+use tower::service_fn;
+
+use crate::cassette;
+use crate::pmx::{
+    factory::pmx_factory_client::PmxFactoryClient,
+    mod_host::mod_host_proxy_client::ModHostProxyClient, pipewire::pipewire_client::PipewireClient,
+    pmx_registry_client::PmxRegistryClient,
+};
+
+/// How to reach each service when it doesn't run on this host.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Connections {
+    pub registry: ServiceConnection,
+    pub factory: ServiceConnection,
+    pub pipewire: ServiceConnection,
+    pub mod_host: ServiceConnection,
+}
+
+/// TLS and credentials of one service connection. Without a CA certificate
+/// the connection is plaintext.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServiceConnection {
+    /// CA certificate the service certificate is checked against
+    pub ca_certificate: Option<PathBuf>,
+    /// Certificate the builder authenticates with, needs `client_key`
+    pub client_certificate: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// Name expected in the service certificate, the URL host if not set
+    pub domain: Option<String>,
+    /// File holding a token sent as bearer token with every call
+    pub token_file: Option<PathBuf>,
+}
+
+/// Adds the configured bearer token to every request.
+#[derive(Debug, Clone, Default)]
+pub struct AuthInterceptor {
+    authorization: Option<MetadataValue<Ascii>>,
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(authorization) = &self.authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.clone());
+        }
+        Ok(request)
+    }
+}
+
+pub type AuthChannel = InterceptedService<Channel, AuthInterceptor>;
+
+fn tls_config(config: &ServiceConnection) -> Result<ClientTlsConfig, Box<dyn std::error::Error>> {
+    let mut tls = ClientTlsConfig::new();
+    if let Some(ca_certificate) = &config.ca_certificate {
+        tls = tls.ca_certificate(Certificate::from_pem(std::fs::read(ca_certificate)?));
+    }
+    match (&config.client_certificate, &config.client_key) {
+        (Some(certificate), Some(key)) => {
+            tls = tls.identity(Identity::from_pem(
+                std::fs::read(certificate)?,
+                std::fs::read(key)?,
+            ));
+        }
+        (None, None) => {}
+        _ => return Err("client_certificate and client_key must be set together".into()),
+    }
+    if let Some(domain) = &config.domain {
+        tls = tls.domain_name(domain);
+    }
+    Ok(tls)
+}
+
+fn interceptor(config: &ServiceConnection) -> Result<AuthInterceptor, Box<dyn std::error::Error>> {
+    let Some(token_file) = &config.token_file else {
+        return Ok(AuthInterceptor::default());
+    };
+    let token = std::fs::read_to_string(token_file)?;
+    Ok(AuthInterceptor {
+        authorization: Some(format!("Bearer {}", token.trim()).parse()?),
+    })
+}
+
+/// Connects to the socket at `path`. The endpoint URL is required by tonic but
+/// never used.
+async fn connect_unix(path: String) -> Result<Channel, Box<dyn std::error::Error>> {
+    let channel = Endpoint::from_static("http://[::]:50051")
+        .connect_with_connector(service_fn(move |_: Uri| {
+            let path = path.clone();
+            async move { Ok::<_, std::io::Error>(TokioIo::new(UnixStream::connect(path).await?)) }
+        }))
+        .await?;
+    Ok(channel)
+}
+
+/// Connects over TCP, or over a unix domain socket for `unix://` URLs. TLS
+/// isn't used for sockets, they never leave the host.
+async fn connect(
+    url: String,
+    config: &ServiceConnection,
+) -> Result<(Channel, AuthInterceptor), Box<dyn std::error::Error>> {
+    if cassette::is_replaying() {
+        // Replayed calls never reach the channel, nothing to connect to
+        return Ok((
+            Endpoint::from_static("http://[::]:50051").connect_lazy(),
+            AuthInterceptor::default(),
+        ));
+    }
+    if let Some(path) = url.strip_prefix("unix://") {
+        return Ok((
+            connect_unix(String::from(path)).await?,
+            interceptor(config)?,
+        ));
+    }
+
+    let mut endpoint = Endpoint::from_shared(url)?;
+    if config.ca_certificate.is_some() || config.client_certificate.is_some() {
+        endpoint = endpoint.tls_config(tls_config(config)?)?;
+    }
+    Ok((endpoint.connect().await?, interceptor(config)?))
+}
+
+pub async fn registry_client(
+    url: String,
+    config: &ServiceConnection,
+) -> Result<PmxRegistryClient<AuthChannel>, Box<dyn std::error::Error>> {
+    let (channel, interceptor) = connect(url, config).await?;
+    Ok(PmxRegistryClient::with_interceptor(channel, interceptor))
+}
+
+pub async fn factory_client(
+    url: String,
+    config: &ServiceConnection,
+) -> Result<PmxFactoryClient<AuthChannel>, Box<dyn std::error::Error>> {
+    let (channel, interceptor) = connect(url, config).await?;
+    Ok(PmxFactoryClient::with_interceptor(channel, interceptor))
+}
+
+pub async fn pipewire_client(
+    url: String,
+    config: &ServiceConnection,
+) -> Result<PipewireClient<AuthChannel>, Box<dyn std::error::Error>> {
+    let (channel, interceptor) = connect(url, config).await?;
+    Ok(PipewireClient::with_interceptor(channel, interceptor))
+}
+
+pub async fn mod_host_client(
+    url: String,
+    config: &ServiceConnection,
+) -> Result<ModHostProxyClient<AuthChannel>, Box<dyn std::error::Error>> {
+    let (channel, interceptor) = connect(url, config).await?;
+    Ok(ModHostProxyClient::with_interceptor(channel, interceptor))
+}
@@ -0,0 +1,730 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::connection::{self, AuthChannel, ServiceConnection};
+use crate::ownership;
+use crate::plan::{self, Link};
+use crate::pmx::pipewire::{
+    device::ListDevice, link::ListLink, node::ListNode, pipewire_client::PipewireClient,
+    port::ListPort, CreateLinkByNameRequest, CreateLinkRequest, DeleteLinkRequest,
+    ListDevicesRequest, ListLinksRequest, ListNodesRequest, ListPortsRequest, MeasureLevelsRequest,
+    PortLevel, SetDeviceProfileRequest,
+};
+use crate::rpc::{self, Service};
+
+/// Audio graph the builder reads nodes, ports and links from and connects
+/// the mixer in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GraphBackend {
+    /// The pipewire registry service
+    #[default]
+    Pipewire,
+    /// A JACK server, clients show up as nodes. Needs the `jack` feature.
+    Jack,
+    /// The local pipewire graph through the `pw-dump`, `pw-link` and
+    /// `pw-cli` commands, for hosts without the registry service
+    PwCli,
+}
+
+/// Lists and links the nodes of an audio graph.
+#[tonic::async_trait]
+pub trait GraphConnector: Send + Sync {
+    async fn nodes(&self) -> Result<Vec<ListNode>, Box<dyn std::error::Error>>;
+
+    async fn ports(&self) -> Result<Vec<ListPort>, Box<dyn std::error::Error>>;
+
+    async fn links(&self) -> Result<Vec<ListLink>, Box<dyn std::error::Error>>;
+
+    /// Creates `link`, `nodes` being the nodes the graph currently has.
+    async fn connect(
+        &self,
+        link: &Link,
+        nodes: &[ListNode],
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Removes the live link `id`, which connects `link`.
+    async fn disconnect(&self, id: u32, link: &Link) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Peak and RMS level of each port in `port_paths` over `duration`.
+    async fn measure_levels(
+        &self,
+        _port_paths: &[String],
+        _duration: Duration,
+    ) -> Result<Vec<PortLevel>, Box<dyn std::error::Error>> {
+        Err("this graph backend can't measure levels".into())
+    }
+
+    /// Audio devices with their active profile.
+    async fn devices(&self) -> Result<Vec<ListDevice>, Box<dyn std::error::Error>> {
+        Err("this graph backend has no devices".into())
+    }
+
+    /// Switches the device `device_id` to `profile`.
+    async fn set_device_profile(
+        &self,
+        _device_id: u32,
+        _profile: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Err("this graph backend can't set device profiles".into())
+    }
+}
+
+pub type Connector = Arc<dyn GraphConnector>;
+
+/// Connects to the graph of `backend`. `url` and `config` are the pipewire
+/// registry's, JACK is reached through its own server. When the registry
+/// service can't be reached the pipewire commands are used instead, if the
+/// host has them, and the reason for the fallback is returned with the
+/// connector.
+pub async fn connect(
+    backend: GraphBackend,
+    url: String,
+    config: &ServiceConnection,
+) -> Result<(Connector, Option<String>), Box<dyn std::error::Error>> {
+    match backend {
+        GraphBackend::Pipewire => match connection::pipewire_client(url, config).await {
+            Ok(client) => Ok((Arc::new(PipewireConnector::new(client)), None)),
+            Err(error) if pw_cli_connector::is_available().await => Ok((
+                Arc::new(pw_cli_connector::PwCliConnector::default()),
+                Some(format!(
+                    "Pipewire registry service unreachable ({error}), using pw-link and pw-cli"
+                )),
+            )),
+            Err(error) => Err(error),
+        },
+        GraphBackend::PwCli => Ok((Arc::new(pw_cli_connector::PwCliConnector::default()), None)),
+        #[cfg(feature = "jack")]
+        GraphBackend::Jack => Ok((Arc::new(jack_connector::JackConnector::new()?), None)),
+        #[cfg(not(feature = "jack"))]
+        GraphBackend::Jack => Err("the builder was built without JACK support".into()),
+    }
+}
+
+/// Graph of the pipewire registry service.
+pub struct PipewireConnector {
+    client: PipewireClient<AuthChannel>,
+}
+
+impl PipewireConnector {
+    pub fn new(client: PipewireClient<AuthChannel>) -> PipewireConnector {
+        PipewireConnector { client }
+    }
+}
+
+#[tonic::async_trait]
+impl GraphConnector for PipewireConnector {
+    async fn nodes(&self) -> Result<Vec<ListNode>, Box<dyn std::error::Error>> {
+        let response = rpc::call(
+            Service::Pipewire,
+            "ListNodes",
+            &self.client,
+            &ListNodesRequest {},
+            |mut client, request| async move { client.list_nodes(request).await },
+        )
+        .await?;
+        Ok(response.nodes)
+    }
+
+    async fn ports(&self) -> Result<Vec<ListPort>, Box<dyn std::error::Error>> {
+        let request = ListPortsRequest {
+            node_id_filter: None,
+        };
+        let response = rpc::call(
+            Service::Pipewire,
+            "ListPorts",
+            &self.client,
+            &request,
+            |mut client, request| async move { client.list_ports(request).await },
+        )
+        .await?;
+        Ok(response.ports)
+    }
+
+    async fn links(&self) -> Result<Vec<ListLink>, Box<dyn std::error::Error>> {
+        let response = rpc::call(
+            Service::Pipewire,
+            "ListLinks",
+            &self.client,
+            &ListLinksRequest {},
+            |mut client, request| async move { client.list_links(request).await },
+        )
+        .await?;
+        Ok(response.links)
+    }
+
+    /// Links by node object ids when both node names are unique among
+    /// `nodes`, by name otherwise.
+    async fn connect(
+        &self,
+        link: &Link,
+        nodes: &[ListNode],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let output_node = plan::unique_node(&link.output_node_name, nodes);
+        let input_node = plan::unique_node(&link.input_node_name, nodes);
+        match (output_node, input_node) {
+            (Some(output_node), Some(input_node)) => {
+                let request = CreateLinkRequest {
+                    output_node_id: output_node.object_serial,
+                    output_port_id: link.output_port_id,
+                    input_node_id: input_node.object_serial,
+                    input_port_id: link.input_port_id,
+                    properties: ownership::link_properties(),
+                };
+                rpc::call(
+                    Service::Pipewire,
+                    "CreateLink",
+                    &self.client,
+                    &request,
+                    |mut client, request| async move { client.create_link(request).await },
+                )
+                .await?;
+            }
+            _ => {
+                let request = CreateLinkByNameRequest {
+                    output_port_id: link.output_port_id,
+                    input_port_id: link.input_port_id,
+                    output_node_name: link.output_node_name.clone(),
+                    input_node_name: link.input_node_name.clone(),
+                    properties: ownership::link_properties(),
+                };
+                rpc::call(
+                    Service::Pipewire,
+                    "CreateLinkByName",
+                    &self.client,
+                    &request,
+                    |mut client, request| async move { client.create_link_by_name(request).await },
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self, id: u32, _link: &Link) -> Result<(), Box<dyn std::error::Error>> {
+        rpc::call(
+            Service::Pipewire,
+            "DeleteLink",
+            &self.client,
+            &DeleteLinkRequest { id },
+            |mut client, request| async move { client.delete_link(request).await },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn measure_levels(
+        &self,
+        port_paths: &[String],
+        duration: Duration,
+    ) -> Result<Vec<PortLevel>, Box<dyn std::error::Error>> {
+        let request = MeasureLevelsRequest {
+            port_paths: port_paths.to_vec(),
+            duration_ms: duration.as_millis() as u32,
+        };
+        let response = rpc::call(
+            Service::Pipewire,
+            "MeasureLevels",
+            &self.client,
+            &request,
+            |mut client, request| async move { client.measure_levels(request).await },
+        )
+        .await?;
+        Ok(response.levels)
+    }
+
+    async fn devices(&self) -> Result<Vec<ListDevice>, Box<dyn std::error::Error>> {
+        let response = rpc::call(
+            Service::Pipewire,
+            "ListDevices",
+            &self.client,
+            &ListDevicesRequest {},
+            |mut client, request| async move { client.list_devices(request).await },
+        )
+        .await?;
+        Ok(response.devices)
+    }
+
+    async fn set_device_profile(
+        &self,
+        device_id: u32,
+        profile: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let request = SetDeviceProfileRequest {
+            device_id,
+            profile: String::from(profile),
+        };
+        rpc::call(
+            Service::Pipewire,
+            "SetDeviceProfile",
+            &self.client,
+            &request,
+            |mut client, request| async move { client.set_device_profile(request).await },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+pub mod pw_cli_connector {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::Value;
+    use tokio::process::Command;
+
+    use super::GraphConnector;
+    use crate::ownership;
+    use crate::plan::Link;
+    use crate::pmx::pipewire::{link::ListLink, node::ListNode, port::ListPort};
+
+    /// Local pipewire graph read with `pw-dump`, linked with `pw-link` and
+    /// unlinked with `pw-cli`. Listing the graph dumps it, links are created
+    /// against the last dump, so it is slower than the registry service but
+    /// needs nothing running besides pipewire.
+    #[derive(Default)]
+    pub struct PwCliConnector {
+        /// Graph the builder listed last, the nodes and ports it links
+        last_dump: Mutex<Option<Arc<Vec<Value>>>>,
+    }
+
+    /// Whether the pipewire commands can be run on this host.
+    pub async fn is_available() -> bool {
+        Command::new("pw-dump")
+            .arg("--version")
+            .output()
+            .await
+            .is_ok_and(|output| output.status.success())
+    }
+
+    async fn run(
+        program: &str,
+        arguments: &[String],
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let output = Command::new(program)
+            .args(arguments)
+            .output()
+            .await
+            .map_err(|e| format!("Couldn't run {program}: {e}"))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{program} {} failed with {}: {}",
+                arguments.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(output.stdout)
+    }
+
+    impl PwCliConnector {
+        /// Every object of the graph as `pw-dump` prints it, kept for the
+        /// links created next.
+        async fn dump(&self) -> Result<Arc<Vec<Value>>, Box<dyn std::error::Error>> {
+            let dump: Arc<Vec<Value>> =
+                Arc::new(serde_json::from_slice(&run("pw-dump", &[]).await?)?);
+            *self.last_dump.lock().unwrap() = Some(dump.clone());
+            Ok(dump)
+        }
+
+        fn last_dump(&self) -> Option<Arc<Vec<Value>>> {
+            self.last_dump.lock().unwrap().clone()
+        }
+    }
+
+    fn objects<'a>(dump: &'a [Value], kind: &'a str) -> impl Iterator<Item = &'a Value> {
+        dump.iter().filter(move |object| {
+            object["type"]
+                .as_str()
+                .is_some_and(|t| t == format!("PipeWire:Interface:{kind}"))
+        })
+    }
+
+    fn as_u32(value: &Value) -> Option<u32> {
+        value
+            .as_u64()
+            .or_else(|| value.as_str().and_then(|v| v.parse().ok()))
+            .and_then(|v| u32::try_from(v).ok())
+    }
+
+    fn global_id(object: &Value) -> Option<u32> {
+        as_u32(&object["id"])
+    }
+
+    fn property<'a>(object: &'a Value, key: &str) -> &'a Value {
+        &object["info"]["props"][key]
+    }
+
+    fn text(object: &Value, key: &str) -> String {
+        String::from(property(object, key).as_str().unwrap_or_default())
+    }
+
+    /// Object serial of each node by its global id. Ports and links name
+    /// their node by global id, the builder by serial. Pipewire before
+    /// 0.3.57 has no serials, its nodes keep their global id.
+    fn node_serials(dump: &[Value]) -> BTreeMap<u32, u32> {
+        list_nodes(dump)
+            .into_iter()
+            .map(|node| (node.id, node.object_serial))
+            .collect()
+    }
+
+    /// Nodes of `dump`, objects without an id are left out.
+    pub fn list_nodes(dump: &[Value]) -> Vec<ListNode> {
+        objects(dump, "Node")
+            .filter_map(|node| {
+                let id = global_id(node)?;
+                Some(ListNode {
+                    id,
+                    object_serial: as_u32(property(node, "object.serial")).unwrap_or(id),
+                    name: text(node, "node.name"),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    /// Ports with their global id, numbered within their node like the
+    /// registry service numbers them. Ports without a number or whose node
+    /// isn't in `dump` are left out rather than put on node 0.
+    pub fn list_ports(dump: &[Value]) -> Vec<(u32, ListPort)> {
+        let serials = node_serials(dump);
+        objects(dump, "Port")
+            .filter_map(|port| {
+                let direction = match port["info"]["direction"].as_str() {
+                    Some("input") => "in",
+                    _ => "out",
+                };
+                let list_port = ListPort {
+                    id: as_u32(property(port, "port.id"))?,
+                    node_id: *serials.get(&as_u32(property(port, "node.id"))?)?,
+                    name: text(port, "port.name"),
+                    path: text(port, "object.path"),
+                    alias: text(port, "port.alias"),
+                    direction: String::from(direction),
+                    audio_channel: text(port, "audio.channel"),
+                    ..Default::default()
+                };
+                Some((global_id(port)?, list_port))
+            })
+            .collect()
+    }
+
+    /// Links with their nodes by serial and their ports by number within
+    /// their node. Links between ports `dump` doesn't have are left out.
+    pub fn list_links(dump: &[Value]) -> Vec<ListLink> {
+        let serials = node_serials(dump);
+        let port_ids: BTreeMap<u32, u32> = list_ports(dump)
+            .into_iter()
+            .map(|(global_id, port)| (global_id, port.id))
+            .collect();
+        let info_id = |link: &Value, key: &str| as_u32(&link["info"][key]);
+        objects(dump, "Link")
+            .filter_map(|link| {
+                let properties: HashMap<String, String> = link["info"]["props"]
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(key, value)| {
+                        value.as_str().map(|v| (key.clone(), String::from(v)))
+                    })
+                    .collect();
+                Some(ListLink {
+                    id: global_id(link)?,
+                    output_node_id: *serials.get(&info_id(link, "output-node-id")?)?,
+                    output_port_id: *port_ids.get(&info_id(link, "output-port-id")?)?,
+                    input_node_id: *serials.get(&info_id(link, "input-node-id")?)?,
+                    input_port_id: *port_ids.get(&info_id(link, "input-port-id")?)?,
+                    properties,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    /// Global id of port `port_id` of the node `node_name` in `direction`.
+    pub fn port_global_id(
+        dump: &[Value],
+        node_name: &str,
+        port_id: u32,
+        direction: &str,
+    ) -> Result<u32, Box<dyn std::error::Error>> {
+        let node_serial = list_nodes(dump)
+            .into_iter()
+            .filter(|n| n.name == node_name)
+            .min_by_key(|n| n.id)
+            .map(|n| n.object_serial)
+            .ok_or_else(|| format!("Node {node_name} isn't in the graph"))?;
+        list_ports(dump)
+            .into_iter()
+            .find(|(_, p)| p.node_id == node_serial && p.id == port_id && p.direction == direction)
+            .map(|(id, _)| id)
+            .ok_or_else(|| format!("{node_name} has no port {port_id}").into())
+    }
+
+    /// Global ids of the output and input port of `link`.
+    fn link_ports(dump: &[Value], link: &Link) -> Result<(u32, u32), Box<dyn std::error::Error>> {
+        Ok((
+            port_global_id(dump, &link.output_node_name, link.output_port_id, "out")?,
+            port_global_id(dump, &link.input_node_name, link.input_port_id, "in")?,
+        ))
+    }
+
+    #[tonic::async_trait]
+    impl GraphConnector for PwCliConnector {
+        async fn nodes(&self) -> Result<Vec<ListNode>, Box<dyn std::error::Error>> {
+            Ok(list_nodes(&self.dump().await?))
+        }
+
+        async fn ports(&self) -> Result<Vec<ListPort>, Box<dyn std::error::Error>> {
+            Ok(list_ports(&self.dump().await?)
+                .into_iter()
+                .map(|(_, port)| port)
+                .collect())
+        }
+
+        async fn links(&self) -> Result<Vec<ListLink>, Box<dyn std::error::Error>> {
+            Ok(list_links(&self.dump().await?))
+        }
+
+        /// Links the ports by global id, `pw-link` keeps the link after it
+        /// exits. The ports are looked up in the graph the builder listed
+        /// last, the graph is only dumped again when they aren't in it.
+        async fn connect(
+            &self,
+            link: &Link,
+            _nodes: &[ListNode],
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            let cached = self
+                .last_dump()
+                .and_then(|dump| link_ports(&dump, link).ok());
+            let (output, input) = match cached {
+                Some(ports) => ports,
+                None => link_ports(&self.dump().await?, link)?,
+            };
+            let properties = serde_json::to_string(&ownership::link_properties())?;
+            run(
+                "pw-link",
+                &[
+                    format!("--props={properties}"),
+                    output.to_string(),
+                    input.to_string(),
+                ],
+            )
+            .await?;
+            Ok(())
+        }
+
+        async fn disconnect(
+            &self,
+            id: u32,
+            _link: &Link,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            run("pw-cli", &[String::from("destroy"), id.to_string()]).await?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "jack")]
+mod jack_connector {
+    use std::sync::Mutex;
+
+    use jack::{Client, ClientOptions, PortFlags};
+
+    use super::GraphConnector;
+    use crate::plan::Link;
+    use crate::pmx::pipewire::{link::ListLink, node::ListNode, port::ListPort};
+
+    /// Graph of a JACK server. JACK has no object ids, so clients and ports
+    /// are numbered in the order the server lists them: a client's number is
+    /// its position among the clients, a port's its position among the ports
+    /// of its client. Links can't carry properties, the builder only knows
+    /// its links from the state file.
+    pub struct JackConnector {
+        client: Mutex<Client>,
+    }
+
+    /// Full name of a port, `client:port`, with its client and number.
+    struct JackPort {
+        full_name: String,
+        client_name: String,
+        id: u32,
+    }
+
+    impl JackConnector {
+        pub fn new() -> Result<JackConnector, Box<dyn std::error::Error>> {
+            let (client, _status) = Client::new("fr-pmx-builder", ClientOptions::NO_START_SERVER)?;
+            Ok(JackConnector {
+                client: Mutex::new(client),
+            })
+        }
+
+        fn client_names(ports: &[JackPort]) -> Vec<String> {
+            let mut names: Vec<String> = Vec::new();
+            for port in ports {
+                if !names.contains(&port.client_name) {
+                    names.push(port.client_name.clone());
+                }
+            }
+            names
+        }
+
+        fn list_ports(client: &Client) -> Vec<JackPort> {
+            let mut ports: Vec<JackPort> = Vec::new();
+            for full_name in client.ports(None, None, PortFlags::empty()) {
+                let Some((client_name, _)) = full_name.split_once(':') else {
+                    continue;
+                };
+                let id = ports
+                    .iter()
+                    .filter(|p| p.client_name == client_name)
+                    .count() as u32;
+                ports.push(JackPort {
+                    client_name: String::from(client_name),
+                    full_name,
+                    id,
+                });
+            }
+            ports
+        }
+
+        /// Full names of the output and input port of `link`.
+        fn port_names(&self, link: &Link) -> Result<(String, String), Box<dyn std::error::Error>> {
+            let ports = JackConnector::list_ports(&self.client.lock().unwrap());
+            let find = |client_name: &str, id: u32| {
+                ports
+                    .iter()
+                    .find(|p| p.client_name == client_name && p.id == id)
+                    .map(|p| p.full_name.clone())
+                    .ok_or_else(|| format!("JACK has no port {id} on {client_name}"))
+            };
+            Ok((
+                find(&link.output_node_name, link.output_port_id)?,
+                find(&link.input_node_name, link.input_port_id)?,
+            ))
+        }
+    }
+
+    fn node_serial(client_names: &[String], client_name: &str) -> u32 {
+        client_names
+            .iter()
+            .position(|name| name == client_name)
+            .map_or(0, |index| index as u32 + 1)
+    }
+
+    #[tonic::async_trait]
+    impl GraphConnector for JackConnector {
+        async fn nodes(&self) -> Result<Vec<ListNode>, Box<dyn std::error::Error>> {
+            let ports = JackConnector::list_ports(&self.client.lock().unwrap());
+            Ok(JackConnector::client_names(&ports)
+                .into_iter()
+                .enumerate()
+                .map(|(index, name)| ListNode {
+                    name,
+                    id: index as u32 + 1,
+                    object_serial: index as u32 + 1,
+                    ..Default::default()
+                })
+                .collect())
+        }
+
+        async fn ports(&self) -> Result<Vec<ListPort>, Box<dyn std::error::Error>> {
+            let client = self.client.lock().unwrap();
+            let ports = JackConnector::list_ports(&client);
+            let client_names = JackConnector::client_names(&ports);
+            Ok(ports
+                .iter()
+                .filter_map(|port| {
+                    let jack_port = client.port_by_name(&port.full_name)?;
+                    let short_name = jack_port.short_name().unwrap_or_default();
+                    let is_midi = jack_port.port_type().unwrap_or_default().contains("midi");
+                    Some(ListPort {
+                        id: port.id,
+                        node_id: node_serial(&client_names, &port.client_name),
+                        direction: String::from(
+                            if jack_port.flags().contains(PortFlags::IS_INPUT) {
+                                "in"
+                            } else {
+                                "out"
+                            },
+                        ),
+                        audio_channel: if is_midi {
+                            String::new()
+                        } else {
+                            short_name.clone()
+                        },
+                        path: port.full_name.clone(),
+                        alias: jack_port
+                            .aliases()
+                            .unwrap_or_default()
+                            .into_iter()
+                            .next()
+                            .unwrap_or_default(),
+                        name: short_name,
+                        ..Default::default()
+                    })
+                })
+                .collect())
+        }
+
+        async fn links(&self) -> Result<Vec<ListLink>, Box<dyn std::error::Error>> {
+            let client = self.client.lock().unwrap();
+            let ports = JackConnector::list_ports(&client);
+            let client_names = JackConnector::client_names(&ports);
+            let mut links = Vec::new();
+            for output in &ports {
+                let Some(jack_port) = client.port_by_name(&output.full_name) else {
+                    continue;
+                };
+                if !jack_port.flags().contains(PortFlags::IS_OUTPUT) {
+                    continue;
+                }
+                for connection in jack_port.get_connections() {
+                    let Some(input) = ports.iter().find(|p| p.full_name == connection) else {
+                        continue;
+                    };
+                    links.push(ListLink {
+                        id: links.len() as u32 + 1,
+                        output_node_id: node_serial(&client_names, &output.client_name),
+                        output_port_id: output.id,
+                        input_node_id: node_serial(&client_names, &input.client_name),
+                        input_port_id: input.id,
+                        ..Default::default()
+                    });
+                }
+            }
+            Ok(links)
+        }
+
+        async fn connect(
+            &self,
+            link: &Link,
+            _nodes: &[ListNode],
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            let (output, input) = self.port_names(link)?;
+            self.client
+                .lock()
+                .unwrap()
+                .connect_ports_by_name(&output, &input)?;
+            Ok(())
+        }
+
+        /// JACK links have no id, the ports of `link` are disconnected.
+        async fn disconnect(
+            &self,
+            _id: u32,
+            link: &Link,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            let (output, input) = self.port_names(link)?;
+            self.client
+                .lock()
+                .unwrap()
+                .disconnect_ports_by_name(&output, &input)?;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeSet;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+use fr_logging::LoggerFactory;
+
+use crate::audit::AuditLog;
+use crate::builder;
+use crate::clients::Clients;
+use crate::confirm;
+use crate::history::{self, History};
+use crate::live;
+use crate::naming::naming;
+use crate::ownership;
+use crate::plan::{self, ConnectionPlan, Link};
+use crate::reload::TopologyWatch;
+use crate::report::Reporter;
+use crate::state::Checkpoints;
+use crate::status::StatusBoard;
+use crate::topology::Topology;
+
+/// Parses durations like `30s`, `5m` or `1h`. Plain numbers are seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("{value} isn't a duration like 30s, 5m or 1h"))?;
+    match unit {
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        "h" => Ok(Duration::from_secs(number * 60 * 60)),
+        _ => Err(format!("{value} has unknown unit {unit}, use s, m or h")),
+    }
+}
+
+/// `interval` plus a random part of `jitter`, so builders started together
+/// don't poll the services at the same moment.
+fn next_delay(interval: Duration, jitter: Duration) -> Duration {
+    if jitter.is_zero() {
+        return interval;
+    }
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    interval + Duration::from_millis(random % jitter.as_millis().max(1) as u64)
+}
+
+/// Drift one reconcile round found and repaired.
+#[derive(Debug, Clone, Default)]
+pub struct Reconciled {
+    /// Live links touching nodes the builder manages
+    pub managed_links: usize,
+    pub missing_links: usize,
+    pub stale_links: usize,
+    /// Managed links as the round found them
+    pub found_links: BTreeSet<Link>,
+    pub created_links: Vec<Link>,
+    pub removed_links: Vec<Link>,
+}
+
+impl Reconciled {
+    pub fn repaired(&self) -> usize {
+        self.missing_links + self.stale_links
+    }
+}
+
+/// Creates the desired links missing from pipewire and removes the links an
+/// earlier run created that the topology no longer wants.
+pub async fn reconcile(
+    topology: &Topology,
+    checkpoints: &mut Checkpoints,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<Reconciled, Box<dyn std::error::Error>> {
+    let live_state = live::read_live_state(clients, reporter)
+        .await?
+        .with_checkpoints(checkpoints);
+    let desired_links = live_state.desired_links(topology, reporter);
+    let managed_links = live_state.managed_live_links(&topology.looper);
+    // Patches may join nodes the builder doesn't manage, so every live link
+    // counts when looking for missing ones
+    let existing_links: BTreeSet<Link> =
+        plan::resolve_live_links(&live_state.links, &live_state.nodes)
+            .into_iter()
+            .collect();
+
+    let missing_links: Vec<Link> = desired_links
+        .iter()
+        .filter(|link| !existing_links.contains(link))
+        .cloned()
+        .collect();
+    let stale_links: Vec<(u32, Link)> = managed_links
+        .iter()
+        .filter(|(id, link)| {
+            (checkpoints.owned_links().contains(link) || live_state.is_builder_link(*id))
+                && !desired_links.contains(link)
+        })
+        .cloned()
+        .collect();
+    let stale_links = confirm::approved(stale_links, |(_, link)| format!("stale link {link}"))?;
+
+    let removed_links: Vec<Link> = stale_links.iter().map(|(_, link)| link.clone()).collect();
+    if !stale_links.is_empty() {
+        builder::delete_links(&stale_links, &topology.protected_nodes, clients, reporter).await?;
+        checkpoints.forget_links(&removed_links)?;
+    }
+    let mut created_links = Vec::new();
+    if !missing_links.is_empty() {
+        let plan = ConnectionPlan::new(missing_links.iter().cloned());
+        plan.check_node_names(&live_state.nodes)?;
+        let created_before = reporter.created_links().len();
+        let result = builder::execute_plan(
+            &plan,
+            &BTreeSet::new(),
+            &topology.protected_nodes,
+            clients,
+            reporter,
+        )
+        .await;
+        created_links = reporter.created_links()[created_before..].to_vec();
+        checkpoints.record_links(created_links.clone())?;
+        result?;
+    }
+    Ok(Reconciled {
+        managed_links: managed_links.len(),
+        missing_links: missing_links.len(),
+        stale_links: stale_links.len(),
+        found_links: managed_links.into_iter().map(|(_, link)| link).collect(),
+        created_links,
+        removed_links,
+    })
+}
+
+/// Creates the group, aux bus, cue and output stage channel strips the
+/// topology declares that aren't in the registry yet, after the topology was
+/// reloaded. Strips the topology dropped stay, only their links are removed
+/// by the next reconcile. Returns how many were created.
+pub async fn build_missing_strips(
+    topology: &Topology,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let live_state = live::read_live_state(clients, reporter).await?;
+    let mut missing = topology.clone();
+    missing
+        .groups
+        .retain(|group| live_state.find_channel_strip(&group.name).is_none());
+    missing
+        .aux_buses
+        .retain(|aux_bus| live_state.find_channel_strip(&aux_bus.name).is_none());
+    missing.cue = missing
+        .cue
+        .filter(|cue| live_state.find_channel_strip(&cue.name).is_none());
+    missing.output_stages.retain(|output_stage| {
+        !live_state
+            .output_stages
+            .iter()
+            .any(|o| o.name == naming().output_stage(&output_stage.name))
+    });
+
+    let created = missing.groups.len()
+        + missing.aux_buses.len()
+        + usize::from(missing.cue.is_some())
+        + missing.output_stages.len();
+    let strips = &live_state.channel_strips;
+    builder::build_group_channel_strips(&missing, strips, clients, reporter).await?;
+    builder::build_aux_bus_channel_strips(&missing, strips, clients, reporter).await?;
+    builder::build_cue_channel_strip(&missing, strips, clients, reporter).await?;
+    builder::build_output_stages(&missing, &live_state.output_stages, clients, reporter).await?;
+    Ok(created)
+}
+
+/// Reads the topology again if `watch` says so and creates the strips it
+/// added. A topology that doesn't load keeps the current one in place.
+/// Returns whether the topology was replaced.
+pub async fn reload_topology(
+    topology: &mut Topology,
+    watch: &mut TopologyWatch,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> bool {
+    let Some(reloaded) = watch.reload() else {
+        return false;
+    };
+    let reloaded = match reloaded {
+        Ok(reloaded) => reloaded,
+        Err(error) => {
+            reporter.skipped(&format!(
+                "Keeping the previous topology, couldn't reload it: {error}"
+            ));
+            return false;
+        }
+    };
+    reporter.start_stage("Reloading topology");
+    *topology = reloaded;
+    match build_missing_strips(topology, clients, reporter).await {
+        Ok(created) => reporter.log_info(&format!(
+            "Reloaded the topology, created {created} channel strips"
+        )),
+        Err(error) => reporter.skipped(&format!(
+            "Couldn't create the channel strips of the reloaded topology: {error}"
+        )),
+    }
+    true
+}
+
+/// Repairs drift between the topology and the live links every `interval`
+/// until the process is stopped. A failed round is logged and retried on the
+/// next one. Each round reports to a reporter of its own, logging through
+/// `logger_factory`. The topology is reloaded when `watch` notices a change,
+/// the round rebuilding it is kept in `history`. Every round is recorded on
+/// `status` and the link changes on `audit_log`.
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    topology: &Topology,
+    interval: Duration,
+    jitter: Duration,
+    mut watch: TopologyWatch,
+    status: &StatusBoard,
+    mut audit_log: Option<AuditLog>,
+    history: &History,
+    checkpoints: &mut Checkpoints,
+    clients: &Clients,
+    logger_factory: &LoggerFactory,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    reporter.log_info(&format!(
+        "Reconciling the live links every {}s",
+        interval.as_secs()
+    ));
+    let mut topology = topology.clone();
+    loop {
+        let round =
+            reporter.next_run(logger_factory.new_logger(String::from("fr_pmx_builder::daemon")));
+        let started = SystemTime::now();
+        let reloaded = reload_topology(&mut topology, &mut watch, clients, &round).await;
+        round.start_stage("Reconciling links");
+        let result = reconcile(&topology, checkpoints, clients, &round).await;
+        match &result {
+            Ok(reconciled) => {
+                match reconciled.repaired() {
+                    0 => round.log_info("No drift, nothing to do"),
+                    repaired => round.log_info(&format!("Repaired {repaired} links")),
+                }
+                status.round_finished(Ok(reconciled));
+                if let Some(audit_log) = &mut audit_log {
+                    if let Err(error) = audit_log.round_finished(reconciled) {
+                        round.skipped(&format!("Couldn't write the audit log: {error}"));
+                    }
+                }
+            }
+            Err(error) => {
+                round.skipped(&format!("Couldn't reconcile links: {error}"));
+                status.round_finished(Err(error.to_string()));
+            }
+        }
+        if reloaded {
+            round.finish(result.as_ref().err().map(|e| e.as_ref()));
+            history.record(
+                &history::build_run_id(started, ownership::run_id()),
+                &topology,
+                started.elapsed().unwrap_or_default(),
+                &round,
+            );
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(next_delay(interval, jitter)) => {}
+            _ = watch.hangup() => {}
+        }
+    }
+}
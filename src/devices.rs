@@ -0,0 +1,97 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::clients::Clients;
+use crate::report::Reporter;
+use crate::topology::Topology;
+
+/// How often the ports are listed while waiting for a device to come up.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Profile an audio interface has to be in before the inputs are wired.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    /// Device name as pipewire lists it
+    pub name: String,
+    pub profile: String,
+    /// Port paths the profile brings up, waited for after switching
+    #[serde(default)]
+    pub ports: Vec<String>,
+    /// Seconds to wait for the ports
+    #[serde(default = "default_wait_seconds")]
+    pub wait_seconds: u64,
+}
+
+fn default_wait_seconds() -> u64 {
+    10
+}
+
+/// Switches the configured devices to their profile and waits for the ports
+/// the profiles bring up. Devices already in their profile are left alone.
+pub async fn prepare_devices(
+    topology: &Topology,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if topology.devices.is_empty() {
+        return Ok(());
+    }
+    reporter.start_counted_stage("Preparing devices", topology.devices.len());
+    let devices = clients.graph.devices().await?;
+    for config in &topology.devices {
+        reporter.step();
+        let Some(device) = devices.iter().find(|d| d.name == config.name) else {
+            return Err(format!("Couldn't find device {}", config.name).into());
+        };
+        if device.active_profile == config.profile {
+            reporter.log_info(&format!(
+                "Device {} is in profile {} already",
+                config.name, config.profile
+            ));
+        } else {
+            reporter.log_info(&format!(
+                "Switching device {} from profile {} to {}",
+                config.name, device.active_profile, config.profile
+            ));
+            clients
+                .graph
+                .set_device_profile(device.id, &config.profile)
+                .await?;
+        }
+        wait_for_ports(config, clients).await?;
+    }
+    Ok(())
+}
+
+async fn wait_for_ports(
+    config: &DeviceConfig,
+    clients: &Clients,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let deadline = Instant::now() + Duration::from_secs(config.wait_seconds);
+    loop {
+        let ports = clients.graph.ports().await?;
+        let missing: Vec<&String> = config
+            .ports
+            .iter()
+            .filter(|path| !ports.iter().any(|p| p.path == **path))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "Ports of device {} didn't come up in {} seconds: {}",
+                config.name,
+                config.wait_seconds,
+                missing
+                    .iter()
+                    .map(|p| p.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into());
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeSet;
+use std::fmt::Display;
+
+use crate::{
+    live::LiveState,
+    naming::naming,
+    plan::{ConnectionPlan, Link},
+    report::Reporter,
+    state::BuildState,
+    topology::Topology,
+};
+
+pub struct Diff<T> {
+    pub to_create: BTreeSet<T>,
+    pub to_delete: BTreeSet<T>,
+    pub correct: BTreeSet<T>,
+}
+
+impl<T: Ord + Clone> Diff<T> {
+    pub fn new(desired: &BTreeSet<T>, live: &BTreeSet<T>) -> Diff<T> {
+        Diff {
+            to_create: desired.difference(live).cloned().collect(),
+            to_delete: live.difference(desired).cloned().collect(),
+            correct: desired.intersection(live).cloned().collect(),
+        }
+    }
+}
+
+pub fn channel_strip_diff(topology: &Topology, live: &LiveState) -> Diff<String> {
+    let desired: BTreeSet<String> = live
+        .inputs
+        .iter()
+        .filter(|i| topology.has_chain(&i.name))
+        .map(|i| naming().input_strip(&i.name))
+        .chain(
+            topology
+                .groups
+                .iter()
+                .map(|g| naming().group_strip(&g.name)),
+        )
+        .chain(
+            topology
+                .aux_buses
+                .iter()
+                .map(|a| naming().aux_bus_strip(&a.name)),
+        )
+        .chain(topology.cue.iter().map(|c| naming().cue_strip(&c.name)))
+        .collect();
+
+    let output_stage_strip_ids = live.output_stage_channel_strip_ids();
+    let existing: BTreeSet<String> = live
+        .channel_strips
+        .iter()
+        .filter(|c| !output_stage_strip_ids.contains(&c.id) && naming().owns(&c.name))
+        .map(|c| c.name.clone())
+        .collect();
+
+    Diff::new(&desired, &existing)
+}
+
+pub fn output_stage_diff(topology: &Topology, live: &LiveState) -> Diff<String> {
+    let desired: BTreeSet<String> = topology
+        .output_stages
+        .iter()
+        .map(|o| naming().output_stage(&o.name))
+        .collect();
+    let existing: BTreeSet<String> = live
+        .output_stages
+        .iter()
+        .filter(|o| naming().owns(&o.name))
+        .map(|o| o.name.clone())
+        .collect();
+    Diff::new(&desired, &existing)
+}
+
+pub fn print_diff(topology: &Topology, live: &LiveState, reporter: &Reporter) {
+    print_section("Channel strips", &channel_strip_diff(topology, live));
+    print_section("Output stages", &output_stage_diff(topology, live));
+
+    let desired_links = live.desired_links(topology, reporter);
+    let existing_links = live.managed_links(&topology.looper);
+    print_section("Links", &Diff::new(&desired_links, &existing_links));
+}
+
+/// What a verify run found missing or unexpected in the live graph.
+pub struct Verification {
+    pub missing_channel_strips: Vec<String>,
+    pub missing_output_stages: Vec<String>,
+    pub missing_links: Vec<String>,
+    /// Links a build created that the topology doesn't want, links patched
+    /// by hand aren't listed
+    pub unexpected_links: Vec<String>,
+}
+
+impl Verification {
+    /// Unexpected links don't fail a verify, they may belong to other tools.
+    pub fn passed(&self) -> bool {
+        self.missing_channel_strips.is_empty()
+            && self.missing_output_stages.is_empty()
+            && self.missing_links.is_empty()
+    }
+}
+
+pub fn verify(topology: &Topology, live: &LiveState, reporter: &Reporter) -> Verification {
+    let desired_links = live.desired_links(topology, reporter);
+    let existing_links = live.managed_links(&topology.looper);
+    let link_diff = Diff::new(&desired_links, &existing_links);
+    let unexpected_links = live
+        .managed_live_links(&topology.looper)
+        .into_iter()
+        .filter(|(id, link)| live.is_builder_link(*id) && !desired_links.contains(link))
+        .map(|(_, link)| link.to_string())
+        .collect();
+
+    Verification {
+        missing_channel_strips: channel_strip_diff(topology, live)
+            .to_create
+            .into_iter()
+            .collect(),
+        missing_output_stages: output_stage_diff(topology, live)
+            .to_create
+            .into_iter()
+            .collect(),
+        missing_links: link_diff.to_create.iter().map(|l| l.to_string()).collect(),
+        unexpected_links,
+    }
+}
+
+pub fn print_verification(verification: &Verification) {
+    for name in &verification.missing_channel_strips {
+        println!("missing channel strip: {name}");
+    }
+    for name in &verification.missing_output_stages {
+        println!("missing output stage: {name}");
+    }
+    for link in &verification.missing_links {
+        println!("missing link: {link}");
+    }
+    for link in &verification.unexpected_links {
+        println!("unexpected link: {link}");
+    }
+    if verification.passed() {
+        println!("Live graph matches the topology");
+    } else {
+        println!("Live graph does not match the topology");
+    }
+}
+
+/// Prints what a build changed compared to the previous one: channel strips
+/// and links added or removed and parameters set to other values.
+pub fn print_build_diff(previous: &BuildState, current: &BuildState) {
+    println!("Changes since the previous build:");
+    let strips = Diff::new(&current.channel_strips, &previous.channel_strips);
+    let links = Diff::new(&current.links, &previous.links);
+    print_changes("Channel strips", &strips);
+    print_changes("Links", &links);
+
+    let symbols: BTreeSet<&String> = previous
+        .parameters
+        .keys()
+        .chain(current.parameters.keys())
+        .collect();
+    let changed: Vec<String> = symbols
+        .into_iter()
+        .filter_map(|symbol| {
+            let before = previous.parameters.get(symbol);
+            let after = current.parameters.get(symbol);
+            (before != after).then(|| {
+                let value = |v: Option<&f32>| v.map_or(String::from("unset"), f32::to_string);
+                format!("  ~ {symbol}: {} -> {}", value(before), value(after))
+            })
+        })
+        .collect();
+    println!("Parameters: {} changed", changed.len());
+    for line in changed {
+        println!("{line}");
+    }
+}
+
+/// Prints the links of `plan` in the order they are made, `+` for the ones
+/// to create and `=` for the ones `existing` has already.
+pub fn print_plan(plan: &ConnectionPlan, existing: &BTreeSet<Link>) {
+    let to_create = plan
+        .links()
+        .iter()
+        .filter(|l| !existing.contains(l))
+        .count();
+    println!("Plan: {} links, {to_create} to create", plan.len());
+    for link in plan.links() {
+        let marker = if existing.contains(link) { '=' } else { '+' };
+        println!("  {marker} {link}");
+    }
+}
+
+/// Prints only what was added and removed.
+fn print_changes<T: Display>(title: &str, diff: &Diff<T>) {
+    println!(
+        "{}: {} added, {} removed",
+        title,
+        diff.to_create.len(),
+        diff.to_delete.len()
+    );
+    for item in &diff.to_create {
+        println!("  + {item}");
+    }
+    for item in &diff.to_delete {
+        println!("  - {item}");
+    }
+}
+
+fn print_section<T: Display>(title: &str, diff: &Diff<T>) {
+    println!(
+        "{}: {} to create, {} to delete, {} already correct",
+        title,
+        diff.to_create.len(),
+        diff.to_delete.len(),
+        diff.correct.len()
+    );
+    for item in &diff.to_create {
+        println!("  + {item}");
+    }
+    for item in &diff.to_delete {
+        println!("  - {item}");
+    }
+    for item in &diff.correct {
+        println!("  = {item}");
+    }
+}
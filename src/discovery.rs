@@ -0,0 +1,129 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::model::{Input, InputType};
+use crate::pmx::pipewire::{node::ListNode, port::ListPort};
+use crate::report::Reporter;
+
+/// Registers inputs for pipewire nodes matching `pattern`, so a fresh machine
+/// doesn't need its inputs set up in the registry by hand.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveryRule {
+    /// Node name pattern, `*` matches any text and `?` a single character
+    pub pattern: String,
+    /// Input name, `{node}` is replaced with the node name and `{n}` with the
+    /// channel number when a node is split into mono inputs
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// Group channel strip the discovered inputs are routed to
+    pub group: String,
+}
+
+fn default_name() -> String {
+    String::from("{node}")
+}
+
+/// Input found in pipewire that isn't registered yet.
+#[derive(Debug, Clone)]
+pub struct DiscoveredInput {
+    pub name: String,
+    pub input_type: InputType,
+    pub left_port_path: Option<String>,
+    pub right_port_path: Option<String>,
+    pub group_channel_strip_name: String,
+}
+
+pub fn glob_to_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let escaped = regex::escape(pattern)
+        .replace(r"\*", ".*")
+        .replace(r"\?", ".");
+    Regex::new(&format!("^{escaped}$"))
+}
+
+/// Capture ports of `node`, ordered by port id.
+fn capture_ports<'a>(node: &ListNode, ports: &'a [ListPort]) -> Vec<&'a ListPort> {
+    let mut capture_ports: Vec<&ListPort> = ports
+        .iter()
+        .filter(|p| p.node_id == node.object_serial && p.direction == "out")
+        .collect();
+    capture_ports.sort_by_key(|p| p.id);
+    capture_ports
+}
+
+/// Inputs for every node matching a rule. Nodes with a FL/FR port pair become
+/// one stereo input, any other node one mono input per capture port. Inputs
+/// already in the registry are left out.
+pub fn discover_inputs(
+    rules: &[DiscoveryRule],
+    nodes: &[ListNode],
+    ports: &[ListPort],
+    existing_inputs: &[Input],
+    reporter: &Reporter,
+) -> Vec<DiscoveredInput> {
+    let mut discovered: Vec<DiscoveredInput> = Vec::new();
+
+    for rule in rules {
+        let pattern = match glob_to_regex(&rule.pattern) {
+            Ok(pattern) => pattern,
+            Err(error) => {
+                reporter.skipped(&format!(
+                    "Invalid discovery pattern {}: {error}",
+                    rule.pattern
+                ));
+                continue;
+            }
+        };
+
+        for node in nodes.iter().filter(|n| pattern.is_match(&n.name)) {
+            let node_ports = capture_ports(node, ports);
+            let channels: Vec<&str> = node_ports
+                .iter()
+                .map(|p| p.audio_channel.as_str())
+                .collect();
+
+            let inputs = if channels == ["FL", "FR"] {
+                vec![DiscoveredInput {
+                    name: rule.name.replace("{node}", &node.name).replace("{n}", "1"),
+                    input_type: InputType::Stereo,
+                    left_port_path: Some(node_ports[0].path.clone()),
+                    right_port_path: Some(node_ports[1].path.clone()),
+                    group_channel_strip_name: rule.group.clone(),
+                }]
+            } else {
+                let name = if rule.name.contains("{n}") || node_ports.len() < 2 {
+                    rule.name.clone()
+                } else {
+                    format!("{} {{n}}", rule.name)
+                };
+                node_ports
+                    .iter()
+                    .enumerate()
+                    .map(|(index, port)| DiscoveredInput {
+                        name: name
+                            .replace("{node}", &node.name)
+                            .replace("{n}", &(index + 1).to_string()),
+                        input_type: InputType::Mono,
+                        left_port_path: Some(port.path.clone()),
+                        right_port_path: None,
+                        group_channel_strip_name: rule.group.clone(),
+                    })
+                    .collect()
+            };
+
+            for input in inputs {
+                let known = existing_inputs.iter().any(|i| i.name == input.name)
+                    || discovered.iter().any(|i| i.name == input.name);
+                if known {
+                    reporter.log_info(&format!(
+                        "Input {} is already registered, nothing to do",
+                        input.name
+                    ));
+                    continue;
+                }
+                discovered.push(input);
+            }
+        }
+    }
+
+    discovered
+}
@@ -0,0 +1,223 @@
+use crate::config;
+use crate::connection::{self, AuthChannel};
+use crate::connector;
+use crate::model::{Input, Output};
+use crate::pmx::pipewire::port::ListPort;
+use crate::pmx::{pmx_registry_client::PmxRegistryClient, EmptyRequest};
+use crate::report::Reporter;
+use crate::rpc::{self, Service};
+use crate::topology::Topology;
+
+/// Outcome of one doctor check, with what was found or what went wrong.
+pub struct Check {
+    pub name: &'static str,
+    pub result: Result<String, String>,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<String, String>) -> Check {
+        Check { name, result }
+    }
+}
+
+/// Port paths that don't belong to any live pipewire port.
+fn unresolved_paths<'a>(
+    paths: impl Iterator<Item = (&'a str, Option<&'a str>)>,
+    ports: &[ListPort],
+) -> Result<String, String> {
+    let mut resolved = 0;
+    let mut unresolved = Vec::new();
+    for (name, path) in paths {
+        match path {
+            Some(path) if ports.iter().any(|p| p.path == path) => resolved += 1,
+            Some(path) => unresolved.push(format!("{name} ({path})")),
+            None => {}
+        }
+    }
+    if unresolved.is_empty() {
+        Ok(format!("{resolved} port paths resolve"))
+    } else {
+        Err(format!("unresolved: {}", unresolved.join(", ")))
+    }
+}
+
+/// The registry is checked on its own, without the other services the
+/// builder's `Clients` connect to.
+async fn registered_inputs(
+    client: &PmxRegistryClient<AuthChannel>,
+    reporter: &Reporter,
+) -> Result<Vec<Input>, Box<dyn std::error::Error>> {
+    reporter.log_info("Reading inputs from registry");
+    let response = rpc::call(
+        Service::Registry,
+        "ListInputs",
+        client,
+        &EmptyRequest {},
+        |mut client, request| async move { client.list_inputs(request).await },
+    )
+    .await?;
+    Ok(response.inputs.into_iter().map(Input::from).collect())
+}
+
+async fn registered_outputs(
+    client: &PmxRegistryClient<AuthChannel>,
+) -> Result<Vec<Output>, Box<dyn std::error::Error>> {
+    let response = rpc::call(
+        Service::Registry,
+        "ListOutputs",
+        client,
+        &EmptyRequest {},
+        |mut client, request| async move { client.list_outputs(request).await },
+    )
+    .await?;
+    Ok(response.outputs.into_iter().map(Output::from).collect())
+}
+
+/// Connects to every service and reads what the build depends on without
+/// changing anything.
+pub async fn run_checks(topology: &Topology, reporter: &Reporter) -> Vec<Check> {
+    let service_urls = config::service_urls();
+    let mut checks = Vec::new();
+
+    let registry_client = connection::registry_client(
+        service_urls.pmx_registry_url.clone(),
+        &topology.connections.registry,
+    )
+    .await;
+    let inputs = match registry_client {
+        Ok(client) => {
+            let inputs = registered_inputs(&client, reporter).await;
+            let outputs = registered_outputs(&client).await.unwrap_or_default();
+            checks.push(Check::new(
+                "pmx registry",
+                inputs
+                    .as_ref()
+                    .map(|inputs| {
+                        format!(
+                            "{} inputs, {} outputs registered",
+                            inputs.len(),
+                            outputs.len()
+                        )
+                    })
+                    .map_err(|e| e.to_string()),
+            ));
+            inputs.ok().map(|inputs| (inputs, outputs))
+        }
+        Err(error) => {
+            checks.push(Check::new("pmx registry", Err(error.to_string())));
+            None
+        }
+    };
+
+    checks.push(Check::new(
+        "pmx factory",
+        connection::factory_client(service_urls.pmx_factory_url, &topology.connections.factory)
+            .await
+            .map(|_| String::from("connected"))
+            .map_err(|e| e.to_string()),
+    ));
+
+    checks.push(Check::new(
+        "mod-host proxy",
+        connection::mod_host_client(
+            service_urls.pmx_mod_host_proxy_url,
+            &topology.connections.mod_host,
+        )
+        .await
+        .map(|_| String::from("connected"))
+        .map_err(|e| e.to_string()),
+    ));
+
+    let audio_graph = connector::connect(
+        topology.graph_backend,
+        service_urls.pipewire_registry_url,
+        &topology.connections.pipewire,
+    )
+    .await;
+    let pipewire_state = match audio_graph {
+        Ok((client, fallback)) => {
+            if let Some(reason) = fallback {
+                reporter.graph_fallback(&reason);
+            }
+            let state = match (client.nodes().await, client.ports().await) {
+                (Ok(nodes), Ok(ports)) => Ok((nodes, ports)),
+                (Err(error), _) | (_, Err(error)) => Err(error.to_string()),
+            };
+            checks.push(Check::new(
+                "pipewire registry",
+                state
+                    .as_ref()
+                    .map(|(nodes, ports)| format!("{} nodes, {} ports", nodes.len(), ports.len()))
+                    .map_err(|e| e.clone()),
+            ));
+            state.ok()
+        }
+        Err(error) => {
+            checks.push(Check::new("pipewire registry", Err(error.to_string())));
+            None
+        }
+    };
+
+    let Some((nodes, ports)) = pipewire_state else {
+        return checks;
+    };
+
+    if topology.looper.enabled {
+        for looper_node in topology.looper.nodes() {
+            checks.push(Check::new(
+                "looper node",
+                if nodes.iter().any(|n| n.name == looper_node) {
+                    Ok(format!("{looper_node} is running"))
+                } else {
+                    Err(format!("no pipewire node {looper_node}"))
+                },
+            ));
+        }
+    }
+
+    if let Some((inputs, outputs)) = &inputs {
+        checks.push(Check::new(
+            "input ports",
+            unresolved_paths(
+                inputs.iter().flat_map(|i| {
+                    [
+                        (i.name.as_str(), i.left_port_path.as_deref()),
+                        (i.name.as_str(), i.right_port_path.as_deref()),
+                    ]
+                }),
+                &ports,
+            ),
+        ));
+        checks.push(Check::new(
+            "output ports",
+            unresolved_paths(
+                topology
+                    .outputs(outputs, &nodes, &ports)
+                    .iter()
+                    .flat_map(|o| {
+                        o.port_paths
+                            .iter()
+                            .filter(|path| !path.is_empty())
+                            .map(move |path| (o.name.as_str(), Some(path.as_str())))
+                    }),
+                &ports,
+            ),
+        ));
+    }
+
+    checks
+}
+
+pub fn passed(checks: &[Check]) -> bool {
+    checks.iter().all(|c| c.result.is_ok())
+}
+
+pub fn print_checks(checks: &[Check]) {
+    let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    for check in checks {
+        match &check.result {
+            Ok(detail) => println!("PASS  {:width$}  {detail}", check.name),
+            Err(detail) => println!("FAIL  {:width$}  {detail}", check.name),
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::clients::Clients;
+use crate::confirm;
+use crate::diff;
+use crate::live::LiveState;
+use crate::model::ChannelStrip;
+use crate::plan::StripPlugins;
+use crate::pmx::{
+    factory::DeleteChannelStripRequest, mod_host::RemovePluginRequest, plugin::PmxPlugin,
+};
+use crate::report::Reporter;
+use crate::rpc::{self, Service};
+use crate::topology::Topology;
+
+/// Channel strips and plugins nothing in the topology uses any more.
+#[derive(Debug, Default)]
+pub struct Garbage {
+    /// Strips the builder made with the reason they're garbage
+    pub channel_strips: Vec<(ChannelStrip, &'static str)>,
+    /// Plugins outside the garbage strips, with the reason
+    pub plugins: Vec<(PmxPlugin, &'static str)>,
+    /// Inputs whose recorded plugin chain goes with the plugins, with the
+    /// chain's plugin ids
+    pub plugin_chains: Vec<(String, Vec<u32>)>,
+    /// Outputs whose recorded limiter goes with the plugins, or is gone
+    /// already, with the limiter's plugin id
+    pub limiters: Vec<(String, u32)>,
+    /// Outputs whose recorded mono sum goes, like `limiters`
+    pub mono_sums: Vec<(String, u32)>,
+}
+
+impl Garbage {
+    pub fn is_empty(&self) -> bool {
+        self.channel_strips.is_empty()
+            && self.plugins.is_empty()
+            && self.limiters.is_empty()
+            && self.mono_sums.is_empty()
+    }
+
+    /// Names of the plugin nodes that go away with the garbage.
+    pub fn node_names(&self, live: &LiveState) -> BTreeSet<String> {
+        let strip_plugin_ids: Vec<u32> = self
+            .channel_strips
+            .iter()
+            .flat_map(|(strip, _)| strip_plugin_ids(strip))
+            .collect();
+        live.plugins
+            .iter()
+            .filter(|p| strip_plugin_ids.contains(&p.id))
+            .chain(self.plugins.iter().map(|(plugin, _)| plugin))
+            .map(|p| p.name.clone())
+            .collect()
+    }
+}
+
+fn strip_plugin_ids(strip: &ChannelStrip) -> Vec<u32> {
+    [strip.gain_plugin_id(), strip.saturator_plugin_id()]
+        .into_iter()
+        .chain(strip.cross_fader_plugin_id())
+        .collect()
+}
+
+fn has_node(live: &LiveState, plugin_id: u32) -> bool {
+    live.plugins
+        .iter()
+        .find(|p| p.id == plugin_id)
+        .is_some_and(|plugin| live.nodes.iter().any(|n| n.name == plugin.name))
+}
+
+/// Recorded output plugins, limiters or mono sums, of outputs that aren't
+/// in `used` any more.
+fn unused_output_plugins(
+    recorded: &BTreeMap<String, u32>,
+    used: &BTreeSet<String>,
+) -> Vec<(String, u32)> {
+    recorded
+        .iter()
+        .filter(|(output_name, _)| !used.contains(*output_name))
+        .map(|(output_name, plugin_id)| (output_name.clone(), *plugin_id))
+        .collect()
+}
+
+/// Finds the strips the builder made that the topology no longer wants or
+/// whose plugin nodes are gone, chain plugins of inputs that no longer have
+/// a chain, limiters and mono sums of outputs that no longer get one, and
+/// plugins whose node is gone. `live` has to carry the state file's plugin
+/// chains, limiters and mono sums. Output stage strips and strips of other
+/// tools are left alone.
+pub fn find_garbage(topology: &Topology, live: &LiveState) -> Garbage {
+    let mut garbage = Garbage::default();
+
+    let strip_diff = diff::channel_strip_diff(topology, live);
+    let output_stage_strips = live.output_stage_channel_strip_ids();
+    for strip in &live.channel_strips {
+        if strip_diff.to_delete.contains(&strip.name) {
+            garbage
+                .channel_strips
+                .push((strip.clone(), "not in the topology"));
+        } else if strip_diff.correct.contains(&strip.name)
+            && !output_stage_strips.contains(&strip.id)
+            && !strip_plugin_ids(strip).iter().all(|id| has_node(live, *id))
+        {
+            garbage
+                .channel_strips
+                .push((strip.clone(), "plugin nodes are gone"));
+        }
+    }
+
+    let kept_chains: BTreeSet<&str> = topology
+        .inputs
+        .iter()
+        .filter(|i| !i.plugins.is_empty())
+        .map(|i| i.name.as_str())
+        .chain(
+            topology
+                .talkback
+                .iter()
+                .filter(|t| t.duck_plugin.is_some())
+                .map(|t| t.input.as_str()),
+        )
+        .collect();
+    let mut orphaned_chain_plugins = BTreeSet::new();
+    for (input_name, plugin_ids) in &live.plugin_chains {
+        if !kept_chains.contains(input_name.as_str()) {
+            garbage
+                .plugin_chains
+                .push((input_name.clone(), plugin_ids.clone()));
+            orphaned_chain_plugins.extend(plugin_ids.iter().copied());
+        }
+    }
+
+    let limited_outputs = if topology.limiter.is_some() {
+        live.output_stage_output_names(topology)
+    } else {
+        BTreeSet::new()
+    };
+    garbage.limiters = unused_output_plugins(&live.limiters, &limited_outputs);
+    garbage.mono_sums =
+        unused_output_plugins(&live.mono_sums, &live.mono_sum_output_names(topology));
+    let is_unused = |unused: &[(String, u32)], plugin: &PmxPlugin| {
+        unused.iter().any(|(_, plugin_id)| *plugin_id == plugin.id)
+    };
+
+    let garbage_strip_plugins: BTreeSet<u32> = garbage
+        .channel_strips
+        .iter()
+        .flat_map(|(strip, _)| strip_plugin_ids(strip))
+        .collect();
+    for plugin in &live.plugins {
+        if garbage_strip_plugins.contains(&plugin.id) {
+            continue;
+        }
+        if orphaned_chain_plugins.contains(&plugin.id) {
+            garbage
+                .plugins
+                .push((plugin.clone(), "chain of an input without plugins"));
+        } else if is_unused(&garbage.limiters, plugin) {
+            garbage
+                .plugins
+                .push((plugin.clone(), "limiter of an output without one"));
+        } else if is_unused(&garbage.mono_sums, plugin) {
+            garbage
+                .plugins
+                .push((plugin.clone(), "mono sum of an output not in mono_sum mode"));
+        } else if !has_node(live, plugin.id) {
+            garbage.plugins.push((plugin.clone(), "node is gone"));
+        }
+    }
+    garbage
+}
+
+pub fn print_garbage(garbage: &Garbage) {
+    for (strip, reason) in &garbage.channel_strips {
+        println!("channel strip {}: {reason}", strip.name);
+    }
+    for (plugin, reason) in &garbage.plugins {
+        println!("plugin {} ({}): {reason}", plugin.name, plugin.id);
+    }
+}
+
+/// Keeps the garbage approved piece by piece. A plugin chain is only
+/// forgotten once every plugin of it goes.
+pub fn approved(garbage: Garbage) -> Result<Garbage, Box<dyn std::error::Error>> {
+    let channel_strips = confirm::approved(garbage.channel_strips, |(strip, reason)| {
+        format!("channel strip {} ({reason})", strip.name)
+    })?;
+    let plugins = confirm::approved(garbage.plugins, |(plugin, reason)| {
+        format!("plugin {} ({reason})", plugin.name)
+    })?;
+    let plugin_chains = chains_gone_with(garbage.plugin_chains, &plugins);
+    let limiters = output_plugins_gone_with(garbage.limiters, &garbage.plugins, &plugins);
+    let mono_sums = output_plugins_gone_with(garbage.mono_sums, &garbage.plugins, &plugins);
+    Ok(Garbage {
+        channel_strips,
+        plugins,
+        plugin_chains,
+        limiters,
+        mono_sums,
+    })
+}
+
+/// The plugin chains every plugin of which is in `plugins`.
+fn chains_gone_with(
+    plugin_chains: Vec<(String, Vec<u32>)>,
+    plugins: &[(PmxPlugin, &'static str)],
+) -> Vec<(String, Vec<u32>)> {
+    plugin_chains
+        .into_iter()
+        .filter(|(_, plugin_ids)| {
+            plugin_ids
+                .iter()
+                .all(|id| plugins.iter().any(|(plugin, _)| plugin.id == *id))
+        })
+        .collect()
+}
+
+/// The recorded output plugins going with `plugins`, and those whose plugin
+/// isn't among the garbage `found` because it isn't live any more.
+fn output_plugins_gone_with(
+    output_plugins: Vec<(String, u32)>,
+    found: &[(PmxPlugin, &'static str)],
+    plugins: &[(PmxPlugin, &'static str)],
+) -> Vec<(String, u32)> {
+    let contains = |plugins: &[(PmxPlugin, &'static str)], id: u32| {
+        plugins.iter().any(|(plugin, _)| plugin.id == id)
+    };
+    output_plugins
+        .into_iter()
+        .filter(|(_, id)| contains(plugins, *id) || !contains(found, *id))
+        .collect()
+}
+
+/// Deletes the garbage strips through the factory, their plugins go with
+/// them, and removes the other garbage plugins from mod-host. Returns the
+/// garbage that is gone, with the error that stopped the collection if one
+/// did, so the state file can forget what was deleted either way.
+pub async fn collect(
+    garbage: &Garbage,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> (Garbage, Result<(), Box<dyn std::error::Error>>) {
+    let mut collected = Garbage::default();
+    let result = collect_into(&mut collected, garbage, clients, reporter).await;
+    collected.plugin_chains = chains_gone_with(garbage.plugin_chains.clone(), &collected.plugins);
+    collected.limiters = output_plugins_gone_with(
+        garbage.limiters.clone(),
+        &garbage.plugins,
+        &collected.plugins,
+    );
+    collected.mono_sums = output_plugins_gone_with(
+        garbage.mono_sums.clone(),
+        &garbage.plugins,
+        &collected.plugins,
+    );
+    (collected, result)
+}
+
+async fn collect_into(
+    collected: &mut Garbage,
+    garbage: &Garbage,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    reporter.start_counted_stage(
+        "Collecting garbage",
+        garbage.channel_strips.len() + garbage.plugins.len(),
+    );
+    for (strip, reason) in &garbage.channel_strips {
+        reporter.step();
+        reporter.log_info(&format!("Deleting channel strip {}", strip.name));
+        let request = DeleteChannelStripRequest { id: strip.id };
+        rpc::call(
+            Service::Factory,
+            "DeleteChannelStrip",
+            &clients.factory().await?,
+            &request,
+            |mut client, request| async move { client.delete_channel_strip(request).await },
+        )
+        .await?;
+        collected.channel_strips.push((strip.clone(), reason));
+    }
+    for (plugin, reason) in &garbage.plugins {
+        reporter.step();
+        reporter.log_info(&format!("Removing plugin {}", plugin.name));
+        let request = RemovePluginRequest {
+            plugin_instance_id: plugin.id,
+        };
+        rpc::call(
+            Service::ModHost,
+            "RemovePlugin",
+            &clients.mod_host().await?,
+            &request,
+            |mut client, request| async move { client.remove_plugin(request).await },
+        )
+        .await?;
+        collected.plugins.push((plugin.clone(), reason));
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::live::LiveState;
+use crate::looper::LooperBackend;
+use crate::plan::StripPlugins;
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum GraphFormat {
+    #[default]
+    Dot,
+    Mermaid,
+    Json,
+}
+
+/// Managed part of the pipewire graph with the ids the services use.
+#[derive(Debug, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<GraphNode>,
+    pub links: Vec<GraphLink>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GraphNode {
+    /// Pipewire object serial
+    pub id: u32,
+    pub name: String,
+    /// Registry id if the node is a plugin
+    pub plugin_id: Option<u32>,
+    /// Channel strip the plugin belongs to
+    pub channel_strip: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GraphLink {
+    /// Pipewire link id
+    pub id: u32,
+    pub output_node_id: u32,
+    pub output_port_id: u32,
+    pub input_node_id: u32,
+    pub input_port_id: u32,
+}
+
+pub fn graph(live_state: &LiveState, looper_backend: &LooperBackend) -> Graph {
+    let managed_ids: BTreeSet<u32> = live_state
+        .managed_live_links(looper_backend)
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+    let links: Vec<GraphLink> = live_state
+        .links
+        .iter()
+        .filter(|l| managed_ids.contains(&l.id))
+        .map(|l| GraphLink {
+            id: l.id,
+            output_node_id: l.output_node_id,
+            output_port_id: l.output_port_id,
+            input_node_id: l.input_node_id,
+            input_port_id: l.input_port_id,
+        })
+        .collect();
+
+    let node_ids: BTreeSet<u32> = links
+        .iter()
+        .flat_map(|l| [l.output_node_id, l.input_node_id])
+        .collect();
+    let nodes = live_state
+        .nodes
+        .iter()
+        .filter(|n| node_ids.contains(&n.object_serial))
+        .map(|n| {
+            let plugin = live_state.plugins.iter().find(|p| p.name == n.name);
+            let channel_strip = plugin.and_then(|plugin| {
+                live_state.channel_strips.iter().find(|c| {
+                    c.gain_plugin_id == plugin.id
+                        || c.saturator_plugin_id == plugin.id
+                        || c.cross_fader_plugin_id() == Some(plugin.id)
+                })
+            });
+            GraphNode {
+                id: n.object_serial,
+                name: n.name.clone(),
+                plugin_id: plugin.map(|p| p.id),
+                channel_strip: channel_strip.map(|c| c.name.clone()),
+            }
+        })
+        .collect();
+
+    Graph { nodes, links }
+}
+
+fn label(node: &GraphNode) -> String {
+    match &node.channel_strip {
+        Some(channel_strip) => format!("{channel_strip}\n{}", node.name),
+        None => node.name.clone(),
+    }
+}
+
+fn render_dot(graph: &Graph) -> String {
+    let mut dot = String::from("digraph pmx {\n    rankdir=LR;\n");
+    for node in &graph.nodes {
+        let label = label(node).replace('"', "\\\"").replace('\n', "\\n");
+        writeln!(dot, "    n{} [label=\"{label}\"];", node.id).unwrap();
+    }
+    for link in &graph.links {
+        writeln!(
+            dot,
+            "    n{} -> n{} [label=\"{}:{}\"];",
+            link.output_node_id, link.input_node_id, link.output_port_id, link.input_port_id
+        )
+        .unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn render_mermaid(graph: &Graph) -> String {
+    let mut mermaid = String::from("graph LR\n");
+    for node in &graph.nodes {
+        let label = label(node).replace('"', "#quot;").replace('\n', "<br/>");
+        writeln!(mermaid, "    n{}[\"{label}\"]", node.id).unwrap();
+    }
+    for link in &graph.links {
+        writeln!(
+            mermaid,
+            "    n{} -->|\"{}:{}\"| n{}",
+            link.output_node_id, link.output_port_id, link.input_port_id, link.input_node_id
+        )
+        .unwrap();
+    }
+    mermaid
+}
+
+pub fn render(graph: &Graph, format: GraphFormat) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(match format {
+        GraphFormat::Dot => render_dot(graph),
+        GraphFormat::Mermaid => render_mermaid(graph),
+        GraphFormat::Json => serde_json::to_string_pretty(graph)?,
+    })
+}
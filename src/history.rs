@@ -0,0 +1,225 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::report::Reporter;
+use crate::topology::Topology;
+
+/// One build kept in the history directory.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub run_id: String,
+    /// Unix time in milliseconds the build finished at
+    pub finished_at: u128,
+    pub duration_ms: u128,
+    pub success: bool,
+    pub error: Option<String>,
+    pub profile: Option<String>,
+    /// Hash of the topology, overlay, venue and patch list files the build
+    /// read and its profile, equal between two runs if the configuration
+    /// didn't change
+    pub config_hash: String,
+    pub channel_strips: usize,
+    pub output_stages: usize,
+    pub links_created: usize,
+    pub links_failed: usize,
+    pub skipped: usize,
+    /// The build report of the run
+    pub report: serde_json::Value,
+}
+
+impl RunRecord {
+    /// Record of the build `reporter` reported on, finished just now.
+    pub fn new(
+        run_id: &str,
+        duration: Duration,
+        config_hash: String,
+        reporter: &Reporter,
+    ) -> Result<RunRecord, Box<dyn std::error::Error>> {
+        let report = reporter.report_json()?;
+        let count = |key: &str| report[key].as_array().map_or(0, |items| items.len());
+        let links = report["links"].as_array().cloned().unwrap_or_default();
+        let links_failed = links.iter().filter(|l| !l["error"].is_null()).count();
+        Ok(RunRecord {
+            run_id: String::from(run_id),
+            finished_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis()),
+            duration_ms: duration.as_millis(),
+            success: report["success"].as_bool().unwrap_or_default(),
+            error: report["error"].as_str().map(String::from),
+            profile: report["profile"].as_str().map(String::from),
+            config_hash,
+            channel_strips: count("channel_strips"),
+            output_stages: count("output_stages"),
+            links_created: links.len() - links_failed,
+            links_failed,
+            skipped: count("skipped"),
+            report,
+        })
+    }
+}
+
+/// FNV-1a over the contents of `paths` and the name of `profile`. Unlike
+/// std's hasher it is the same in every build of the builder, so hashes
+/// from older runs compare.
+pub fn config_hash(paths: &[Option<&Path>], profile: Option<&str>) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let contents = paths
+        .iter()
+        .flatten()
+        .map(|path| std::fs::read(path).unwrap_or_default())
+        .chain(profile.map(|profile| profile.as_bytes().to_vec()));
+    for content in contents {
+        for byte in content {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+/// Where builds are kept and the configuration they are built from, for
+/// every command that builds: build, serve and the daemon's reloads.
+#[derive(Debug, Clone)]
+pub struct History {
+    pub directory: PathBuf,
+    pub keep: usize,
+    pub topology: Option<PathBuf>,
+    pub overlay: Option<PathBuf>,
+    pub venue: Option<PathBuf>,
+    pub profile: Option<String>,
+}
+
+impl History {
+    /// Keeps the build `reporter` reported on. A history that can't be
+    /// written is logged, it never changes the outcome of the build.
+    pub fn record(
+        &self,
+        run_id: &str,
+        topology: &Topology,
+        duration: Duration,
+        reporter: &Reporter,
+    ) {
+        let patch_list =
+            topology.patch_list_path(self.topology.as_deref(), self.overlay.as_deref());
+        let config_hash = config_hash(
+            &[
+                self.topology.as_deref(),
+                self.overlay.as_deref(),
+                self.venue.as_deref(),
+                patch_list.as_deref(),
+            ],
+            self.profile.as_deref(),
+        );
+        let result = RunRecord::new(run_id, duration, config_hash, reporter)
+            .and_then(|run| record(&self.directory, self.keep, &run));
+        if let Err(error) = result {
+            reporter.log_info(&format!(
+                "Couldn't keep build {run_id} in the history: {error}"
+            ));
+        }
+    }
+}
+
+/// Id of one of the builds of a process that runs several, its start time
+/// in seconds in front of the id the process tags its links with.
+pub fn build_run_id(started: SystemTime, run_id: &str) -> String {
+    let started = started
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    format!("{started}-{run_id}")
+}
+
+fn record_path(directory: &Path, run_id: &str) -> PathBuf {
+    directory.join(format!("{run_id}.json"))
+}
+
+/// Files of the kept runs, oldest first by the start time in seconds their
+/// run ids begin with.
+fn record_paths(directory: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(directory)
+        .map_err(|e| format!("{}: {e}", directory.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .collect();
+    paths.sort_by_key(|path| {
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        let started: u64 = stem
+            .split_once('-')
+            .and_then(|(started, _)| started.parse().ok())
+            .unwrap_or_default();
+        (started, path.clone())
+    });
+    Ok(paths)
+}
+
+fn read_record(path: &Path) -> Result<RunRecord, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    Ok(serde_json::from_str(&contents).map_err(|e| format!("{}: {e}", path.display()))?)
+}
+
+/// Writes `record` to `directory` and deletes the oldest runs beyond `keep`.
+pub fn record(
+    directory: &Path,
+    keep: usize,
+    record: &RunRecord,
+) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(directory).map_err(|e| format!("{}: {e}", directory.display()))?;
+    let path = record_path(directory, &record.run_id);
+    std::fs::write(&path, serde_json::to_string_pretty(record)?)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    let paths = record_paths(directory)?;
+    for old in &paths[..paths.len().saturating_sub(keep)] {
+        std::fs::remove_file(old).map_err(|e| format!("{}: {e}", old.display()))?;
+    }
+    Ok(())
+}
+
+/// Prints one line per kept run, newest first, marking the runs whose
+/// configuration differs from the run before.
+pub fn print_history(directory: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let records = record_paths(directory)?
+        .iter()
+        .map(|path| read_record(path))
+        .collect::<Result<Vec<RunRecord>, _>>()?;
+    if records.is_empty() {
+        println!("No builds recorded in {}", directory.display());
+        return Ok(());
+    }
+    for (index, record) in records.iter().enumerate().rev() {
+        let changed = index > 0 && records[index - 1].config_hash != record.config_hash;
+        println!(
+            "{}  {}  {:.1}s  {} strips, {} output stages, {} links, {} failed, {} skipped  config {}{}",
+            record.run_id,
+            if record.success { "ok" } else { "failed" },
+            record.duration_ms as f64 / 1000.0,
+            record.channel_strips,
+            record.output_stages,
+            record.links_created,
+            record.links_failed,
+            record.skipped,
+            record.config_hash,
+            if changed { " (changed)" } else { "" },
+        );
+    }
+    Ok(())
+}
+
+/// Prints the build report kept for `run_id`.
+pub fn print_run(directory: &Path, run_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let path = record_path(directory, run_id);
+    if !path.exists() {
+        return Err(format!("No build {run_id} in {}", directory.display()).into());
+    }
+    let record = read_record(&path)?;
+    println!("{}", serde_json::to_string_pretty(&record)?);
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+use crate::report::Reporter;
+use crate::state::Stage;
+use crate::topology::Topology;
+
+/// Whether a hook runs before or after its stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookTime {
+    Before,
+    After,
+}
+
+/// External command run around a build stage, for tools the builder doesn't
+/// manage itself. A hook that fails stops the build.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HookConfig {
+    pub stage: Stage,
+    pub when: HookTime,
+    /// Program and its arguments, like `["sooperlooper", "-l", "12"]`
+    pub command: Vec<String>,
+}
+
+/// Runs the hooks of `stage` configured for `when`, in the order they are
+/// defined. The results of the build so far are passed in `FR_PMX_*`
+/// environment variables.
+pub async fn run_hooks(
+    topology: &Topology,
+    stage: Stage,
+    when: HookTime,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for hook in topology
+        .hooks
+        .iter()
+        .filter(|h| h.stage == stage && h.when == when)
+    {
+        let Some((program, arguments)) = hook.command.split_first() else {
+            continue;
+        };
+        reporter.log_info(&format!("Running hook {}", hook.command.join(" ")));
+        let status = tokio::process::Command::new(program)
+            .args(arguments)
+            .env("FR_PMX_STAGE", stage.name())
+            .env(
+                "FR_PMX_HOOK",
+                match when {
+                    HookTime::Before => "before",
+                    HookTime::After => "after",
+                },
+            )
+            .envs(reporter.hook_environment())
+            .status()
+            .await
+            .map_err(|e| format!("Couldn't run hook {program}: {e}"))?;
+        if !status.success() {
+            return Err(format!(
+                "Hook {} of stage {} failed with {status}",
+                hook.command.join(" "),
+                stage.name()
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::live::LiveState;
+use crate::plan::Link;
+use crate::pmx::pipewire::node::ListNode;
+use crate::report::Reporter;
+use crate::topology::Topology;
+
+/// Latency budget checked after every build.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LatencyConfig {
+    /// Longest a path from an input to an output may take, in milliseconds
+    pub budget_ms: Option<f64>,
+}
+
+/// Signal path from an input to a node without outgoing links, usually a
+/// physical output, with the summed latency of the nodes on the way.
+#[derive(Debug, Clone, Serialize)]
+pub struct PathLatency {
+    pub input: String,
+    pub nodes: Vec<String>,
+    pub latency_ms: f64,
+    /// Delay that would align the path with the slowest path into the same node
+    pub compensation_ms: f64,
+}
+
+impl std::fmt::Display for PathLatency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} ({:.2} ms)",
+            self.input,
+            self.nodes.join(" -> "),
+            self.latency_ms
+        )
+    }
+}
+
+/// Latency of a node from its `quantum/rate` latency property. Nodes without
+/// one add nothing to a path.
+fn node_latency_ms(node: &ListNode) -> Option<f64> {
+    let (quantum, rate) = node.latency.split_once('/')?;
+    let quantum: f64 = quantum.trim().parse().ok()?;
+    let rate: f64 = rate.trim().parse().ok()?;
+    (rate > 0.0).then(|| quantum / rate * 1000.0)
+}
+
+/// Follows `edges` from `node` to every node without outgoing links. Nodes
+/// already on the path are skipped, so feedback loops end the walk.
+fn walk<'a>(
+    node: &'a str,
+    edges: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+    path: &mut Vec<&'a str>,
+    paths: &mut Vec<Vec<&'a str>>,
+) {
+    path.push(node);
+    let next: Vec<&str> = edges
+        .get(node)
+        .into_iter()
+        .flatten()
+        .copied()
+        .filter(|next| !path.contains(next))
+        .collect();
+    if next.is_empty() {
+        paths.push(path.clone());
+    }
+    for next in next {
+        walk(next, edges, path, paths);
+    }
+    path.pop();
+}
+
+/// Latency of every path from an input through the `links`. The looper nodes
+/// are left out, it runs in parallel to the channel strips rather than in
+/// line with them.
+pub fn path_latencies(
+    topology: &Topology,
+    live_state: &LiveState,
+    links: &BTreeSet<Link>,
+) -> Vec<PathLatency> {
+    let mut edges: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for link in links {
+        if topology.looper.is_looper_node(&link.input_node_name) {
+            continue;
+        }
+        edges
+            .entry(&link.output_node_name)
+            .or_default()
+            .insert(&link.input_node_name);
+    }
+    let latencies: BTreeMap<&str, f64> = live_state
+        .nodes
+        .iter()
+        .filter_map(|node| node_latency_ms(node).map(|latency| (node.name.as_str(), latency)))
+        .collect();
+
+    let mut result = Vec::new();
+    for input in &live_state.inputs {
+        let input_ports: Vec<_> = [&input.left_port_path, &input.right_port_path]
+            .into_iter()
+            .flatten()
+            .filter_map(|path| live_state.ports.iter().find(|p| p.path == *path))
+            .collect();
+        // Inputs share the node of their interface, so the walk starts at the
+        // nodes the input's own ports are linked into.
+        let first_hops: BTreeSet<&str> = links
+            .iter()
+            .filter(|link| !topology.looper.is_looper_node(&link.input_node_name))
+            .filter(|link| {
+                input_ports.iter().any(|port| {
+                    port.id == link.output_port_id
+                        && live_state.nodes.iter().any(|n| {
+                            n.object_serial == port.node_id && n.name == link.output_node_name
+                        })
+                })
+            })
+            .map(|link| link.input_node_name.as_str())
+            .collect();
+
+        let mut paths = Vec::new();
+        for first_hop in first_hops {
+            walk(first_hop, &edges, &mut Vec::new(), &mut paths);
+        }
+        for path in paths {
+            let latency_ms = path
+                .iter()
+                .filter_map(|node| latencies.get(node))
+                .sum::<f64>();
+            result.push(PathLatency {
+                input: input.name.clone(),
+                nodes: path.iter().map(|node| String::from(*node)).collect(),
+                latency_ms,
+                compensation_ms: 0.0,
+            });
+        }
+    }
+
+    let mut slowest: BTreeMap<String, f64> = BTreeMap::new();
+    for path in &result {
+        let sink = path.nodes.last().cloned().unwrap_or_default();
+        let latency = slowest.entry(sink).or_default();
+        *latency = latency.max(path.latency_ms);
+    }
+    for path in &mut result {
+        let sink = path.nodes.last().cloned().unwrap_or_default();
+        path.compensation_ms = slowest[&sink] - path.latency_ms;
+    }
+    result
+}
+
+/// Reports the latency of every path through the live links and flags the
+/// ones over the budget.
+pub fn report_latencies(topology: &Topology, live_state: &LiveState, reporter: &Reporter) {
+    reporter.start_stage("Measuring path latency");
+    let paths = path_latencies(
+        topology,
+        live_state,
+        &live_state.managed_links(&topology.looper),
+    );
+    for path in &paths {
+        let over_budget = topology
+            .latency
+            .budget_ms
+            .is_some_and(|budget| path.latency_ms > budget);
+        reporter.path_latency(path, over_budget);
+    }
+}
@@ -0,0 +1,643 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    builder,
+    clients::Clients,
+    looper::{self, LooperBackend},
+    midi,
+    model::{ChannelStrip, Input, Looper, Output, OutputStage},
+    naming::naming,
+    ownership, patch,
+    plan::{self, ConnectionPlan, Link, StripPlugins},
+    pmx::{
+        pipewire::{link::ListLink, node::ListNode, port::ListPort},
+        plugin::PmxPlugin,
+    },
+    protection,
+    report::Reporter,
+    state::Checkpoints,
+    topology::{OutputMode, Topology},
+};
+
+/// Everything the registry and pipewire currently know about the mixer.
+pub struct LiveState {
+    pub inputs: Vec<Input>,
+    pub channel_strips: Vec<ChannelStrip>,
+    pub plugins: Vec<PmxPlugin>,
+    pub loopers: Vec<Looper>,
+    pub output_stages: Vec<OutputStage>,
+    pub outputs: Vec<Output>,
+    pub ports: Vec<ListPort>,
+    pub nodes: Vec<ListNode>,
+    pub links: Vec<ListLink>,
+    /// Extra plugins of each input's chain, known from the state file only
+    pub plugin_chains: BTreeMap<String, Vec<u32>>,
+    /// Loop number of each input, known from the state file only
+    pub loop_numbers: BTreeMap<String, u32>,
+    /// Limiter plugin of each output, known from the state file only
+    pub limiters: BTreeMap<String, u32>,
+    /// Mono sum plugin of each `mono_sum` output, known from the state file
+    /// only
+    pub mono_sums: BTreeMap<String, u32>,
+}
+
+pub async fn read_live_state(
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<LiveState, Box<dyn std::error::Error>> {
+    reporter.log_info("Reading live state from registry and pipewire");
+    Ok(LiveState {
+        inputs: builder::get_inputs(clients, reporter).await?,
+        channel_strips: builder::get_all_channel_strips(clients).await?,
+        plugins: builder::get_plugins(clients).await?,
+        loopers: builder::get_loopers(clients).await?,
+        output_stages: builder::get_output_stages(clients).await?,
+        outputs: builder::get_all_outputs(clients).await?,
+        ports: builder::get_ports(clients).await?,
+        nodes: builder::get_nodes(clients).await?,
+        links: builder::get_links(clients).await?,
+        plugin_chains: BTreeMap::new(),
+        loop_numbers: BTreeMap::new(),
+        limiters: BTreeMap::new(),
+        mono_sums: BTreeMap::new(),
+    })
+}
+
+impl LiveState {
+    /// Adds what only the state file knows: the plugin chains, loop numbers,
+    /// output limiters and mono sums of earlier builds.
+    pub fn with_checkpoints(mut self, checkpoints: &Checkpoints) -> LiveState {
+        self.plugin_chains = checkpoints.plugin_chains().clone();
+        self.loop_numbers = checkpoints.loop_numbers().clone();
+        self.limiters = checkpoints.limiters().clone();
+        self.mono_sums = checkpoints.mono_sums().clone();
+        self
+    }
+
+    /// Channel strip of the input, group, aux bus or cue `name`. If the
+    /// registry holds several, the one with the lowest id wins, so the
+    /// choice doesn't depend on the order the registry lists them in.
+    pub fn find_channel_strip(&self, name: &str) -> Option<&ChannelStrip> {
+        self.channel_strips
+            .iter()
+            .filter(|c| naming().matches(&c.name, name))
+            .min_by_key(|c| c.id)
+    }
+
+    pub fn find_output_stage(&self, name: &str) -> Option<&OutputStage> {
+        self.output_stages
+            .iter()
+            .filter(|o| naming().matches(&o.name, name))
+            .min_by_key(|o| o.id)
+    }
+
+    /// Plugins of the channel strips and output stages carrying the naming
+    /// prefix, including the channel strips of owned output stages and the
+    /// extra plugins of the chains, the output limiters and the mono sums.
+    fn owned_plugin_ids(&self) -> BTreeSet<u32> {
+        let mut owned_strip_ids = BTreeSet::new();
+        let mut plugin_ids = BTreeSet::new();
+        for output_stage in &self.output_stages {
+            if naming().owns(&output_stage.name) {
+                owned_strip_ids.insert(output_stage.left_channel_strip_id);
+                owned_strip_ids.insert(output_stage.right_channel_strip_id);
+                plugin_ids.insert(output_stage.cross_fader_plugin_id);
+            }
+        }
+        for channel_strip in &self.channel_strips {
+            if naming().owns(&channel_strip.name) || owned_strip_ids.contains(&channel_strip.id) {
+                plugin_ids.insert(channel_strip.gain_plugin_id());
+                plugin_ids.insert(channel_strip.saturator_plugin_id());
+                plugin_ids.extend(channel_strip.cross_fader_plugin_id());
+            }
+        }
+        plugin_ids.extend(self.plugin_chains.values().flatten());
+        plugin_ids.extend(self.limiters.values());
+        plugin_ids.extend(self.mono_sums.values());
+        plugin_ids
+    }
+
+    /// Outputs with the topology's layouts applied and their mono sums.
+    fn layout_outputs(&self, topology: &Topology) -> Vec<Output> {
+        let mut outputs = topology.outputs(&self.outputs, &self.nodes, &self.ports);
+        for output in &mut outputs {
+            output.mono_sum_plugin_id = self.mono_sums.get(&output.name).copied();
+        }
+        outputs
+    }
+
+    /// Names of the outputs in `mono_sum` mode.
+    pub fn mono_sum_output_names(&self, topology: &Topology) -> BTreeSet<String> {
+        self.layout_outputs(topology)
+            .into_iter()
+            .filter(|output| output.mode == OutputMode::MonoSum)
+            .map(|output| output.name)
+            .collect()
+    }
+
+    /// Names of the outputs some output stage feeds, each once.
+    pub fn output_stage_output_names(&self, topology: &Topology) -> BTreeSet<String> {
+        let outputs = self.layout_outputs(topology);
+        topology
+            .output_stages
+            .iter()
+            .flat_map(|config| plan::output_stage_outputs(config, &outputs))
+            .map(|output| output.name)
+            .collect()
+    }
+
+    pub fn group_channel_strips(&self, topology: &Topology) -> Vec<ChannelStrip> {
+        topology
+            .groups
+            .iter()
+            .filter_map(|group| self.find_channel_strip(&group.name))
+            .cloned()
+            .collect()
+    }
+
+    pub fn aux_bus_channel_strips(&self, topology: &Topology) -> Vec<ChannelStrip> {
+        topology
+            .aux_buses
+            .iter()
+            .filter_map(|aux_bus| self.find_channel_strip(&aux_bus.name))
+            .cloned()
+            .collect()
+    }
+
+    /// Left and right channel strips of the topology's output stages.
+    pub fn output_stage_channel_strips(&self, topology: &Topology) -> Vec<ChannelStrip> {
+        topology
+            .output_stages
+            .iter()
+            .filter_map(|o| self.find_output_stage(&o.name))
+            .flat_map(|o| [o.left_channel_strip_id, o.right_channel_strip_id])
+            .filter_map(|id| self.channel_strips.iter().find(|c| c.id == id))
+            .cloned()
+            .collect()
+    }
+
+    /// Channel strips owned by an output stage rather than by an input or group.
+    pub fn output_stage_channel_strip_ids(&self) -> BTreeSet<u32> {
+        self.output_stages
+            .iter()
+            .flat_map(|o| [o.left_channel_strip_id, o.right_channel_strip_id])
+            .collect()
+    }
+
+    /// Links of one input's chain: the input into its channel strip, the
+    /// strip into its group and the looper in between.
+    pub fn input_chain_links(
+        &self,
+        topology: &Topology,
+        input: &Input,
+        looper_inputs: &[(u32, &Input)],
+        group_channel_strips: &[ChannelStrip],
+        reporter: &Reporter,
+    ) -> Vec<Link> {
+        let mut links = Vec::new();
+        let channel_strip = self.find_channel_strip(&input.name);
+        let looper = looper_inputs
+            .iter()
+            .find(|(_, i)| i.name == input.name)
+            .and_then(|(loop_number, _)| {
+                self.loopers.iter().find(|l| l.loop_number == *loop_number)
+            });
+        let input_config = topology.input_config(&input.name);
+
+        if let Some(channel_strip) = channel_strip {
+            links.extend(plan::input_strip_links(
+                input,
+                &input_config,
+                channel_strip,
+                &self.plugins,
+                &self.ports,
+                &self.nodes,
+                reporter,
+            ));
+            links.extend(plan::group_links(
+                input,
+                channel_strip,
+                group_channel_strips,
+                &self.plugins,
+                &input_config.group_ports,
+                input_config.group_send,
+                reporter,
+            ));
+            if let Some(plugin_ids) = self.plugin_chains.get(&input.name) {
+                links.extend(plan::plugin_chain_links(
+                    channel_strip,
+                    plugin_ids,
+                    &input_config.plugins,
+                    &self.plugins,
+                    reporter,
+                ));
+            }
+            if let Some(looper) = looper {
+                links.extend(plan::looper_strip_links(
+                    looper,
+                    &topology.looper,
+                    channel_strip,
+                    &self.plugins,
+                    &self.ports,
+                    &self.nodes,
+                    reporter,
+                ));
+            }
+        }
+
+        if let Some(looper) = looper.filter(|_| input_config.looper_direct_monitoring) {
+            links.extend(plan::looper_group_links(
+                input,
+                looper,
+                &topology.looper,
+                group_channel_strips,
+                &self.plugins,
+                &self.ports,
+                &self.nodes,
+                reporter,
+            ));
+        }
+
+        if let Some(looper) = looper {
+            links.extend(plan::looper_input_links(
+                input,
+                &input_config,
+                looper,
+                &topology.looper,
+                &self.ports,
+                &self.nodes,
+                reporter,
+            ));
+        }
+
+        links
+    }
+
+    /// Live links of one input's chain, the ones `input_chain_links` plans
+    /// plus whatever else feeds the strip or its loop.
+    pub fn input_chain_live_links(
+        &self,
+        looper_backend: &LooperBackend,
+        channel_strip: &ChannelStrip,
+        group_channel_strips: &[ChannelStrip],
+        loop_number: Option<u32>,
+    ) -> Vec<(u32, Link)> {
+        let plugin_name = |id: u32| plan::find_plugin(&self.plugins, id).map(|p| p.name.as_str());
+        let strip_input = plugin_name(channel_strip.input_plugin_id());
+        let strip_output = plugin_name(channel_strip.gain_plugin_id);
+        let chain_plugins: BTreeSet<&str> = self
+            .plugin_chains
+            .iter()
+            .filter(|(input, _)| naming().matches(&channel_strip.name, input))
+            .flat_map(|(_, plugin_ids)| plugin_ids)
+            .filter_map(|id| plugin_name(*id))
+            .collect();
+        let group_inputs: BTreeSet<&str> = group_channel_strips
+            .iter()
+            .filter_map(|g| plugin_name(g.saturator_plugin_id))
+            .collect();
+        let looper_node = loop_number.map(|loop_number| looper_backend.loop_node(loop_number));
+        let loop_channels = || {
+            loop_number.into_iter().flat_map(|loop_number| {
+                [0, 1]
+                    .into_iter()
+                    .filter(|channel| looper_backend.has_channel(*channel))
+                    .map(move |channel| (loop_number, channel))
+            })
+        };
+        let record_ports: BTreeSet<u32> = loop_channels()
+            .filter_map(|(loop_number, channel)| {
+                looper_backend.record_port(loop_number, channel, &self.nodes, &self.ports)
+            })
+            .collect();
+        let playback_ports: BTreeSet<u32> = loop_channels()
+            .filter_map(|(loop_number, channel)| {
+                looper_backend.playback_port(loop_number, channel, &self.nodes, &self.ports)
+            })
+            .collect();
+
+        self.managed_live_links(looper_backend)
+            .into_iter()
+            .filter(|(_, link)| {
+                Some(link.input_node_name.as_str()) == strip_input
+                    || chain_plugins.contains(link.input_node_name.as_str())
+                    || chain_plugins.contains(link.output_node_name.as_str())
+                    || (Some(link.output_node_name.as_str()) == strip_output
+                        && group_inputs.contains(link.input_node_name.as_str()))
+                    || (looper_node.as_ref() == Some(&link.output_node_name)
+                        && playback_ports.contains(&link.output_port_id))
+                    || (looper_node.as_ref() == Some(&link.input_node_name)
+                        && record_ports.contains(&link.input_port_id))
+            })
+            .collect()
+    }
+
+    /// Links the builder would create for the strips that already exist.
+    pub fn desired_links(&self, topology: &Topology, reporter: &Reporter) -> BTreeSet<Link> {
+        self.desired_links_by_input(topology, reporter).0
+    }
+
+    /// Desired links ordered for creation, the chains of inputs with a
+    /// higher priority and everything downstream of them first.
+    pub fn connection_plan(&self, topology: &Topology, reporter: &Reporter) -> ConnectionPlan {
+        let (links, input_links) = self.desired_links_by_input(topology, reporter);
+        let mut priorities: BTreeMap<Link, i32> = BTreeMap::new();
+        for (input_name, chain_links) in input_links {
+            let priority = topology.input_config(&input_name).priority;
+            for link in chain_links {
+                let entry = priorities.entry(link).or_insert(priority);
+                *entry = (*entry).max(priority);
+            }
+        }
+        ConnectionPlan::prioritized(links, &priorities)
+    }
+
+    /// Desired links, and the links of each input's own chain among them.
+    /// Links touching protected nodes are left out and reported as skipped.
+    fn desired_links_by_input(
+        &self,
+        topology: &Topology,
+        reporter: &Reporter,
+    ) -> (BTreeSet<Link>, BTreeMap<String, Vec<Link>>) {
+        let mut links = BTreeSet::new();
+        let mut input_links: BTreeMap<String, Vec<Link>> = BTreeMap::new();
+        let group_channel_strips = self.group_channel_strips(topology);
+        let aux_bus_channel_strips = self.aux_bus_channel_strips(topology);
+        let grouped_inputs = plan::grouped_inputs(topology, &self.inputs, reporter);
+        let inputs: Vec<Input> = grouped_inputs
+            .iter()
+            .filter(|i| topology.has_chain(&i.name))
+            .cloned()
+            .collect();
+        let looper_inputs = plan::looper_inputs(topology, &inputs, &self.loop_numbers, reporter);
+        let outputs = self.layout_outputs(topology);
+
+        for input in &inputs {
+            input_links.insert(
+                input.name.clone(),
+                self.input_chain_links(
+                    topology,
+                    input,
+                    &looper_inputs,
+                    &group_channel_strips,
+                    reporter,
+                ),
+            );
+        }
+
+        for input in grouped_inputs
+            .iter()
+            .filter(|i| topology.is_passthrough(&i.name))
+        {
+            input_links.insert(
+                input.name.clone(),
+                plan::passthrough_links(
+                    input,
+                    &topology.input_config(&input.name),
+                    &group_channel_strips,
+                    &outputs,
+                    &self.plugins,
+                    &self.ports,
+                    &self.nodes,
+                    reporter,
+                ),
+            );
+        }
+        for chain_links in input_links.values() {
+            links.extend(chain_links.iter().cloned());
+        }
+
+        if let Some(record) = &topology.record {
+            links.extend(plan::record_links(
+                record,
+                &plan::record_channels(topology, &grouped_inputs),
+                topology,
+                &grouped_inputs,
+                &self.ports,
+                &self.nodes,
+                reporter,
+            ));
+        }
+
+        for group in &topology.groups {
+            if let Some(group_channel_strip) = self.find_channel_strip(&group.name) {
+                links.extend(plan::parent_group_links(
+                    group,
+                    group_channel_strip,
+                    &group_channel_strips,
+                    &self.plugins,
+                    reporter,
+                ));
+                links.extend(plan::group_destination_links(
+                    group,
+                    group_channel_strip,
+                    &outputs,
+                    &self.ports,
+                    &self.nodes,
+                    &self.plugins,
+                    reporter,
+                ));
+            }
+        }
+
+        for aux_bus in &topology.aux_buses {
+            if let Some(aux_bus_channel_strip) = self.find_channel_strip(&aux_bus.name) {
+                links.extend(plan::aux_bus_links(
+                    aux_bus,
+                    aux_bus_channel_strip,
+                    &self.channel_strips,
+                    &group_channel_strips,
+                    &self.plugins,
+                    reporter,
+                ));
+            }
+        }
+
+        for insert in &topology.inserts {
+            if let Some(channel_strip) = self.find_channel_strip(&insert.channel_strip) {
+                links.extend(plan::insert_links(
+                    insert,
+                    channel_strip,
+                    &outputs,
+                    &grouped_inputs,
+                    &self.ports,
+                    &self.nodes,
+                    &self.plugins,
+                    reporter,
+                ));
+            }
+        }
+
+        for sidechain in &topology.sidechains {
+            links.extend(plan::sidechain_links(
+                sidechain,
+                &self.channel_strips,
+                &self.plugins,
+                reporter,
+            ));
+        }
+
+        if let Some(cue) = &topology.cue {
+            if let Some(cue_channel_strip) = self.find_channel_strip(&cue.name) {
+                links.extend(plan::cue_tap_links(
+                    cue,
+                    cue_channel_strip,
+                    &inputs,
+                    &self.channel_strips,
+                    &self.plugins,
+                    reporter,
+                ));
+                links.extend(plan::strip_output_links(
+                    cue_channel_strip,
+                    &cue.output,
+                    &outputs,
+                    &self.ports,
+                    &self.nodes,
+                    &self.plugins,
+                    reporter,
+                ));
+            }
+        }
+
+        if let Some(talkback) = &topology.talkback {
+            match grouped_inputs.iter().find(|i| i.name == talkback.input) {
+                Some(input) => links.extend(plan::talkback_links(
+                    talkback,
+                    input,
+                    &topology.input_config(&input.name),
+                    topology
+                        .cue
+                        .as_ref()
+                        .and_then(|cue| self.find_channel_strip(&cue.name)),
+                    self.plugin_chains
+                        .get(&talkback.input)
+                        .and_then(|ids| ids.first().copied()),
+                    &self.output_stage_channel_strips(topology),
+                    &self.plugins,
+                    &self.ports,
+                    &self.nodes,
+                    reporter,
+                )),
+                None => reporter.skipped(&format!(
+                    "Talkback input {} isn't registered",
+                    talkback.input
+                )),
+            }
+        }
+
+        for output_stage_config in &topology.output_stages {
+            let Some(output_stage) = self.find_output_stage(&output_stage_config.name) else {
+                continue;
+            };
+            let sources = plan::output_stage_sources(
+                topology,
+                output_stage_config,
+                &group_channel_strips,
+                &aux_bus_channel_strips,
+            );
+            links.extend(plan::output_stage_links(
+                topology,
+                &sources,
+                output_stage,
+                &self.plugins,
+                &self.channel_strips,
+                &output_stage_config.source_ports,
+                reporter,
+            ));
+            links.extend(plan::output_links(
+                output_stage,
+                &plan::output_stage_outputs(output_stage_config, &outputs),
+                topology.limiter.as_ref().map(|_| &self.limiters),
+                &self.ports,
+                &self.nodes,
+                &self.plugins,
+                reporter,
+            ));
+        }
+
+        let mut links: BTreeSet<Link> = plan::resolve_plugin_ports(
+            links.into_iter().collect(),
+            &self.plugins,
+            &self.ports,
+            &self.nodes,
+        )
+        .into_iter()
+        .collect();
+        links.extend(patch::patch_links(
+            &topology.patches,
+            &self.ports,
+            &self.nodes,
+            reporter,
+        ));
+        links.extend(midi::midi_links(
+            &topology.midi,
+            &topology.looper,
+            &self.channel_strips,
+            &self.plugins,
+            &self.nodes,
+            &self.ports,
+            reporter,
+        ));
+        links.extend(looper::sync_links(
+            &topology.looper,
+            &self.nodes,
+            &self.ports,
+            reporter,
+        ));
+        // Resolved the same way as the links themselves, so they match up
+        for chain_links in input_links.values_mut() {
+            *chain_links = plan::resolve_plugin_ports(
+                std::mem::take(chain_links),
+                &self.plugins,
+                &self.ports,
+                &self.nodes,
+            );
+            chain_links.retain(|link| {
+                protection::protected_node(link, &topology.protected_nodes).is_none()
+            });
+        }
+        protection::retain_allowed(&mut links, &topology.protected_nodes, reporter);
+        (links, input_links)
+    }
+
+    /// Live links touching at least one node the builder manages.
+    pub fn managed_links(&self, looper_backend: &LooperBackend) -> BTreeSet<Link> {
+        self.managed_live_links(looper_backend)
+            .into_iter()
+            .map(|(_, link)| link)
+            .collect()
+    }
+
+    /// Whether the pipewire link `id` carries the builder's ownership tag.
+    pub fn is_builder_link(&self, id: u32) -> bool {
+        self.links
+            .iter()
+            .any(|l| l.id == id && ownership::is_builder_link(l))
+    }
+
+    /// Managed links together with their pipewire link id.
+    pub fn managed_live_links(&self, looper_backend: &LooperBackend) -> Vec<(u32, Link)> {
+        let owned_plugin_ids = self.owned_plugin_ids();
+        let looper_nodes = looper_backend.nodes();
+        let managed_nodes: BTreeSet<&str> = self
+            .plugins
+            .iter()
+            .filter(|p| owned_plugin_ids.contains(&p.id))
+            .map(|p| p.name.as_str())
+            .chain(looper_nodes.iter().map(|n| n.as_str()))
+            .collect();
+
+        self.links
+            .iter()
+            .filter_map(|live_link| {
+                plan::resolve_live_link(live_link, &self.nodes).map(|link| (live_link.id, link))
+            })
+            .filter(|(id, link)| {
+                managed_nodes.contains(link.output_node_name.as_str())
+                    || managed_nodes.contains(link.input_node_name.as_str())
+                    // Passthrough inputs link to outputs past every managed node
+                    || self.is_builder_link(*id)
+            })
+            .collect()
+    }
+}
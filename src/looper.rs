@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+
+use crate::plan::Link;
+use crate::pmx::pipewire::{node::ListNode, port::ListPort};
+use crate::report::Reporter;
+
+/// Looper application the input channels are recorded into and played back
+/// from. Defaults to the sooperlooper setup the builder has always wired.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LooperBackend {
+    /// Register and wire loopers at all
+    pub enabled: bool,
+    /// Pipewire node name of the looper
+    pub node: String,
+    /// Audio channels per loop, 1 for mono loopers
+    pub channels: u32,
+    /// Number of ports in front of the first loop's ports, only counted
+    /// with the `per_loop` scheme
+    pub first_port: u32,
+    pub port_scheme: LooperPortScheme,
+    /// Most loops one looper instance supports, inputs beyond the capacity
+    /// of all instances get no looper
+    pub max_loops: Option<u32>,
+    /// Looper nodes the loops are spread over, `max_loops` loops each. The
+    /// instances after the first are named `{node}-2`, `{node}-3` and so on.
+    pub instances: u32,
+    /// Clock or beat sources wired into the sync input of the instances
+    pub sync: Vec<LooperSync>,
+}
+
+/// Tempo source of one looper instance, or of all of them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LooperSync {
+    /// Instance the source drives, counted from 1, every instance if not set
+    pub instance: Option<u32>,
+    /// Node name of the master clock or beat source
+    pub source: String,
+    /// Name of the source port, its first output if not set
+    pub source_port: Option<String>,
+    /// Name of the looper's sync input port
+    pub port: String,
+}
+
+/// How the ports of a loop are found.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LooperPortScheme {
+    /// Sooperlooper's port names, `loop0_in_1` records the first channel of
+    /// loop 0 and `loop0_out_1` plays it back
+    #[default]
+    Sooperlooper,
+    /// Recording and playback ports both advance by the channel count per loop
+    PerLoop,
+}
+
+impl Default for LooperBackend {
+    fn default() -> Self {
+        LooperBackend {
+            enabled: true,
+            node: String::from("sooperlooper"),
+            channels: 2,
+            first_port: 2,
+            port_scheme: LooperPortScheme::default(),
+            max_loops: None,
+            instances: 1,
+            sync: Vec::new(),
+        }
+    }
+}
+
+impl LooperBackend {
+    pub fn has_channel(&self, channel: u32) -> bool {
+        channel < self.channels
+    }
+
+    /// Loops all instances together support, unlimited without `max_loops`.
+    pub fn capacity(&self) -> Option<u32> {
+        self.max_loops
+            .map(|max_loops| max_loops * self.instances.max(1))
+    }
+
+    /// Node name of the instance running `loop_number`.
+    pub fn loop_node(&self, loop_number: u32) -> String {
+        let instance = self
+            .max_loops
+            .filter(|max_loops| *max_loops > 0)
+            .map_or(0, |max_loops| loop_number / max_loops);
+        self.instance_node(instance)
+    }
+
+    /// Node names of every instance.
+    pub fn nodes(&self) -> Vec<String> {
+        (0..self.instances.max(1))
+            .map(|instance| self.instance_node(instance))
+            .collect()
+    }
+
+    pub fn is_looper_node(&self, name: &str) -> bool {
+        self.nodes().iter().any(|node| node == name)
+    }
+
+    fn instance_node(&self, instance: u32) -> String {
+        match instance {
+            0 => self.node.clone(),
+            _ => format!("{}-{}", self.node, instance + 1),
+        }
+    }
+
+    /// Number of `loop_number` within its instance.
+    fn instance_loop(&self, loop_number: u32) -> u32 {
+        self.max_loops
+            .filter(|max_loops| *max_loops > 0)
+            .map_or(loop_number, |max_loops| loop_number % max_loops)
+    }
+
+    /// Port of `loop_number` on its instance the input channel is recorded
+    /// through.
+    pub fn record_port(
+        &self,
+        loop_number: u32,
+        channel: u32,
+        nodes: &[ListNode],
+        ports: &[ListPort],
+    ) -> Option<u32> {
+        self.loop_port(loop_number, "in", channel, nodes, ports)
+    }
+
+    /// Port of `loop_number` on its instance wired to the channel strip cross
+    /// fader.
+    pub fn playback_port(
+        &self,
+        loop_number: u32,
+        channel: u32,
+        nodes: &[ListNode],
+        ports: &[ListPort],
+    ) -> Option<u32> {
+        self.loop_port(loop_number, "out", channel, nodes, ports)
+    }
+
+    /// Pipewire id of the `direction` port of a loop's channel. Sooperlooper
+    /// ports are looked up by name, none if the instance doesn't have it.
+    fn loop_port(
+        &self,
+        loop_number: u32,
+        direction: &str,
+        channel: u32,
+        nodes: &[ListNode],
+        ports: &[ListPort],
+    ) -> Option<u32> {
+        let instance_loop = self.instance_loop(loop_number);
+        match self.port_scheme {
+            LooperPortScheme::Sooperlooper => {
+                let node_name = self.loop_node(loop_number);
+                let node = nodes.iter().find(|n| n.name == node_name)?;
+                let port_name = sooperlooper_port_name(instance_loop, direction, channel);
+                ports
+                    .iter()
+                    .find(|p| {
+                        p.node_id == node.object_serial
+                            && p.direction == direction
+                            && p.name == port_name
+                    })
+                    .map(|p| p.id)
+            }
+            LooperPortScheme::PerLoop => {
+                Some(self.first_port + self.channels * instance_loop + channel)
+            }
+        }
+    }
+}
+
+/// Name sooperlooper gives the `direction` port of a loop's channel, its
+/// channels count from 1.
+pub fn sooperlooper_port_name(loop_number: u32, direction: &str, channel: u32) -> String {
+    format!("loop{loop_number}_{direction}_{}", channel + 1)
+}
+
+/// Output port `name` of `node`, its first output by port id if not set.
+fn source_port<'a>(
+    node: &ListNode,
+    name: Option<&str>,
+    ports: &'a [ListPort],
+) -> Option<&'a ListPort> {
+    ports
+        .iter()
+        .filter(|p| p.node_id == node.object_serial && p.direction == "out")
+        .filter(|p| name.map_or(true, |name| p.name == name))
+        .min_by_key(|p| p.id)
+}
+
+/// Links from each sync source into the sync input of its looper instances.
+/// Port ids are pipewire's, like those of MIDI links.
+pub fn sync_links(
+    looper_backend: &LooperBackend,
+    nodes: &[ListNode],
+    ports: &[ListPort],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    if !looper_backend.enabled {
+        return Vec::new();
+    }
+    let mut links = Vec::new();
+    for sync in &looper_backend.sync {
+        let Some(source) = nodes.iter().find(|n| n.name == sync.source) else {
+            reporter.skipped(&format!("No looper sync source {}", sync.source));
+            continue;
+        };
+        let Some(source_port) = source_port(source, sync.source_port.as_deref(), ports) else {
+            reporter.skipped(&format!(
+                "Sync source {} has no matching output",
+                sync.source
+            ));
+            continue;
+        };
+        let instances: Vec<String> = match sync.instance {
+            Some(instance) => vec![looper_backend.instance_node(instance.saturating_sub(1))],
+            None => looper_backend.nodes(),
+        };
+        for instance in instances {
+            let sync_port = nodes.iter().find(|n| n.name == instance).and_then(|node| {
+                ports.iter().find(|p| {
+                    p.node_id == node.object_serial && p.direction == "in" && p.name == sync.port
+                })
+            });
+            let Some(sync_port) = sync_port else {
+                reporter.skipped(&format!(
+                    "Looper {instance} has no sync input {}, not wiring {}",
+                    sync.port, sync.source
+                ));
+                continue;
+            };
+            links.push(Link::new(
+                &source.name,
+                source_port.id,
+                &instance,
+                sync_port.id,
+            ));
+        }
+    }
+    links
+}
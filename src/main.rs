@@ -1,8 +1,69 @@
+mod audit;
+mod backup;
+mod budget;
 mod builder;
+mod calibration;
+mod cassette;
+mod chaos;
+mod cli;
+mod clients;
+mod config;
+mod confirm;
+mod connection;
+mod connector;
+mod daemon;
+mod devices;
+mod diff;
+mod discovery;
+mod doctor;
+mod gc;
+mod graph;
+mod history;
+mod hooks;
+mod latency;
+mod live;
+mod looper;
+mod metrics;
+mod midi;
+mod model;
+mod naming;
+mod ownership;
+mod patch;
+mod plan;
+mod port_map;
+mod progress;
+mod protection;
+mod reload;
+mod report;
+mod rpc;
+mod safe_mode;
+mod script;
+mod server;
+mod shutdown;
+mod snapshot;
+mod state;
+mod status;
+mod telemetry;
+mod templates;
+#[cfg(test)]
+mod testing;
+mod timings;
+mod topology;
+mod tui;
+mod validate;
+mod venue;
+
+use std::io::IsTerminal;
+
+use clap::Parser;
 
 pub mod pmx {
     tonic::include_proto!("pmx");
 
+    pub mod builder {
+        tonic::include_proto!("pmx.builder");
+    }
+
     pub mod mod_host {
         tonic::include_proto!("pmx.mod_host");
 
@@ -74,115 +135,1222 @@ pub mod pmx {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = cli::Cli::parse();
+
     fr_logging::setup_logging();
     let (logger_sender, logger_receiver) = tokio::sync::mpsc::unbounded_channel();
-    let logger_factory = fr_logging::LoggerFactory::new(logger_sender);
+    let logger_factory = std::sync::Arc::new(fr_logging::LoggerFactory::new(logger_sender));
 
-    let logger = logger_factory.new_logger(String::from("fr_pmx_builder"));
-
-    tokio::join!(
-        build_pmx(logger),
+    let cassette = match (&cli.record, &cli.replay) {
+        (_, Some(path)) => {
+            let cassette = cassette::Cassette::read(path)?;
+            if let Some(run_id) = cassette.run_id() {
+                // Links are tagged with the run id, the requests have to match
+                ownership::set_run_id(String::from(run_id));
+            }
+            Some(std::sync::Arc::new(cassette))
+        }
+        (Some(_), None) => Some(std::sync::Arc::new(cassette::Cassette::recording())),
+        (None, None) => None,
+    };
+    let record = cli.record.clone();
+    let result = tokio::join!(
+        cassette::scope(cassette.clone(), run(cli, logger_factory)),
         fr_logging::run_logging_task(logger_receiver)
     )
-    .0
+    .0;
+    telemetry::finish_run();
+    if let Some(cassette) = &cassette {
+        match &record {
+            Some(path) => cassette.write(path)?,
+            None => {
+                let unused = cassette.unused();
+                if !unused.is_empty() {
+                    eprintln!(
+                        "{} recorded calls weren't made during the replay",
+                        unused.len()
+                    );
+                }
+            }
+        }
+    }
+    result
 }
 
-async fn build_pmx(logger: fr_logging::Logger) -> Result<(), Box<dyn std::error::Error>> {
-    let service_urls = fr_pmx_config_lib::read_service_urls();
-    let registry_client =
-        pmx::pmx_registry_client::PmxRegistryClient::connect(service_urls.pmx_registry_url).await?;
-    let factory_client =
-        pmx::factory::pmx_factory_client::PmxFactoryClient::connect(service_urls.pmx_factory_url)
-            .await?;
-    let pipewire_client =
-        pmx::pipewire::pipewire_client::PipewireClient::connect(service_urls.pipewire_registry_url)
-            .await?;
+async fn run(
+    cli: cli::Cli,
+    logger_factory: std::sync::Arc<fr_logging::LoggerFactory>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // None of these needs the topology or the services
+    match cli.command {
+        cli::Command::Completions { shell } => return cli::print_completions(shell),
+        cli::Command::Manpage => return cli::print_manpage(),
+        cli::Command::History { command } => {
+            return match command {
+                None => history::print_history(&cli.history_dir),
+                Some(cli::HistoryCommand::Show { run_id }) => {
+                    history::print_run(&cli.history_dir, &run_id)
+                }
+            }
+        }
+        _ => {}
+    }
+    let logger = logger_factory.new_logger(String::from("fr_pmx_builder"));
+    let topology = topology::load_topology(
+        cli.topology.as_deref(),
+        cli.overlay.as_deref(),
+        cli.venue.as_deref(),
+        cli.profile.as_deref(),
+    )?;
+    rpc::set_timeouts(topology.timeouts);
+    rpc::set_reconnect(topology.reconnect);
+    let build_history = history::History {
+        directory: cli.history_dir.clone(),
+        keep: cli.history_keep,
+        topology: cli.topology.clone(),
+        overlay: cli.overlay.clone(),
+        venue: cli.venue.clone(),
+        profile: cli.profile.clone(),
+    };
+    if cli.timings.is_some() {
+        timings::enable();
+    }
+    if cli.restore_on_failure {
+        backup::enable_restore_on_failure();
+    }
+    if let Some(endpoint) = &cli.otlp_endpoint {
+        telemetry::export_otlp(endpoint)?;
+    }
+    if let Some(failure_percent) = cli.chaos {
+        chaos::enable(failure_percent, cli.chaos_delay)?;
+    }
+    // gc asks unless told not to, the other commands only when interactive
+    if !cli.yes && (cli.interactive || matches!(cli.command, cli::Command::Gc)) {
+        confirm::enable();
+    }
+    naming::set_naming(topology.naming.clone());
+    let metrics = std::sync::Arc::new(metrics::Metrics::new()?);
+    if let Some(address) = cli.metrics_address {
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        tokio::spawn(metrics::serve_metrics(listener, metrics.clone()));
+    }
 
-    let input_channels = builder::get_inputs(registry_client.clone(), &logger).await?;
-    let channel_strips =
-        builder::build_channel_strips(&input_channels, factory_client.clone(), &logger).await?;
+    let command = cli.command.clone();
+    telemetry::start_run(&format!("{command:?}"));
+    let mut reporter = report::Reporter::new(logger, metrics.clone());
+    if cli.quiet {
+        reporter = reporter.quiet();
+    } else if matches!(
+        command,
+        cli::Command::Build
+            | cli::Command::Teardown
+            | cli::Command::RebuildInput { .. }
+            | cli::Command::Restore { .. }
+    ) && std::io::stderr().is_terminal()
+        && !confirm::is_enabled()
+    {
+        reporter = reporter.with_progress_display();
+    }
+    reporter = reporter.with_failure_policy(if cli.strict {
+        report::FailurePolicy::Strict
+    } else {
+        cli.on_failure
+    });
+    if let Some(profile) = &cli.profile {
+        reporter.profile_applied(profile);
+    }
+    let _lock = command
+        .changes_graph()
+        .then(|| state::StateLock::acquire(&cli.state_file))
+        .transpose()?;
+    let result = match command {
+        cli::Command::Build => {
+            // Only a build ramps the outputs up again
+            if cli.safe {
+                safe_mode::enable();
+            }
+            let mut checkpoints = state::Checkpoints::new(&cli.state_file, cli.resume)?
+                .with_selection(state::StageSelection {
+                    only: cli.only.clone(),
+                    skip: cli.skip.clone(),
+                });
+            shutdown::listen()?;
+            let started = std::time::Instant::now();
+            let result = timed_build(&topology, &mut checkpoints, &reporter, &metrics).await;
+            if let Some(path) = &cli.report_json {
+                reporter.write_json(path)?;
+            }
+            build_history.record(ownership::run_id(), &topology, started.elapsed(), &reporter);
+            result
+        }
+        cli::Command::Plan => {
+            let checkpoints = state::Checkpoints::new(&cli.state_file, true)?;
+            plan_pmx(&topology, &checkpoints, &reporter).await
+        }
+        cli::Command::Diff => diff_pmx(&topology, &reporter).await,
+        cli::Command::Gc => {
+            let mut checkpoints = state::Checkpoints::new(&cli.state_file, true)?;
+            gc_pmx(&topology, &mut checkpoints, &reporter).await
+        }
+        cli::Command::Teardown => {
+            let mut checkpoints = state::Checkpoints::new(&cli.state_file, true)?;
+            let result = teardown_pmx(&topology, &mut checkpoints, &reporter).await;
+            reporter.finish(result.as_ref().err().map(|e| e.as_ref()));
+            if let Some(path) = &cli.report_json {
+                reporter.write_json(path)?;
+            }
+            result
+        }
+        cli::Command::Verify => {
+            let verification = verify_pmx(&topology, &reporter).await?;
+            diff::print_verification(&verification);
+            if verification.passed() {
+                Ok(())
+            } else {
+                Err("The mixer doesn't match the topology".into())
+            }
+        }
+        cli::Command::Doctor => {
+            let checks = doctor::run_checks(&topology, &reporter).await;
+            doctor::print_checks(&checks);
+            if doctor::passed(&checks) {
+                Ok(())
+            } else {
+                Err("Doctor checks failed".into())
+            }
+        }
+        cli::Command::RebuildInput { name } => {
+            let mut checkpoints = state::Checkpoints::new(&cli.state_file, true)?;
+            let result = rebuild_input_pmx(&topology, &name, &mut checkpoints, &reporter)
+                .await
+                .and_then(|_| reporter.check_failures());
+            reporter.finish(result.as_ref().err().map(|e| e.as_ref()));
+            if let Some(path) = &cli.report_json {
+                reporter.write_json(path)?;
+            }
+            result
+        }
+        cli::Command::Watch { interval } => {
+            let mut checkpoints = state::Checkpoints::new(&cli.state_file, true)?;
+            let watch = reload::TopologyWatch::new(
+                cli.topology.as_deref(),
+                cli.overlay.as_deref(),
+                cli.venue.as_deref(),
+                cli.profile.as_deref(),
+            )?;
+            watch_pmx(
+                &topology,
+                std::time::Duration::from_secs(interval),
+                watch,
+                &mut checkpoints,
+                &reporter,
+            )
+            .await
+        }
+        cli::Command::Daemon {
+            interval,
+            jitter,
+            status_address,
+            audit_log,
+        } => {
+            let mut checkpoints = state::Checkpoints::new(&cli.state_file, true)?;
+            let watch = reload::TopologyWatch::new(
+                cli.topology.as_deref(),
+                cli.overlay.as_deref(),
+                cli.venue.as_deref(),
+                cli.profile.as_deref(),
+            )?;
+            let status = std::sync::Arc::new(status::StatusBoard::default());
+            if let Some(address) = status_address {
+                let listener = tokio::net::TcpListener::bind(address).await?;
+                tokio::spawn(status::serve_status(listener, status.clone()));
+            }
+            let audit_log = audit_log
+                .as_deref()
+                .map(audit::AuditLog::open)
+                .transpose()?;
+            daemon_pmx(
+                &topology,
+                interval,
+                jitter,
+                watch,
+                &status,
+                audit_log,
+                &build_history,
+                &mut checkpoints,
+                &logger_factory,
+                &reporter,
+            )
+            .await
+        }
+        cli::Command::Graph { format, output } => {
+            let graph = graph_pmx(&topology, &reporter).await?;
+            let rendered = graph::render(&graph, format)?;
+            match output {
+                Some(path) => std::fs::write(path, rendered)?,
+                None => print!("{rendered}"),
+            }
+            Ok(())
+        }
+        cli::Command::ExportScript { format, output } => {
+            let checkpoints = state::Checkpoints::new(&cli.state_file, true)?;
+            let rendered = export_script_pmx(&topology, &checkpoints, format, &reporter).await?;
+            match output {
+                Some(path) => std::fs::write(path, rendered)?,
+                None => print!("{rendered}"),
+            }
+            Ok(())
+        }
+        cli::Command::Snapshot { path } => {
+            let snapshot = snapshot_pmx(&topology, &reporter).await?;
+            snapshot::write_snapshot(&snapshot, &path)?;
+            reporter.log_info(&format!(
+                "Wrote snapshot with {} links to {}",
+                snapshot.links.len(),
+                path.display()
+            ));
+            Ok(())
+        }
+        cli::Command::Restore { path } => {
+            let snapshot = snapshot::read_snapshot(&path)?;
+            let result = restore_pmx(&topology, &snapshot, &reporter)
+                .await
+                .and_then(|_| reporter.check_failures());
+            reporter.finish(result.as_ref().err().map(|e| e.as_ref()));
+            if let Some(path) = &cli.report_json {
+                reporter.write_json(path)?;
+            }
+            result
+        }
+        cli::Command::ExportPatches { output } => {
+            let patches = export_patches_pmx(&topology, &reporter).await?;
+            let rendered = patch::render_patch_list(&patches);
+            match output {
+                Some(path) => std::fs::write(path, rendered)?,
+                None => print!("{rendered}"),
+            }
+            Ok(())
+        }
+        cli::Command::ApplyPatches { path } => {
+            let patches = patch::read_patch_list(&path)?;
+            let mut checkpoints = state::Checkpoints::new(&cli.state_file, true)?;
+            let result = apply_patches_pmx(&topology, &patches, &mut checkpoints, &reporter)
+                .await
+                .and_then(|_| reporter.check_failures());
+            reporter.finish(result.as_ref().err().map(|e| e.as_ref()));
+            result
+        }
+        cli::Command::Tui => {
+            let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+            let reporter = report::Reporter::new(
+                logger_factory.new_logger(String::from("fr_pmx_builder::tui")),
+                metrics,
+            )
+            .silent()
+            .with_progress(sender);
+            let mut checkpoints = state::Checkpoints::new(&cli.state_file, true)?;
+            tui_pmx(&topology, &mut checkpoints, &reporter, receiver).await
+        }
+        cli::Command::Completions { .. } | cli::Command::Manpage => Ok(()),
+        cli::Command::Config {
+            command: cli::ConfigCommand::Dump,
+        } => config::dump(
+            &topology,
+            cli.topology.as_deref(),
+            cli.overlay.as_deref(),
+            cli.venue.as_deref(),
+            cli.profile.as_deref(),
+        ),
+        cli::Command::Serve { address } => {
+            reporter.log_info(&format!("Serving pmx.builder on {address}"));
+            let service = server::BuilderService::new(
+                topology,
+                cli.state_file.clone(),
+                build_history,
+                logger_factory,
+                metrics,
+            );
+            server::serve(address, service).await
+        }
+    };
+    if let Some(path) = &cli.timings {
+        timings::write_report(path, 10)?;
+    }
+    result
+}
+
+/// Runs a build and records its duration and outcome in the metrics and report.
+async fn timed_build(
+    topology: &topology::Topology,
+    checkpoints: &mut state::Checkpoints,
+    reporter: &report::Reporter,
+    metrics: &metrics::Metrics,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let started = std::time::Instant::now();
+    let mut result = build_pmx(topology, checkpoints, reporter)
+        .await
+        .and_then(|_| reporter.check_failures());
+    let cancelled = shutdown::is_requested();
+    if cancelled {
+        // The stages record as they go, the links are added in case the
+        // build stopped between creating and recording them
+        checkpoints.record_links(reporter.created_links())?;
+        if let Err(error) = result {
+            result = Err(format!("Build cancelled: {error}").into());
+        }
+    }
+    metrics.build_finished(started.elapsed(), result.is_ok());
+    reporter.finish(result.as_ref().err().map(|e| e.as_ref()));
+    if cancelled {
+        reporter.print_created();
+    }
+    result
+}
 
-    let plugins = builder::get_plugins(registry_client.clone()).await?;
-    let ports = builder::get_ports(pipewire_client.clone()).await?;
-    let nodes = builder::get_nodes(pipewire_client.clone()).await?;
+/// Deletes what `gc::find_garbage` finds and the user approves. Links the
+/// state file holds to the deleted nodes are forgotten with them.
+async fn gc_pmx(
+    topology: &topology::Topology,
+    checkpoints: &mut state::Checkpoints,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
 
-    builder::connect_inputs_to_channel_strips(
-        &input_channels,
-        &channel_strips,
-        &plugins,
-        &ports,
-        &nodes,
-        pipewire_client.clone(),
-        &logger,
+    let live_state = live::read_live_state(&clients, reporter)
+        .await?
+        .with_checkpoints(checkpoints);
+    let garbage = gc::find_garbage(topology, &live_state);
+    if garbage.is_empty() {
+        reporter.log_info("No garbage to collect");
+        return Ok(());
+    }
+    gc::print_garbage(&garbage);
+    let garbage = gc::approved(garbage)?;
+    if garbage.is_empty() {
+        reporter.log_info("Nothing deleted");
+        return Ok(());
+    }
+
+    let (collected, result) = gc::collect(&garbage, &clients, reporter).await;
+    forget_collected(&collected, &live_state, checkpoints)?;
+    result
+}
+
+/// Forgets the plugin chains, limiters and mono sums gc deleted in the
+/// state file, and the links of their nodes.
+fn forget_collected(
+    collected: &gc::Garbage,
+    live_state: &live::LiveState,
+    checkpoints: &mut state::Checkpoints,
+) -> Result<(), Box<dyn std::error::Error>> {
+    checkpoints.forget_plugin_chains(
+        collected
+            .plugin_chains
+            .iter()
+            .map(|(input_name, _)| input_name),
+    )?;
+    checkpoints.forget_limiters(
+        collected
+            .limiters
+            .iter()
+            .map(|(output_name, _)| output_name),
+    )?;
+    checkpoints.forget_mono_sums(
+        collected
+            .mono_sums
+            .iter()
+            .map(|(output_name, _)| output_name),
+    )?;
+    let node_names = collected.node_names(live_state);
+    let removed: Vec<plan::Link> = checkpoints
+        .owned_links()
+        .iter()
+        .filter(|link| {
+            node_names.contains(&link.output_node_name)
+                || node_names.contains(&link.input_node_name)
+        })
+        .cloned()
+        .collect();
+    checkpoints.forget_links(&removed)
+}
+
+/// Removes the managed links a build created, found by their ownership tag
+/// or in the state file. Links patched by hand stay.
+async fn teardown_pmx(
+    topology: &topology::Topology,
+    checkpoints: &mut state::Checkpoints,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    let live_state = live::read_live_state(&clients, reporter).await?;
+    reporter.start_stage("Removing managed links");
+    let builder_links: Vec<(u32, plan::Link)> = live_state
+        .managed_live_links(&topology.looper)
+        .into_iter()
+        .filter(|(id, link)| {
+            live_state.is_builder_link(*id) || checkpoints.owned_links().contains(link)
+        })
+        .collect();
+    let builder_links = confirm::approved(builder_links, |(_, link)| format!("link {link}"))?;
+    builder::delete_links(
+        &builder_links,
+        &topology.protected_nodes,
+        &clients,
+        reporter,
     )
     .await?;
+    let removed: Vec<plan::Link> = builder_links.into_iter().map(|(_, link)| link).collect();
+    checkpoints.forget_links(&removed)
+}
 
-    let loopers =
-        builder::register_loopers_for_input_channels(&input_channels, registry_client.clone())
-            .await;
+async fn verify_pmx(
+    topology: &topology::Topology,
+    reporter: &report::Reporter,
+) -> Result<diff::Verification, Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
 
-    builder::connect_loopers_to_inputs(
-        &input_channels,
-        &loopers,
-        &nodes,
-        &ports,
-        pipewire_client.clone(),
-        &logger,
-    )
-    .await;
+    let live_state = live::read_live_state(&clients, reporter).await?;
+    Ok(diff::verify(topology, &live_state, reporter))
+}
 
-    builder::connect_loopers_to_channel_strips(
-        &loopers,
-        &channel_strips,
-        &plugins,
-        pipewire_client.clone(),
-        &logger,
+async fn plan_pmx(
+    topology: &topology::Topology,
+    checkpoints: &state::Checkpoints,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    let live_state = live::read_live_state(&clients, reporter)
+        .await?
+        .with_checkpoints(checkpoints);
+    let connection_plan = live_state.connection_plan(topology, reporter);
+    let existing_links = plan::resolve_live_links(&live_state.links, &live_state.nodes)
+        .into_iter()
+        .collect();
+    diff::print_plan(&connection_plan, &existing_links);
+
+    Ok(())
+}
+
+async fn diff_pmx(
+    topology: &topology::Topology,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    let live_state = live::read_live_state(&clients, reporter).await?;
+    diff::print_diff(topology, &live_state, reporter);
+
+    Ok(())
+}
+
+/// Rewires the chain of one input, leaving the rest of the graph alone. The
+/// channel strip and looper are only created when they are missing, existing
+/// ones are kept and rewired.
+async fn rebuild_input_pmx(
+    topology: &topology::Topology,
+    name: &str,
+    checkpoints: &mut state::Checkpoints,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    rebuild_input_with_clients(topology, name, checkpoints, &clients, reporter).await
+}
+
+async fn tui_pmx(
+    topology: &topology::Topology,
+    checkpoints: &mut state::Checkpoints,
+    reporter: &report::Reporter,
+    progress: tokio::sync::mpsc::UnboundedReceiver<report::Progress>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    tui::run(topology, checkpoints, &clients, reporter, progress).await
+}
+
+/// Polls the registry and builds the chain of every input registered after
+/// the watch started.
+async fn watch_pmx(
+    topology: &topology::Topology,
+    interval: std::time::Duration,
+    mut watch: reload::TopologyWatch,
+    checkpoints: &mut state::Checkpoints,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    let mut known_inputs: std::collections::BTreeSet<String> =
+        builder::get_inputs(&clients, reporter)
+            .await?
+            .into_iter()
+            .map(|i| i.name)
+            .collect();
+    reporter.log_info(&format!(
+        "Watching the registry for new inputs every {}s",
+        interval.as_secs()
+    ));
+
+    let mut topology = topology.clone();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = watch.hangup() => {}
+        }
+        let reloaded = daemon::reload_topology(&mut topology, &mut watch, &clients, reporter).await;
+        if reloaded {
+            if let Err(error) = daemon::reconcile(&topology, checkpoints, &clients, reporter).await
+            {
+                reporter.skipped(&format!("Couldn't rewire the reloaded topology: {error}"));
+            }
+        }
+        let inputs = builder::get_inputs(&clients, reporter).await?;
+        for input in inputs {
+            if !known_inputs.insert(input.name.clone()) {
+                continue;
+            }
+            reporter.log_info(&format!("New input {}, building its chain", input.name));
+            let result =
+                rebuild_input_with_clients(&topology, &input.name, checkpoints, &clients, reporter)
+                    .await;
+            if let Err(error) = result {
+                reporter.skipped(&format!("Couldn't build new input {}: {error}", input.name));
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn daemon_pmx(
+    topology: &topology::Topology,
+    interval: std::time::Duration,
+    jitter: std::time::Duration,
+    watch: reload::TopologyWatch,
+    status: &status::StatusBoard,
+    audit_log: Option<audit::AuditLog>,
+    history: &history::History,
+    checkpoints: &mut state::Checkpoints,
+    logger_factory: &fr_logging::LoggerFactory,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    daemon::run(
+        topology,
+        interval,
+        jitter,
+        watch,
+        status,
+        audit_log,
+        history,
+        checkpoints,
+        &clients,
+        logger_factory,
+        reporter,
     )
-    .await;
+    .await
+}
+
+async fn rebuild_input_with_clients(
+    topology: &topology::Topology,
+    name: &str,
+    checkpoints: &mut state::Checkpoints,
+    clients: &clients::Clients,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let live_state = live::read_live_state(clients, reporter).await?;
+    let Some(input) = live_state.inputs.iter().find(|i| i.name == name) else {
+        return Err(format!("Input {name} isn't registered").into());
+    };
+    if !topology.has_chain(name) {
+        return Err(format!(
+            "Input {name} is an insert return or the talkback, it has no chain of its own"
+        )
+        .into());
+    }
+
+    if live_state.find_channel_strip(name).is_none() {
+        builder::build_channel_strips(
+            topology,
+            &vec![input.clone()],
+            &live_state.channel_strips,
+            clients,
+            reporter,
+        )
+        .await?;
+    }
+    let looper_inputs = plan::looper_inputs(
+        topology,
+        &live_state.inputs,
+        checkpoints.loop_numbers(),
+        reporter,
+    );
+    let loop_number = looper_inputs
+        .iter()
+        .find(|(_, i)| i.name == name)
+        .map(|(loop_number, _)| *loop_number);
+    if let Some(loop_number) = loop_number {
+        if !live_state
+            .loopers
+            .iter()
+            .any(|l| l.loop_number == loop_number)
+        {
+            let looper = builder::register_looper(loop_number, clients).await?;
+            reporter.looper_registered(looper.loop_number);
+        }
+    }
+    checkpoints.record_loop_numbers(&looper_inputs)?;
 
-    let group_channel_strips =
-        builder::build_group_channel_strips(factory_client.clone(), &logger).await;
+    let live_state = live::read_live_state(clients, reporter)
+        .await?
+        .with_checkpoints(checkpoints);
+    let input = live_state
+        .inputs
+        .iter()
+        .find(|i| i.name == name)
+        .ok_or_else(|| format!("Input {name} disappeared from the registry"))?;
+    let channel_strip = live_state
+        .find_channel_strip(name)
+        .ok_or_else(|| format!("Channel strip {name} is missing from the registry"))?;
+    let group_channel_strips = live_state.group_channel_strips(topology);
+    let looper_inputs = plan::looper_inputs(
+        topology,
+        &live_state.inputs,
+        &live_state.loop_numbers,
+        reporter,
+    );
 
-    let plugins = builder::get_plugins(registry_client.clone()).await?;
+    reporter.start_stage(&format!("Disconnecting input {name}"));
+    let old_links = live_state.input_chain_live_links(
+        &topology.looper,
+        channel_strip,
+        &group_channel_strips,
+        loop_number,
+    );
+    builder::delete_links(&old_links, &topology.protected_nodes, clients, reporter).await?;
+    let removed: Vec<plan::Link> = old_links.into_iter().map(|(_, link)| link).collect();
+    checkpoints.forget_links(&removed)?;
 
-    builder::connect_channel_strips_to_group_channel_strips(
-        &input_channels,
-        &channel_strips,
+    reporter.start_stage(&format!("Connecting input {name}"));
+    let links = live_state.input_chain_links(
+        topology,
+        input,
+        &looper_inputs,
         &group_channel_strips,
-        &plugins,
-        pipewire_client.clone(),
-        &logger,
+        reporter,
+    );
+    let result = builder::create_links(
+        &links,
+        &live_state.plugins,
+        &topology.protected_nodes,
+        clients,
+        reporter,
     )
     .await;
+    checkpoints.record_links(reporter.created_links())?;
+    result
+}
 
-    let output_stage = builder::build_output_stage(factory_client.clone(), &logger).await;
+async fn graph_pmx(
+    topology: &topology::Topology,
+    reporter: &report::Reporter,
+) -> Result<graph::Graph, Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
 
-    let plugins = builder::get_plugins(registry_client.clone()).await?;
+    let live_state = live::read_live_state(&clients, reporter).await?;
+    Ok(graph::graph(&live_state, &topology.looper))
+}
 
-    let channel_strips = builder::get_all_channel_strips(registry_client.clone()).await;
+async fn export_script_pmx(
+    topology: &topology::Topology,
+    checkpoints: &state::Checkpoints,
+    format: script::ScriptFormat,
+    reporter: &report::Reporter,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
 
-    builder::connect_group_channel_strips_to_output_stage_channels(
-        &group_channel_strips,
-        &output_stage,
-        &plugins,
-        &channel_strips,
-        pipewire_client.clone(),
-        &logger,
+    let live_state = live::read_live_state(&clients, reporter)
+        .await?
+        .with_checkpoints(checkpoints);
+    let connection_plan = live_state.connection_plan(topology, reporter);
+    Ok(script::render(&connection_plan, &live_state.ports, format))
+}
+
+async fn export_patches_pmx(
+    topology: &topology::Topology,
+    reporter: &report::Reporter,
+) -> Result<Vec<patch::Patch>, Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    let live_state = live::read_live_state(&clients, reporter).await?;
+    let desired_links = live_state.desired_links(topology, reporter);
+    Ok(patch::live_patches(&live_state, &desired_links))
+}
+
+/// Connects the patches that aren't live yet. The links are recorded like
+/// any other the builder made, a patch list only applied once isn't kept by
+/// later builds.
+async fn apply_patches_pmx(
+    topology: &topology::Topology,
+    patches: &[patch::Patch],
+    checkpoints: &mut state::Checkpoints,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    let live_state = live::read_live_state(&clients, reporter).await?;
+    reporter.start_stage("Planning patches");
+    let connection_plan = plan::ConnectionPlan::new(patch::patch_links(
+        patches,
+        &live_state.ports,
+        &live_state.nodes,
+        reporter,
+    ));
+    let existing_links = plan::resolve_live_links(&live_state.links, &live_state.nodes)
+        .into_iter()
+        .collect();
+    let result = builder::execute_plan(
+        &connection_plan,
+        &existing_links,
+        &topology.protected_nodes,
+        &clients,
+        reporter,
     )
     .await;
+    checkpoints.record_links(reporter.created_links())?;
+    result
+}
+
+async fn snapshot_pmx(
+    topology: &topology::Topology,
+    reporter: &report::Reporter,
+) -> Result<snapshot::Snapshot, Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    let live_state = live::read_live_state(&clients, reporter).await?;
+    Ok(snapshot::take_snapshot(&live_state))
+}
+
+async fn restore_pmx(
+    topology: &topology::Topology,
+    snapshot: &snapshot::Snapshot,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    snapshot::restore_snapshot(snapshot, &topology.protected_nodes, &clients, reporter).await
+}
+
+async fn build_pmx(
+    topology: &topology::Topology,
+    checkpoints: &mut state::Checkpoints,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let clients = clients::Clients::connect(topology, reporter).await?;
+
+    let backup = if backup::restores_on_failure() {
+        Some(backup::capture(topology, checkpoints, &clients, reporter).await?)
+    } else {
+        None
+    };
+    let result = build_with_clients(topology, checkpoints, &clients, reporter)
+        .await
+        .and_then(|_| reporter.check_failures());
+    if let (Err(error), Some(backup)) = (&result, &backup) {
+        // Restoring needs the services, which a cancelled build no longer
+        // calls
+        if !shutdown::is_requested() {
+            reporter.log_info(&format!(
+                "Build failed, restoring the links it started from: {error}"
+            ));
+            backup::restore(backup, topology, checkpoints, &clients, reporter).await?;
+        }
+    }
+    result?;
+
+    let channel_strips = builder::get_all_channel_strips(&clients).await?;
+    let mut parameter_settings = plan::parameter_settings(topology, &channel_strips, reporter);
+    if let Some(calibration) = &topology.calibration {
+        let inputs: Vec<model::Input> = builder::get_inputs(&clients, reporter)
+            .await?
+            .into_iter()
+            .filter(|i| topology.has_chain(&i.name))
+            .collect();
+        let gains = calibration::measure_inputs(
+            calibration,
+            &inputs,
+            checkpoints.calibrated_gains(),
+            &clients,
+            reporter,
+        )
+        .await?;
+        checkpoints.record_calibrated_gains(gains)?;
+        parameter_settings.extend(calibration::gain_settings(
+            topology,
+            checkpoints.calibrated_gains(),
+            &channel_strips,
+        ));
+    }
+    let output_gains = if safe_mode::is_enabled() {
+        let output_strips = live::read_live_state(&clients, reporter)
+            .await?
+            .output_stage_channel_strips(topology);
+        safe_mode::hold_output_gains(&mut parameter_settings, &output_strips)
+    } else {
+        Vec::new()
+    };
+    if !parameter_settings.is_empty() {
+        builder::apply_parameters(&parameter_settings, &clients, reporter).await?;
+    }
+    checkpoints.record_build(
+        channel_strips
+            .iter()
+            .filter(|s| naming::naming().owns(&s.name))
+            .map(|s| s.name.clone())
+            .collect(),
+        parameter_settings
+            .iter()
+            .map(|s| (format!("{} {}", s.channel_strip, s.symbol), s.value))
+            .collect(),
+    )?;
+    diff::print_build_diff(checkpoints.previous(), checkpoints.state());
+    if safe_mode::is_enabled() {
+        safe_mode::verify_and_unmute(topology, &output_gains, &clients, reporter).await?;
+    }
+
+    let live_state = live::read_live_state(&clients, reporter)
+        .await?
+        .with_checkpoints(checkpoints);
+    latency::report_latencies(topology, &live_state, reporter);
+    Ok(())
+}
 
-    let output_channels = builder::get_all_outputs(registry_client.clone()).await;
+async fn build_with_clients(
+    topology: &topology::Topology,
+    checkpoints: &mut state::Checkpoints,
+    clients: &clients::Clients,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    devices::prepare_devices(topology, clients, reporter).await?;
+    if !topology.discovery.is_empty() {
+        reporter.start_stage("Discovering inputs");
+        let registered_inputs = builder::get_inputs(clients, reporter).await?;
+        let discovered_inputs = discovery::discover_inputs(
+            &topology.discovery,
+            &builder::get_nodes(clients).await?,
+            &builder::get_ports(clients).await?,
+            &registered_inputs,
+            reporter,
+        );
+        builder::register_inputs(&discovered_inputs, clients, reporter).await?;
+    }
 
-    builder::connect_output_stage_to_outputs(
-        &output_stage,
+    let input_channels: Vec<model::Input> = builder::get_inputs(clients, reporter)
+        .await?
+        .into_iter()
+        .filter(|i| topology.has_chain(&i.name))
+        .collect();
+    let output_channels = builder::get_all_outputs(clients).await?;
+    validate::into_result(validate::plan_violations(
+        topology,
+        &input_channels,
         &output_channels,
-        &ports,
-        &nodes,
-        &plugins,
-        pipewire_client.clone(),
-        &logger,
+        &builder::get_nodes(clients).await?,
+        &builder::get_ports(clients).await?,
+    ))?;
+    budget::check(topology, &input_channels, checkpoints, clients, reporter).await?;
+    if checkpoints.is_completed(state::Stage::ChannelStrips) {
+        reporter.log_info("Channel strips already built, skipping");
+    } else if !checkpoints.is_selected(state::Stage::ChannelStrips) {
+        reporter.log_info("Channel strips not selected, skipping");
+    } else {
+        hooks::run_hooks(
+            topology,
+            state::Stage::ChannelStrips,
+            hooks::HookTime::Before,
+            reporter,
+        )
+        .await?;
+        let existing = builder::get_all_channel_strips(clients).await?;
+        builder::build_channel_strips(topology, &input_channels, &existing, clients, reporter)
+            .await?;
+        checkpoints.complete(state::Stage::ChannelStrips)?;
+        hooks::run_hooks(
+            topology,
+            state::Stage::ChannelStrips,
+            hooks::HookTime::After,
+            reporter,
+        )
+        .await?;
+    }
+
+    if topology.inputs.iter().any(|i| !i.plugins.is_empty()) {
+        let plugin_chains = builder::add_plugin_chains(
+            topology,
+            &input_channels,
+            checkpoints.plugin_chains(),
+            &builder::get_plugins(clients).await?,
+            clients,
+            reporter,
+        )
+        .await?;
+        for (input_name, plugin_ids) in plugin_chains {
+            checkpoints.record_plugin_chain(&input_name, plugin_ids)?;
+        }
+    }
+
+    if let Some(talkback) = topology
+        .talkback
+        .as_ref()
+        .filter(|t| t.duck_plugin.is_some())
+    {
+        let duck_plugin = builder::add_talkback_plugin(
+            talkback,
+            checkpoints.plugin_chains(),
+            &builder::get_plugins(clients).await?,
+            clients,
+            reporter,
+        )
+        .await?;
+        if let Some(plugin_id) = duck_plugin {
+            // Kept with the plugin chains, the duck plugin is the talkback's
+            // chain
+            checkpoints.record_plugin_chain(&talkback.input, vec![plugin_id])?;
+        }
+    }
+
+    if checkpoints.is_completed(state::Stage::Loopers) {
+        reporter.log_info("Loopers already registered, skipping");
+    } else if !checkpoints.is_selected(state::Stage::Loopers) {
+        reporter.log_info("Loopers not selected, skipping");
+    } else {
+        hooks::run_hooks(
+            topology,
+            state::Stage::Loopers,
+            hooks::HookTime::Before,
+            reporter,
+        )
+        .await?;
+        let looper_inputs = plan::looper_inputs(
+            topology,
+            &input_channels,
+            checkpoints.loop_numbers(),
+            reporter,
+        );
+        let existing_loopers = builder::get_loopers(clients).await?;
+        builder::register_loopers_for_input_channels(
+            &looper_inputs,
+            &existing_loopers,
+            clients,
+            reporter,
+        )
+        .await?;
+        let orphaned = builder::unregister_orphaned_loopers(
+            &looper_inputs,
+            checkpoints.loop_numbers(),
+            &existing_loopers,
+            clients,
+            reporter,
+        )
+        .await?;
+        checkpoints.forget_loop_numbers(&orphaned)?;
+        checkpoints.record_loop_numbers(&looper_inputs)?;
+        checkpoints.complete(state::Stage::Loopers)?;
+        hooks::run_hooks(
+            topology,
+            state::Stage::Loopers,
+            hooks::HookTime::After,
+            reporter,
+        )
+        .await?;
+    }
+
+    if checkpoints.is_completed(state::Stage::GroupChannelStrips) {
+        reporter.log_info("Group channel strips already built, skipping");
+    } else if !checkpoints.is_selected(state::Stage::GroupChannelStrips) {
+        reporter.log_info("Group channel strips not selected, skipping");
+    } else {
+        hooks::run_hooks(
+            topology,
+            state::Stage::GroupChannelStrips,
+            hooks::HookTime::Before,
+            reporter,
+        )
+        .await?;
+        let existing = builder::get_all_channel_strips(clients).await?;
+        builder::build_group_channel_strips(topology, &existing, clients, reporter).await?;
+        builder::build_aux_bus_channel_strips(topology, &existing, clients, reporter).await?;
+        builder::build_cue_channel_strip(topology, &existing, clients, reporter).await?;
+        checkpoints.complete(state::Stage::GroupChannelStrips)?;
+        hooks::run_hooks(
+            topology,
+            state::Stage::GroupChannelStrips,
+            hooks::HookTime::After,
+            reporter,
+        )
+        .await?;
+    }
+
+    if checkpoints.is_completed(state::Stage::OutputStage) {
+        reporter.log_info("Output stages already built, skipping");
+    } else if !checkpoints.is_selected(state::Stage::OutputStage) {
+        reporter.log_info("Output stages not selected, skipping");
+    } else {
+        hooks::run_hooks(
+            topology,
+            state::Stage::OutputStage,
+            hooks::HookTime::Before,
+            reporter,
+        )
+        .await?;
+        let existing = builder::get_output_stages(clients).await?;
+        builder::build_output_stages(topology, &existing, clients, reporter).await?;
+        let output_stages = builder::get_output_stages(clients).await?;
+        let cross_faders =
+            plan::output_stage_parameter_settings(topology, &output_stages, reporter);
+        if !cross_faders.is_empty() {
+            builder::apply_parameters(&cross_faders, clients, reporter).await?;
+        }
+        checkpoints.complete(state::Stage::OutputStage)?;
+        hooks::run_hooks(
+            topology,
+            state::Stage::OutputStage,
+            hooks::HookTime::After,
+            reporter,
+        )
+        .await?;
+    }
+
+    if let Some(limiter) = &topology.limiter {
+        let live_state = live::read_live_state(clients, reporter).await?;
+        let (limiters, result) = builder::add_output_limiters(
+            limiter,
+            &live_state.output_stage_output_names(topology),
+            checkpoints.limiters(),
+            &live_state.plugins,
+            clients,
+            reporter,
+        )
+        .await;
+        checkpoints.record_limiters(limiters)?;
+        result?;
+    }
+
+    if topology
+        .outputs
+        .iter()
+        .any(|output| output.mode == topology::OutputMode::MonoSum)
+    {
+        let live_state = live::read_live_state(clients, reporter).await?;
+        let (mono_sums, result) = builder::add_mono_sums(
+            &topology.mono_sum,
+            &live_state.mono_sum_output_names(topology),
+            checkpoints.mono_sums(),
+            &live_state.plugins,
+            clients,
+            reporter,
+        )
+        .await;
+        checkpoints.record_mono_sums(mono_sums)?;
+        result?;
+    }
+
+    if safe_mode::is_enabled() {
+        safe_mode::mute_outputs(topology, clients, reporter).await?;
+    }
+
+    if checkpoints.is_completed(state::Stage::OutputStageWired) {
+        reporter.log_info("Output stages already connected, nothing to do");
+        return Ok(());
+    }
+
+    // The wiring stages share one plan, it is made whenever one of them is
+    // selected and only the selected ones are completed
+    let wiring_stages: Vec<state::Stage> = [
+        state::Stage::Inputs,
+        state::Stage::Groups,
+        state::Stage::OutputStageWired,
+    ]
+    .into_iter()
+    .filter(|stage| checkpoints.is_selected(*stage))
+    .collect();
+    if wiring_stages.is_empty() {
+        reporter.log_info("Wiring not selected, nothing to do");
+        return Ok(());
+    }
+    for stage in wiring_stages.iter().copied() {
+        hooks::run_hooks(topology, stage, hooks::HookTime::Before, reporter).await?;
+    }
+    reporter.start_stage("Planning links");
+    let live_state = live::read_live_state(clients, reporter)
+        .await?
+        .with_checkpoints(checkpoints);
+    let connection_plan = live_state.connection_plan(topology, reporter);
+    let existing_links: std::collections::BTreeSet<plan::Link> = live_state
+        .links
+        .iter()
+        .filter_map(|link| plan::resolve_live_link(link, &live_state.nodes))
+        .collect();
+    connection_plan.check_node_names(&live_state.nodes)?;
+    reporter.log_info(&format!("Planned {} links", connection_plan.len()));
+    if topology.record.is_some() {
+        reporter.record_channels_planned(plan::record_channels(topology, &live_state.inputs));
+    }
+
+    builder::disconnect_insert_bypasses(topology, &live_state, clients, reporter).await?;
+    builder::disconnect_plugin_chain_bypasses(topology, &live_state, clients, reporter).await?;
+    let result = builder::execute_plan(
+        &connection_plan,
+        &existing_links,
+        &topology.protected_nodes,
+        clients,
+        reporter,
     )
     .await;
+    checkpoints.record_links(reporter.created_links())?;
+    result?;
+    // Links that failed under a continuing policy leave the wiring stages
+    // open, so a resumed build tries them again.
+    if reporter.failed_links().is_empty() {
+        for stage in wiring_stages {
+            checkpoints.complete(stage)?;
+            hooks::run_hooks(topology, stage, hooks::HookTime::After, reporter).await?;
+        }
+    }
 
-    Ok(())
+    remove_stale_links(topology, checkpoints, clients, reporter).await
+}
+
+/// Removes links an earlier build created that the topology no longer wants.
+async fn remove_stale_links(
+    topology: &topology::Topology,
+    checkpoints: &mut state::Checkpoints,
+    clients: &clients::Clients,
+    reporter: &report::Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    reporter.start_stage("Removing stale links");
+    let live_state = live::read_live_state(clients, reporter)
+        .await?
+        .with_checkpoints(checkpoints);
+    let desired_links = live_state.desired_links(topology, reporter);
+
+    let stale_links: Vec<(u32, plan::Link)> = live_state
+        .managed_live_links(&topology.looper)
+        .into_iter()
+        .filter(|(id, link)| {
+            (checkpoints.owned_links().contains(link) || live_state.is_builder_link(*id))
+                && !desired_links.contains(link)
+        })
+        .collect();
+    let stale_links = confirm::approved(stale_links, |(_, link)| format!("stale link {link}"))?;
+    if stale_links.is_empty() {
+        reporter.log_info("No stale links, nothing to do");
+        return Ok(());
+    }
+
+    builder::delete_links(&stale_links, &topology.protected_nodes, clients, reporter).await?;
+    let removed: Vec<plan::Link> = stale_links.into_iter().map(|(_, link)| link).collect();
+    checkpoints.forget_links(&removed)
 }
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{extract::State, routing::get, Router};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+pub struct Metrics {
+    registry: Registry,
+    pub links_created: IntCounter,
+    pub links_failed: IntCounter,
+    pub channel_strips_created: IntCounter,
+    builds: IntCounterVec,
+    build_duration: Histogram,
+    last_build_success: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Metrics, prometheus::Error> {
+        let registry = Registry::new();
+
+        let links_created = IntCounter::new(
+            "pmx_builder_links_created_total",
+            "Links created by the builder",
+        )?;
+        let links_failed = IntCounter::new(
+            "pmx_builder_links_failed_total",
+            "Link requests rejected by pipewire",
+        )?;
+        let channel_strips_created = IntCounter::new(
+            "pmx_builder_channel_strips_created_total",
+            "Channel strips created through the factory",
+        )?;
+        let builds = IntCounterVec::new(
+            Opts::new("pmx_builder_builds_total", "Finished builds by result"),
+            &["result"],
+        )?;
+        let build_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "pmx_builder_build_duration_seconds",
+                "Duration of complete builds",
+            )
+            .buckets(vec![1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]),
+        )?;
+        let last_build_success = IntGauge::new(
+            "pmx_builder_last_build_success",
+            "1 if the last build succeeded, 0 otherwise",
+        )?;
+
+        registry.register(Box::new(links_created.clone()))?;
+        registry.register(Box::new(links_failed.clone()))?;
+        registry.register(Box::new(channel_strips_created.clone()))?;
+        registry.register(Box::new(builds.clone()))?;
+        registry.register(Box::new(build_duration.clone()))?;
+        registry.register(Box::new(last_build_success.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            links_created,
+            links_failed,
+            channel_strips_created,
+            builds,
+            build_duration,
+            last_build_success,
+        })
+    }
+
+    pub fn build_finished(&self, duration: Duration, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.builds.with_label_values(&[result]).inc();
+        self.build_duration.observe(duration.as_secs_f64());
+        self.last_build_success.set(success as i64);
+    }
+
+    fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        let encoder = TextEncoder::new();
+        encoder
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+pub async fn serve_metrics(
+    listener: tokio::net::TcpListener,
+    metrics: Arc<Metrics>,
+) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(render_metrics))
+        .with_state(metrics);
+    axum::serve(listener, app).await
+}
+
+async fn render_metrics(State(metrics): State<Arc<Metrics>>) -> String {
+    metrics.encode()
+}
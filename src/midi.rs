@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::glob_to_regex;
+use crate::looper::LooperBackend;
+use crate::naming::naming;
+use crate::plan::{self, Link, StripPlugins};
+use crate::pmx::{
+    pipewire::{node::ListNode, port::ListPort},
+    plugin::PmxPlugin,
+};
+use crate::report::Reporter;
+use crate::topology::PluginRole;
+
+/// Routes the MIDI output of a control surface to the looper or to a plugin
+/// of a channel strip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MidiMapping {
+    /// Controller node name pattern, `*` matches any text and `?` a single
+    /// character
+    pub controller: String,
+    /// Name of the controller port, its first MIDI output if not set
+    pub port: Option<String>,
+    pub target: MidiTarget,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MidiTarget {
+    Looper,
+    ChannelStrip {
+        name: String,
+        #[serde(default)]
+        plugin: PluginRole,
+    },
+}
+
+impl std::fmt::Display for MidiTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MidiTarget::Looper => write!(f, "looper"),
+            MidiTarget::ChannelStrip { name, plugin } => write!(f, "{name} {plugin:?}"),
+        }
+    }
+}
+
+/// Ports without an audio channel carry MIDI.
+fn is_midi_port(port: &ListPort) -> bool {
+    port.audio_channel.is_empty()
+}
+
+/// MIDI ports of `node` in `direction`, ordered by port id.
+fn midi_ports<'a>(node: &ListNode, direction: &str, ports: &'a [ListPort]) -> Vec<&'a ListPort> {
+    let mut midi_ports: Vec<&ListPort> = ports
+        .iter()
+        .filter(|p| p.node_id == node.object_serial && p.direction == direction && is_midi_port(p))
+        .collect();
+    midi_ports.sort_by_key(|p| p.id);
+    midi_ports
+}
+
+/// Pipewire node name of the mapping's target.
+fn target_node_name<'a, S: StripPlugins>(
+    target: &'a MidiTarget,
+    looper_backend: &'a LooperBackend,
+    channel_strips: &[S],
+    plugins: &'a [PmxPlugin],
+) -> Option<&'a str> {
+    match target {
+        MidiTarget::Looper => Some(&looper_backend.node),
+        MidiTarget::ChannelStrip { name, plugin } => channel_strips
+            .iter()
+            .find(|s| naming().matches(s.strip_name(), name))
+            .and_then(|s| s.plugin_id(*plugin))
+            .and_then(|id| plan::find_plugin(plugins, id))
+            .map(|p| p.name.as_str()),
+    }
+}
+
+/// Links from every controller matching a mapping to the first MIDI input of
+/// the mapping's target. Port ids are pipewire's, so the links must not go
+/// through `plan::resolve_plugin_ports`.
+pub fn midi_links<S: StripPlugins>(
+    mappings: &[MidiMapping],
+    looper_backend: &LooperBackend,
+    channel_strips: &[S],
+    plugins: &[PmxPlugin],
+    nodes: &[ListNode],
+    ports: &[ListPort],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    for mapping in mappings {
+        let pattern = match glob_to_regex(&mapping.controller) {
+            Ok(pattern) => pattern,
+            Err(error) => {
+                reporter.skipped(&format!(
+                    "Invalid MIDI controller pattern {}: {error}",
+                    mapping.controller
+                ));
+                continue;
+            }
+        };
+
+        let target_port =
+            target_node_name(&mapping.target, looper_backend, channel_strips, plugins)
+                .and_then(|name| nodes.iter().find(|n| n.name == name))
+                .and_then(|node| {
+                    midi_ports(node, "in", ports)
+                        .first()
+                        .map(|port| (node.name.as_str(), port.id))
+                });
+        let Some((target_node_name, target_port_id)) = target_port else {
+            reporter.skipped(&format!(
+                "No MIDI input for {}, not wiring {}",
+                mapping.target, mapping.controller
+            ));
+            continue;
+        };
+
+        let controllers: Vec<&ListNode> =
+            nodes.iter().filter(|n| pattern.is_match(&n.name)).collect();
+        if controllers.is_empty() {
+            reporter.skipped(&format!(
+                "No MIDI controller matching {}",
+                mapping.controller
+            ));
+        }
+
+        for controller in controllers {
+            let controller_ports = midi_ports(controller, "out", ports);
+            let controller_port = match &mapping.port {
+                Some(name) => controller_ports.into_iter().find(|p| p.name == *name),
+                None => controller_ports.into_iter().next(),
+            };
+            let Some(controller_port) = controller_port else {
+                reporter.skipped(&format!(
+                    "Controller {} has no matching MIDI output",
+                    controller.name
+                ));
+                continue;
+            };
+            links.push(Link::new(
+                &controller.name,
+                controller_port.id,
+                target_node_name,
+                target_port_id,
+            ));
+        }
+    }
+
+    links
+}
@@ -0,0 +1,219 @@
+use crate::plan::{OutputStagePlugins, StripPlugins};
+use crate::pmx::{
+    channel_strip::PmxChannelStrip as RegistryChannelStrip,
+    factory::{
+        channel_strip::PmxChannelStrip as FactoryChannelStrip,
+        output_stage::PmxOutputStage as FactoryOutputStage,
+    },
+    input::{PmxInput, PmxInputType},
+    looper::PmxLooper,
+    output::PmxOutput,
+    output_stage::PmxOutputStage as RegistryOutputStage,
+};
+use crate::port_map::PortMap;
+use crate::topology::{ChannelStripType, OutputMode};
+
+pub use crate::plan::Link;
+
+/// Number of audio ports an input brings into its channel strip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputType {
+    /// Registered without ports, nothing to wire
+    None,
+    Mono,
+    Stereo,
+}
+
+impl InputType {
+    pub fn to_pmx(self) -> PmxInputType {
+        match self {
+            InputType::None => PmxInputType::None,
+            InputType::Mono => PmxInputType::MonoInput,
+            InputType::Stereo => PmxInputType::StereoInput,
+        }
+    }
+}
+
+impl From<PmxInputType> for InputType {
+    fn from(input_type: PmxInputType) -> Self {
+        match input_type {
+            PmxInputType::None => InputType::None,
+            PmxInputType::MonoInput => InputType::Mono,
+            PmxInputType::StereoInput => InputType::Stereo,
+        }
+    }
+}
+
+/// Registered input. Unknown input types read as `InputType::None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    pub name: String,
+    pub input_type: InputType,
+    pub left_port_path: Option<String>,
+    pub right_port_path: Option<String>,
+    pub group_channel_strip_name: String,
+}
+
+impl From<PmxInput> for Input {
+    fn from(input: PmxInput) -> Self {
+        Input {
+            input_type: input.input_type().into(),
+            name: input.name,
+            left_port_path: input.left_port_path,
+            right_port_path: input.right_port_path,
+            group_channel_strip_name: input.group_channel_strip_name,
+        }
+    }
+}
+
+/// Channel strip as the factory created it or the registry lists it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelStrip {
+    pub id: u32,
+    pub name: String,
+    pub gain_plugin_id: u32,
+    pub saturator_plugin_id: u32,
+    pub cross_fader_plugin_id: Option<u32>,
+}
+
+impl ChannelStrip {
+    pub fn channel_strip_type(&self) -> ChannelStripType {
+        match self.cross_fader_plugin_id {
+            Some(_) => ChannelStripType::CrossFaded,
+            None => ChannelStripType::Basic,
+        }
+    }
+}
+
+impl From<FactoryChannelStrip> for ChannelStrip {
+    fn from(channel_strip: FactoryChannelStrip) -> Self {
+        ChannelStrip {
+            id: channel_strip.id,
+            name: channel_strip.name,
+            gain_plugin_id: channel_strip.gain_plugin_id,
+            saturator_plugin_id: channel_strip.saturator_plugin_id,
+            cross_fader_plugin_id: channel_strip.cross_fader_plugin_id,
+        }
+    }
+}
+
+impl From<RegistryChannelStrip> for ChannelStrip {
+    fn from(channel_strip: RegistryChannelStrip) -> Self {
+        ChannelStrip {
+            id: channel_strip.id,
+            name: channel_strip.name,
+            gain_plugin_id: channel_strip.gain_plugin_id,
+            saturator_plugin_id: channel_strip.saturator_plugin_id,
+            cross_fader_plugin_id: channel_strip.cross_fader_plugin_id,
+        }
+    }
+}
+
+impl StripPlugins for ChannelStrip {
+    fn strip_name(&self) -> &str {
+        &self.name
+    }
+
+    fn gain_plugin_id(&self) -> u32 {
+        self.gain_plugin_id
+    }
+
+    fn saturator_plugin_id(&self) -> u32 {
+        self.saturator_plugin_id
+    }
+
+    fn cross_fader_plugin_id(&self) -> Option<u32> {
+        self.cross_fader_plugin_id
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Looper {
+    pub loop_number: u32,
+}
+
+impl From<PmxLooper> for Looper {
+    fn from(looper: PmxLooper) -> Self {
+        Looper {
+            loop_number: looper.loop_number,
+        }
+    }
+}
+
+/// Physical output with the port path of every channel in order. Registry
+/// outputs have a left and a right channel, the topology can describe more.
+/// A channel without a port is an empty path, so later channels keep their
+/// position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub name: String,
+    pub port_paths: Vec<String>,
+    /// Which plugin port feeds which channel, the wiring's default if not set
+    pub channel_map: Option<PortMap>,
+    pub mode: OutputMode,
+    /// Plugin summing both sides in `mono_sum` mode, known from the state
+    /// file only
+    pub mono_sum_plugin_id: Option<u32>,
+}
+
+impl From<PmxOutput> for Output {
+    fn from(output: PmxOutput) -> Self {
+        Output {
+            name: output.name,
+            port_paths: match output.right_port_path {
+                Some(right) => vec![output.left_port_path.unwrap_or_default(), right],
+                None => output.left_port_path.into_iter().collect(),
+            },
+            channel_map: None,
+            mode: OutputMode::default(),
+            mono_sum_plugin_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputStage {
+    pub id: u32,
+    pub name: String,
+    pub cross_fader_plugin_id: u32,
+    pub left_channel_strip_id: u32,
+    pub right_channel_strip_id: u32,
+}
+
+impl From<FactoryOutputStage> for OutputStage {
+    fn from(output_stage: FactoryOutputStage) -> Self {
+        OutputStage {
+            id: output_stage.id,
+            name: output_stage.name,
+            cross_fader_plugin_id: output_stage.cross_fader_plugin_id,
+            left_channel_strip_id: output_stage.left_channel_strip_id,
+            right_channel_strip_id: output_stage.right_channel_strip_id,
+        }
+    }
+}
+
+impl From<RegistryOutputStage> for OutputStage {
+    fn from(output_stage: RegistryOutputStage) -> Self {
+        OutputStage {
+            id: output_stage.id,
+            name: output_stage.name,
+            cross_fader_plugin_id: output_stage.cross_fader_plugin_id,
+            left_channel_strip_id: output_stage.left_channel_strip_id,
+            right_channel_strip_id: output_stage.right_channel_strip_id,
+        }
+    }
+}
+
+impl OutputStagePlugins for OutputStage {
+    fn cross_fader_plugin_id(&self) -> u32 {
+        self.cross_fader_plugin_id
+    }
+
+    fn left_channel_strip_id(&self) -> u32 {
+        self.left_channel_strip_id
+    }
+
+    fn right_channel_strip_id(&self) -> u32 {
+        self.right_channel_strip_id
+    }
+}
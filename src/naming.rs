@@ -0,0 +1,93 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+/// Templates for the names of the channel strips and output stages the
+/// builder creates. Each template holds the placeholder named after its
+/// resource, `{input}`, `{group}`, `{aux_bus}`, `{cue}` or `{output_stage}`,
+/// which is replaced by the name from the topology.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Naming {
+    /// Put in front of every created name, marks the resources the builder
+    /// owns
+    pub prefix: String,
+    pub input: String,
+    pub group: String,
+    pub aux_bus: String,
+    pub cue: String,
+    pub output_stage: String,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Naming {
+            prefix: String::new(),
+            input: String::from(INPUT_PLACEHOLDER),
+            group: String::from(GROUP_PLACEHOLDER),
+            aux_bus: String::from(AUX_BUS_PLACEHOLDER),
+            cue: String::from(CUE_PLACEHOLDER),
+            output_stage: String::from(OUTPUT_STAGE_PLACEHOLDER),
+        }
+    }
+}
+
+pub const INPUT_PLACEHOLDER: &str = "{input}";
+pub const GROUP_PLACEHOLDER: &str = "{group}";
+pub const AUX_BUS_PLACEHOLDER: &str = "{aux_bus}";
+pub const CUE_PLACEHOLDER: &str = "{cue}";
+pub const OUTPUT_STAGE_PLACEHOLDER: &str = "{output_stage}";
+
+impl Naming {
+    fn apply(&self, template: &str, placeholder: &str, name: &str) -> String {
+        format!("{}{}", self.prefix, template.replace(placeholder, name))
+    }
+
+    pub fn input_strip(&self, input: &str) -> String {
+        self.apply(&self.input, INPUT_PLACEHOLDER, input)
+    }
+
+    pub fn group_strip(&self, group: &str) -> String {
+        self.apply(&self.group, GROUP_PLACEHOLDER, group)
+    }
+
+    pub fn aux_bus_strip(&self, aux_bus: &str) -> String {
+        self.apply(&self.aux_bus, AUX_BUS_PLACEHOLDER, aux_bus)
+    }
+
+    pub fn cue_strip(&self, cue: &str) -> String {
+        self.apply(&self.cue, CUE_PLACEHOLDER, cue)
+    }
+
+    pub fn output_stage(&self, output_stage: &str) -> String {
+        self.apply(&self.output_stage, OUTPUT_STAGE_PLACEHOLDER, output_stage)
+    }
+
+    /// Whether `registry_name` is what any of the templates makes of the
+    /// topology name `name`.
+    pub fn matches(&self, registry_name: &str, name: &str) -> bool {
+        registry_name == self.input_strip(name)
+            || registry_name == self.group_strip(name)
+            || registry_name == self.aux_bus_strip(name)
+            || registry_name == self.cue_strip(name)
+            || registry_name == self.output_stage(name)
+    }
+
+    /// Whether the builder created the resource named `registry_name`.
+    /// Without a prefix everything counts as owned.
+    pub fn owns(&self, registry_name: &str) -> bool {
+        registry_name.starts_with(&self.prefix)
+    }
+}
+
+static NAMING: OnceLock<Naming> = OnceLock::new();
+
+/// Sets the naming for every following lookup. Only the first call has an
+/// effect, lookups made before it use the defaults.
+pub fn set_naming(naming: Naming) {
+    let _ = NAMING.set(naming);
+}
+
+pub fn naming() -> &'static Naming {
+    NAMING.get_or_init(Naming::default)
+}
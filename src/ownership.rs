@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::pmx::pipewire::link::ListLink;
+
+/// Link property marking a link as created by the builder. Its value is the
+/// id of the run that created it.
+pub const RUN_ID_PROPERTY: &str = "pmx.builder/run-id";
+
+static RUN_ID: OnceLock<String> = OnceLock::new();
+
+/// Sets the id the links of this run are tagged with, for replays that have
+/// to send the recorded one. Only the first call has an effect, calls made
+/// before it get a new id.
+pub fn set_run_id(run_id: String) {
+    let _ = RUN_ID.set(run_id);
+}
+
+/// Id of this run, from its start time and process id.
+pub fn run_id() -> &'static str {
+    RUN_ID.get_or_init(|| {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        format!("{started}-{}", std::process::id())
+    })
+}
+
+/// Properties every link the builder creates is tagged with.
+pub fn link_properties() -> HashMap<String, String> {
+    HashMap::from([(String::from(RUN_ID_PROPERTY), String::from(run_id()))])
+}
+
+/// Whether any build created the link, as opposed to someone patching it by
+/// hand. Works without the state file, the tag lives in pipewire.
+pub fn is_builder_link(link: &ListLink) -> bool {
+    link.properties.contains_key(RUN_ID_PROPERTY)
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::live::LiveState;
+use crate::ownership;
+use crate::plan::{self, Link};
+use crate::pmx::pipewire::{node::ListNode, port::ListPort};
+use crate::report::Reporter;
+
+const HEADER: &str = "output_node,output_port,input_node,input_port";
+
+/// Connection kept in a patch list. Ports are named rather than numbered,
+/// pipewire hands out new port ids whenever a node comes back.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Patch {
+    pub output_node: String,
+    pub output_port: String,
+    pub input_node: String,
+    pub input_port: String,
+}
+
+/// Reads a CSV patch list. The header line is optional, empty lines and
+/// lines starting with `#` are left out.
+pub fn parse_patch_list(contents: &str) -> Result<Vec<Patch>, Box<dyn std::error::Error>> {
+    let mut patches = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line == HEADER {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [output_node, output_port, input_node, input_port] = fields[..] else {
+            return Err(format!(
+                "line {}: expected {HEADER}, found {} fields",
+                index + 1,
+                fields.len()
+            )
+            .into());
+        };
+        patches.push(Patch {
+            output_node: String::from(output_node),
+            output_port: String::from(output_port),
+            input_node: String::from(input_node),
+            input_port: String::from(input_port),
+        });
+    }
+    Ok(patches)
+}
+
+pub fn read_patch_list(path: &Path) -> Result<Vec<Patch>, Box<dyn std::error::Error>> {
+    parse_patch_list(&std::fs::read_to_string(path)?)
+        .map_err(|e| format!("{}: {e}", path.display()).into())
+}
+
+pub fn render_patch_list(patches: &[Patch]) -> String {
+    let mut rendered = format!("{HEADER}\n");
+    for patch in patches {
+        rendered.push_str(&format!(
+            "{},{},{},{}\n",
+            patch.output_node, patch.output_port, patch.input_node, patch.input_port
+        ));
+    }
+    rendered
+}
+
+fn find_named_port<'a>(
+    ports: &'a [ListPort],
+    nodes: &[ListNode],
+    node_name: &str,
+    port_name: &str,
+    direction: &str,
+) -> Option<&'a ListPort> {
+    let node = plan::unique_node(node_name, nodes)?;
+    ports.iter().find(|p| {
+        p.node_id == node.object_serial && p.direction == direction && p.name == port_name
+    })
+}
+
+/// Links of the patches whose nodes and ports are live.
+pub fn patch_links(
+    patches: &[Patch],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+    for patch in patches {
+        let output_port =
+            find_named_port(ports, nodes, &patch.output_node, &patch.output_port, "out");
+        let input_port = find_named_port(ports, nodes, &patch.input_node, &patch.input_port, "in");
+        match output_port.zip(input_port) {
+            Some((output_port, input_port)) => links.push(Link::new(
+                &patch.output_node,
+                output_port.id,
+                &patch.input_node,
+                input_port.id,
+            )),
+            None => reporter.skipped(&format!(
+                "Couldn't find ports of patch {}:{} -> {}:{}",
+                patch.output_node, patch.output_port, patch.input_node, patch.input_port
+            )),
+        }
+    }
+    links
+}
+
+/// Live links neither the topology nor an earlier build made, the ad-hoc
+/// connections worth keeping in a patch list.
+pub fn live_patches(live_state: &LiveState, desired: &BTreeSet<Link>) -> Vec<Patch> {
+    let port_name = |id: u32| {
+        live_state
+            .ports
+            .iter()
+            .find(|p| p.id == id)
+            .map(|p| p.name.clone())
+    };
+    let mut patches: Vec<Patch> = live_state
+        .links
+        .iter()
+        .filter(|link| !ownership::is_builder_link(link))
+        .filter_map(|live_link| {
+            let link = plan::resolve_live_link(live_link, &live_state.nodes)?;
+            if desired.contains(&link) {
+                return None;
+            }
+            Some(Patch {
+                output_port: port_name(link.output_port_id)?,
+                input_port: port_name(link.input_port_id)?,
+                output_node: link.output_node_name,
+                input_node: link.input_node_name,
+            })
+        })
+        .collect();
+    patches.sort();
+    patches
+}
@@ -0,0 +1,1952 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::glob_to_regex;
+use crate::looper::LooperBackend;
+use crate::model::{ChannelStrip, Input, InputType, Looper, Output, OutputStage};
+use crate::naming::naming;
+use crate::pmx::{
+    pipewire::{link::ListLink, node::ListNode, port::ListPort},
+    plugin::PmxPlugin,
+};
+use crate::port_map::PortMap;
+use crate::report::Reporter;
+use crate::topology::{
+    AuxBusConfig, ChainPluginConfig, ChannelStripType, CrossFaderSide, CueConfig, GroupConfig,
+    GroupSend, InputConfig, InsertConfig, MonoMode, OutputMode, OutputStageConfig, PluginRole,
+    PortMatch, PresetConfig, RecordConfig, SidechainConfig, TalkbackConfig, Topology,
+};
+
+/// Left and right audio output ports of every channel strip plugin.
+pub const AUDIO_OUTPUT_PORTS: [u32; 2] = [0, 1];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Link {
+    pub output_node_name: String,
+    pub output_port_id: u32,
+    pub input_node_name: String,
+    pub input_port_id: u32,
+}
+
+impl Link {
+    pub fn new(
+        output_node_name: &str,
+        output_port_id: u32,
+        input_node_name: &str,
+        input_port_id: u32,
+    ) -> Link {
+        Link {
+            output_node_name: String::from(output_node_name),
+            output_port_id,
+            input_node_name: String::from(input_node_name),
+            input_port_id,
+        }
+    }
+}
+
+impl std::fmt::Display for Link {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{} -> {}:{}",
+            self.output_node_name, self.output_port_id, self.input_node_name, self.input_port_id
+        )
+    }
+}
+
+/// Every link a build wants, deduplicated and ordered so the links into a
+/// node come before the links out of it. Nodes on a cycle keep name order.
+#[derive(Debug, Default)]
+pub struct ConnectionPlan {
+    links: Vec<Link>,
+    /// Priority of each link, links without one have priority 0
+    priorities: BTreeMap<Link, i32>,
+}
+
+impl ConnectionPlan {
+    pub fn new(links: impl IntoIterator<Item = Link>) -> ConnectionPlan {
+        let links: BTreeSet<Link> = links.into_iter().collect();
+
+        let mut incoming: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut edges: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for link in &links {
+            incoming.entry(&link.output_node_name).or_default();
+            if edges
+                .entry(&link.output_node_name)
+                .or_default()
+                .insert(&link.input_node_name)
+            {
+                *incoming.entry(&link.input_node_name).or_default() += 1;
+            }
+        }
+
+        let mut ranks: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut ready: VecDeque<&str> = incoming
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(node, _)| *node)
+            .collect();
+        while let Some(node) = ready.pop_front() {
+            ranks.insert(node, ranks.len());
+            for next in edges.get(node).into_iter().flatten() {
+                let count = incoming.get_mut(next).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    ready.push_back(*next);
+                }
+            }
+        }
+
+        let mut ordered: Vec<&Link> = links.iter().collect();
+        ordered.sort_by_key(|link| {
+            ranks
+                .get(link.output_node_name.as_str())
+                .copied()
+                .unwrap_or(usize::MAX)
+        });
+        ConnectionPlan {
+            links: ordered.into_iter().cloned().collect(),
+            priorities: BTreeMap::new(),
+        }
+    }
+
+    /// Plan with the links of a higher priority first, each priority keeps
+    /// the order `new` gives. Links downstream of a prioritized link inherit
+    /// its priority, so a prioritized input is audible all the way to the
+    /// outputs before the rest is wired.
+    pub fn prioritized(
+        links: impl IntoIterator<Item = Link>,
+        priorities: &BTreeMap<Link, i32>,
+    ) -> ConnectionPlan {
+        let mut plan = ConnectionPlan::new(links);
+        let mut node_priorities: BTreeMap<&str, i32> = BTreeMap::new();
+        let mut link_priorities: BTreeMap<Link, i32> = BTreeMap::new();
+        // Priorities only grow, so this settles even on cycles
+        loop {
+            let mut changed = false;
+            for link in &plan.links {
+                let inherited = node_priorities.get(link.output_node_name.as_str());
+                let priority = match (priorities.get(link), inherited) {
+                    (Some(own), Some(inherited)) => *own.max(inherited),
+                    (Some(priority), None) | (None, Some(priority)) => *priority,
+                    (None, None) => 0,
+                };
+                link_priorities.insert(link.clone(), priority);
+                match node_priorities.get_mut(link.input_node_name.as_str()) {
+                    Some(node_priority) if *node_priority >= priority => {}
+                    Some(node_priority) => {
+                        *node_priority = priority;
+                        changed = true;
+                    }
+                    None => {
+                        node_priorities.insert(&link.input_node_name, priority);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        plan.links
+            .sort_by_key(|link| std::cmp::Reverse(link_priorities[link]));
+        plan.priorities = link_priorities;
+        plan
+    }
+
+    pub fn priority(&self, link: &Link) -> i32 {
+        self.priorities.get(link).copied().unwrap_or_default()
+    }
+
+    /// Whether any link has a priority other than the default.
+    pub fn is_prioritized(&self) -> bool {
+        self.priorities.values().any(|priority| *priority != 0)
+    }
+
+    pub fn links(&self) -> &[Link] {
+        &self.links
+    }
+
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Fails if a node the plan links by name is shared by several live
+    /// nodes, two identical interfaces for example. Links by name can't tell
+    /// them apart and would end up on whichever pipewire finds first.
+    pub fn check_node_names(&self, nodes: &[ListNode]) -> Result<(), Box<dyn std::error::Error>> {
+        let names: BTreeSet<&str> = self
+            .links
+            .iter()
+            .flat_map(|link| {
+                [
+                    link.output_node_name.as_str(),
+                    link.input_node_name.as_str(),
+                ]
+            })
+            .collect();
+        let ambiguous: Vec<&str> = names
+            .into_iter()
+            .filter(|name| nodes.iter().filter(|n| n.name == *name).count() > 1)
+            .collect();
+        if ambiguous.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "Several nodes are named {}, give them unique names before linking",
+            ambiguous.join(", ")
+        )
+        .into())
+    }
+}
+
+/// The only node named `name`, none if there is no such node or several.
+pub fn unique_node<'a>(name: &str, nodes: &'a [ListNode]) -> Option<&'a ListNode> {
+    let mut matching = nodes.iter().filter(|n| n.name == name);
+    let node = matching.next()?;
+    matching.next().is_none().then_some(node)
+}
+
+/// Plugin ids of a channel strip, shared by channel strips and groups so the
+/// same wiring can be planned from either.
+pub trait StripPlugins {
+    fn strip_name(&self) -> &str;
+    fn gain_plugin_id(&self) -> u32;
+    fn saturator_plugin_id(&self) -> u32;
+    fn cross_fader_plugin_id(&self) -> Option<u32>;
+
+    /// First plugin of the chain, the cross fader if the strip has one.
+    fn input_plugin_id(&self) -> u32 {
+        self.cross_fader_plugin_id()
+            .unwrap_or_else(|| self.saturator_plugin_id())
+    }
+
+    fn plugin_id(&self, role: PluginRole) -> Option<u32> {
+        match role {
+            PluginRole::CrossFader => self.cross_fader_plugin_id(),
+            PluginRole::Saturator => Some(self.saturator_plugin_id()),
+            PluginRole::Gain => Some(self.gain_plugin_id()),
+        }
+    }
+}
+
+impl<T: StripPlugins> StripPlugins for &T {
+    fn strip_name(&self) -> &str {
+        (**self).strip_name()
+    }
+
+    fn gain_plugin_id(&self) -> u32 {
+        (**self).gain_plugin_id()
+    }
+
+    fn saturator_plugin_id(&self) -> u32 {
+        (**self).saturator_plugin_id()
+    }
+
+    fn cross_fader_plugin_id(&self) -> Option<u32> {
+        (**self).cross_fader_plugin_id()
+    }
+}
+
+/// Plugin and channel strip ids of an output stage.
+pub trait OutputStagePlugins {
+    fn cross_fader_plugin_id(&self) -> u32;
+    fn left_channel_strip_id(&self) -> u32;
+    fn right_channel_strip_id(&self) -> u32;
+}
+
+pub fn find_plugin(plugins: &[PmxPlugin], id: u32) -> Option<&PmxPlugin> {
+    plugins.iter().find(|p| p.id == id)
+}
+
+fn find_port<'a>(ports: &'a [ListPort], path: Option<&str>) -> Option<&'a ListPort> {
+    path.and_then(|path| ports.iter().find(|p| p.path == path))
+}
+
+/// Port of an input by its registry path, falling back to the configured
+/// `PortMatch` when the path doesn't resolve.
+fn find_input_port<'a>(
+    ports: &'a [ListPort],
+    nodes: &[ListNode],
+    path: Option<&str>,
+    port_match: Option<&PortMatch>,
+    reporter: &Reporter,
+) -> Option<&'a ListPort> {
+    if let Some(port) = find_port(ports, path) {
+        return Some(port);
+    }
+    let port_match = port_match?;
+
+    if let (Some(node_name), Some(port_name)) = (&port_match.node, &port_match.port) {
+        let port = ports.iter().find(|p| {
+            p.name == *port_name && find_node(nodes, p).is_some_and(|n| n.name == *node_name)
+        });
+        if port.is_some() {
+            return port;
+        }
+    }
+
+    if let Some(pattern) = &port_match.alias {
+        match Regex::new(pattern) {
+            Ok(regex) => {
+                let port = ports.iter().find(|p| regex.is_match(&p.alias));
+                if port.is_some() {
+                    return port;
+                }
+            }
+            Err(error) => {
+                reporter.skipped(&format!("Invalid port alias pattern {pattern}: {error}"));
+            }
+        }
+    }
+
+    if let Some(pattern) = &port_match.application {
+        match glob_to_regex(pattern) {
+            Ok(regex) => {
+                let port = application_port(ports, nodes, &regex, port_match.channel.as_deref());
+                if port.is_some() {
+                    return port;
+                }
+            }
+            Err(error) => {
+                reporter.skipped(&format!("Invalid application pattern {pattern}: {error}"));
+            }
+        }
+    }
+
+    None
+}
+
+/// Output port of the first application stream node matching `application`,
+/// the one carrying `channel` or the lowest id when no channel is given.
+fn application_port<'a>(
+    ports: &'a [ListPort],
+    nodes: &[ListNode],
+    application: &Regex,
+    channel: Option<&str>,
+) -> Option<&'a ListPort> {
+    let mut nodes: Vec<&ListNode> = nodes
+        .iter()
+        .filter(|n| application.is_match(&n.name))
+        .collect();
+    nodes.sort_by_key(|n| n.id);
+    nodes.into_iter().find_map(|node| {
+        ports
+            .iter()
+            .filter(|p| p.node_id == node.object_serial && p.direction == "out")
+            .filter(|p| channel.is_none() || channel == Some(p.audio_channel.as_str()))
+            .min_by_key(|p| p.id)
+    })
+}
+
+fn find_node<'a>(nodes: &'a [ListNode], port: &ListPort) -> Option<&'a ListNode> {
+    nodes.iter().find(|n| n.object_serial == port.node_id)
+}
+
+/// Plugin input ports the left and right source ports of an input are linked to.
+fn input_port_targets(input: &Input, input_config: &InputConfig) -> (Vec<u32>, Vec<u32>) {
+    match (input.input_type, input_config.mono_mode) {
+        (InputType::Mono, MonoMode::LeftOnly) => (vec![0], vec![]),
+        (InputType::Mono, _) => (vec![0, 1], vec![]),
+        (InputType::Stereo, MonoMode::MonoSum) => (vec![0, 1], vec![0, 1]),
+        (InputType::Stereo, _) => (vec![0], vec![1]),
+        (InputType::None, _) => (vec![], vec![]),
+    }
+}
+
+pub fn input_strip_links<S: StripPlugins>(
+    input: &Input,
+    input_config: &InputConfig,
+    channel_strip: &S,
+    plugins: &[PmxPlugin],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    if input.input_type == InputType::None {
+        reporter.log_info("Input type is None, nothing to do");
+        return links;
+    }
+
+    let (left_targets, right_targets) = input_port_targets(input, input_config);
+
+    let left_port = find_input_port(
+        ports,
+        nodes,
+        input.left_port_path.as_deref(),
+        input_config.left_port.as_ref(),
+        reporter,
+    );
+    let plugin = find_plugin(plugins, channel_strip.input_plugin_id());
+
+    match (left_port, plugin) {
+        (Some(port), Some(plugin)) => {
+            if let Some(node) = find_node(nodes, port) {
+                for target in &left_targets {
+                    links.push(Link::new(&node.name, port.id, &plugin.name, *target));
+                }
+            } else {
+                reporter.skipped(&format!(
+                    "Couldn't find node for port of input {}",
+                    input.name
+                ));
+            }
+        }
+        (None, None) => {
+            reporter.skipped(&format!(
+                "Can't connect input {}, port and plugin not found",
+                input.name
+            ));
+        }
+        (None, Some(_)) => {
+            reporter.skipped(&format!(
+                "Can't connect input {}, port not found",
+                input.name
+            ));
+        }
+        (Some(_), None) => {
+            reporter.skipped(&format!(
+                "Can't connect input {}, plugin not found",
+                input.name
+            ));
+        }
+    };
+
+    if !right_targets.is_empty() {
+        let right_port = find_input_port(
+            ports,
+            nodes,
+            input.right_port_path.as_deref(),
+            input_config.right_port.as_ref(),
+            reporter,
+        );
+        if let (Some(port), Some(plugin)) = (right_port, plugin) {
+            if let Some(node) = find_node(nodes, port) {
+                for target in &right_targets {
+                    links.push(Link::new(&node.name, port.id, &plugin.name, *target));
+                }
+            }
+        }
+    }
+
+    links
+}
+
+/// Inputs that get a looper, paired with their loop number. Inputs keep the
+/// number `assigned` to them by an earlier build, so the registry listing
+/// inputs in a different order can't swap loops. New inputs get the lowest
+/// free numbers in input order.
+pub fn looper_inputs<'a>(
+    topology: &Topology,
+    inputs: &'a [Input],
+    assigned: &BTreeMap<String, u32>,
+    reporter: &Reporter,
+) -> Vec<(u32, &'a Input)> {
+    let mut looper_inputs = Vec::new();
+    if !topology.looper.enabled {
+        reporter.log_info("Loopers are disabled, nothing to do");
+        return looper_inputs;
+    }
+
+    let mut eligible = Vec::new();
+    for input in inputs {
+        if !topology.has_chain(&input.name) {
+            continue;
+        }
+        let input_config = topology.input_config(&input.name);
+        if !input_config.looper {
+            reporter.log_info(&format!(
+                "Looper disabled for input {}, nothing to do",
+                input.name
+            ));
+            continue;
+        }
+        if input_config.channel_strip_type == ChannelStripType::Basic {
+            reporter.log_info(&format!(
+                "Channel strip of input {} is Basic, no looper to play back into",
+                input.name
+            ));
+            continue;
+        }
+        eligible.push(input);
+    }
+
+    let mut taken: BTreeSet<u32> = eligible
+        .iter()
+        .filter_map(|input| assigned.get(&input.name))
+        .copied()
+        .collect();
+    for input in eligible {
+        let loop_number = match assigned.get(&input.name) {
+            Some(loop_number) => *loop_number,
+            None => {
+                let loop_number = (0..).find(|n| !taken.contains(n)).unwrap();
+                taken.insert(loop_number);
+                loop_number
+            }
+        };
+        if topology
+            .looper
+            .capacity()
+            .is_some_and(|capacity| loop_number >= capacity)
+        {
+            reporter.skipped(&format!(
+                "No looper left for input {}, loop {loop_number} is past the last one",
+                input.name
+            ));
+            continue;
+        }
+        looper_inputs.push((loop_number, input));
+    }
+    looper_inputs.sort_by_key(|(loop_number, _)| *loop_number);
+    looper_inputs
+}
+
+/// Input ports into the recording ports of the input's loop.
+pub fn looper_input_links(
+    input: &Input,
+    input_config: &InputConfig,
+    looper: &Looper,
+    looper_backend: &LooperBackend,
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    if input.input_type == InputType::None {
+        reporter.log_info("Input type is None, nothing to do");
+        return links;
+    }
+
+    let (left_targets, right_targets) = input_port_targets(input, input_config);
+    let sides = [
+        (
+            input.left_port_path.as_deref(),
+            input_config.left_port.as_ref(),
+            left_targets,
+        ),
+        (
+            input.right_port_path.as_deref(),
+            input_config.right_port.as_ref(),
+            right_targets,
+        ),
+    ];
+    let looper_node = looper_backend.loop_node(looper.loop_number);
+    for (path, port_match, targets) in sides {
+        if targets.is_empty() {
+            continue;
+        }
+        let Some((port, node)) = find_input_port(ports, nodes, path, port_match, reporter)
+            .and_then(|port| find_node(nodes, port).map(|node| (port, node)))
+        else {
+            continue;
+        };
+        for target in targets
+            .into_iter()
+            .filter(|t| looper_backend.has_channel(*t))
+        {
+            match looper_backend.record_port(looper.loop_number, target, nodes, ports) {
+                Some(record_port) => {
+                    links.push(Link::new(&node.name, port.id, &looper_node, record_port))
+                }
+                None => reporter.skipped(&format!(
+                    "Looper {looper_node} has no recording port for channel {target} of loop {} \
+                     of input {}",
+                    looper.loop_number, input.name
+                )),
+            }
+        }
+    }
+
+    links
+}
+
+/// Playback ports of `looper` into `plugin`, each channel into the port
+/// `channel + port_offset`.
+fn looper_playback_links(
+    looper: &Looper,
+    looper_backend: &LooperBackend,
+    plugin: &PmxPlugin,
+    port_offset: u32,
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let looper_node = looper_backend.loop_node(looper.loop_number);
+    [0, 1]
+        .into_iter()
+        .filter(|channel| looper_backend.has_channel(*channel))
+        .filter_map(|channel| {
+            let Some(playback_port) =
+                looper_backend.playback_port(looper.loop_number, channel, nodes, ports)
+            else {
+                reporter.skipped(&format!(
+                    "Looper {looper_node} has no playback port for channel {channel} of loop {}",
+                    looper.loop_number
+                ));
+                return None;
+            };
+            Some(Link::new(
+                &looper_node,
+                playback_port,
+                &plugin.name,
+                channel + port_offset,
+            ))
+        })
+        .collect()
+}
+
+pub fn looper_strip_links<S: StripPlugins>(
+    looper: &Looper,
+    looper_backend: &LooperBackend,
+    channel_strip: &S,
+    plugins: &[PmxPlugin],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let Some(cross_fader_plugin_id) = channel_strip.cross_fader_plugin_id() else {
+        reporter.log_info("Channel strip type is Basic, nothing to do!");
+        return Vec::new();
+    };
+
+    match find_plugin(plugins, cross_fader_plugin_id) {
+        Some(plugin) => {
+            looper_playback_links(looper, looper_backend, plugin, 2, ports, nodes, reporter)
+        }
+        None => {
+            reporter.skipped(&format!(
+                "Couldn't find cross fader plugin for channel strip {}",
+                channel_strip.strip_name()
+            ));
+            Vec::new()
+        }
+    }
+}
+
+/// Looper playback of `input` wired into its group channel strip, next to
+/// the path through the input strip crossfader.
+#[allow(clippy::too_many_arguments)]
+pub fn looper_group_links<G: StripPlugins>(
+    input: &Input,
+    looper: &Looper,
+    looper_backend: &LooperBackend,
+    group_channel_strips: &[G],
+    plugins: &[PmxPlugin],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let group_name = &input.group_channel_strip_name;
+    let group_plugin = group_channel_strips
+        .iter()
+        .find(|g| g.strip_name() == naming().group_strip(group_name))
+        .and_then(|g| find_plugin(plugins, g.saturator_plugin_id()));
+    let Some(group_plugin) = group_plugin else {
+        reporter.skipped(&format!(
+            "Couldn't find group {group_name} to monitor the looper of input {}",
+            input.name
+        ));
+        return Vec::new();
+    };
+
+    looper_playback_links(
+        looper,
+        looper_backend,
+        group_plugin,
+        0,
+        ports,
+        nodes,
+        reporter,
+    )
+}
+
+/// Group the topology's rules put the input `input_name` in: the first
+/// group whose `match_inputs` matches the whole input name or whose
+/// `match_tags` matches one of its tags. Invalid patterns match nothing,
+/// validation reports them.
+pub fn matched_group<'a>(topology: &'a Topology, input_name: &str) -> Option<&'a str> {
+    let tags = topology.input_config(input_name).tags;
+    let matches = |pattern: &Option<String>, text: &str| {
+        pattern
+            .as_ref()
+            .and_then(|p| Regex::new(&format!("^(?:{p})$")).ok())
+            .is_some_and(|regex| regex.is_match(text))
+    };
+    topology
+        .groups
+        .iter()
+        .find(|group| {
+            matches(&group.match_inputs, input_name)
+                || tags.iter().any(|tag| matches(&group.match_tags, tag))
+        })
+        .map(|group| group.name.as_str())
+}
+
+/// The inputs with their groups as the topology's rules assign them. Inputs
+/// no rule matches keep their registered group and are reported, as long as
+/// any group has rules at all.
+pub fn grouped_inputs(topology: &Topology, inputs: &[Input], reporter: &Reporter) -> Vec<Input> {
+    let has_rules = topology
+        .groups
+        .iter()
+        .any(|g| g.match_inputs.is_some() || g.match_tags.is_some());
+    if !has_rules {
+        return inputs.to_vec();
+    }
+    let mut ungrouped = Vec::new();
+    let grouped = inputs
+        .iter()
+        .map(|input| {
+            let mut input = input.clone();
+            match matched_group(topology, &input.name) {
+                Some(group) => input.group_channel_strip_name = String::from(group),
+                None => ungrouped.push(input.name.clone()),
+            }
+            input
+        })
+        .collect();
+    reporter.inputs_ungrouped(ungrouped);
+    grouped
+}
+
+pub fn group_links<S: StripPlugins, G: StripPlugins>(
+    input_channel: &Input,
+    input_channel_strip: &S,
+    group_channel_strips: &[G],
+    plugins: &[PmxPlugin],
+    group_ports: &PortMap,
+    group_send: GroupSend,
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let group_name = &input_channel.group_channel_strip_name;
+
+    let Some(group_channel_strip) = group_channel_strips
+        .iter()
+        .find(|g| g.strip_name() == naming().group_strip(group_name))
+    else {
+        reporter.skipped(&format!(
+            "Couldn't find group channel {} for input channel {}",
+            group_name, input_channel.name
+        ));
+        return Vec::new();
+    };
+
+    let group_channel_plugin = find_plugin(plugins, group_channel_strip.saturator_plugin_id());
+    let send_plugin_id = match group_send {
+        GroupSend::PreFader => input_channel_strip.input_plugin_id(),
+        GroupSend::PostFader => input_channel_strip.gain_plugin_id(),
+    };
+    let input_channel_plugin = find_plugin(plugins, send_plugin_id);
+
+    if let Some((group_channel_plugin, input_channel_plugin)) =
+        group_channel_plugin.zip(input_channel_plugin)
+    {
+        group_ports.links(&input_channel_plugin.name, &group_channel_plugin.name)
+    } else {
+        reporter.skipped(&format!(
+            "Couldn't find plugins to connect input channel {} to group {}",
+            input_channel.name, group_name
+        ));
+        Vec::new()
+    }
+}
+
+pub fn aux_bus_links<S: StripPlugins, B: StripPlugins, G: StripPlugins>(
+    aux_bus: &AuxBusConfig,
+    aux_bus_channel_strip: &B,
+    input_channel_strips: &[S],
+    group_channel_strips: &[G],
+    plugins: &[PmxPlugin],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    let Some(bus_input_plugin) = find_plugin(plugins, aux_bus_channel_strip.saturator_plugin_id())
+    else {
+        reporter.skipped(&format!(
+            "Couldn't find input plugin for aux bus {}",
+            aux_bus.name
+        ));
+        return links;
+    };
+
+    for send in &aux_bus.sends {
+        let send_plugin = input_channel_strips
+            .iter()
+            .find(|s| s.strip_name() == naming().input_strip(send))
+            .and_then(|s| find_plugin(plugins, s.gain_plugin_id()));
+
+        if let Some(send_plugin) = send_plugin {
+            links.extend(
+                aux_bus
+                    .send_ports
+                    .links(&send_plugin.name, &bus_input_plugin.name),
+            );
+        } else {
+            reporter.skipped(&format!(
+                "Couldn't find send plugin for input {} on aux bus {}",
+                send, aux_bus.name
+            ));
+        }
+    }
+
+    if let Some(destination) = &aux_bus.destination {
+        let bus_output_plugin = find_plugin(plugins, aux_bus_channel_strip.gain_plugin_id());
+        let destination_plugin = group_channel_strips
+            .iter()
+            .find(|g| g.strip_name() == naming().group_strip(destination))
+            .and_then(|g| find_plugin(plugins, g.saturator_plugin_id()));
+
+        if let Some((bus_output_plugin, destination_plugin)) =
+            bus_output_plugin.zip(destination_plugin)
+        {
+            links.extend(
+                aux_bus
+                    .return_ports
+                    .links(&bus_output_plugin.name, &destination_plugin.name),
+            );
+        } else {
+            reporter.skipped(&format!(
+                "Couldn't route aux bus {} into group {}",
+                aux_bus.name, destination
+            ));
+        }
+    }
+
+    links
+}
+
+pub fn sidechain_links<S: StripPlugins>(
+    sidechain: &SidechainConfig,
+    channel_strips: &[S],
+    plugins: &[PmxPlugin],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let find_role_plugin = |strip_name: &str, role: PluginRole| {
+        channel_strips
+            .iter()
+            .find(|s| naming().matches(s.strip_name(), strip_name))
+            .and_then(|s| s.plugin_id(role))
+            .and_then(|id| find_plugin(plugins, id))
+    };
+
+    let source_plugin = find_role_plugin(&sidechain.source, sidechain.source_plugin);
+    let destination_plugin = find_role_plugin(&sidechain.destination, sidechain.destination_plugin);
+
+    if let Some((source_plugin, destination_plugin)) = source_plugin.zip(destination_plugin) {
+        std::iter::zip(AUDIO_OUTPUT_PORTS, &sidechain.destination_ports)
+            .map(|(output_port, input_port)| {
+                Link::new(
+                    &source_plugin.name,
+                    output_port,
+                    &destination_plugin.name,
+                    *input_port,
+                )
+            })
+            .collect()
+    } else {
+        reporter.skipped(&format!(
+            "Couldn't find plugins for sidechain {} -> {}",
+            sidechain.source, sidechain.destination
+        ));
+        Vec::new()
+    }
+}
+
+pub fn cue_tap_links<C: StripPlugins, S: StripPlugins>(
+    cue: &CueConfig,
+    cue_channel_strip: &C,
+    input_channels: &[Input],
+    channel_strips: &[S],
+    plugins: &[PmxPlugin],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    let Some(cue_plugin) = find_plugin(plugins, cue_channel_strip.saturator_plugin_id()) else {
+        reporter.skipped(&format!(
+            "Couldn't find input plugin for cue bus {}",
+            cue.name
+        ));
+        return links;
+    };
+
+    for input in input_channels {
+        let tap_plugin = channel_strips
+            .iter()
+            .find(|s| s.strip_name() == naming().input_strip(&input.name))
+            .and_then(|s| s.plugin_id(cue.tap))
+            .and_then(|id| find_plugin(plugins, id));
+
+        if let Some(tap_plugin) = tap_plugin {
+            links.extend(cue.tap_ports.links(&tap_plugin.name, &cue_plugin.name));
+        } else {
+            reporter.skipped(&format!(
+                "Couldn't find cue tap plugin for input {}",
+                input.name
+            ));
+        }
+    }
+
+    links
+}
+
+/// Links from the ports of `input` into the input ports of `plugin`.
+fn input_plugin_links(
+    input: &Input,
+    input_config: &InputConfig,
+    plugin: &PmxPlugin,
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+    let (left_targets, right_targets) = input_port_targets(input, input_config);
+    let sides = [
+        (
+            input.left_port_path.as_deref(),
+            input_config.left_port.as_ref(),
+            left_targets,
+        ),
+        (
+            input.right_port_path.as_deref(),
+            input_config.right_port.as_ref(),
+            right_targets,
+        ),
+    ];
+    for (path, port_match, targets) in sides {
+        if targets.is_empty() {
+            continue;
+        }
+        let port = find_input_port(ports, nodes, path, port_match, reporter);
+        match port.and_then(|port| find_node(nodes, port).map(|node| (port, node))) {
+            Some((port, node)) => {
+                for target in targets {
+                    links.push(Link::new(&node.name, port.id, &plugin.name, target));
+                }
+            }
+            None => {
+                reporter.skipped(&format!("Couldn't find port of input {}", input.name));
+            }
+        }
+    }
+    links
+}
+
+/// A passthrough input linked into its group channel strip, or straight to
+/// its `passthrough_output`.
+#[allow(clippy::too_many_arguments)]
+pub fn passthrough_links<G: StripPlugins>(
+    input: &Input,
+    input_config: &InputConfig,
+    group_channel_strips: &[G],
+    outputs: &[Output],
+    plugins: &[PmxPlugin],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    if let Some(output_name) = &input_config.passthrough_output {
+        let Some(output) = outputs.iter().find(|o| o.name == *output_name) else {
+            reporter.skipped(&format!(
+                "Couldn't find output {output_name} of passthrough input {}",
+                input.name
+            ));
+            return Vec::new();
+        };
+        let (left_targets, right_targets) = input_port_targets(input, input_config);
+        let sides = [
+            (
+                input.left_port_path.as_deref(),
+                input_config.left_port.as_ref(),
+                left_targets,
+            ),
+            (
+                input.right_port_path.as_deref(),
+                input_config.right_port.as_ref(),
+                right_targets,
+            ),
+        ];
+        let mut links = Vec::new();
+        for (path, port_match, channels) in sides {
+            if channels.is_empty() {
+                continue;
+            }
+            let Some((port, node)) = find_input_port(ports, nodes, path, port_match, reporter)
+                .and_then(|port| find_node(nodes, port).map(|node| (port, node)))
+            else {
+                reporter.skipped(&format!("Couldn't find port of input {}", input.name));
+                continue;
+            };
+            for channel in channels {
+                let output_path = output.port_paths.get(channel as usize).map(|p| p.as_str());
+                match find_port(ports, output_path)
+                    .and_then(|o| find_node(nodes, o).map(|n| (o, n)))
+                {
+                    Some((output_port, output_node)) => links.push(Link::new(
+                        &node.name,
+                        port.id,
+                        &output_node.name,
+                        output_port.id,
+                    )),
+                    None => reporter.skipped(&format!(
+                        "Couldn't find port {output_path:?} of channel {channel} of output {}",
+                        output.name
+                    )),
+                }
+            }
+        }
+        return links;
+    }
+
+    let group_name = &input.group_channel_strip_name;
+    let group_plugin = group_channel_strips
+        .iter()
+        .find(|g| g.strip_name() == naming().group_strip(group_name))
+        .and_then(|g| find_plugin(plugins, g.saturator_plugin_id()));
+    match group_plugin {
+        Some(group_plugin) => {
+            input_plugin_links(input, input_config, group_plugin, ports, nodes, reporter)
+        }
+        None => {
+            reporter.skipped(&format!(
+                "Couldn't find group {group_name} of passthrough input {}",
+                input.name
+            ));
+            Vec::new()
+        }
+    }
+}
+
+/// Channel of the record node an input port is captured on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RecordChannel {
+    pub channel: usize,
+    pub input: String,
+    /// `left` or `right` port of the input
+    pub side: &'static str,
+}
+
+/// Channels of the record node, the recorded inputs in the order the
+/// topology lists them with the left port first. Every recorded input keeps
+/// a pair of channels whether the registry lists it or not, so an unplugged
+/// input doesn't move the inputs after it. Mono inputs take the first
+/// channel of their pair.
+pub fn record_channels(topology: &Topology, inputs: &[Input]) -> Vec<RecordChannel> {
+    let mut channels = Vec::new();
+    for (index, input_config) in topology.inputs.iter().filter(|i| i.record).enumerate() {
+        let Some(input) = inputs.iter().find(|i| i.name == input_config.name) else {
+            continue;
+        };
+        let sides: &[&'static str] = match input.input_type {
+            InputType::Mono => &["left"],
+            InputType::Stereo => &["left", "right"],
+            InputType::None => &[],
+        };
+        for (offset, &side) in sides.iter().enumerate() {
+            channels.push(RecordChannel {
+                channel: index * 2 + offset,
+                input: input.name.clone(),
+                side,
+            });
+        }
+    }
+    channels
+}
+
+/// Raw input ports into their channels of the record node.
+pub fn record_links(
+    record: &RecordConfig,
+    channels: &[RecordChannel],
+    topology: &Topology,
+    inputs: &[Input],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let Some(record_node) = unique_node(&record.node, nodes) else {
+        reporter.skipped(&format!("Couldn't find record node {}", record.node));
+        return Vec::new();
+    };
+    let mut record_ports: Vec<&ListPort> = ports
+        .iter()
+        .filter(|p| p.node_id == record_node.object_serial && p.direction == "in")
+        .collect();
+    record_ports.sort_by_key(|p| p.id);
+
+    let mut links = Vec::new();
+    for channel in channels {
+        let Some(record_port) = record_ports.get(channel.channel) else {
+            reporter.skipped(&format!(
+                "Record node {} has no port for channel {} of input {}",
+                record.node, channel.channel, channel.input
+            ));
+            continue;
+        };
+        let Some(input) = inputs.iter().find(|i| i.name == channel.input) else {
+            continue;
+        };
+        let input_config = topology.input_config(&input.name);
+        let (path, port_match) = match channel.side {
+            "left" => (
+                input.left_port_path.as_deref(),
+                input_config.left_port.as_ref(),
+            ),
+            _ => (
+                input.right_port_path.as_deref(),
+                input_config.right_port.as_ref(),
+            ),
+        };
+        match find_input_port(ports, nodes, path, port_match, reporter)
+            .and_then(|port| find_node(nodes, port).map(|node| (port, node)))
+        {
+            Some((port, node)) => links.push(Link::new(
+                &node.name,
+                port.id,
+                &record_node.name,
+                record_port.id,
+            )),
+            None => reporter.skipped(&format!(
+                "Couldn't find {} port of recorded input {}",
+                channel.side, input.name
+            )),
+        }
+    }
+    links
+}
+
+/// Talkback microphone into the cue bus, and through the duck plugin into
+/// the channel strips of the output stages.
+#[allow(clippy::too_many_arguments)]
+pub fn talkback_links<C: StripPlugins, S: StripPlugins>(
+    talkback: &TalkbackConfig,
+    input: &Input,
+    input_config: &InputConfig,
+    cue_channel_strip: Option<&C>,
+    duck_plugin_id: Option<u32>,
+    output_stage_strips: &[S],
+    plugins: &[PmxPlugin],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    if let Some(cue_channel_strip) = cue_channel_strip {
+        match find_plugin(plugins, cue_channel_strip.saturator_plugin_id()) {
+            Some(cue_plugin) => links.extend(input_plugin_links(
+                input,
+                input_config,
+                cue_plugin,
+                ports,
+                nodes,
+                reporter,
+            )),
+            None => reporter.skipped(&format!(
+                "Couldn't find input plugin of cue bus {} for talkback",
+                cue_channel_strip.strip_name()
+            )),
+        }
+    }
+
+    if talkback.duck_plugin.is_none() {
+        return links;
+    }
+    let Some(duck_plugin) = duck_plugin_id.and_then(|id| find_plugin(plugins, id)) else {
+        reporter.skipped(&format!(
+            "Couldn't find duck plugin of talkback {}",
+            talkback.input
+        ));
+        return links;
+    };
+    links.extend(input_plugin_links(
+        input,
+        input_config,
+        duck_plugin,
+        ports,
+        nodes,
+        reporter,
+    ));
+    for strip in output_stage_strips {
+        match find_plugin(plugins, strip.saturator_plugin_id()) {
+            Some(strip_plugin) => links.extend(
+                talkback
+                    .duck_ports
+                    .links(&duck_plugin.name, &strip_plugin.name),
+            ),
+            None => reporter.skipped(&format!(
+                "Couldn't find input plugin of output stage channel strip {} for talkback",
+                strip.strip_name()
+            )),
+        }
+    }
+    links
+}
+
+/// Plugin port to channel pairs with the output's mode applied. Even plugin
+/// ports are the left, odd ports the right side. In `mono_sum` mode the
+/// ports are those of the mono sum plugin, its single output feeds every
+/// mapped channel.
+fn output_mode_pairs(mode: OutputMode, pairs: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    match mode {
+        OutputMode::Stereo => pairs.to_vec(),
+        OutputMode::Swap => pairs
+            .iter()
+            .map(|(plugin_port, channel)| (plugin_port ^ 1, *channel))
+            .collect(),
+        OutputMode::MonoSum => {
+            let mut summed = Vec::new();
+            for (_, channel) in pairs {
+                if !summed.contains(&(0, *channel)) {
+                    summed.push((0, *channel));
+                }
+            }
+            summed
+        }
+    }
+}
+
+/// Links from plugin ports to the output channels `pairs` maps them to. In
+/// `mono_sum` mode both sides of `plugin` go into the output's mono sum
+/// plugin and the mono sum feeds the channels, without one the output is
+/// left unwired.
+fn output_channel_links(
+    plugin: &PmxPlugin,
+    output: &Output,
+    pairs: &[(u32, u32)],
+    plugins: &[PmxPlugin],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+    let mut source = plugin;
+    if output.mode == OutputMode::MonoSum {
+        let Some(mono_sum) = output
+            .mono_sum_plugin_id
+            .and_then(|id| find_plugin(plugins, id))
+        else {
+            reporter.skipped(&format!(
+                "Couldn't find mono sum plugin of output {}",
+                output.name
+            ));
+            return links;
+        };
+        // Pipewire sums both links into the plugin's single input
+        links.push(Link::new(&plugin.name, 0, &mono_sum.name, 0));
+        links.push(Link::new(&plugin.name, 1, &mono_sum.name, 0));
+        source = mono_sum;
+    }
+    for (plugin_port, channel) in output_mode_pairs(output.mode, pairs) {
+        let path = output.port_paths.get(channel as usize).map(|p| p.as_str());
+        let port = find_port(ports, path);
+        match port.and_then(|port| find_node(nodes, port).map(|node| (port, node))) {
+            Some((port, node)) => {
+                links.push(Link::new(&source.name, plugin_port, &node.name, port.id));
+            }
+            None => {
+                reporter.skipped(&format!(
+                    "Couldn't find port {:?} of channel {channel} of output {}",
+                    path, output.name
+                ));
+            }
+        }
+    }
+    links
+}
+
+/// Routes the gain plugin of a channel strip to the named physical output,
+/// left and right to its first two channels unless it has a channel map.
+pub fn strip_output_links<S: StripPlugins>(
+    channel_strip: &S,
+    output_name: &str,
+    outputs: &[Output],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    plugins: &[PmxPlugin],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let Some(output) = outputs.iter().find(|o| o.name == output_name) else {
+        reporter.skipped(&format!("Couldn't find output {output_name}"));
+        return Vec::new();
+    };
+
+    let Some(plugin) = find_plugin(plugins, channel_strip.gain_plugin_id()) else {
+        reporter.skipped(&format!(
+            "Couldn't find gain plugin of channel strip {}",
+            channel_strip.strip_name()
+        ));
+        return Vec::new();
+    };
+
+    let default_map = PortMap::default();
+    let channel_map = output.channel_map.as_ref().unwrap_or(&default_map);
+    output_channel_links(
+        plugin,
+        output,
+        channel_map.pairs(),
+        plugins,
+        ports,
+        nodes,
+        reporter,
+    )
+}
+
+/// Routes a group channel strip to each of its extra destinations the same
+/// way strips are routed to a physical output.
+pub fn group_destination_links<S: StripPlugins>(
+    group: &GroupConfig,
+    channel_strip: &S,
+    outputs: &[Output],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    plugins: &[PmxPlugin],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    group
+        .destinations
+        .iter()
+        .flat_map(|destination| {
+            strip_output_links(
+                channel_strip,
+                destination,
+                outputs,
+                ports,
+                nodes,
+                plugins,
+                reporter,
+            )
+        })
+        .collect()
+}
+
+/// Routes a group with a parent into the parent group's channel strip.
+pub fn parent_group_links<S: StripPlugins>(
+    group: &GroupConfig,
+    channel_strip: &S,
+    group_channel_strips: &[S],
+    plugins: &[PmxPlugin],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let Some(parent) = &group.parent else {
+        return Vec::new();
+    };
+    let group_plugin = find_plugin(plugins, channel_strip.gain_plugin_id());
+    let parent_plugin = group_channel_strips
+        .iter()
+        .find(|g| g.strip_name() == naming().group_strip(parent))
+        .and_then(|g| find_plugin(plugins, g.saturator_plugin_id()));
+    match group_plugin.zip(parent_plugin) {
+        Some((group_plugin, parent_plugin)) => {
+            PortMap::default().links(&group_plugin.name, &parent_plugin.name)
+        }
+        None => {
+            reporter.skipped(&format!(
+                "Couldn't route group {} into its parent group {parent}",
+                group.name
+            ));
+            Vec::new()
+        }
+    }
+}
+
+/// Plugins an insert splits the chain between: the one feeding the send and
+/// the one the return feeds.
+fn insert_plugins<'a, S: StripPlugins>(
+    insert: &InsertConfig,
+    channel_strip: &S,
+    plugins: &'a [PmxPlugin],
+) -> Option<(&'a PmxPlugin, &'a PmxPlugin)> {
+    let next = match insert.after {
+        PluginRole::CrossFader => PluginRole::Saturator,
+        PluginRole::Saturator => PluginRole::Gain,
+        PluginRole::Gain => return None,
+    };
+    let send_plugin = find_plugin(plugins, channel_strip.plugin_id(insert.after)?)?;
+    let return_plugin = find_plugin(plugins, channel_strip.plugin_id(next)?)?;
+    Some((send_plugin, return_plugin))
+}
+
+/// The four links of a hardware insert: the plugin before the split into the
+/// send output and the return input into the plugin after it.
+#[allow(clippy::too_many_arguments)]
+pub fn insert_links<S: StripPlugins>(
+    insert: &InsertConfig,
+    channel_strip: &S,
+    outputs: &[Output],
+    inputs: &[Input],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    plugins: &[PmxPlugin],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    let Some((send_plugin, return_plugin)) = insert_plugins(insert, channel_strip, plugins) else {
+        reporter.skipped(&format!(
+            "Couldn't find the plugins to split channel strip {} for its insert",
+            channel_strip.strip_name()
+        ));
+        return links;
+    };
+    let Some(send) = outputs.iter().find(|o| o.name == insert.send) else {
+        reporter.skipped(&format!("Couldn't find insert send output {}", insert.send));
+        return links;
+    };
+    let Some(return_input) = inputs.iter().find(|i| i.name == insert.return_input) else {
+        reporter.skipped(&format!(
+            "Couldn't find insert return input {}",
+            insert.return_input
+        ));
+        return links;
+    };
+
+    let send_paths =
+        [send.port_paths.first(), send.port_paths.get(1)].map(|p| p.map(|p| p.as_str()));
+    let return_paths =
+        [&return_input.left_port_path, &return_input.right_port_path].map(|p| p.as_deref());
+    for (channel, (send_path, return_path)) in
+        std::iter::zip(AUDIO_OUTPUT_PORTS, std::iter::zip(send_paths, return_paths))
+    {
+        let send_port = find_port(ports, send_path)
+            .and_then(|port| find_node(nodes, port).map(|node| (port, node)));
+        let return_port = find_port(ports, return_path)
+            .and_then(|port| find_node(nodes, port).map(|node| (port, node)));
+        match (send_port, return_port) {
+            (Some((send_port, send_node)), Some((return_port, return_node))) => {
+                links.push(Link::new(
+                    &send_plugin.name,
+                    channel,
+                    &send_node.name,
+                    send_port.id,
+                ));
+                links.push(Link::new(
+                    &return_node.name,
+                    return_port.id,
+                    &return_plugin.name,
+                    channel,
+                ));
+            }
+            _ => reporter.skipped(&format!(
+                "Couldn't find ports {:?} -> {:?} of the insert on channel strip {}",
+                send_path,
+                return_path,
+                channel_strip.strip_name()
+            )),
+        }
+    }
+
+    links
+}
+
+/// Live links between the plugins an insert splits, removed so the signal
+/// only passes through the hardware.
+pub fn insert_bypass_links<S: StripPlugins>(
+    insert: &InsertConfig,
+    channel_strip: &S,
+    plugins: &[PmxPlugin],
+    links: &[ListLink],
+    nodes: &[ListNode],
+) -> Vec<(u32, Link)> {
+    let Some((send_plugin, return_plugin)) = insert_plugins(insert, channel_strip, plugins) else {
+        return Vec::new();
+    };
+    live_links_between(send_plugin, return_plugin, links, nodes)
+}
+
+fn live_links_between(
+    output_plugin: &PmxPlugin,
+    input_plugin: &PmxPlugin,
+    links: &[ListLink],
+    nodes: &[ListNode],
+) -> Vec<(u32, Link)> {
+    links
+        .iter()
+        .filter_map(|live_link| resolve_live_link(live_link, nodes).map(|l| (live_link.id, l)))
+        .filter(|(_, link)| {
+            link.output_node_name == output_plugin.name && link.input_node_name == input_plugin.name
+        })
+        .collect()
+}
+
+/// Plugins of a channel strip's extra chain, `None` unless every one of them
+/// is live.
+fn chain_plugins<'a>(plugin_ids: &[u32], plugins: &'a [PmxPlugin]) -> Option<Vec<&'a PmxPlugin>> {
+    plugin_ids
+        .iter()
+        .map(|id| find_plugin(plugins, *id))
+        .collect()
+}
+
+/// Links running the saturator through the extra plugins of the chain into
+/// the gain plugin.
+pub fn plugin_chain_links<S: StripPlugins>(
+    channel_strip: &S,
+    plugin_ids: &[u32],
+    configs: &[ChainPluginConfig],
+    plugins: &[PmxPlugin],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+    let saturator_plugin = find_plugin(plugins, channel_strip.saturator_plugin_id());
+    let gain_plugin = find_plugin(plugins, channel_strip.gain_plugin_id());
+    let chain = chain_plugins(plugin_ids, plugins);
+    let (Some(saturator_plugin), Some(gain_plugin), Some(chain)) =
+        (saturator_plugin, gain_plugin, chain)
+    else {
+        reporter.skipped(&format!(
+            "Couldn't find the plugin chain of channel strip {}",
+            channel_strip.strip_name()
+        ));
+        return links;
+    };
+
+    let mut previous = saturator_plugin;
+    for (plugin, config) in std::iter::zip(chain, configs) {
+        links.extend(config.ports.links(&previous.name, &plugin.name));
+        previous = plugin;
+    }
+    links.extend(PortMap::default().links(&previous.name, &gain_plugin.name));
+    links
+}
+
+/// Live links from the saturator straight into the gain plugin, removed once
+/// the extra plugins of the chain sit in between.
+pub fn plugin_chain_bypass_links<S: StripPlugins>(
+    channel_strip: &S,
+    plugin_ids: &[u32],
+    plugins: &[PmxPlugin],
+    links: &[ListLink],
+    nodes: &[ListNode],
+) -> Vec<(u32, Link)> {
+    let saturator_plugin = find_plugin(plugins, channel_strip.saturator_plugin_id());
+    let gain_plugin = find_plugin(plugins, channel_strip.gain_plugin_id());
+    match (
+        saturator_plugin,
+        gain_plugin,
+        chain_plugins(plugin_ids, plugins),
+    ) {
+        (Some(saturator_plugin), Some(gain_plugin), Some(_)) => {
+            live_links_between(saturator_plugin, gain_plugin, links, nodes)
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Channel strips feeding an output stage: the configured sources, or every
+/// group plus the aux buses that don't return into a group.
+pub fn output_stage_sources<'a, G: StripPlugins>(
+    topology: &Topology,
+    output_stage: &OutputStageConfig,
+    group_channel_strips: &'a [G],
+    aux_bus_channel_strips: &'a [G],
+) -> Vec<&'a G> {
+    if let Some(sources) = &output_stage.sources {
+        return group_channel_strips
+            .iter()
+            .chain(aux_bus_channel_strips)
+            .filter(|strip| {
+                sources
+                    .iter()
+                    .any(|s| naming().matches(strip.strip_name(), s))
+            })
+            .collect();
+    }
+
+    let top_groups = group_channel_strips.iter().filter(|strip| {
+        !topology.groups.iter().any(|group| {
+            naming().group_strip(&group.name) == strip.strip_name() && group.parent.is_some()
+        })
+    });
+    let direct_aux_buses = aux_bus_channel_strips.iter().filter(|strip| {
+        topology.aux_buses.iter().any(|bus| {
+            naming().aux_bus_strip(&bus.name) == strip.strip_name() && bus.destination.is_none()
+        })
+    });
+    top_groups.chain(direct_aux_buses).collect()
+}
+
+/// Routes every source into the output stage: into its left channel strip,
+/// crossfader side A, and its right one, side B. Groups assigned to a side
+/// only feed that side.
+pub fn output_stage_links<G: StripPlugins, O: OutputStagePlugins>(
+    topology: &Topology,
+    group_channel_strips: &[G],
+    output_stage: &O,
+    plugins: &[PmxPlugin],
+    channel_strips: &[ChannelStrip],
+    source_ports: &PortMap,
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    let left_channel_strip = channel_strips
+        .iter()
+        .find(|c| c.id == output_stage.left_channel_strip_id());
+
+    let right_channel_strip = channel_strips
+        .iter()
+        .find(|c| c.id == output_stage.right_channel_strip_id());
+
+    let (Some(left_channel_strip), Some(right_channel_strip)) =
+        (left_channel_strip, right_channel_strip)
+    else {
+        reporter.skipped(&format!(
+            "Couldn't find a channel strip left: {}, right: {}",
+            output_stage.left_channel_strip_id(),
+            output_stage.right_channel_strip_id()
+        ));
+        return links;
+    };
+
+    let left_plugin = find_plugin(plugins, left_channel_strip.saturator_plugin_id);
+    let right_plugin = find_plugin(plugins, right_channel_strip.saturator_plugin_id);
+
+    let (Some(left_plugin), Some(right_plugin)) = (left_plugin, right_plugin) else {
+        reporter.skipped(&format!(
+            "Couldn't find a plugin left: {:?}, right: {:?}",
+            left_plugin, right_plugin
+        ));
+        return links;
+    };
+
+    for group_channel_strip in group_channel_strips {
+        let side = topology
+            .groups
+            .iter()
+            .find(|g| naming().matches(group_channel_strip.strip_name(), &g.name))
+            .and_then(|g| g.cross_fader);
+        let destinations = match side {
+            Some(CrossFaderSide::A) => vec![left_plugin],
+            Some(CrossFaderSide::B) => vec![right_plugin],
+            None => vec![left_plugin, right_plugin],
+        };
+        if let Some(gain_plugin) = find_plugin(plugins, group_channel_strip.gain_plugin_id()) {
+            for destination in destinations {
+                links.extend(source_ports.links(&gain_plugin.name, &destination.name));
+            }
+        } else {
+            reporter.skipped(&format!(
+                "Couldn't find {} gain plugin",
+                group_channel_strip.strip_name()
+            ));
+        }
+    }
+
+    links
+}
+
+/// Outputs an output stage is routed to.
+pub fn output_stage_outputs(output_stage: &OutputStageConfig, outputs: &[Output]) -> Vec<Output> {
+    outputs
+        .iter()
+        .filter(|output| {
+            output_stage
+                .outputs
+                .as_ref()
+                .map_or(true, |names| names.contains(&output.name))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Routes the cross fader of an output stage to its outputs. Without a
+/// channel map its left and right ports alternate over the channels of the
+/// output, so a four channel output gets left, right, left, right. With
+/// `limiters` the cross fader feeds each output's limiter and the limiter
+/// the output, an output without a limiter is left unwired.
+pub fn output_links<O: OutputStagePlugins>(
+    output_stage: &O,
+    outputs: &[Output],
+    limiters: Option<&BTreeMap<String, u32>>,
+    ports: &[ListPort],
+    nodes: &[ListNode],
+    plugins: &[PmxPlugin],
+    reporter: &Reporter,
+) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    let Some(cross_fader_plugin) = find_plugin(plugins, output_stage.cross_fader_plugin_id())
+    else {
+        reporter.skipped("Couldn't find output stage cross fader plugin");
+        return links;
+    };
+
+    for output in outputs {
+        if output.port_paths.is_empty() {
+            reporter.skipped(&format!("Output {} has no port paths", output.name));
+            continue;
+        }
+        let pairs = match &output.channel_map {
+            Some(channel_map) => channel_map.pairs().to_vec(),
+            None => (0..output.port_paths.len() as u32)
+                .map(|channel| (channel % 2, channel))
+                .collect(),
+        };
+        let source = match limiters {
+            None => cross_fader_plugin,
+            Some(limiters) => {
+                let Some(limiter) = limiters
+                    .get(&output.name)
+                    .and_then(|id| find_plugin(plugins, *id))
+                else {
+                    reporter.skipped(&format!("Couldn't find limiter of output {}", output.name));
+                    continue;
+                };
+                links.extend(PortMap::default().links(&cross_fader_plugin.name, &limiter.name));
+                limiter
+            }
+        };
+        links.extend(output_channel_links(
+            source, output, &pairs, plugins, ports, nodes, reporter,
+        ));
+    }
+
+    links
+}
+
+/// Maps the port numbers planned for plugin nodes to the pipewire ports
+/// carrying the matching audio channel. Even numbers are the left, odd numbers
+/// the right channel of consecutive stereo pairs, so the cross fader's second
+/// input pair 2/3 resolves to the second FL/FR input ports. Mono plugins
+/// count their MONO ports instead. Numbers are kept when the plugin's ports
+/// have no channel metadata.
+pub fn resolve_plugin_ports(
+    links: Vec<Link>,
+    plugins: &[PmxPlugin],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+) -> Vec<Link> {
+    links
+        .into_iter()
+        .map(|link| Link {
+            output_port_id: resolve_plugin_port(
+                &link.output_node_name,
+                link.output_port_id,
+                "out",
+                plugins,
+                ports,
+                nodes,
+            ),
+            input_port_id: resolve_plugin_port(
+                &link.input_node_name,
+                link.input_port_id,
+                "in",
+                plugins,
+                ports,
+                nodes,
+            ),
+            ..link
+        })
+        .collect()
+}
+
+fn resolve_plugin_port(
+    node_name: &str,
+    port_number: u32,
+    direction: &str,
+    plugins: &[PmxPlugin],
+    ports: &[ListPort],
+    nodes: &[ListNode],
+) -> u32 {
+    if !plugins.iter().any(|p| p.name == node_name) {
+        return port_number;
+    }
+    let Some(node) = nodes.iter().find(|n| n.name == node_name) else {
+        return port_number;
+    };
+
+    let node_ports = |channel: &str| {
+        let mut channel_ports: Vec<&ListPort> = ports
+            .iter()
+            .filter(|p| {
+                p.node_id == node.object_serial
+                    && p.direction == direction
+                    && p.audio_channel == channel
+            })
+            .collect();
+        channel_ports.sort_by_key(|p| p.id);
+        channel_ports
+    };
+
+    let mono_ports = node_ports("MONO");
+    if !mono_ports.is_empty() && node_ports("FL").is_empty() {
+        return mono_ports
+            .get(port_number as usize)
+            .map_or(port_number, |p| p.id);
+    }
+    let channel = if port_number % 2 == 0 { "FL" } else { "FR" };
+    node_ports(channel)
+        .get((port_number / 2) as usize)
+        .map_or(port_number, |p| p.id)
+}
+
+/// Resolves live pipewire links to node names so they can be compared with
+/// planned links. Links whose nodes can't be found are dropped.
+pub fn resolve_live_links(links: &[ListLink], nodes: &[ListNode]) -> Vec<Link> {
+    links
+        .iter()
+        .filter_map(|link| resolve_live_link(link, nodes))
+        .collect()
+}
+
+pub fn resolve_live_link(link: &ListLink, nodes: &[ListNode]) -> Option<Link> {
+    let output_node = nodes
+        .iter()
+        .find(|n| n.object_serial == link.output_node_id)?;
+    let input_node = nodes
+        .iter()
+        .find(|n| n.object_serial == link.input_node_id)?;
+    Some(Link::new(
+        &output_node.name,
+        link.output_port_id,
+        &input_node.name,
+        link.input_port_id,
+    ))
+}
+
+pub const GAIN_PARAMETER: &str = "gain";
+pub const SATURATOR_DRIVE_PARAMETER: &str = "drive";
+pub const CROSS_FADER_PARAMETER: &str = "fade";
+pub const CROSS_FADER_CURVE_PARAMETER: &str = "curve";
+
+/// Plugin parameter value set once the mixer is wired.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterSetting {
+    pub channel_strip: String,
+    pub plugin_id: u32,
+    pub symbol: &'static str,
+    pub value: f32,
+}
+
+impl std::fmt::Display for ParameterSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} = {}", self.channel_strip, self.symbol, self.value)
+    }
+}
+
+/// Parameters for the crossfader sides of the inputs and the presets of the
+/// topology. Channel strips or plugins that don't exist are skipped.
+pub fn parameter_settings<S: StripPlugins>(
+    topology: &Topology,
+    channel_strips: &[S],
+    reporter: &Reporter,
+) -> Vec<ParameterSetting> {
+    let mut settings = Vec::new();
+    let mut add = |strip_name: &str, role: PluginRole, symbol: &'static str, value: f32| {
+        let Some(channel_strip) = channel_strips
+            .iter()
+            .find(|s| naming().matches(s.strip_name(), strip_name))
+        else {
+            reporter.skipped(&format!(
+                "No channel strip {strip_name}, not setting {symbol}"
+            ));
+            return;
+        };
+        let Some(plugin_id) = channel_strip.plugin_id(role) else {
+            reporter.skipped(&format!(
+                "Channel strip {strip_name} has no {role:?} plugin, not setting {symbol}"
+            ));
+            return;
+        };
+        settings.push(ParameterSetting {
+            channel_strip: String::from(strip_name),
+            plugin_id,
+            symbol,
+            value,
+        });
+    };
+
+    for input in &topology.inputs {
+        if let Some(side) = input.cross_fader {
+            add(
+                &input.name,
+                PluginRole::CrossFader,
+                CROSS_FADER_PARAMETER,
+                side.value(),
+            );
+        }
+    }
+
+    for PresetConfig {
+        channel_strip,
+        gain,
+        saturator_drive,
+        cross_fader_curve,
+    } in &topology.presets
+    {
+        if let Some(gain) = gain {
+            add(channel_strip, PluginRole::Gain, GAIN_PARAMETER, *gain);
+        }
+        if let Some(drive) = saturator_drive {
+            add(
+                channel_strip,
+                PluginRole::Saturator,
+                SATURATOR_DRIVE_PARAMETER,
+                *drive,
+            );
+        }
+        if let Some(curve) = cross_fader_curve {
+            add(
+                channel_strip,
+                PluginRole::CrossFader,
+                CROSS_FADER_CURVE_PARAMETER,
+                *curve,
+            );
+        }
+    }
+
+    settings
+}
+
+/// Crossfader positions of the output stages. Groups on side A only feed
+/// the left channel strip and groups on side B the right one, so a stage
+/// playing sided groups starts centred unless its position is configured.
+pub fn output_stage_parameter_settings(
+    topology: &Topology,
+    output_stages: &[OutputStage],
+    reporter: &Reporter,
+) -> Vec<ParameterSetting> {
+    let mut settings = Vec::new();
+    for config in &topology.output_stages {
+        let sided = topology.groups.iter().any(|group| {
+            group.cross_fader.is_some()
+                && config
+                    .sources
+                    .as_ref()
+                    .map_or(group.parent.is_none(), |sources| {
+                        sources.contains(&group.name)
+                    })
+        });
+        let Some(value) = config.cross_fader.or(sided.then_some(0.5)) else {
+            continue;
+        };
+        let Some(output_stage) = output_stages
+            .iter()
+            .find(|s| naming().matches(&s.name, &config.name))
+        else {
+            reporter.skipped(&format!(
+                "No output stage {}, not setting {CROSS_FADER_PARAMETER}",
+                config.name
+            ));
+            continue;
+        };
+        settings.push(ParameterSetting {
+            channel_strip: config.name.clone(),
+            plugin_id: output_stage.cross_fader_plugin_id,
+            symbol: CROSS_FADER_PARAMETER,
+            value,
+        });
+    }
+    settings
+}
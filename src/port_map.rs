@@ -0,0 +1,125 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::plan::Link;
+
+/// Which output port of a connection feeds which input port, written as
+/// `out[0..1] -> in[2..3]`. Port lists hold indices and inclusive ranges
+/// separated by commas, several mappings are separated by `;`. A single
+/// output port fans out to every listed input port and several output ports
+/// into a single input port are summed, otherwise both lists pair up in
+/// order, so `out[0, 1] -> in[1, 0]` swaps the channels.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortMap {
+    pairs: Vec<(u32, u32)>,
+}
+
+impl Default for PortMap {
+    /// Left to left and right to right.
+    fn default() -> Self {
+        PortMap {
+            pairs: vec![(0, 0), (1, 1)],
+        }
+    }
+}
+
+impl PortMap {
+    /// Output and input port index pairs in the order they were written.
+    pub fn pairs(&self) -> &[(u32, u32)] {
+        &self.pairs
+    }
+
+    pub fn links(&self, output_node_name: &str, input_node_name: &str) -> Vec<Link> {
+        self.pairs
+            .iter()
+            .map(|(output_port, input_port)| {
+                Link::new(output_node_name, *output_port, input_node_name, *input_port)
+            })
+            .collect()
+    }
+}
+
+fn parse_ports(list: &str, side: &str) -> Result<Vec<u32>, String> {
+    let list = list
+        .trim()
+        .strip_prefix(side)
+        .and_then(|rest| rest.trim_start().strip_prefix('['))
+        .and_then(|rest| rest.trim_end().strip_suffix(']'))
+        .ok_or_else(|| format!("expected {side}[...] in {list}"))?;
+
+    let mut ports = Vec::new();
+    for item in list.split(',') {
+        let parse = |port: &str| {
+            port.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("{port} isn't a port index"))
+        };
+        match item.split_once("..") {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(format!("range {} is reversed", item.trim()));
+                }
+                ports.extend(first..=last);
+            }
+            None => ports.push(parse(item)?),
+        }
+    }
+    Ok(ports)
+}
+
+impl FromStr for PortMap {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut pairs = Vec::new();
+        for mapping in value.split(';').filter(|m| !m.trim().is_empty()) {
+            let (outputs, inputs) = mapping
+                .split_once("->")
+                .ok_or_else(|| format!("mapping {} is missing ->", mapping.trim()))?;
+            let outputs = parse_ports(outputs, "out")?;
+            let inputs = parse_ports(inputs, "in")?;
+            match (outputs.as_slice(), inputs.as_slice()) {
+                ([output], _) => pairs.extend(inputs.iter().map(|input| (*output, *input))),
+                (_, [input]) => pairs.extend(outputs.iter().map(|output| (*output, *input))),
+                _ if outputs.len() == inputs.len() => {
+                    pairs.extend(outputs.iter().copied().zip(inputs.iter().copied()))
+                }
+                _ => {
+                    return Err(format!(
+                        "mapping {} pairs {} output ports with {} input ports",
+                        mapping.trim(),
+                        outputs.len(),
+                        inputs.len()
+                    ))
+                }
+            }
+        }
+        if pairs.is_empty() {
+            return Err(String::from("port mapping is empty"));
+        }
+        Ok(PortMap { pairs })
+    }
+}
+
+impl From<PortMap> for String {
+    /// One mapping per pair, `out[0] -> in[0]; out[1] -> in[1]`.
+    fn from(port_map: PortMap) -> Self {
+        port_map
+            .pairs
+            .iter()
+            .map(|(output, input)| format!("out[{output}] -> in[{input}]"))
+            .collect::<Vec<String>>()
+            .join("; ")
+    }
+}
+
+impl TryFrom<String> for PortMap {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
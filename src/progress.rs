@@ -0,0 +1,78 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use console::style;
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Interactive terminal display with one progress line per stage.
+pub struct ProgressDisplay {
+    bar: Mutex<Option<ProgressBar>>,
+}
+
+impl ProgressDisplay {
+    pub fn new() -> ProgressDisplay {
+        ProgressDisplay {
+            bar: Mutex::new(None),
+        }
+    }
+
+    /// Finishes the line of the previous stage and starts a new one, counting
+    /// up to `total` if the stage has a known number of steps.
+    pub fn start_stage(&self, stage: &str, total: Option<usize>) {
+        let mut bar = self.bar.lock().unwrap();
+        if let Some(previous) = bar.take() {
+            finish_bar(&previous, true);
+        }
+
+        let next = match total {
+            Some(total) => ProgressBar::new(total as u64).with_style(
+                ProgressStyle::with_template("{spinner} {prefix} {pos}/{len} {wide_msg:.dim}")
+                    .unwrap(),
+            ),
+            None => ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template("{spinner} {prefix} {wide_msg:.dim}").unwrap(),
+            ),
+        };
+        next.set_prefix(String::from(stage));
+        next.enable_steady_tick(Duration::from_millis(100));
+        *bar = Some(next);
+    }
+
+    pub fn step(&self) {
+        if let Some(bar) = &*self.bar.lock().unwrap() {
+            bar.inc(1);
+        }
+    }
+
+    pub fn message(&self, message: &str) {
+        if let Some(bar) = &*self.bar.lock().unwrap() {
+            bar.set_message(String::from(message));
+        }
+    }
+
+    /// Prints a failure above the progress line so it stays visible.
+    pub fn failure(&self, message: &str) {
+        let line = format!("{} {}", style("✗").red(), style(message).red());
+        match &*self.bar.lock().unwrap() {
+            Some(bar) => bar.println(line),
+            None => eprintln!("{line}"),
+        }
+    }
+
+    pub fn finish(&self, success: bool) {
+        if let Some(bar) = self.bar.lock().unwrap().take() {
+            finish_bar(&bar, success);
+        }
+    }
+}
+
+fn finish_bar(bar: &ProgressBar, success: bool) {
+    let template = match (success, bar.length().is_some()) {
+        (true, true) => "{prefix:.green} ✓ {pos}/{len}",
+        (true, false) => "{prefix:.green} ✓",
+        (false, true) => "{prefix:.red} ✗ {pos}/{len}",
+        (false, false) => "{prefix:.red} ✗",
+    };
+    bar.set_style(ProgressStyle::with_template(template).unwrap());
+    bar.finish();
+}
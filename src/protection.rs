@@ -0,0 +1,39 @@
+use std::collections::BTreeSet;
+
+use crate::discovery::glob_to_regex;
+use crate::plan::Link;
+use crate::report::Reporter;
+
+/// The node `link` touches that matches one of the `protected_nodes`
+/// patterns, like `easyeffects_*`, if any.
+pub fn protected_node<'a>(link: &'a Link, protected_nodes: &[String]) -> Option<&'a str> {
+    if protected_nodes.is_empty() {
+        return None;
+    }
+    let patterns: Vec<_> = protected_nodes
+        .iter()
+        .filter_map(|pattern| glob_to_regex(pattern).ok())
+        .collect();
+    [&link.output_node_name, &link.input_node_name]
+        .into_iter()
+        .find(|name| patterns.iter().any(|p| p.is_match(name)))
+        .map(|name| name.as_str())
+}
+
+/// Whether the builder may touch `link`. Links to protected nodes are
+/// reported as skipped.
+pub fn allows(link: &Link, protected_nodes: &[String], reporter: &Reporter) -> bool {
+    match protected_node(link, protected_nodes) {
+        Some(node) => {
+            reporter.skipped(&format!("Not touching {link}, node {node} is protected"));
+            false
+        }
+        None => true,
+    }
+}
+
+/// Drops the planned links touching protected nodes, each reported as
+/// skipped once.
+pub fn retain_allowed(links: &mut BTreeSet<Link>, protected_nodes: &[String], reporter: &Reporter) {
+    links.retain(|link| allows(link, protected_nodes, reporter));
+}
@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+use crate::topology::{self, Topology};
+
+/// Tells long running commands when to read the topology file again: on
+/// SIGHUP or when the file, its overlay or the venue changed since it was
+/// last read. Naming and timeouts keep the values the process started with.
+pub struct TopologyWatch {
+    path: Option<PathBuf>,
+    overlay: Option<PathBuf>,
+    venue: Option<PathBuf>,
+    profile: Option<String>,
+    modified: Option<SystemTime>,
+    hangup: Signal,
+    hangup_received: bool,
+}
+
+impl TopologyWatch {
+    pub fn new(
+        path: Option<&Path>,
+        overlay: Option<&Path>,
+        venue: Option<&Path>,
+        profile: Option<&str>,
+    ) -> Result<TopologyWatch, Box<dyn std::error::Error>> {
+        Ok(TopologyWatch {
+            path: path.map(Path::to_path_buf),
+            overlay: overlay.map(Path::to_path_buf),
+            venue: venue.map(Path::to_path_buf),
+            profile: profile.map(String::from),
+            modified: last_modified(&[path, overlay, venue]),
+            hangup: signal(SignalKind::hangup())?,
+            hangup_received: false,
+        })
+    }
+
+    /// Resolves when SIGHUP arrives, for waits that should end early then.
+    pub async fn hangup(&mut self) {
+        self.hangup.recv().await;
+        self.hangup_received = true;
+    }
+
+    /// The topology read again if SIGHUP arrived or the file changed, none
+    /// if neither happened.
+    pub fn reload(&mut self) -> Option<Result<Topology, Box<dyn std::error::Error>>> {
+        let modified = last_modified(&[
+            self.path.as_deref(),
+            self.overlay.as_deref(),
+            self.venue.as_deref(),
+        ]);
+        if !std::mem::take(&mut self.hangup_received) && modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(topology::load_topology(
+            self.path.as_deref(),
+            self.overlay.as_deref(),
+            self.venue.as_deref(),
+            self.profile.as_deref(),
+        ))
+    }
+}
+
+/// Latest modification of the topology file, its overlay and the venue.
+fn last_modified(paths: &[Option<&Path>]) -> Option<SystemTime> {
+    paths.iter().flatten().filter_map(|p| modified(p)).max()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
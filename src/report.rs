@@ -0,0 +1,506 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use clap::ValueEnum;
+use console::style;
+use fr_logging::Logger;
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::latency::PathLatency;
+use crate::metrics::Metrics;
+use crate::plan::{Link, RecordChannel};
+use crate::progress::ProgressDisplay;
+use crate::telemetry;
+use crate::timings;
+
+/// Machine readable record of everything a build did.
+#[derive(Debug, Default, Serialize)]
+pub struct BuildReport {
+    pub success: bool,
+    pub error: Option<String>,
+    pub profile: Option<String>,
+    /// Why the pipewire commands were used instead of the registry service
+    pub graph_fallback: Option<String>,
+    pub channel_strips: Vec<CreatedResource>,
+    pub output_stages: Vec<CreatedResource>,
+    /// Channel strips and output stages found in the registry and reused
+    pub adopted: Vec<CreatedResource>,
+    pub loopers: Vec<u32>,
+    pub links: Vec<LinkResult>,
+    pub removed_links: Vec<Link>,
+    pub skipped: Vec<SkippedItem>,
+    pub paths: Vec<PathLatency>,
+    /// Input port captured on each channel of the record node
+    pub record_channels: Vec<RecordChannel>,
+    /// Inputs no grouping rule matched, left in their registered group
+    pub ungrouped_inputs: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedResource {
+    pub stage: String,
+    pub name: String,
+    pub id: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkResult {
+    pub stage: String,
+    pub link: Link,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SkippedItem {
+    pub stage: String,
+    pub reason: String,
+}
+
+/// Snapshot sent to progress listeners whenever the build moves on.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub stage: String,
+    pub message: String,
+    pub links_created: u32,
+    pub links_failed: u32,
+    pub links_removed: u32,
+    pub finished: bool,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// What a failed connection does to the run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum FailurePolicy {
+    /// Stop the build at the first failed connection
+    #[default]
+    Abort,
+    /// Carry on and report the failures, the run still succeeds
+    Continue,
+    /// Carry on, then fail the run if a connection failed or was skipped
+    Strict,
+}
+
+/// Logs through `fr_logging` and records every action into a `BuildReport`.
+pub struct Reporter {
+    logger: Logger,
+    metrics: Arc<Metrics>,
+    stage: Mutex<String>,
+    report: Mutex<BuildReport>,
+    progress: Option<UnboundedSender<Progress>>,
+    display: Option<ProgressDisplay>,
+    quiet: bool,
+    silent: bool,
+    failure_policy: FailurePolicy,
+}
+
+impl Reporter {
+    pub fn new(logger: Logger, metrics: Arc<Metrics>) -> Reporter {
+        Reporter {
+            logger,
+            metrics,
+            stage: Mutex::new(String::new()),
+            report: Mutex::new(BuildReport::default()),
+            progress: None,
+            display: None,
+            quiet: false,
+            silent: false,
+            failure_policy: FailurePolicy::default(),
+        }
+    }
+
+    /// Shows stages as progress lines instead of logging every step.
+    pub fn with_progress_display(mut self) -> Reporter {
+        self.display = Some(ProgressDisplay::new());
+        self
+    }
+
+    /// Only logs failures.
+    pub fn quiet(mut self) -> Reporter {
+        self.quiet = true;
+        self
+    }
+
+    /// Logs nothing, not even failures. For front ends drawing the progress
+    /// themselves.
+    pub fn silent(mut self) -> Reporter {
+        self.silent = true;
+        self
+    }
+
+    /// Decides whether failed connections stop the build, see `check_failures`.
+    pub fn with_failure_policy(mut self, failure_policy: FailurePolicy) -> Reporter {
+        self.failure_policy = failure_policy;
+        self
+    }
+
+    /// Reporter for the next run of a long running command, like a daemon
+    /// round, logging to `logger` with the same settings and an empty report.
+    pub fn next_run(&self, logger: Logger) -> Reporter {
+        Reporter {
+            logger,
+            metrics: self.metrics.clone(),
+            stage: Mutex::new(String::new()),
+            report: Mutex::new(BuildReport::default()),
+            progress: self.progress.clone(),
+            display: None,
+            quiet: self.quiet,
+            silent: self.silent,
+            failure_policy: self.failure_policy,
+        }
+    }
+
+    /// Whether a failed connection should stop the build right away.
+    pub fn aborts_on_failure(&self) -> bool {
+        self.failure_policy == FailurePolicy::Abort
+    }
+
+    /// Sends a `Progress` snapshot to `sender` for every logged step.
+    pub fn with_progress(mut self, sender: UnboundedSender<Progress>) -> Reporter {
+        self.progress = Some(sender);
+        self
+    }
+
+    fn send_progress(&self, message: &str) {
+        if let Some(sender) = &self.progress {
+            let report = self.report.lock().unwrap();
+            let progress = Progress {
+                stage: self.current_stage(),
+                message: String::from(message),
+                links_created: report.links.iter().filter(|l| l.error.is_none()).count() as u32,
+                links_failed: report.links.iter().filter(|l| l.error.is_some()).count() as u32,
+                links_removed: report.removed_links.len() as u32,
+                finished: report.success || report.error.is_some(),
+                success: report.success,
+                error: report.error.clone(),
+            };
+            let _ = sender.send(progress);
+        }
+    }
+
+    pub fn log_info(&self, message: &str) {
+        match &self.display {
+            Some(display) => display.message(message),
+            None if !self.quiet && !self.silent => self.logger.log_info(message),
+            None => {}
+        }
+        self.send_progress(message);
+    }
+
+    fn log_failure(&self, message: &str) {
+        match &self.display {
+            Some(display) => display.failure(message),
+            None if !self.silent => self.logger.log_info(message),
+            None => {}
+        }
+        self.send_progress(message);
+    }
+
+    /// Logs the start of a pipeline stage, later entries are attributed to it.
+    pub fn start_stage(&self, stage: &str) {
+        self.start_stage_with_total(stage, None);
+    }
+
+    /// Starts a stage made of `total` steps, each announced with `step`.
+    pub fn start_counted_stage(&self, stage: &str, total: usize) {
+        self.start_stage_with_total(stage, Some(total));
+    }
+
+    fn start_stage_with_total(&self, stage: &str, total: Option<usize>) {
+        *self.stage.lock().unwrap() = String::from(stage);
+        timings::enter_stage(stage);
+        telemetry::enter_stage(stage);
+        if let Some(display) = &self.display {
+            display.start_stage(stage, total);
+        }
+        self.log_info(stage);
+    }
+
+    pub fn step(&self) {
+        if let Some(display) = &self.display {
+            display.step();
+        }
+    }
+
+    pub fn current_stage(&self) -> String {
+        self.stage.lock().unwrap().clone()
+    }
+
+    pub fn profile_applied(&self, name: &str) {
+        self.log_info(&format!("Applied profile {name}"));
+        self.report.lock().unwrap().profile = Some(String::from(name));
+    }
+
+    pub fn graph_fallback(&self, reason: &str) {
+        self.log_info(reason);
+        self.report.lock().unwrap().graph_fallback = Some(String::from(reason));
+    }
+
+    pub fn channel_strip_created(&self, name: &str, id: u32) {
+        self.metrics.channel_strips_created.inc();
+        let stage = self.current_stage();
+        self.report
+            .lock()
+            .unwrap()
+            .channel_strips
+            .push(CreatedResource {
+                stage,
+                name: String::from(name),
+                id,
+            });
+    }
+
+    pub fn output_stage_created(&self, name: &str, id: u32) {
+        let stage = self.current_stage();
+        self.report
+            .lock()
+            .unwrap()
+            .output_stages
+            .push(CreatedResource {
+                stage,
+                name: String::from(name),
+                id,
+            });
+    }
+
+    pub fn channel_strip_adopted(&self, name: &str, id: u32) {
+        self.log_info(&format!("Adopting existing channel strip {name}"));
+        self.resource_adopted(name, id);
+    }
+
+    pub fn output_stage_adopted(&self, name: &str, id: u32) {
+        self.log_info(&format!("Adopting existing output stage {name}"));
+        self.resource_adopted(name, id);
+    }
+
+    fn resource_adopted(&self, name: &str, id: u32) {
+        let stage = self.current_stage();
+        self.report.lock().unwrap().adopted.push(CreatedResource {
+            stage,
+            name: String::from(name),
+            id,
+        });
+    }
+
+    pub fn record_channels_planned(&self, channels: Vec<RecordChannel>) {
+        for channel in &channels {
+            self.log_info(&format!(
+                "Recording {} port of {} on channel {}",
+                channel.side, channel.input, channel.channel
+            ));
+        }
+        self.report.lock().unwrap().record_channels = channels;
+    }
+
+    pub fn inputs_ungrouped(&self, inputs: Vec<String>) {
+        if !inputs.is_empty() {
+            self.log_info(&format!(
+                "No grouping rule matches {}, keeping their registered groups",
+                inputs.join(", ")
+            ));
+        }
+        self.report.lock().unwrap().ungrouped_inputs = inputs;
+    }
+
+    pub fn looper_registered(&self, loop_number: u32) {
+        self.report.lock().unwrap().loopers.push(loop_number);
+    }
+
+    pub fn link_created(&self, link: &Link) {
+        self.metrics.links_created.inc();
+        let stage = self.current_stage();
+        self.report.lock().unwrap().links.push(LinkResult {
+            stage,
+            link: link.clone(),
+            error: None,
+        });
+    }
+
+    /// Links that failed to connect with the latest error of each.
+    pub fn failed_links(&self) -> Vec<(Link, String)> {
+        self.report
+            .lock()
+            .unwrap()
+            .links
+            .iter()
+            .filter_map(|l| l.error.clone().map(|error| (l.link.clone(), error)))
+            .collect()
+    }
+
+    /// Links this run created successfully.
+    pub fn created_links(&self) -> Vec<Link> {
+        self.report
+            .lock()
+            .unwrap()
+            .links
+            .iter()
+            .filter(|l| l.error.is_none())
+            .map(|l| l.link.clone())
+            .collect()
+    }
+
+    /// Counts of what the build did so far, as environment variables for
+    /// stage hooks.
+    pub fn hook_environment(&self) -> Vec<(&'static str, String)> {
+        let report = self.report.lock().unwrap();
+        let created = report.links.iter().filter(|l| l.error.is_none()).count();
+        vec![
+            (
+                "FR_PMX_CHANNEL_STRIPS_CREATED",
+                report.channel_strips.len().to_string(),
+            ),
+            (
+                "FR_PMX_OUTPUT_STAGES_CREATED",
+                report.output_stages.len().to_string(),
+            ),
+            (
+                "FR_PMX_LOOPERS_REGISTERED",
+                report.loopers.len().to_string(),
+            ),
+            ("FR_PMX_LINKS_CREATED", created.to_string()),
+            (
+                "FR_PMX_LINKS_FAILED",
+                (report.links.len() - created).to_string(),
+            ),
+            (
+                "FR_PMX_LINKS_REMOVED",
+                report.removed_links.len().to_string(),
+            ),
+            ("FR_PMX_SKIPPED", report.skipped.len().to_string()),
+        ]
+    }
+
+    pub fn link_removed(&self, link: &Link) {
+        self.report.lock().unwrap().removed_links.push(link.clone());
+    }
+
+    pub fn link_failed(&self, link: &Link, error: &str) {
+        self.log_failure(&format!("Failed to connect {link}: {error}"));
+        self.metrics.links_failed.inc();
+        let stage = self.current_stage();
+        self.report.lock().unwrap().links.push(LinkResult {
+            stage,
+            link: link.clone(),
+            error: Some(String::from(error)),
+        });
+    }
+
+    /// Records the latency of a signal path, logged as a failure when it's
+    /// over the budget.
+    pub fn path_latency(&self, path: &PathLatency, over_budget: bool) {
+        if over_budget {
+            self.log_failure(&format!("Over the latency budget: {path}"));
+        } else if path.compensation_ms > 0.0 {
+            self.log_info(&format!(
+                "{path}, {:.2} ms behind the slowest parallel path",
+                path.compensation_ms
+            ));
+        } else {
+            self.log_info(&path.to_string());
+        }
+        self.report.lock().unwrap().paths.push(path.clone());
+    }
+
+    /// Logs and records something the current stage couldn't wire.
+    pub fn skipped(&self, reason: &str) {
+        self.log_info(reason);
+        let stage = self.current_stage();
+        self.report.lock().unwrap().skipped.push(SkippedItem {
+            stage,
+            reason: String::from(reason),
+        });
+    }
+
+    /// Turns links that never connected into an error for the run unless
+    /// the policy is to continue. In strict mode skipped wiring fails it too.
+    pub fn check_failures(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let created = self.created_links();
+        let failed = self
+            .failed_links()
+            .iter()
+            .filter(|(link, _)| !created.contains(link))
+            .count();
+        let skipped = self.report.lock().unwrap().skipped.len();
+        match self.failure_policy {
+            FailurePolicy::Continue => Ok(()),
+            FailurePolicy::Abort if failed > 0 => Err(format!("{failed} links failed").into()),
+            FailurePolicy::Strict if failed > 0 || skipped > 0 => {
+                Err(format!("{failed} links failed, {skipped} connections were skipped").into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Prints every skipped connection with its cause, grouped by stage.
+    fn print_skipped_summary(&self) {
+        let report = self.report.lock().unwrap();
+        if report.skipped.is_empty() {
+            return;
+        }
+        let width = report
+            .skipped
+            .iter()
+            .map(|s| s.stage.len())
+            .chain(["Stage".len()])
+            .max()
+            .unwrap_or_default();
+
+        eprintln!(
+            "{}",
+            style(format!("{} connections were skipped", report.skipped.len())).yellow()
+        );
+        eprintln!("{:width$}  Cause", "Stage");
+        for item in &report.skipped {
+            eprintln!("{:width$}  {}", item.stage, item.reason);
+        }
+    }
+
+    /// Prints what the build created before it was stopped.
+    pub fn print_created(&self) {
+        let report = self.report.lock().unwrap();
+        eprintln!("{}", style("Created before the build stopped").yellow());
+        for channel_strip in &report.channel_strips {
+            eprintln!(
+                "channel strip {} ({})",
+                channel_strip.name, channel_strip.id
+            );
+        }
+        for output_stage in &report.output_stages {
+            eprintln!("output stage {} ({})", output_stage.name, output_stage.id);
+        }
+        for loop_number in &report.loopers {
+            eprintln!("looper {loop_number}");
+        }
+        for link in report.links.iter().filter(|l| l.error.is_none()) {
+            eprintln!("link {}", link.link);
+        }
+    }
+
+    pub fn finish(&self, error: Option<&dyn std::error::Error>) {
+        {
+            let mut report = self.report.lock().unwrap();
+            report.success = error.is_none();
+            report.error = error.map(|e| e.to_string());
+        }
+        if let Some(display) = &self.display {
+            display.finish(error.is_none());
+        }
+        self.print_skipped_summary();
+        match error {
+            Some(error) => self.log_failure(&format!("Failed: {error}")),
+            None => self.log_info("Finished"),
+        }
+    }
+
+    pub fn report_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        serde_json::to_value(&*self.report.lock().unwrap())
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let report = self.report.lock().unwrap();
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(file, &*report)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use tonic::{Code, Request, Response, Status};
+use tracing::{field, info_span, Instrument};
+
+use crate::cassette;
+use crate::chaos;
+use crate::shutdown;
+use crate::telemetry;
+use crate::timings;
+
+/// Seconds each service gets to answer a single call before the build gives
+/// up on it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    pub registry: u64,
+    pub factory: u64,
+    pub pipewire: u64,
+    pub mod_host: u64,
+    /// Seconds a node gets to show up in the graph before it is linked
+    pub node_ready: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            registry: 10,
+            factory: 30,
+            pipewire: 10,
+            mod_host: 10,
+            node_ready: 5,
+        }
+    }
+}
+
+static TIMEOUTS: OnceLock<Timeouts> = OnceLock::new();
+
+/// How often a call is tried again when its service can't be reached, after
+/// a service restart for example. The channel redials the service for every
+/// attempt, the wait doubles after each one.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Reconnect {
+    pub attempts: u32,
+    /// Milliseconds waited before the first retry
+    pub backoff: u64,
+    /// Longest wait between two attempts in milliseconds
+    pub max_backoff: u64,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Reconnect {
+            attempts: 5,
+            backoff: 200,
+            max_backoff: 5000,
+        }
+    }
+}
+
+static RECONNECT: OnceLock<Reconnect> = OnceLock::new();
+
+/// Sets the timeouts for every following call. Only the first call has an
+/// effect, calls made before it use the defaults.
+pub fn set_timeouts(timeouts: Timeouts) {
+    let _ = TIMEOUTS.set(timeouts);
+}
+
+/// How long linking waits for a node to show up in the graph.
+pub fn node_ready_timeout() -> Duration {
+    Duration::from_secs(TIMEOUTS.get().copied().unwrap_or_default().node_ready)
+}
+
+/// Sets the retries of every following call, like `set_timeouts`.
+pub fn set_reconnect(reconnect: Reconnect) {
+    let _ = RECONNECT.set(reconnect);
+}
+
+/// Whether the call failed before reaching the service, so sending it again
+/// can't apply it twice.
+fn is_transport_error(status: &Status) -> bool {
+    status.code() == Code::Unavailable
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Service {
+    Registry,
+    Factory,
+    Pipewire,
+    ModHost,
+}
+
+impl Service {
+    fn timeout(self) -> Duration {
+        let timeouts = TIMEOUTS.get().copied().unwrap_or_default();
+        Duration::from_secs(match self {
+            Service::Registry => timeouts.registry,
+            Service::Factory => timeouts.factory,
+            Service::Pipewire => timeouts.pipewire,
+            Service::ModHost => timeouts.mod_host,
+        })
+    }
+}
+
+impl std::fmt::Display for Service {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Service::Registry => "pmx registry",
+            Service::Factory => "pmx factory",
+            Service::Pipewire => "pipewire registry",
+            Service::ModHost => "mod-host proxy",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Failed call, naming the service and the RPC that failed.
+#[derive(Debug)]
+pub enum RpcError {
+    Timeout {
+        service: Service,
+        rpc: &'static str,
+        timeout: Duration,
+    },
+    Status {
+        service: Service,
+        rpc: &'static str,
+        status: Status,
+    },
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::Timeout {
+                service,
+                rpc,
+                timeout,
+            } => write!(
+                f,
+                "{service} didn't answer {rpc} within {}s",
+                timeout.as_secs()
+            ),
+            RpcError::Status {
+                service,
+                rpc,
+                status,
+            } => write!(f, "{service} failed {rpc}: {}", status.message()),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl RpcError {
+    /// Whether the service doesn't know the RPC, like an older server
+    /// without a newer call.
+    pub fn is_unimplemented(&self) -> bool {
+        matches!(self, RpcError::Status { status, .. } if status.code() == Code::Unimplemented)
+    }
+}
+
+/// Sends `request` with `send` on a clone of `client` and awaits the
+/// response of `rpc` on `service` within the service's timeout. Calls failing
+/// because the service is unreachable are sent again with backoff.
+pub async fn call<C, Q, T, F, R>(
+    service: Service,
+    rpc: &'static str,
+    client: &C,
+    request: &Q,
+    send: F,
+) -> Result<T, RpcError>
+where
+    C: Clone,
+    Q: Message + Clone,
+    T: Message + Default,
+    F: Fn(C, Request<Q>) -> R,
+    R: Future<Output = Result<Response<T>, Status>>,
+{
+    let span = info_span!(
+        parent: &telemetry::stage_span(),
+        "rpc",
+        service = %service,
+        rpc,
+        attempts = field::Empty,
+        error = field::Empty,
+    );
+    let response = call_with_retries(service, rpc, client, request, send)
+        .instrument(span.clone())
+        .await;
+    if let Err(error) = &response {
+        span.record("error", field::display(error));
+    }
+    response
+}
+
+async fn call_with_retries<C, Q, T, F, R>(
+    service: Service,
+    rpc: &'static str,
+    client: &C,
+    request: &Q,
+    send: F,
+) -> Result<T, RpcError>
+where
+    C: Clone,
+    Q: Message + Clone,
+    T: Message + Default,
+    F: Fn(C, Request<Q>) -> R,
+    R: Future<Output = Result<Response<T>, Status>>,
+{
+    let reconnect = RECONNECT.get().copied().unwrap_or_default();
+    let mut backoff = Duration::from_millis(reconnect.backoff);
+    let mut attempt = 0;
+    loop {
+        if shutdown::is_requested() {
+            return Err(RpcError::Status {
+                service,
+                rpc,
+                status: Status::cancelled("the build is shutting down"),
+            });
+        }
+        let response = call_once(
+            service,
+            rpc,
+            request,
+            send(client.clone(), Request::new(request.clone())),
+        )
+        .await;
+        match response {
+            Err(RpcError::Status { status, .. })
+                if is_transport_error(&status) && attempt < reconnect.attempts =>
+            {
+                attempt += 1;
+                if !cassette::is_replaying() {
+                    tokio::time::sleep(backoff).await;
+                }
+                backoff = (backoff * 2).min(Duration::from_millis(reconnect.max_backoff));
+            }
+            response => {
+                tracing::Span::current().record("attempts", attempt + 1);
+                return response;
+            }
+        }
+    }
+}
+
+/// One attempt of `call`. `request` is the message sent, so the call can be
+/// recorded to or answered from a cassette.
+async fn call_once<Q: Message, T: Message + Default>(
+    service: Service,
+    rpc: &'static str,
+    request: &Q,
+    response: impl Future<Output = Result<Response<T>, Status>>,
+) -> Result<T, RpcError> {
+    let request = request.encode_to_vec();
+    if let Some(replayed) = cassette::replay(service, rpc, &request) {
+        return replayed
+            .and_then(|response| {
+                T::decode(response.as_slice()).map_err(|e| Status::data_loss(e.to_string()))
+            })
+            .map_err(|status| RpcError::Status {
+                service,
+                rpc,
+                status,
+            });
+    }
+
+    if let Some(status) = chaos::failure() {
+        return Err(RpcError::Status {
+            service,
+            rpc,
+            status,
+        });
+    }
+    let timeout = service.timeout();
+    let started = std::time::Instant::now();
+    let response = tokio::time::timeout(timeout, async {
+        chaos::delay().await;
+        response.await
+    })
+    .await;
+    timings::record_call(service, rpc, started.elapsed());
+    match response {
+        Ok(Ok(response)) => {
+            let response = response.into_inner();
+            cassette::record(service, rpc, &request, Ok(&response.encode_to_vec()));
+            Ok(response)
+        }
+        Ok(Err(status)) => {
+            cassette::record(service, rpc, &request, Err(&status));
+            Err(RpcError::Status {
+                service,
+                rpc,
+                status,
+            })
+        }
+        Err(_) => Err(RpcError::Timeout {
+            service,
+            rpc,
+            timeout,
+        }),
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::builder;
+use crate::clients::Clients;
+use crate::diff;
+use crate::live;
+use crate::model::ChannelStrip;
+use crate::plan::{ParameterSetting, StripPlugins, GAIN_PARAMETER};
+use crate::report::Reporter;
+use crate::topology::Topology;
+
+/// Gain in dB the outputs are held at until the build is verified.
+const SILENT_DB: f32 = -90.0;
+/// Steps the outputs are ramped up in once the build passed verification.
+const RAMP_STEPS: u32 = 20;
+const RAMP_STEP_INTERVAL: Duration = Duration::from_millis(100);
+/// Gain of an output stage strip without a preset.
+const UNITY_DB: f32 = 0.0;
+
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Makes builds mute the output stages before wiring and only bring them up
+/// once the wiring verified.
+pub fn enable() {
+    SAFE_MODE.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    SAFE_MODE.load(Ordering::Relaxed)
+}
+
+fn gain_setting(channel_strip: &ChannelStrip, value: f32) -> ParameterSetting {
+    ParameterSetting {
+        channel_strip: channel_strip.name.clone(),
+        plugin_id: channel_strip.gain_plugin_id(),
+        symbol: GAIN_PARAMETER,
+        value,
+    }
+}
+
+/// Turns the gain of every output stage strip all the way down.
+pub async fn mute_outputs(
+    topology: &Topology,
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let live_state = live::read_live_state(clients, reporter).await?;
+    let settings: Vec<ParameterSetting> = live_state
+        .output_stage_channel_strips(topology)
+        .iter()
+        .map(|strip| gain_setting(strip, SILENT_DB))
+        .collect();
+    reporter.start_counted_stage("Muting output stages", settings.len());
+    for setting in &settings {
+        reporter.step();
+        builder::set_parameter(setting, clients).await?;
+    }
+    Ok(())
+}
+
+/// Takes the gain settings of the output stage strips out of `settings`, so
+/// applying them doesn't unmute the outputs. Returns the gain every output
+/// stage strip is ramped up to, the preset's or unity.
+pub fn hold_output_gains(
+    settings: &mut Vec<ParameterSetting>,
+    output_strips: &[ChannelStrip],
+) -> Vec<ParameterSetting> {
+    let targets = output_strips
+        .iter()
+        .map(|strip| {
+            let preset = settings
+                .iter()
+                .find(|s| s.plugin_id == strip.gain_plugin_id() && s.symbol == GAIN_PARAMETER);
+            gain_setting(strip, preset.map_or(UNITY_DB, |s| s.value))
+        })
+        .collect();
+    settings.retain(|s| {
+        s.symbol != GAIN_PARAMETER
+            || !output_strips
+                .iter()
+                .any(|strip| strip.gain_plugin_id() == s.plugin_id)
+    });
+    targets
+}
+
+/// Verifies the build and ramps the outputs up to `targets` if it passed.
+/// A failed verification leaves them muted and fails the build.
+pub async fn verify_and_unmute(
+    topology: &Topology,
+    targets: &[ParameterSetting],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    reporter.start_stage("Verifying before unmuting");
+    let live_state = live::read_live_state(clients, reporter).await?;
+    let verification = diff::verify(topology, &live_state, reporter);
+    if !verification.passed() {
+        diff::print_verification(&verification);
+        return Err("Verification failed, the output stages stay muted".into());
+    }
+
+    reporter.start_counted_stage("Ramping up output stages", RAMP_STEPS as usize);
+    for step in 1..=RAMP_STEPS {
+        reporter.step();
+        let progress = step as f32 / RAMP_STEPS as f32;
+        for target in targets {
+            let setting = ParameterSetting {
+                value: SILENT_DB + (target.value - SILENT_DB) * progress,
+                ..target.clone()
+            };
+            builder::set_parameter(&setting, clients).await?;
+        }
+        tokio::time::sleep(RAMP_STEP_INTERVAL).await;
+    }
+    reporter.log_info("Output stages unmuted");
+    Ok(())
+}
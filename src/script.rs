@@ -0,0 +1,146 @@
+use std::fmt::Write;
+
+use clap::ValueEnum;
+
+use crate::plan::{ConnectionPlan, Link};
+use crate::pmx::pipewire::port::ListPort;
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum ScriptFormat {
+    /// Shell script of `pw-link` commands
+    #[default]
+    PwLink,
+    /// WirePlumber Lua script linking the ports as they show up
+    Wireplumber,
+}
+
+/// Link of the plan by node and port names, the ids don't survive a restart.
+struct NamedLink<'a> {
+    output_node: &'a str,
+    output_port: &'a str,
+    input_node: &'a str,
+    input_port: &'a str,
+}
+
+fn port_name(ports: &[ListPort], id: u32) -> Option<&str> {
+    ports.iter().find(|p| p.id == id).map(|p| p.name.as_str())
+}
+
+/// Names the links of `plan`, the ones with a port pipewire doesn't know are
+/// returned apart.
+fn named_links<'a>(
+    plan: &'a ConnectionPlan,
+    ports: &'a [ListPort],
+) -> (Vec<NamedLink<'a>>, Vec<&'a Link>) {
+    let mut named = Vec::new();
+    let mut unnamed = Vec::new();
+    for link in plan.links() {
+        match (
+            port_name(ports, link.output_port_id),
+            port_name(ports, link.input_port_id),
+        ) {
+            (Some(output_port), Some(input_port)) => named.push(NamedLink {
+                output_node: &link.output_node_name,
+                output_port,
+                input_node: &link.input_node_name,
+                input_port,
+            }),
+            _ => unnamed.push(link),
+        }
+    }
+    (named, unnamed)
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn lua_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn render_pw_link(links: &[NamedLink]) -> String {
+    let mut script = String::from("#!/bin/sh\n# Links of the PMX mixer, made by fr-pmx-builder\n");
+    for link in links {
+        writeln!(
+            script,
+            "pw-link {} {} || true",
+            shell_quote(&format!("{}:{}", link.output_node, link.output_port)),
+            shell_quote(&format!("{}:{}", link.input_node, link.input_port)),
+        )
+        .unwrap();
+    }
+    script
+}
+
+const WIREPLUMBER_LINKER: &str = r#"
+local nodes = ObjectManager { Interest { type = "node" } }
+local ports = ObjectManager { Interest { type = "port" } }
+local linked = {}
+
+local function find_port(node_name, port_name, direction)
+  local node = nodes:lookup { Constraint { "node.name", "=", node_name } }
+  if not node then
+    return nil
+  end
+  return ports:lookup {
+    Constraint { "node.id", "=", tostring(node["bound-id"]) },
+    Constraint { "port.name", "=", port_name },
+    Constraint { "port.direction", "=", direction },
+  }
+end
+
+local function link_ports()
+  for index, l in ipairs(links) do
+    local output = not linked[index] and find_port(l[1], l[2], "out")
+    local input = output and find_port(l[3], l[4], "in")
+    if input then
+      linked[index] = true
+      Link("link-factory", {
+        ["link.output.node"] = output.properties["node.id"],
+        ["link.output.port"] = output["bound-id"],
+        ["link.input.node"] = input.properties["node.id"],
+        ["link.input.port"] = input["bound-id"],
+        ["object.linger"] = true,
+      }):activate(Feature.Proxy.BOUND)
+    end
+  end
+end
+
+ports:connect("object-added", link_ports)
+nodes:activate()
+ports:activate()
+"#;
+
+fn render_wireplumber(links: &[NamedLink]) -> String {
+    let mut script =
+        String::from("-- Links of the PMX mixer, made by fr-pmx-builder\nlocal links = {\n");
+    for link in links {
+        writeln!(
+            script,
+            "  {{ {}, {}, {}, {} }},",
+            lua_quote(link.output_node),
+            lua_quote(link.output_port),
+            lua_quote(link.input_node),
+            lua_quote(link.input_port),
+        )
+        .unwrap();
+    }
+    script.push_str("}\n");
+    script.push_str(WIREPLUMBER_LINKER);
+    script
+}
+
+/// Script making the links of `plan` without the PMX services. Links whose
+/// ports pipewire doesn't know yet are left out and listed at the end.
+pub fn render(plan: &ConnectionPlan, ports: &[ListPort], format: ScriptFormat) -> String {
+    let (named, unnamed) = named_links(plan, ports);
+    let (mut script, comment) = match format {
+        ScriptFormat::PwLink => (render_pw_link(&named), "#"),
+        ScriptFormat::Wireplumber => (render_wireplumber(&named), "--"),
+    };
+    for link in unnamed {
+        writeln!(script, "{comment} Left out, ports aren't live: {link}").unwrap();
+    }
+    script
+}
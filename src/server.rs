@@ -0,0 +1,212 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use fr_logging::LoggerFactory;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio_stream::{wrappers::UnboundedReceiverStream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use crate::history::{self, History};
+use crate::metrics::Metrics;
+use crate::ownership;
+use crate::pmx::builder::{
+    pmx_builder_server::{PmxBuilder, PmxBuilderServer},
+    BuildProgress, BuildRequest, BuilderStatus, GetStatusRequest, TeardownRequest, VerifyRequest,
+    VerifyResponse,
+};
+use crate::report::{Progress, Reporter};
+use crate::state::Checkpoints;
+use crate::topology::Topology;
+
+#[derive(Default)]
+struct BuilderState {
+    operation: Option<String>,
+    reporter: Option<Arc<Reporter>>,
+    last_success: Option<bool>,
+    last_error: Option<String>,
+}
+
+/// Exposes build, teardown and verify over the `pmx.builder` gRPC service.
+/// Only one build or teardown runs at a time.
+pub struct BuilderService {
+    topology: Arc<Topology>,
+    state_file: PathBuf,
+    history: Arc<History>,
+    logger_factory: Arc<LoggerFactory>,
+    metrics: Arc<Metrics>,
+    state: Arc<Mutex<BuilderState>>,
+}
+
+impl BuilderService {
+    pub fn new(
+        topology: Topology,
+        state_file: PathBuf,
+        history: History,
+        logger_factory: Arc<LoggerFactory>,
+        metrics: Arc<Metrics>,
+    ) -> BuilderService {
+        BuilderService {
+            topology: Arc::new(topology),
+            state_file,
+            history: Arc::new(history),
+            logger_factory,
+            metrics,
+            state: Arc::new(Mutex::new(BuilderState::default())),
+        }
+    }
+
+    fn new_reporter(&self, operation: &str) -> Reporter {
+        let logger = self
+            .logger_factory
+            .new_logger(format!("fr_pmx_builder::{operation}"));
+        Reporter::new(logger, self.metrics.clone())
+    }
+
+    /// Marks `operation` as running and returns its reporter together with
+    /// the stream its progress is sent to.
+    fn start_operation(
+        &self,
+        operation: &str,
+    ) -> Result<(Arc<Reporter>, UnboundedReceiverStream<Progress>), Status> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(running) = &state.operation {
+            return Err(Status::failed_precondition(format!(
+                "{running} is already running"
+            )));
+        }
+
+        let (sender, receiver) = unbounded_channel();
+        let reporter = Arc::new(self.new_reporter(operation).with_progress(sender));
+        state.operation = Some(String::from(operation));
+        state.reporter = Some(reporter.clone());
+        Ok((reporter, UnboundedReceiverStream::new(receiver)))
+    }
+}
+
+fn finish_operation(state: &Mutex<BuilderState>, result: &Result<(), String>) {
+    let mut state = state.lock().unwrap();
+    state.operation = None;
+    state.reporter = None;
+    state.last_success = Some(result.is_ok());
+    state.last_error = result.as_ref().err().cloned();
+}
+
+fn to_build_progress(progress: Progress) -> Result<BuildProgress, Status> {
+    Ok(BuildProgress {
+        stage: progress.stage,
+        message: progress.message,
+        links_created: progress.links_created,
+        links_failed: progress.links_failed,
+        links_removed: progress.links_removed,
+        finished: progress.finished,
+        success: progress.success,
+        error: progress.error,
+    })
+}
+
+type ProgressStream = std::pin::Pin<
+    Box<dyn tokio_stream::Stream<Item = Result<BuildProgress, Status>> + Send + 'static>,
+>;
+
+#[tonic::async_trait]
+impl PmxBuilder for BuilderService {
+    type BuildStream = ProgressStream;
+    type TeardownStream = ProgressStream;
+
+    async fn build(
+        &self,
+        _request: Request<BuildRequest>,
+    ) -> Result<Response<Self::BuildStream>, Status> {
+        let (reporter, stream) = self.start_operation("build")?;
+        let mut checkpoints = match Checkpoints::new(&self.state_file, false) {
+            Ok(checkpoints) => checkpoints,
+            Err(error) => {
+                finish_operation(&self.state, &Err(error.to_string()));
+                return Err(Status::internal(error.to_string()));
+            }
+        };
+        let topology = self.topology.clone();
+        let history = self.history.clone();
+        let metrics = self.metrics.clone();
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let started = std::time::SystemTime::now();
+            let result = crate::timed_build(&topology, &mut checkpoints, &reporter, &metrics)
+                .await
+                .map_err(|e| e.to_string());
+            history.record(
+                &history::build_run_id(started, ownership::run_id()),
+                &topology,
+                started.elapsed().unwrap_or_default(),
+                &reporter,
+            );
+            finish_operation(&state, &result);
+        });
+        Ok(Response::new(Box::pin(stream.map(to_build_progress))))
+    }
+
+    async fn teardown(
+        &self,
+        _request: Request<TeardownRequest>,
+    ) -> Result<Response<Self::TeardownStream>, Status> {
+        let (reporter, stream) = self.start_operation("teardown")?;
+        let mut checkpoints = match Checkpoints::new(&self.state_file, true) {
+            Ok(checkpoints) => checkpoints,
+            Err(error) => {
+                finish_operation(&self.state, &Err(error.to_string()));
+                return Err(Status::internal(error.to_string()));
+            }
+        };
+        let topology = self.topology.clone();
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let result = crate::teardown_pmx(&topology, &mut checkpoints, &reporter).await;
+            reporter.finish(result.as_ref().err().map(|e| e.as_ref()));
+            finish_operation(&state, &result.map_err(|e| e.to_string()));
+        });
+        Ok(Response::new(Box::pin(stream.map(to_build_progress))))
+    }
+
+    async fn verify(
+        &self,
+        _request: Request<VerifyRequest>,
+    ) -> Result<Response<VerifyResponse>, Status> {
+        let reporter = self.new_reporter("verify");
+        let verification = crate::verify_pmx(&self.topology, &reporter)
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        Ok(Response::new(VerifyResponse {
+            in_sync: verification.passed(),
+            missing_channel_strips: verification.missing_channel_strips,
+            missing_output_stages: verification.missing_output_stages,
+            missing_links: verification.missing_links,
+            unexpected_links: verification.unexpected_links,
+        }))
+    }
+
+    async fn get_status(
+        &self,
+        _request: Request<GetStatusRequest>,
+    ) -> Result<Response<BuilderStatus>, Status> {
+        let state = self.state.lock().unwrap();
+        Ok(Response::new(BuilderStatus {
+            running: state.operation.is_some(),
+            operation: state.operation.clone(),
+            stage: state.reporter.as_ref().map(|r| r.current_stage()),
+            last_success: state.last_success,
+            last_error: state.last_error.clone(),
+        }))
+    }
+}
+
+pub async fn serve(
+    address: SocketAddr,
+    service: BuilderService,
+) -> Result<(), Box<dyn std::error::Error>> {
+    tonic::transport::Server::builder()
+        .add_service(PmxBuilderServer::new(service))
+        .serve(address)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::signal::unix::{signal, SignalKind};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Winds the build down on SIGINT or SIGTERM: calls in flight finish, no new
+/// ones are sent, so the build fails at its next call and records what it
+/// created. A second signal exits right away.
+pub fn listen() -> Result<(), Box<dyn std::error::Error>> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = interrupt.recv() => {}
+                _ = terminate.recv() => {}
+            }
+            if REQUESTED.swap(true, Ordering::Relaxed) {
+                eprintln!("Exiting without waiting for the build");
+                std::process::exit(130);
+            }
+            eprintln!(
+                "Stopping the build once the calls in flight are done, signal again to exit now"
+            );
+        }
+    });
+    Ok(())
+}
+
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
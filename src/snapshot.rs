@@ -0,0 +1,361 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::builder;
+use crate::clients::Clients;
+use crate::live::LiveState;
+use crate::plan::{Link, StripPlugins};
+use crate::pmx::{
+    factory::{CreateChannelStripRequest, CreateOutputStageRequest},
+    RegisterInputRequest,
+};
+use crate::report::Reporter;
+use crate::rpc::{self, Service};
+use crate::topology::PluginRole;
+
+/// Version of the snapshot file format written by this builder.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Complete mixer graph at the time the snapshot was taken.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub inputs: Vec<InputSnapshot>,
+    pub channel_strips: Vec<ChannelStripSnapshot>,
+    pub output_stages: Vec<String>,
+    pub loopers: Vec<u32>,
+    pub outputs: Vec<String>,
+    pub links: Vec<LinkSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InputSnapshot {
+    pub name: String,
+    pub input_type: i32,
+    pub left_port_path: Option<String>,
+    pub right_port_path: Option<String>,
+    pub group_channel_strip_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelStripSnapshot {
+    pub name: String,
+    pub channel_type: i32,
+}
+
+/// Node of a link. Plugin nodes are named after their instance, which changes
+/// when the plugin is created again, so they are kept by what they belong to.
+/// Channel strips and output stages are kept by their registry name and
+/// restored under it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Endpoint {
+    Node {
+        name: String,
+    },
+    Plugin {
+        channel_strip: String,
+        role: PluginRole,
+    },
+    OutputStage {
+        name: String,
+    },
+}
+
+/// Link between two ports, which are kept by name since port ids change with
+/// the node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkSnapshot {
+    pub output: Endpoint,
+    pub output_port: String,
+    pub input: Endpoint,
+    pub input_port: String,
+}
+
+const PLUGIN_ROLES: [PluginRole; 3] = [
+    PluginRole::CrossFader,
+    PluginRole::Saturator,
+    PluginRole::Gain,
+];
+
+fn endpoint(live_state: &LiveState, node_name: &str) -> Endpoint {
+    let plugin = live_state.plugins.iter().find(|p| p.name == node_name);
+    if let Some(plugin) = plugin {
+        for channel_strip in &live_state.channel_strips {
+            for role in PLUGIN_ROLES {
+                if channel_strip.plugin_id(role) == Some(plugin.id) {
+                    return Endpoint::Plugin {
+                        channel_strip: channel_strip.name.clone(),
+                        role,
+                    };
+                }
+            }
+        }
+        if let Some(output_stage) = live_state
+            .output_stages
+            .iter()
+            .find(|o| o.cross_fader_plugin_id == plugin.id)
+        {
+            return Endpoint::OutputStage {
+                name: output_stage.name.clone(),
+            };
+        }
+    }
+    Endpoint::Node {
+        name: String::from(node_name),
+    }
+}
+
+fn node_name(live_state: &LiveState, endpoint: &Endpoint) -> Option<String> {
+    let plugin_id = match endpoint {
+        Endpoint::Node { name } => return Some(name.clone()),
+        Endpoint::Plugin {
+            channel_strip,
+            role,
+        } => live_state
+            .channel_strips
+            .iter()
+            .find(|c| c.name == *channel_strip)
+            .and_then(|c| c.plugin_id(*role))?,
+        Endpoint::OutputStage { name } => {
+            live_state
+                .output_stages
+                .iter()
+                .find(|o| o.name == *name)?
+                .cross_fader_plugin_id
+        }
+    };
+    live_state
+        .plugins
+        .iter()
+        .find(|p| p.id == plugin_id)
+        .map(|p| p.name.clone())
+}
+
+fn port_name(live_state: &LiveState, node_name: &str, port_id: u32) -> Option<String> {
+    let node = live_state.nodes.iter().find(|n| n.name == node_name)?;
+    live_state
+        .ports
+        .iter()
+        .find(|p| p.node_id == node.object_serial && p.id == port_id)
+        .map(|p| p.name.clone())
+}
+
+fn port_id(live_state: &LiveState, node_name: &str, port_name: &str) -> Option<u32> {
+    let node = live_state.nodes.iter().find(|n| n.name == node_name)?;
+    live_state
+        .ports
+        .iter()
+        .find(|p| p.node_id == node.object_serial && p.name == port_name)
+        .map(|p| p.id)
+}
+
+/// Snapshot of the live registry and pipewire state. Channel strips owned by
+/// an output stage are left out, restoring the output stage brings them back.
+pub fn take_snapshot(live_state: &LiveState) -> Snapshot {
+    let output_stage_channel_strip_ids = live_state.output_stage_channel_strip_ids();
+
+    let links = live_state
+        .links
+        .iter()
+        .filter_map(|live_link| {
+            let link = crate::plan::resolve_live_link(live_link, &live_state.nodes)?;
+            Some(LinkSnapshot {
+                output: endpoint(live_state, &link.output_node_name),
+                output_port: port_name(live_state, &link.output_node_name, link.output_port_id)?,
+                input: endpoint(live_state, &link.input_node_name),
+                input_port: port_name(live_state, &link.input_node_name, link.input_port_id)?,
+            })
+        })
+        .collect();
+
+    Snapshot {
+        version: SNAPSHOT_VERSION,
+        inputs: live_state
+            .inputs
+            .iter()
+            .map(|i| InputSnapshot {
+                name: i.name.clone(),
+                input_type: i.input_type.to_pmx() as i32,
+                left_port_path: i.left_port_path.clone(),
+                right_port_path: i.right_port_path.clone(),
+                group_channel_strip_name: i.group_channel_strip_name.clone(),
+            })
+            .collect(),
+        channel_strips: live_state
+            .channel_strips
+            .iter()
+            .filter(|c| !output_stage_channel_strip_ids.contains(&c.id))
+            .map(|c| ChannelStripSnapshot {
+                name: c.name.clone(),
+                channel_type: c.channel_strip_type().to_pmx() as i32,
+            })
+            .collect(),
+        output_stages: live_state
+            .output_stages
+            .iter()
+            .map(|o| o.name.clone())
+            .collect(),
+        loopers: live_state.loopers.iter().map(|l| l.loop_number).collect(),
+        outputs: live_state.outputs.iter().map(|o| o.name.clone()).collect(),
+        links,
+    }
+}
+
+pub fn write_snapshot(snapshot: &Snapshot, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let file = std::fs::File::create(path)?;
+    serde_json::to_writer_pretty(file, snapshot)?;
+    Ok(())
+}
+
+pub fn read_snapshot(path: &Path) -> Result<Snapshot, Box<dyn std::error::Error>> {
+    let snapshot: Snapshot = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(format!(
+            "Snapshot {} has version {}, this builder reads version {SNAPSHOT_VERSION}",
+            path.display(),
+            snapshot.version
+        )
+        .into());
+    }
+    Ok(snapshot)
+}
+
+/// Links of the snapshot resolved against the live state. Links whose nodes
+/// or ports don't exist are skipped.
+fn resolve_links(snapshot: &Snapshot, live_state: &LiveState, reporter: &Reporter) -> Vec<Link> {
+    let mut links = Vec::new();
+    for link in &snapshot.links {
+        let resolve = |endpoint: &Endpoint, port: &str| {
+            let node = node_name(live_state, endpoint)?;
+            let port = port_id(live_state, &node, port)?;
+            Some((node, port))
+        };
+        match (
+            resolve(&link.output, &link.output_port),
+            resolve(&link.input, &link.input_port),
+        ) {
+            (Some((output_node, output_port)), Some((input_node, input_port))) => {
+                links.push(Link::new(
+                    &output_node,
+                    output_port,
+                    &input_node,
+                    input_port,
+                ));
+            }
+            _ => reporter.skipped(&format!(
+                "Couldn't resolve snapshot link {:?}:{} -> {:?}:{}",
+                link.output, link.output_port, link.input, link.input_port
+            )),
+        }
+    }
+    links
+}
+
+/// Creates whatever part of the snapshot is missing from the registry and
+/// pipewire. Nothing that exists already is touched.
+pub async fn restore_snapshot(
+    snapshot: &Snapshot,
+    protected_nodes: &[String],
+    clients: &Clients,
+    reporter: &Reporter,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let live_state = crate::live::read_live_state(clients, reporter).await?;
+
+    reporter.start_counted_stage("Restoring inputs", snapshot.inputs.len());
+    for input in &snapshot.inputs {
+        reporter.step();
+        if live_state.inputs.iter().any(|i| i.name == input.name) {
+            continue;
+        }
+        reporter.log_info(&format!("Registering input {}", input.name));
+        let request = RegisterInputRequest {
+            name: input.name.clone(),
+            input_type: input.input_type,
+            left_port_path: input.left_port_path.clone(),
+            right_port_path: input.right_port_path.clone(),
+            group_channel_strip_name: input.group_channel_strip_name.clone(),
+        };
+        rpc::call(
+            Service::Registry,
+            "RegisterInput",
+            &clients.registry,
+            &request,
+            |mut client, request| async move { client.register_input(request).await },
+        )
+        .await?;
+    }
+
+    reporter.start_counted_stage("Restoring channel strips", snapshot.channel_strips.len());
+    for channel_strip in &snapshot.channel_strips {
+        reporter.step();
+        if live_state
+            .channel_strips
+            .iter()
+            .any(|c| c.name == channel_strip.name)
+        {
+            continue;
+        }
+        let request = CreateChannelStripRequest {
+            name: channel_strip.name.clone(),
+            channel_type: channel_strip.channel_type,
+        };
+        let created = rpc::call(
+            Service::Factory,
+            "CreateChannelStrip",
+            &clients.factory().await?,
+            &request,
+            |mut client, request| async move { client.create_channel_strip(request).await },
+        )
+        .await?;
+        reporter.channel_strip_created(&created.name, created.id);
+    }
+
+    reporter.start_counted_stage("Restoring output stages", snapshot.output_stages.len());
+    for name in &snapshot.output_stages {
+        reporter.step();
+        if live_state.output_stages.iter().any(|o| o.name == *name) {
+            continue;
+        }
+        let request = CreateOutputStageRequest { name: name.clone() };
+        let created = rpc::call(
+            Service::Factory,
+            "CreateOutputStage",
+            &clients.factory().await?,
+            &request,
+            |mut client, request| async move { client.create_output_stage(request).await },
+        )
+        .await?;
+        reporter.output_stage_created(&created.name, created.id);
+    }
+
+    reporter.start_counted_stage("Restoring loopers", snapshot.loopers.len());
+    for loop_number in &snapshot.loopers {
+        reporter.step();
+        if live_state
+            .loopers
+            .iter()
+            .any(|l| l.loop_number == *loop_number)
+        {
+            continue;
+        }
+        let looper = builder::register_looper(*loop_number, clients).await?;
+        reporter.looper_registered(looper.loop_number);
+    }
+
+    for output in &snapshot.outputs {
+        if !live_state.outputs.iter().any(|o| o.name == *output) {
+            reporter.skipped(&format!("Output {output} isn't registered"));
+        }
+    }
+
+    reporter.start_stage("Restoring links");
+    let live_state = crate::live::read_live_state(clients, reporter).await?;
+    let live_links = crate::plan::resolve_live_links(&live_state.links, &live_state.nodes);
+    let missing_links: Vec<Link> = resolve_links(snapshot, &live_state, reporter)
+        .into_iter()
+        .filter(|link| !live_links.contains(link))
+        .collect();
+    builder::create_links(&missing_links, &[], protected_nodes, clients, reporter).await
+}
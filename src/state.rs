@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::model::Input;
+use crate::plan::Link;
+
+/// Pipeline stages recorded in the state file once they completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    ChannelStrips,
+    Inputs,
+    Loopers,
+    GroupChannelStrips,
+    Groups,
+    OutputStage,
+    OutputStageWired,
+}
+
+impl Stage {
+    /// Every stage, for listing them.
+    pub const ALL: [Stage; 7] = [
+        Stage::ChannelStrips,
+        Stage::Inputs,
+        Stage::Loopers,
+        Stage::GroupChannelStrips,
+        Stage::Groups,
+        Stage::OutputStage,
+        Stage::OutputStageWired,
+    ];
+
+    /// Name the stage has in the state file and the topology.
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::ChannelStrips => "channel_strips",
+            Stage::Inputs => "inputs",
+            Stage::Loopers => "loopers",
+            Stage::GroupChannelStrips => "group_channel_strips",
+            Stage::Groups => "groups",
+            Stage::OutputStage => "output_stage",
+            Stage::OutputStageWired => "output_stage_wired",
+        }
+    }
+}
+
+/// Version of the state file layout. Raised whenever the layout changes in
+/// a way field defaults can't cover, with a migration for the step.
+const STATE_VERSION: u32 = 1;
+
+/// Migrations bringing a state file up by one version, indexed by the
+/// version they start from.
+const MIGRATIONS: [fn(&mut Map<String, Value>); STATE_VERSION as usize] = [migrate_unversioned];
+
+/// Files from before the version was kept have the current layout, minus
+/// the fields added since. Those read as their defaults.
+fn migrate_unversioned(_state: &mut Map<String, Value>) {}
+
+/// Reads a state file of any earlier version in the current layout.
+fn read_state(contents: &str) -> Result<BuildState, Box<dyn std::error::Error>> {
+    let Value::Object(mut state) = serde_json::from_str(contents)? else {
+        return Err("the state file isn't a JSON object".into());
+    };
+    let version = state.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    if version > STATE_VERSION {
+        return Err(format!(
+            "the state file has version {version}, this builder reads up to version {STATE_VERSION}"
+        )
+        .into());
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut state);
+    }
+    let mut state: BuildState = serde_json::from_value(Value::Object(state))?;
+    state.version = STATE_VERSION;
+    Ok(state)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BuildState {
+    /// Layout version, 0 for files from before it was kept
+    #[serde(default)]
+    pub version: u32,
+    pub completed_stages: Vec<Stage>,
+    /// Links created by the builder that haven't been removed since
+    #[serde(default)]
+    pub links: BTreeSet<Link>,
+    /// Plugin ids of the extra plugins in each input's chain, in chain order
+    #[serde(default)]
+    pub plugin_chains: BTreeMap<String, Vec<u32>>,
+    /// Loop number given to each input, kept when inputs come and go
+    #[serde(default)]
+    pub loop_numbers: BTreeMap<String, u32>,
+    /// Gain in dB calibration measured for each input
+    #[serde(default)]
+    pub calibrated_gains: BTreeMap<String, f32>,
+    /// Channel strips the last finished build left behind
+    #[serde(default)]
+    pub channel_strips: BTreeSet<String>,
+    /// Parameter values the last finished build set, by strip and symbol
+    #[serde(default)]
+    pub parameters: BTreeMap<String, f32>,
+    /// Plugin id of the limiter in front of each output
+    #[serde(default)]
+    pub limiters: BTreeMap<String, u32>,
+    /// Plugin id of the mono sum of each output in `mono_sum` mode
+    #[serde(default)]
+    pub mono_sums: BTreeMap<String, u32>,
+}
+
+/// Stages a build is limited to by `--only` and `--skip`.
+#[derive(Debug, Clone, Default)]
+pub struct StageSelection {
+    /// Run just these stages, all of them if empty
+    pub only: Vec<Stage>,
+    pub skip: Vec<Stage>,
+}
+
+impl StageSelection {
+    pub fn runs(&self, stage: Stage) -> bool {
+        (self.only.is_empty() || self.only.contains(&stage)) && !self.skip.contains(&stage)
+    }
+}
+
+/// Records completed stages in the state file so a crashed build can be
+/// resumed without creating everything a second time.
+pub struct Checkpoints {
+    path: PathBuf,
+    state: BuildState,
+    /// State file as the previous run left it
+    previous: BuildState,
+    selection: StageSelection,
+}
+
+impl Checkpoints {
+    /// Continues from the state file if `resume` is set, otherwise starts
+    /// over and only keeps the links earlier builds created.
+    pub fn new(path: &Path, resume: bool) -> Result<Checkpoints, Box<dyn std::error::Error>> {
+        let mut state = if path.exists() {
+            read_state(&std::fs::read_to_string(path)?)
+                .map_err(|e| format!("{}: {e}", path.display()))?
+        } else {
+            BuildState {
+                version: STATE_VERSION,
+                ..BuildState::default()
+            }
+        };
+        let previous = state.clone();
+        if !resume {
+            state.completed_stages.clear();
+        }
+
+        let checkpoints = Checkpoints {
+            path: path.to_path_buf(),
+            state,
+            previous,
+            selection: StageSelection::default(),
+        };
+        checkpoints.write()?;
+        Ok(checkpoints)
+    }
+
+    /// Limits the build to the stages `selection` runs.
+    pub fn with_selection(mut self, selection: StageSelection) -> Checkpoints {
+        self.selection = selection;
+        self
+    }
+
+    /// Whether the stage is part of this build.
+    pub fn is_selected(&self, stage: Stage) -> bool {
+        self.selection.runs(stage)
+    }
+
+    pub fn is_completed(&self, stage: Stage) -> bool {
+        self.state.completed_stages.contains(&stage)
+    }
+
+    pub fn complete(&mut self, stage: Stage) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.is_completed(stage) {
+            self.state.completed_stages.push(stage);
+        }
+        self.write()
+    }
+
+    /// Marks the stage as not completed so the next resumed build runs it again.
+    pub fn reset(&mut self, stage: Stage) -> Result<(), Box<dyn std::error::Error>> {
+        self.state.completed_stages.retain(|s| *s != stage);
+        self.write()
+    }
+
+    pub fn owned_links(&self) -> &BTreeSet<Link> {
+        &self.state.links
+    }
+
+    pub fn record_links(&mut self, links: Vec<Link>) -> Result<(), Box<dyn std::error::Error>> {
+        self.state.links.extend(links);
+        self.write()
+    }
+
+    pub fn forget_links(&mut self, links: &[Link]) -> Result<(), Box<dyn std::error::Error>> {
+        for link in links {
+            self.state.links.remove(link);
+        }
+        self.write()
+    }
+
+    pub fn plugin_chains(&self) -> &BTreeMap<String, Vec<u32>> {
+        &self.state.plugin_chains
+    }
+
+    pub fn limiters(&self) -> &BTreeMap<String, u32> {
+        &self.state.limiters
+    }
+
+    /// Adds or replaces the limiters of the outputs in `limiters`. Limiters
+    /// of other outputs stay until gc removes them.
+    pub fn record_limiters(
+        &mut self,
+        limiters: BTreeMap<String, u32>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.state.limiters.extend(limiters);
+        self.write()
+    }
+
+    pub fn forget_limiters<'a>(
+        &mut self,
+        output_names: impl IntoIterator<Item = &'a String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for output_name in output_names {
+            self.state.limiters.remove(output_name);
+        }
+        self.write()
+    }
+
+    pub fn mono_sums(&self) -> &BTreeMap<String, u32> {
+        &self.state.mono_sums
+    }
+
+    /// Adds or replaces the mono sums of the outputs in `mono_sums`, like
+    /// `record_limiters`.
+    pub fn record_mono_sums(
+        &mut self,
+        mono_sums: BTreeMap<String, u32>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.state.mono_sums.extend(mono_sums);
+        self.write()
+    }
+
+    pub fn forget_mono_sums<'a>(
+        &mut self,
+        output_names: impl IntoIterator<Item = &'a String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for output_name in output_names {
+            self.state.mono_sums.remove(output_name);
+        }
+        self.write()
+    }
+
+    pub fn record_plugin_chain(
+        &mut self,
+        input_name: &str,
+        plugin_ids: Vec<u32>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.state
+            .plugin_chains
+            .insert(String::from(input_name), plugin_ids);
+        self.write()
+    }
+
+    pub fn forget_plugin_chains<'a>(
+        &mut self,
+        input_names: impl IntoIterator<Item = &'a String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for input_name in input_names {
+            self.state.plugin_chains.remove(input_name);
+        }
+        self.write()
+    }
+
+    pub fn loop_numbers(&self) -> &BTreeMap<String, u32> {
+        &self.state.loop_numbers
+    }
+
+    pub fn record_loop_numbers(
+        &mut self,
+        looper_inputs: &[(u32, &Input)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for (loop_number, input) in looper_inputs {
+            self.state
+                .loop_numbers
+                .insert(input.name.clone(), *loop_number);
+        }
+        self.write()
+    }
+
+    pub fn forget_loop_numbers(
+        &mut self,
+        input_names: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for input_name in input_names {
+            self.state.loop_numbers.remove(input_name);
+        }
+        self.write()
+    }
+
+    pub fn calibrated_gains(&self) -> &BTreeMap<String, f32> {
+        &self.state.calibrated_gains
+    }
+
+    pub fn record_calibrated_gains(
+        &mut self,
+        gains: BTreeMap<String, f32>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.state.calibrated_gains.extend(gains);
+        self.write()
+    }
+
+    pub fn state(&self) -> &BuildState {
+        &self.state
+    }
+
+    pub fn previous(&self) -> &BuildState {
+        &self.previous
+    }
+
+    /// Records what a finished build left, for comparing with the next one.
+    pub fn record_build(
+        &mut self,
+        channel_strips: BTreeSet<String>,
+        parameters: BTreeMap<String, f32>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.state.channel_strips = channel_strips;
+        self.state.parameters = parameters;
+        self.write()
+    }
+
+    /// Writes the state next to the state file and renames it over it, so a
+    /// crash mid-write leaves the previous state rather than half a file.
+    fn write(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        let mut file = std::fs::File::create(&temporary)?;
+        serde_json::to_writer_pretty(&mut file, &self.state)?;
+        file.sync_all()?;
+        std::fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Lock file next to the state file holding the pid of the builder using it,
+/// so two builders don't fight over the graph. The lock is held on the open
+/// file, so it goes away with the process holding it, however it ends.
+pub struct StateLock {
+    _file: std::fs::File,
+}
+
+impl StateLock {
+    /// Takes the lock of the state file at `state_path`, failing if another
+    /// builder holds it.
+    pub fn acquire(state_path: &Path) -> Result<StateLock, Box<dyn std::error::Error>> {
+        let path = state_path.with_extension("lock");
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(std::fs::TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                return Err(format!(
+                    "{} is locked by running builder {}",
+                    state_path.display(),
+                    pid.trim()
+                )
+                .into());
+            }
+            Err(std::fs::TryLockError::Error(error)) => {
+                return Err(format!("Couldn't lock {}: {error}", path.display()).into());
+            }
+        }
+        file.set_len(0)?;
+        file.write_all(std::process::id().to_string().as_bytes())?;
+        Ok(StateLock { _file: file })
+    }
+}
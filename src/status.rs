@@ -0,0 +1,72 @@
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{extract::State, routing::get, Json, Router};
+use serde::Serialize;
+
+use crate::daemon::Reconciled;
+
+/// Outcome of the daemon's last reconcile round, served as JSON for the
+/// rig's monitoring.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DaemonStatus {
+    /// Unix time the last round finished at, none before the first one
+    pub last_round_at: Option<u64>,
+    pub last_round_success: bool,
+    pub last_error: Option<String>,
+    pub rounds: u64,
+    /// Live links touching nodes the builder manages
+    pub managed_links: usize,
+    /// Drift the last round found: desired links that weren't live
+    pub missing_links: usize,
+    /// Drift the last round found: links the topology no longer wants
+    pub stale_links: usize,
+}
+
+/// Status shared between the reconcile loop and the endpoint.
+#[derive(Debug, Default)]
+pub struct StatusBoard {
+    status: Mutex<DaemonStatus>,
+}
+
+impl StatusBoard {
+    pub fn round_finished(&self, result: Result<&Reconciled, String>) {
+        let mut status = self.status.lock().unwrap();
+        status.last_round_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs());
+        status.rounds += 1;
+        match result {
+            Ok(reconciled) => {
+                status.last_round_success = true;
+                status.last_error = None;
+                status.managed_links = reconciled.managed_links;
+                status.missing_links = reconciled.missing_links;
+                status.stale_links = reconciled.stale_links;
+            }
+            Err(error) => {
+                status.last_round_success = false;
+                status.last_error = Some(error);
+            }
+        }
+    }
+
+    pub fn status(&self) -> DaemonStatus {
+        self.status.lock().unwrap().clone()
+    }
+}
+
+pub async fn serve_status(
+    listener: tokio::net::TcpListener,
+    board: Arc<StatusBoard>,
+) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/status", get(render_status))
+        .with_state(board);
+    axum::serve(listener, app).await
+}
+
+async fn render_status(State(board): State<Arc<StatusBoard>>) -> Json<DaemonStatus> {
+    Json(board.status())
+}
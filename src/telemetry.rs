@@ -0,0 +1,71 @@
+use std::sync::{Mutex, OnceLock};
+
+use tracing::{info_span, Span};
+
+/// Span of the whole run, stage spans are its children.
+static RUN_SPAN: OnceLock<Span> = OnceLock::new();
+
+/// Span of the stage the reporter is in, RPCs are its children.
+static STAGE_SPAN: Mutex<Option<Span>> = Mutex::new(None);
+
+/// Opens the span of the run for `command`. Spans are only recorded when a
+/// subscriber is installed by `export_otlp`, the log output doesn't change.
+pub fn start_run(command: &str) {
+    let _ = RUN_SPAN.set(info_span!("run", command));
+}
+
+fn run_span() -> Span {
+    RUN_SPAN.get().cloned().unwrap_or_else(Span::none)
+}
+
+/// Closes the span of the previous stage and opens the one of `stage`.
+pub fn enter_stage(stage: &str) {
+    *STAGE_SPAN.lock().unwrap() = Some(info_span!(parent: &run_span(), "stage", stage));
+}
+
+/// Span new RPC and link spans go under, the run's outside of any stage.
+pub fn stage_span() -> Span {
+    STAGE_SPAN.lock().unwrap().clone().unwrap_or_else(run_span)
+}
+
+/// Closes the open spans so they get exported.
+pub fn finish_run() {
+    STAGE_SPAN.lock().unwrap().take();
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Installs a subscriber sending the spans to the OTLP collector at
+/// `endpoint`, like Jaeger's `http://localhost:4317`.
+#[cfg(feature = "otlp")]
+pub fn export_otlp(endpoint: &str) -> Result<(), Box<dyn std::error::Error>> {
+    use opentelemetry::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(opentelemetry_sdk::trace::Config::default().with_resource(
+            opentelemetry_sdk::Resource::new([opentelemetry::KeyValue::new(
+                "service.name",
+                "fr-pmx-builder",
+            )]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+    let tracer = provider.tracer("fr-pmx-builder");
+    opentelemetry::global::set_tracer_provider(provider);
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "otlp"))]
+pub fn export_otlp(_endpoint: &str) -> Result<(), Box<dyn std::error::Error>> {
+    Err("the builder was built without OTLP support".into())
+}
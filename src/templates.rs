@@ -0,0 +1,93 @@
+use toml::{Table, Value};
+
+/// Key of the template an input uses
+const TEMPLATE_KEY: &str = "template";
+/// Key of the initial parameters inside a template
+const PRESET_KEY: &str = "preset";
+
+/// Fills the inputs naming a template with its settings, before the layers
+/// are read into a topology. A template under `[templates.<name>]` takes any
+/// input setting, and its `preset` table the initial parameters of the
+/// input's channel strip. Settings the input makes itself win over the
+/// template, so do parameters of a preset for the input's channel strip.
+pub fn expand(layers: &mut Table) -> Result<(), String> {
+    let Some(templates) = layers.get("templates") else {
+        return Ok(());
+    };
+    let templates = templates
+        .as_table()
+        .ok_or("templates has to be a table of templates")?
+        .clone();
+
+    let mut presets = Vec::new();
+    let inputs = layers
+        .get_mut("inputs")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_table_mut);
+    for input in inputs {
+        let Some(template_name) = input.get(TEMPLATE_KEY) else {
+            continue;
+        };
+        let input_name = input
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let template_name = template_name
+            .as_str()
+            .ok_or_else(|| format!("template of input {input_name} has to be a name"))?;
+        let template = templates
+            .get(template_name)
+            .and_then(Value::as_table)
+            .ok_or_else(|| format!("input {input_name} uses unknown template {template_name}"))?;
+        for (key, value) in template {
+            if key == PRESET_KEY {
+                presets.push((input_name.clone(), value.clone()));
+            } else if !input.contains_key(key) {
+                input.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    for (input_name, template_preset) in presets {
+        add_preset(layers, &input_name, template_preset)?;
+    }
+    Ok(())
+}
+
+/// Fills the preset of `input_name`'s channel strip with the template's
+/// parameters, adding the preset if there is none.
+fn add_preset(layers: &mut Table, input_name: &str, template_preset: Value) -> Result<(), String> {
+    let Value::Table(template_preset) = template_preset else {
+        return Err(format!(
+            "preset of the template of input {input_name} has to be a table"
+        ));
+    };
+    let presets = layers
+        .entry("presets")
+        .or_insert_with(|| Value::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or("presets has to be a list of presets")?;
+    let existing = presets
+        .iter_mut()
+        .filter_map(Value::as_table_mut)
+        .find(|p| p.get("channel_strip").and_then(Value::as_str) == Some(input_name));
+    match existing {
+        Some(preset) => {
+            for (key, value) in template_preset {
+                preset.entry(key).or_insert(value);
+            }
+        }
+        None => {
+            let mut preset = template_preset;
+            preset.insert(
+                String::from("channel_strip"),
+                Value::String(String::from(input_name)),
+            );
+            presets.push(Value::Table(preset));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::sync::{Arc, Mutex};
+
+use tonic::{Request, Response, Status};
+
+use super::MockPmx;
+use crate::pmx::factory::{
+    channel_strip::PmxChannelStrip,
+    output_stage::PmxOutputStage,
+    pmx_factory_server::{PmxFactory, PmxFactoryServer},
+    CreateChannelStripRequest, CreateChannelStripResult, CreateChannelStripsRequest,
+    CreateChannelStripsResponse, CreateOutputStageRequest, DeleteChannelStripRequest,
+    DeleteChannelStripResponse,
+};
+
+pub struct MockFactory {
+    state: Arc<Mutex<MockPmx>>,
+}
+
+pub fn server(state: Arc<Mutex<MockPmx>>) -> PmxFactoryServer<MockFactory> {
+    PmxFactoryServer::new(MockFactory { state })
+}
+
+#[tonic::async_trait]
+impl PmxFactory for MockFactory {
+    async fn create_channel_strip(
+        &self,
+        request: Request<CreateChannelStripRequest>,
+    ) -> Result<Response<PmxChannelStrip>, Status> {
+        let request = request.into_inner();
+        let channel_strip = self
+            .state
+            .lock()
+            .unwrap()
+            .create_channel_strip(&request.name, request.channel_type);
+        Ok(Response::new(PmxChannelStrip {
+            id: channel_strip.id,
+            name: channel_strip.name,
+            gain_plugin_id: channel_strip.gain_plugin_id,
+            saturator_plugin_id: channel_strip.saturator_plugin_id,
+            cross_fader_plugin_id: channel_strip.cross_fader_plugin_id,
+            channel_type: channel_strip.channel_type,
+        }))
+    }
+
+    async fn create_channel_strips(
+        &self,
+        request: Request<CreateChannelStripsRequest>,
+    ) -> Result<Response<CreateChannelStripsResponse>, Status> {
+        let mut state = self.state.lock().unwrap();
+        let results = request
+            .into_inner()
+            .channel_strips
+            .iter()
+            .map(|request| {
+                let channel_strip = state.create_channel_strip(&request.name, request.channel_type);
+                CreateChannelStripResult {
+                    channel_strip: Some(PmxChannelStrip {
+                        id: channel_strip.id,
+                        name: channel_strip.name,
+                        gain_plugin_id: channel_strip.gain_plugin_id,
+                        saturator_plugin_id: channel_strip.saturator_plugin_id,
+                        cross_fader_plugin_id: channel_strip.cross_fader_plugin_id,
+                        channel_type: channel_strip.channel_type,
+                    }),
+                    error: String::new(),
+                }
+            })
+            .collect();
+        Ok(Response::new(CreateChannelStripsResponse { results }))
+    }
+
+    async fn create_output_stage(
+        &self,
+        request: Request<CreateOutputStageRequest>,
+    ) -> Result<Response<PmxOutputStage>, Status> {
+        let output_stage = self
+            .state
+            .lock()
+            .unwrap()
+            .create_output_stage(&request.into_inner().name);
+        Ok(Response::new(PmxOutputStage {
+            id: output_stage.id,
+            name: output_stage.name,
+            cross_fader_plugin_id: output_stage.cross_fader_plugin_id,
+            left_channel_strip_id: output_stage.left_channel_strip_id,
+            right_channel_strip_id: output_stage.right_channel_strip_id,
+        }))
+    }
+
+    async fn delete_channel_strip(
+        &self,
+        request: Request<DeleteChannelStripRequest>,
+    ) -> Result<Response<DeleteChannelStripResponse>, Status> {
+        let id = request.into_inner().id;
+        let mut state = self.state.lock().unwrap();
+        let Some(index) = state.channel_strips.iter().position(|c| c.id == id) else {
+            return Err(Status::not_found(format!("no channel strip {id}")));
+        };
+        let channel_strip = state.channel_strips.remove(index);
+        let plugin_ids: Vec<u32> = [
+            Some(channel_strip.gain_plugin_id),
+            Some(channel_strip.saturator_plugin_id),
+            channel_strip.cross_fader_plugin_id,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        state.plugins.retain(|p| !plugin_ids.contains(&p.id));
+        Ok(Response::new(DeleteChannelStripResponse {}))
+    }
+}
@@ -0,0 +1,20 @@
+use super::MockPmx;
+use crate::pmx::input::PmxInputType;
+
+/// Small studio: a mono kick drum and a stereo synth, sooperlooper running
+/// and a stereo main output.
+pub fn studio() -> MockPmx {
+    let mut mock = MockPmx::default();
+    mock.add_input("Kick", PmxInputType::MonoInput, "Drums");
+    mock.add_input("Synth", PmxInputType::StereoInput, "Melody");
+    mock.add_sooperlooper(4);
+    mock.add_output("Main");
+    mock
+}
+
+/// Graph of a small studio as `pw-dump` prints it: an audio interface
+/// linked to a plugin node, global ids and serials apart, and a port and
+/// link whose node is gone.
+pub fn pw_dump() -> Vec<serde_json::Value> {
+    serde_json::from_str(include_str!("pw-dump.json")).unwrap()
+}
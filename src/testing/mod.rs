@@ -0,0 +1,332 @@
+//! In-process mock implementations of the registry, factory, mod-host and
+//! pipewire services, so the build pipeline can run without a PMX stack.
+
+mod factory;
+mod fixtures;
+mod mod_host;
+mod pipewire;
+mod registry;
+mod tests;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+
+use crate::clients::Clients;
+use crate::connection::{self, ServiceConnection};
+use crate::connector::{self, GraphBackend};
+use crate::pmx::{
+    channel_strip::PmxChannelStrip,
+    factory::channel_strip::PmxChannelStripType,
+    input::{PmxInput, PmxInputType},
+    looper::PmxLooper,
+    output::PmxOutput,
+    output_stage::PmxOutputStage,
+    pipewire::{device::ListDevice, link::ListLink, node::ListNode, port::ListPort},
+    plugin::PmxPlugin,
+    RegisterLinkRequest,
+};
+
+pub use fixtures::{pw_dump, studio};
+
+/// Everything the mock services know, shared between them the way the real
+/// registry, factory and pipewire share the running graph.
+#[derive(Debug, Default)]
+pub struct MockPmx {
+    next_id: u32,
+    pub inputs: Vec<PmxInput>,
+    pub outputs: Vec<PmxOutput>,
+    pub channel_strips: Vec<PmxChannelStrip>,
+    pub output_stages: Vec<PmxOutputStage>,
+    pub plugins: Vec<PmxPlugin>,
+    pub loopers: Vec<PmxLooper>,
+    pub nodes: Vec<ListNode>,
+    pub ports: Vec<ListPort>,
+    pub links: Vec<ListLink>,
+    pub devices: Vec<ListDevice>,
+    /// Links the builder reported to the registry
+    pub registered_links: Vec<RegisterLinkRequest>,
+    /// Last value mod-host was given for each plugin and parameter symbol
+    pub parameters: BTreeMap<(u32, String), f32>,
+}
+
+impl MockPmx {
+    fn next_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Adds a pipewire node with one port per `(direction, channel)` pair.
+    pub fn add_node(&mut self, name: &str, ports: &[(&str, &str)]) -> u32 {
+        let object_serial = self.next_id();
+        self.nodes.push(ListNode {
+            name: String::from(name),
+            object_serial,
+            ..Default::default()
+        });
+        for (id, (direction, channel)) in ports.iter().enumerate() {
+            self.ports.push(ListPort {
+                id: id as u32,
+                node_id: object_serial,
+                name: format!("{direction}_{channel}"),
+                path: format!("{name}:{direction}_{id}"),
+                alias: format!("{name}:{direction}_{channel}"),
+                direction: String::from(*direction),
+                audio_channel: String::from(*channel),
+                ..Default::default()
+            });
+        }
+        object_serial
+    }
+
+    /// Adds a stereo sooperlooper node with the common ports and `loops`
+    /// loops, the ports named the way sooperlooper names them.
+    pub fn add_sooperlooper(&mut self, loops: u32) {
+        let object_serial = self.add_node("sooperlooper", &[]);
+        let prefixes = std::iter::once(String::from("common"))
+            .chain((0..loops).map(|loop_number| format!("loop{loop_number}")));
+        for prefix in prefixes {
+            for direction in ["in", "out"] {
+                for (channel, audio_channel) in [(1, "FL"), (2, "FR")] {
+                    let name = format!("{prefix}_{direction}_{channel}");
+                    let id = self
+                        .ports
+                        .iter()
+                        .filter(|p| p.node_id == object_serial)
+                        .count() as u32;
+                    self.ports.push(ListPort {
+                        id,
+                        node_id: object_serial,
+                        path: format!("sooperlooper:{name}"),
+                        alias: format!("sooperlooper:{name}"),
+                        name,
+                        direction: String::from(direction),
+                        audio_channel: String::from(audio_channel),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn port_path(&self, node_name: &str, direction: &str, channel: &str) -> Option<String> {
+        let node = self.nodes.iter().find(|n| n.name == node_name)?;
+        self.ports
+            .iter()
+            .find(|p| {
+                p.node_id == node.object_serial
+                    && p.direction == direction
+                    && p.audio_channel == channel
+            })
+            .map(|p| p.path.clone())
+    }
+
+    /// Adds a capture device and registers it as an input routed to `group`.
+    pub fn add_input(&mut self, name: &str, input_type: PmxInputType, group: &str) {
+        let node_name = format!("alsa_input.{}", name.to_lowercase());
+        let channels: &[(&str, &str)] = match input_type {
+            PmxInputType::StereoInput => &[("out", "FL"), ("out", "FR")],
+            _ => &[("out", "MONO")],
+        };
+        self.add_node(&node_name, channels);
+
+        let (left, right) = match input_type {
+            PmxInputType::StereoInput => (
+                self.port_path(&node_name, "out", "FL"),
+                self.port_path(&node_name, "out", "FR"),
+            ),
+            _ => (self.port_path(&node_name, "out", "MONO"), None),
+        };
+        self.inputs.push(PmxInput {
+            name: String::from(name),
+            input_type: input_type as i32,
+            left_port_path: left,
+            right_port_path: right,
+            group_channel_strip_name: String::from(group),
+            ..Default::default()
+        });
+    }
+
+    /// Adds a stereo playback device and registers it as an output.
+    pub fn add_output(&mut self, name: &str) {
+        let node_name = format!("alsa_output.{}", name.to_lowercase());
+        self.add_node(&node_name, &[("in", "FL"), ("in", "FR")]);
+        let left_port_path = self.port_path(&node_name, "in", "FL");
+        let right_port_path = self.port_path(&node_name, "in", "FR");
+        self.outputs.push(PmxOutput {
+            name: String::from(name),
+            left_port_path,
+            right_port_path,
+            ..Default::default()
+        });
+    }
+
+    /// Registers a plugin together with its pipewire node. Cross faders get a
+    /// second stereo input pair.
+    fn add_plugin(&mut self, name: &str, cross_fader: bool) -> u32 {
+        let ports: &[(&str, &str)] = if cross_fader {
+            &[
+                ("in", "FL"),
+                ("in", "FR"),
+                ("in", "FL"),
+                ("in", "FR"),
+                ("out", "FL"),
+                ("out", "FR"),
+            ]
+        } else {
+            &[("in", "FL"), ("in", "FR"), ("out", "FL"), ("out", "FR")]
+        };
+        self.add_plugin_node(name, ports)
+    }
+
+    /// Registers a plugin with a single mono input and output.
+    fn add_mono_plugin(&mut self, name: &str) -> u32 {
+        self.add_plugin_node(name, &[("in", "MONO"), ("out", "MONO")])
+    }
+
+    fn add_plugin_node(&mut self, name: &str, ports: &[(&str, &str)]) -> u32 {
+        let id = self.next_id();
+        let plugin_name = format!("{name}_{id}");
+        self.add_node(&plugin_name, ports);
+        self.plugins.push(PmxPlugin {
+            id,
+            name: plugin_name,
+            ..Default::default()
+        });
+        id
+    }
+
+    fn create_channel_strip(&mut self, name: &str, channel_type: i32) -> PmxChannelStrip {
+        let cross_fader_plugin_id = (channel_type == PmxChannelStripType::CrossFaded as i32)
+            .then(|| self.add_plugin("cross_fader", true));
+        let saturator_plugin_id = self.add_plugin("saturator", false);
+        let gain_plugin_id = self.add_plugin("gain", false);
+        let channel_strip = PmxChannelStrip {
+            id: self.next_id(),
+            name: String::from(name),
+            gain_plugin_id,
+            saturator_plugin_id,
+            cross_fader_plugin_id,
+            channel_type,
+        };
+        self.channel_strips.push(channel_strip.clone());
+        channel_strip
+    }
+
+    fn create_output_stage(&mut self, name: &str) -> PmxOutputStage {
+        let basic = PmxChannelStripType::Basic as i32;
+        let left = self.create_channel_strip(&format!("{name} Left"), basic);
+        let right = self.create_channel_strip(&format!("{name} Right"), basic);
+        let cross_fader_plugin_id = self.add_plugin("output_cross_fader", true);
+        let output_stage = PmxOutputStage {
+            id: self.next_id(),
+            name: String::from(name),
+            cross_fader_plugin_id,
+            left_channel_strip_id: left.id,
+            right_channel_strip_id: right.id,
+        };
+        self.output_stages.push(output_stage.clone());
+        output_stage
+    }
+
+    /// Links between the named nodes, as `(output node, output port, input
+    /// node, input port)`.
+    pub fn named_links(&self) -> Vec<(String, u32, String, u32)> {
+        let node_name = |serial: u32| {
+            self.nodes
+                .iter()
+                .find(|n| n.object_serial == serial)
+                .map(|n| n.name.clone())
+                .unwrap_or_default()
+        };
+        self.links
+            .iter()
+            .map(|l| {
+                (
+                    node_name(l.output_node_id),
+                    l.output_port_id,
+                    node_name(l.input_node_id),
+                    l.input_port_id,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Running mock services and clients connected to them.
+pub struct MockServices {
+    pub state: Arc<Mutex<MockPmx>>,
+    pub clients: Clients,
+}
+
+async fn bind() -> (String, TcpListenerStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    (url, TcpListenerStream::new(listener))
+}
+
+/// Serves the mock registry, factory, mod-host and pipewire services on local
+/// sockets.
+pub async fn start(mock: MockPmx) -> MockServices {
+    let state = Arc::new(Mutex::new(mock));
+
+    let (registry_url, incoming) = bind().await;
+    tokio::spawn(
+        Server::builder()
+            .add_service(registry::server(state.clone()))
+            .serve_with_incoming(incoming),
+    );
+
+    let (factory_url, incoming) = bind().await;
+    tokio::spawn(
+        Server::builder()
+            .add_service(factory::server(state.clone()))
+            .serve_with_incoming(incoming),
+    );
+
+    let (pipewire_url, incoming) = bind().await;
+    tokio::spawn(
+        Server::builder()
+            .add_service(pipewire::server(state.clone()))
+            .serve_with_incoming(incoming),
+    );
+
+    let (mod_host_url, incoming) = bind().await;
+    tokio::spawn(
+        Server::builder()
+            .add_service(mod_host::server(state.clone()))
+            .serve_with_incoming(incoming),
+    );
+    let mod_host = connection::mod_host_client(mod_host_url, &ServiceConnection::default())
+        .await
+        .unwrap();
+
+    MockServices {
+        state,
+        clients: connect_clients(registry_url, factory_url, pipewire_url)
+            .await
+            .with_mod_host_client(mod_host),
+    }
+}
+
+/// Clients of the services at the given URLs, without a mod-host.
+pub async fn connect_clients(
+    registry_url: String,
+    factory_url: String,
+    pipewire_url: String,
+) -> Clients {
+    let plaintext = ServiceConnection::default();
+    let registry = connection::registry_client(registry_url, &plaintext)
+        .await
+        .unwrap();
+    let factory = connection::factory_client(factory_url, &plaintext)
+        .await
+        .unwrap();
+    let (graph, _) = connector::connect(GraphBackend::Pipewire, pipewire_url, &plaintext)
+        .await
+        .unwrap();
+    Clients::new(registry, graph).with_factory_client(factory)
+}
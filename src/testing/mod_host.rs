@@ -0,0 +1,75 @@
+use std::sync::{Arc, Mutex};
+
+use tonic::{Request, Response, Status};
+
+use super::MockPmx;
+use crate::pmx::mod_host::{
+    mod_host_proxy_server::{ModHostProxy, ModHostProxyServer},
+    AddPluginRequest, AddPluginResponse, RemovePluginRequest, RemovePluginResponse,
+    UpdateParameterRequest, UpdateParameterResponse,
+};
+
+pub struct MockModHost {
+    state: Arc<Mutex<MockPmx>>,
+}
+
+pub fn server(state: Arc<Mutex<MockPmx>>) -> ModHostProxyServer<MockModHost> {
+    ModHostProxyServer::new(MockModHost { state })
+}
+
+#[tonic::async_trait]
+impl ModHostProxy for MockModHost {
+    async fn add_plugin(
+        &self,
+        request: Request<AddPluginRequest>,
+    ) -> Result<Response<AddPluginResponse>, Status> {
+        let plugin_uri = request.into_inner().plugin_uri;
+        let name = plugin_uri
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let plugin_id = self.state.lock().unwrap().add_plugin(&name, false);
+        Ok(Response::new(AddPluginResponse { plugin_id }))
+    }
+
+    async fn remove_plugin(
+        &self,
+        request: Request<RemovePluginRequest>,
+    ) -> Result<Response<RemovePluginResponse>, Status> {
+        let plugin_instance_id = request.into_inner().plugin_instance_id;
+        let mut state = self.state.lock().unwrap();
+        let Some(index) = state
+            .plugins
+            .iter()
+            .position(|p| p.id == plugin_instance_id)
+        else {
+            return Err(Status::not_found(format!("no plugin {plugin_instance_id}")));
+        };
+        state.plugins.remove(index);
+        Ok(Response::new(RemovePluginResponse {}))
+    }
+
+    async fn update_parameter(
+        &self,
+        request: Request<UpdateParameterRequest>,
+    ) -> Result<Response<UpdateParameterResponse>, Status> {
+        let request = request.into_inner();
+        let mut state = self.state.lock().unwrap();
+        if !state
+            .plugins
+            .iter()
+            .any(|p| p.id == request.plugin_instance_id)
+        {
+            return Err(Status::not_found(format!(
+                "no plugin {}",
+                request.plugin_instance_id
+            )));
+        }
+        state.parameters.insert(
+            (request.plugin_instance_id, request.parameter_symbol),
+            request.value,
+        );
+        Ok(Response::new(UpdateParameterResponse {}))
+    }
+}
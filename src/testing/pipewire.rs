@@ -0,0 +1,163 @@
+use std::sync::{Arc, Mutex};
+
+use tonic::{Request, Response, Status};
+
+use super::MockPmx;
+use crate::pmx::pipewire::{
+    link::ListLink,
+    pipewire_server::{Pipewire, PipewireServer},
+    CreateLinkByNameRequest, CreateLinkRequest, CreateLinkResponse, DeleteLinkRequest,
+    DeleteLinkResponse, ListDevicesRequest, ListDevicesResponse, ListLinksRequest,
+    ListLinksResponse, ListNodesRequest, ListNodesResponse, ListPortsRequest, ListPortsResponse,
+    MeasureLevelsRequest, MeasureLevelsResponse, PortLevel, SetDeviceProfileRequest,
+    SetDeviceProfileResponse,
+};
+
+pub struct MockPipewire {
+    state: Arc<Mutex<MockPmx>>,
+}
+
+pub fn server(state: Arc<Mutex<MockPmx>>) -> PipewireServer<MockPipewire> {
+    PipewireServer::new(MockPipewire { state })
+}
+
+#[tonic::async_trait]
+impl Pipewire for MockPipewire {
+    async fn list_nodes(
+        &self,
+        _request: Request<ListNodesRequest>,
+    ) -> Result<Response<ListNodesResponse>, Status> {
+        let nodes = self.state.lock().unwrap().nodes.clone();
+        Ok(Response::new(ListNodesResponse { nodes }))
+    }
+
+    async fn list_ports(
+        &self,
+        request: Request<ListPortsRequest>,
+    ) -> Result<Response<ListPortsResponse>, Status> {
+        let node_id_filter = request.into_inner().node_id_filter;
+        let ports = self
+            .state
+            .lock()
+            .unwrap()
+            .ports
+            .iter()
+            .filter(|p| node_id_filter.map_or(true, |id| p.node_id == id))
+            .cloned()
+            .collect();
+        Ok(Response::new(ListPortsResponse { ports }))
+    }
+
+    async fn list_links(
+        &self,
+        _request: Request<ListLinksRequest>,
+    ) -> Result<Response<ListLinksResponse>, Status> {
+        let links = self.state.lock().unwrap().links.clone();
+        Ok(Response::new(ListLinksResponse { links }))
+    }
+
+    async fn create_link(
+        &self,
+        request: Request<CreateLinkRequest>,
+    ) -> Result<Response<CreateLinkResponse>, Status> {
+        let request = request.into_inner();
+        let mut state = self.state.lock().unwrap();
+        for node_id in [request.output_node_id, request.input_node_id] {
+            if !state.nodes.iter().any(|n| n.object_serial == node_id) {
+                return Err(Status::not_found(format!("No node with id {node_id}")));
+            }
+        }
+
+        let id = state.next_id();
+        state.links.push(ListLink {
+            id,
+            output_node_id: request.output_node_id,
+            output_port_id: request.output_port_id,
+            input_node_id: request.input_node_id,
+            input_port_id: request.input_port_id,
+            properties: request.properties,
+            ..Default::default()
+        });
+        Ok(Response::new(CreateLinkResponse::default()))
+    }
+
+    async fn create_link_by_name(
+        &self,
+        request: Request<CreateLinkByNameRequest>,
+    ) -> Result<Response<CreateLinkResponse>, Status> {
+        let request = request.into_inner();
+        let mut state = self.state.lock().unwrap();
+        let find_node = |name: &str| {
+            state
+                .nodes
+                .iter()
+                .find(|n| n.name == name)
+                .map(|n| n.object_serial)
+                .ok_or_else(|| Status::not_found(format!("No node named {name}")))
+        };
+        let output_node_id = find_node(&request.output_node_name)?;
+        let input_node_id = find_node(&request.input_node_name)?;
+
+        let id = state.next_id();
+        state.links.push(ListLink {
+            id,
+            output_node_id,
+            output_port_id: request.output_port_id,
+            input_node_id,
+            input_port_id: request.input_port_id,
+            properties: request.properties,
+            ..Default::default()
+        });
+        Ok(Response::new(CreateLinkResponse::default()))
+    }
+
+    async fn delete_link(
+        &self,
+        request: Request<DeleteLinkRequest>,
+    ) -> Result<Response<DeleteLinkResponse>, Status> {
+        let id = request.into_inner().id;
+        self.state.lock().unwrap().links.retain(|l| l.id != id);
+        Ok(Response::new(DeleteLinkResponse::default()))
+    }
+
+    /// Every port measures the same moderate level.
+    async fn measure_levels(
+        &self,
+        request: Request<MeasureLevelsRequest>,
+    ) -> Result<Response<MeasureLevelsResponse>, Status> {
+        let levels = request
+            .into_inner()
+            .port_paths
+            .into_iter()
+            .map(|port_path| PortLevel {
+                port_path,
+                peak_dbfs: -12.0,
+                rms_dbfs: -24.0,
+            })
+            .collect();
+        Ok(Response::new(MeasureLevelsResponse { levels }))
+    }
+
+    async fn list_devices(
+        &self,
+        _request: Request<ListDevicesRequest>,
+    ) -> Result<Response<ListDevicesResponse>, Status> {
+        let devices = self.state.lock().unwrap().devices.clone();
+        Ok(Response::new(ListDevicesResponse { devices }))
+    }
+
+    async fn set_device_profile(
+        &self,
+        request: Request<SetDeviceProfileRequest>,
+    ) -> Result<Response<SetDeviceProfileResponse>, Status> {
+        let request = request.into_inner();
+        let mut state = self.state.lock().unwrap();
+        let device = state
+            .devices
+            .iter_mut()
+            .find(|d| d.id == request.device_id)
+            .ok_or_else(|| Status::not_found(format!("No device with id {}", request.device_id)))?;
+        device.active_profile = request.profile;
+        Ok(Response::new(SetDeviceProfileResponse::default()))
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::{Arc, Mutex};
+
+use tonic::{Request, Response, Status};
+
+use super::MockPmx;
+use crate::pmx::{
+    input::PmxInput,
+    looper::PmxLooper,
+    pmx_registry_server::{PmxRegistry, PmxRegistryServer},
+    EmptyRequest, ListChannelStripsResponse, ListInputsResponse, ListLoopersResponse,
+    ListOutputStagesResponse, ListOutputsResponse, ListPluginsResponse, RegisterInputRequest,
+    RegisterLinkRequest, RegisterLinkResponse, RegisterLooperRequest, UnregisterLinkRequest,
+    UnregisterLinkResponse, UnregisterLooperRequest, UnregisterLooperResponse,
+};
+
+pub struct MockRegistry {
+    state: Arc<Mutex<MockPmx>>,
+}
+
+pub fn server(state: Arc<Mutex<MockPmx>>) -> PmxRegistryServer<MockRegistry> {
+    PmxRegistryServer::new(MockRegistry { state })
+}
+
+#[tonic::async_trait]
+impl PmxRegistry for MockRegistry {
+    async fn list_inputs(
+        &self,
+        _request: Request<EmptyRequest>,
+    ) -> Result<Response<ListInputsResponse>, Status> {
+        let inputs = self.state.lock().unwrap().inputs.clone();
+        Ok(Response::new(ListInputsResponse { inputs }))
+    }
+
+    async fn register_input(
+        &self,
+        request: Request<RegisterInputRequest>,
+    ) -> Result<Response<PmxInput>, Status> {
+        let request = request.into_inner();
+        let input = PmxInput {
+            name: request.name,
+            input_type: request.input_type,
+            left_port_path: request.left_port_path,
+            right_port_path: request.right_port_path,
+            group_channel_strip_name: request.group_channel_strip_name,
+            ..Default::default()
+        };
+        self.state.lock().unwrap().inputs.push(input.clone());
+        Ok(Response::new(input))
+    }
+
+    async fn list_outputs(
+        &self,
+        _request: Request<EmptyRequest>,
+    ) -> Result<Response<ListOutputsResponse>, Status> {
+        let outputs = self.state.lock().unwrap().outputs.clone();
+        Ok(Response::new(ListOutputsResponse { outputs }))
+    }
+
+    async fn list_channel_strips(
+        &self,
+        _request: Request<EmptyRequest>,
+    ) -> Result<Response<ListChannelStripsResponse>, Status> {
+        let channel_strips = self.state.lock().unwrap().channel_strips.clone();
+        Ok(Response::new(ListChannelStripsResponse { channel_strips }))
+    }
+
+    async fn list_output_stages(
+        &self,
+        _request: Request<EmptyRequest>,
+    ) -> Result<Response<ListOutputStagesResponse>, Status> {
+        let output_stages = self.state.lock().unwrap().output_stages.clone();
+        Ok(Response::new(ListOutputStagesResponse { output_stages }))
+    }
+
+    async fn list_plugins(
+        &self,
+        _request: Request<EmptyRequest>,
+    ) -> Result<Response<ListPluginsResponse>, Status> {
+        let plugins = self.state.lock().unwrap().plugins.clone();
+        Ok(Response::new(ListPluginsResponse { plugins }))
+    }
+
+    async fn list_loopers(
+        &self,
+        _request: Request<EmptyRequest>,
+    ) -> Result<Response<ListLoopersResponse>, Status> {
+        let loopers = self.state.lock().unwrap().loopers.clone();
+        Ok(Response::new(ListLoopersResponse { loopers }))
+    }
+
+    async fn register_looper(
+        &self,
+        request: Request<RegisterLooperRequest>,
+    ) -> Result<Response<PmxLooper>, Status> {
+        let looper = PmxLooper {
+            loop_number: request.into_inner().loop_number,
+            ..Default::default()
+        };
+        self.state.lock().unwrap().loopers.push(looper.clone());
+        Ok(Response::new(looper))
+    }
+
+    async fn unregister_looper(
+        &self,
+        request: Request<UnregisterLooperRequest>,
+    ) -> Result<Response<UnregisterLooperResponse>, Status> {
+        let loop_number = request.into_inner().loop_number;
+        self.state
+            .lock()
+            .unwrap()
+            .loopers
+            .retain(|l| l.loop_number != loop_number);
+        Ok(Response::new(UnregisterLooperResponse {}))
+    }
+
+    async fn register_link(
+        &self,
+        request: Request<RegisterLinkRequest>,
+    ) -> Result<Response<RegisterLinkResponse>, Status> {
+        self.state
+            .lock()
+            .unwrap()
+            .registered_links
+            .push(request.into_inner());
+        Ok(Response::new(RegisterLinkResponse {}))
+    }
+
+    async fn unregister_link(
+        &self,
+        request: Request<UnregisterLinkRequest>,
+    ) -> Result<Response<UnregisterLinkResponse>, Status> {
+        let request = request.into_inner();
+        self.state.lock().unwrap().registered_links.retain(|link| {
+            link.output_node_name != request.output_node_name
+                || link.output_port_id != request.output_port_id
+                || link.input_node_name != request.input_node_name
+                || link.input_port_id != request.input_port_id
+        });
+        Ok(Response::new(UnregisterLinkResponse {}))
+    }
+}
@@ -0,0 +1,990 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use super::{connect_clients, pw_dump, start, studio, MockPmx, MockServices};
+use crate::cassette::{self, Cassette};
+use crate::connector::pw_cli_connector;
+use crate::gc;
+use crate::history;
+use crate::live;
+use crate::looper::LooperBackend;
+use crate::metrics::Metrics;
+use crate::model::{Output, OutputStage};
+use crate::plan::{self, Link};
+use crate::pmx::input::PmxInputType;
+use crate::pmx::pipewire::{node::ListNode, port::ListPort};
+use crate::pmx::plugin::PmxPlugin;
+use crate::report::Reporter;
+use crate::state::Checkpoints;
+use crate::topology::{OutputMode, Topology};
+
+async fn build(services: &MockServices, topology: &Topology, test_name: &str) {
+    let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+    let logger = fr_logging::LoggerFactory::new(sender).new_logger(String::from("test"));
+    let reporter = Reporter::new(logger, Arc::new(Metrics::new().unwrap()));
+    let state_file = std::env::temp_dir().join(format!(
+        "fr-pmx-builder-{}-{test_name}.state.json",
+        std::process::id()
+    ));
+    let mut checkpoints = Checkpoints::new(&state_file, false).unwrap();
+
+    crate::build_with_clients(topology, &mut checkpoints, &services.clients, &reporter)
+        .await
+        .unwrap();
+    std::fs::remove_file(state_file).unwrap();
+}
+
+#[tokio::test]
+async fn builds_channel_strips_for_inputs_groups_and_output_stage() {
+    let services = start(studio()).await;
+    build(&services, &Topology::default(), "channel_strips").await;
+
+    let state = services.state.lock().unwrap();
+    let names: Vec<&str> = state
+        .channel_strips
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect();
+    for name in ["Kick", "Synth", "Drums", "Bass", "Melody", "Atmos"] {
+        assert!(names.contains(&name), "missing channel strip {name}");
+    }
+    assert_eq!(state.output_stages.len(), 1);
+    assert_eq!(state.loopers.len(), 2);
+}
+
+#[tokio::test]
+async fn reuses_registered_loopers_when_building_again() {
+    let services = start(studio()).await;
+    build(&services, &Topology::default(), "loopers-first").await;
+    build(&services, &Topology::default(), "loopers-again").await;
+
+    let state = services.state.lock().unwrap();
+    assert_eq!(state.loopers.len(), 2);
+}
+
+#[tokio::test]
+async fn wires_inputs_through_groups_to_the_output() {
+    let services = start(studio()).await;
+    build(&services, &Topology::default(), "wiring").await;
+
+    let state = services.state.lock().unwrap();
+    let links = state.named_links();
+    let kick = state
+        .channel_strips
+        .iter()
+        .find(|c| c.name == "Kick")
+        .unwrap();
+    let kick_cross_fader = state
+        .plugins
+        .iter()
+        .find(|p| Some(p.id) == kick.cross_fader_plugin_id)
+        .unwrap();
+
+    assert!(links.iter().any(|(output, _, input, _)| {
+        output == "alsa_input.kick" && *input == kick_cross_fader.name
+    }));
+    assert!(links
+        .iter()
+        .any(|(_, _, input, _)| input == "alsa_output.main"));
+}
+
+#[tokio::test]
+async fn records_inputs_through_the_named_loop_ports() {
+    let services = start(studio()).await;
+    build(&services, &Topology::default(), "loop-ports").await;
+
+    let state = services.state.lock().unwrap();
+    let port_name = |node_name: &str, port_id: u32| {
+        let node = state.nodes.iter().find(|n| n.name == node_name).unwrap();
+        state
+            .ports
+            .iter()
+            .find(|p| p.node_id == node.object_serial && p.id == port_id)
+            .map(|p| p.name.clone())
+            .unwrap()
+    };
+    let mut recorded: Vec<(String, String)> = state
+        .named_links()
+        .into_iter()
+        .filter(|(_, _, input, _)| input == "sooperlooper")
+        .map(|(output, _, input, port)| (output, port_name(&input, port)))
+        .collect();
+    recorded.sort();
+    assert_eq!(
+        recorded,
+        [
+            ("alsa_input.kick", "loop0_in_1"),
+            ("alsa_input.synth", "loop1_in_1"),
+            ("alsa_input.synth", "loop1_in_2"),
+        ]
+        .map(|(output, port)| (String::from(output), String::from(port)))
+    );
+}
+
+/// Ports of a looper node named `node`, `(id, direction, name)` each.
+fn looper_ports(
+    node: &str,
+    object_serial: u32,
+    ports: &[(u32, &str, &str)],
+) -> (ListNode, Vec<ListPort>) {
+    let node = ListNode {
+        name: String::from(node),
+        object_serial,
+        ..Default::default()
+    };
+    let ports = ports
+        .iter()
+        .map(|(id, direction, name)| ListPort {
+            id: *id,
+            node_id: object_serial,
+            name: String::from(*name),
+            direction: String::from(*direction),
+            ..Default::default()
+        })
+        .collect();
+    (node, ports)
+}
+
+#[test]
+fn resolves_sooperlooper_ports_by_name() {
+    let (node, ports) = looper_ports(
+        "sooperlooper",
+        7,
+        &[
+            (40, "out", "loop1_out_2"),
+            (41, "in", "loop1_in_1"),
+            (42, "in", "common_in_1"),
+            (43, "out", "loop1_out_1"),
+            (44, "in", "loop1_in_2"),
+        ],
+    );
+    let nodes = [node];
+    let looper = LooperBackend::default();
+
+    assert_eq!(looper.record_port(1, 0, &nodes, &ports), Some(41));
+    assert_eq!(looper.record_port(1, 1, &nodes, &ports), Some(44));
+    assert_eq!(looper.playback_port(1, 0, &nodes, &ports), Some(43));
+    assert_eq!(looper.playback_port(1, 1, &nodes, &ports), Some(40));
+    assert_eq!(looper.record_port(0, 0, &nodes, &ports), None);
+}
+
+#[test]
+fn resolves_loop_ports_on_the_instance_running_the_loop() {
+    let (first, mut ports) = looper_ports("sooperlooper", 1, &[(10, "in", "loop0_in_1")]);
+    let (second, second_ports) = looper_ports("sooperlooper-2", 2, &[(20, "in", "loop0_in_1")]);
+    ports.extend(second_ports);
+    let nodes = [first, second];
+    let looper = LooperBackend {
+        max_loops: Some(2),
+        instances: 2,
+        ..Default::default()
+    };
+
+    assert_eq!(looper.record_port(0, 0, &nodes, &ports), Some(10));
+    assert_eq!(looper.record_port(2, 0, &nodes, &ports), Some(20));
+    assert_eq!(looper.record_port(3, 0, &nodes, &ports), None);
+}
+
+/// Links `plan::output_links` plans from an output stage to a four channel
+/// output in `mode`, with the plugins named `cross_fader` and `mono_sum`.
+fn output_mode_links(mode: OutputMode, channels: Option<&str>) -> Vec<Link> {
+    let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+    let logger = fr_logging::LoggerFactory::new(sender).new_logger(String::from("test"));
+    let reporter = Reporter::new(logger, Arc::new(Metrics::new().unwrap()));
+    let mut mock = MockPmx::default();
+    let cross_fader_plugin_id = mock.add_plugin("cross_fader", true);
+    let mono_sum_plugin_id = mock.add_mono_plugin("mono_sum");
+    mock.add_node(
+        "alsa_output.quad",
+        &[
+            ("in", "AUX0"),
+            ("in", "AUX1"),
+            ("in", "AUX2"),
+            ("in", "AUX3"),
+        ],
+    );
+    let output_stage = OutputStage {
+        id: 0,
+        name: String::from("Output Stage"),
+        cross_fader_plugin_id,
+        left_channel_strip_id: 0,
+        right_channel_strip_id: 0,
+    };
+    let output = Output {
+        name: String::from("Quad"),
+        port_paths: (0..4)
+            .map(|channel| format!("alsa_output.quad:in_{channel}"))
+            .collect(),
+        channel_map: channels.map(|channels| channels.parse().unwrap()),
+        mode,
+        mono_sum_plugin_id: Some(mono_sum_plugin_id),
+    };
+
+    let plugin_name = |name: &str| {
+        let plugin = mock.plugins.iter().find(|p| p.name == name);
+        match plugin.map(|p| p.id) {
+            Some(id) if id == cross_fader_plugin_id => String::from("cross_fader"),
+            Some(id) if id == mono_sum_plugin_id => String::from("mono_sum"),
+            _ => String::from(name),
+        }
+    };
+    let mut links: Vec<Link> = plan::output_links(
+        &output_stage,
+        &[output],
+        None,
+        &mock.ports,
+        &mock.nodes,
+        &mock.plugins,
+        &reporter,
+    )
+    .into_iter()
+    .map(|link| Link {
+        output_node_name: plugin_name(&link.output_node_name),
+        input_node_name: plugin_name(&link.input_node_name),
+        ..link
+    })
+    .collect();
+    links.sort();
+    links
+}
+
+#[test]
+fn plays_both_sides_in_stereo_outputs() {
+    let output = "alsa_output.quad";
+    assert_eq!(
+        output_mode_links(OutputMode::Stereo, None),
+        [
+            Link::new("cross_fader", 0, output, 0),
+            Link::new("cross_fader", 0, output, 2),
+            Link::new("cross_fader", 1, output, 1),
+            Link::new("cross_fader", 1, output, 3),
+        ]
+    );
+    assert_eq!(
+        output_mode_links(OutputMode::Stereo, Some("out[0, 1] -> in[2, 3]")),
+        [
+            Link::new("cross_fader", 0, output, 2),
+            Link::new("cross_fader", 1, output, 3),
+        ]
+    );
+}
+
+#[test]
+fn swaps_the_sides_in_swap_outputs() {
+    let output = "alsa_output.quad";
+    assert_eq!(
+        output_mode_links(OutputMode::Swap, None),
+        [
+            Link::new("cross_fader", 0, output, 1),
+            Link::new("cross_fader", 0, output, 3),
+            Link::new("cross_fader", 1, output, 0),
+            Link::new("cross_fader", 1, output, 2),
+        ]
+    );
+    assert_eq!(
+        output_mode_links(OutputMode::Swap, Some("out[0, 1] -> in[2, 3]")),
+        [
+            Link::new("cross_fader", 0, output, 3),
+            Link::new("cross_fader", 1, output, 2),
+        ]
+    );
+}
+
+#[test]
+fn sums_both_sides_through_the_mono_sum_plugin_in_mono_sum_outputs() {
+    let output = "alsa_output.quad";
+    assert_eq!(
+        output_mode_links(OutputMode::MonoSum, None),
+        [
+            Link::new("cross_fader", 0, "mono_sum", 0),
+            Link::new("cross_fader", 1, "mono_sum", 0),
+            Link::new("mono_sum", 0, output, 0),
+            Link::new("mono_sum", 0, output, 1),
+            Link::new("mono_sum", 0, output, 2),
+            Link::new("mono_sum", 0, output, 3),
+        ]
+    );
+    assert_eq!(
+        output_mode_links(OutputMode::MonoSum, Some("out[0, 1] -> in[2, 3]")),
+        [
+            Link::new("cross_fader", 0, "mono_sum", 0),
+            Link::new("cross_fader", 1, "mono_sum", 0),
+            Link::new("mono_sum", 0, output, 2),
+            Link::new("mono_sum", 0, output, 3),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn finds_strips_and_plugins_nothing_uses_any_more() {
+    let services = start(studio()).await;
+    build(&services, &Topology::default(), "garbage").await;
+    {
+        let mut state = services.state.lock().unwrap();
+        let kick = state
+            .channel_strips
+            .iter()
+            .find(|c| c.name == "Kick")
+            .unwrap();
+        let kick_gain = state
+            .plugins
+            .iter()
+            .find(|p| p.id == kick.gain_plugin_id)
+            .map(|p| p.name.clone())
+            .unwrap();
+        state.nodes.retain(|n| n.name != kick_gain);
+        let id = state.next_id();
+        state.plugins.push(PmxPlugin {
+            id,
+            name: String::from("stray"),
+            ..Default::default()
+        });
+    }
+    let topology: Topology = toml::from_str(
+        r#"
+        [[groups]]
+        name = "Drums"
+
+        [[groups]]
+        name = "Bass"
+
+        [[groups]]
+        name = "Melody"
+        "#,
+    )
+    .unwrap();
+    let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+    let logger = fr_logging::LoggerFactory::new(sender).new_logger(String::from("test"));
+    let reporter = Reporter::new(logger, Arc::new(Metrics::new().unwrap()));
+
+    let live_state = live::read_live_state(&services.clients, &reporter)
+        .await
+        .unwrap();
+    let garbage = gc::find_garbage(&topology, &live_state);
+
+    let mut channel_strips: Vec<(&str, &str)> = garbage
+        .channel_strips
+        .iter()
+        .map(|(strip, reason)| (strip.name.as_str(), *reason))
+        .collect();
+    channel_strips.sort();
+    assert_eq!(
+        channel_strips,
+        [
+            ("Atmos", "not in the topology"),
+            ("Kick", "plugin nodes are gone"),
+        ]
+    );
+    let plugins: Vec<(&str, &str)> = garbage
+        .plugins
+        .iter()
+        .map(|(plugin, reason)| (plugin.name.as_str(), *reason))
+        .collect();
+    assert_eq!(plugins, [("stray", "node is gone")]);
+}
+
+#[tokio::test]
+async fn forgets_what_gc_deleted_when_removing_a_plugin_fails() {
+    let services = start(studio()).await;
+    build(&services, &Topology::default(), "failed-gc").await;
+    let (atmos_gain, chain, gone) = {
+        let mut state = services.state.lock().unwrap();
+        let atmos = state
+            .channel_strips
+            .iter()
+            .find(|c| c.name == "Atmos")
+            .unwrap();
+        let atmos_gain = state
+            .plugins
+            .iter()
+            .find(|p| p.id == atmos.gain_plugin_id)
+            .map(|p| p.name.clone())
+            .unwrap();
+        (
+            atmos_gain,
+            state.add_plugin("chain", false),
+            state.add_plugin("gone", false),
+        )
+    };
+    let state_file = std::env::temp_dir().join(format!(
+        "fr-pmx-builder-{}-failed-gc-collect.state.json",
+        std::process::id()
+    ));
+    let mut checkpoints = Checkpoints::new(&state_file, false).unwrap();
+    checkpoints
+        .record_plugin_chain("Kick", vec![chain])
+        .unwrap();
+    checkpoints
+        .record_plugin_chain("Synth", vec![gone])
+        .unwrap();
+    let kept_link = Link::new(&format!("gone_{gone}"), 0, "Melody", 0);
+    checkpoints
+        .record_links(vec![
+            Link::new(&atmos_gain, 0, "alsa_output.main", 0),
+            Link::new(&format!("chain_{chain}"), 0, "Drums", 0),
+            kept_link.clone(),
+        ])
+        .unwrap();
+    let topology: Topology = toml::from_str(
+        r#"
+        [[groups]]
+        name = "Drums"
+
+        [[groups]]
+        name = "Bass"
+
+        [[groups]]
+        name = "Melody"
+        "#,
+    )
+    .unwrap();
+    let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+    let logger = fr_logging::LoggerFactory::new(sender).new_logger(String::from("test"));
+    let reporter = Reporter::new(logger, Arc::new(Metrics::new().unwrap()));
+
+    let live_state = live::read_live_state(&services.clients, &reporter)
+        .await
+        .unwrap()
+        .with_checkpoints(&checkpoints);
+    let garbage = gc::find_garbage(&topology, &live_state);
+    // Removed behind the builder's back, RemovePlugin fails after the strip
+    // and the first chain are gone
+    services
+        .state
+        .lock()
+        .unwrap()
+        .plugins
+        .retain(|p| p.id != gone);
+    let (collected, result) = gc::collect(&garbage, &services.clients, &reporter).await;
+    crate::forget_collected(&collected, &live_state, &mut checkpoints).unwrap();
+    std::fs::remove_file(&state_file).unwrap();
+
+    assert!(result.is_err());
+    let chains: Vec<&str> = checkpoints
+        .plugin_chains()
+        .keys()
+        .map(|k| k.as_str())
+        .collect();
+    assert_eq!(chains, ["Synth"]);
+    assert_eq!(
+        checkpoints.owned_links().iter().collect::<Vec<_>>(),
+        [&kept_link]
+    );
+}
+
+#[tokio::test]
+async fn leaves_links_of_protected_nodes_out_of_the_plan() {
+    let services = start(studio()).await;
+    build(&services, &Topology::default(), "protected-nodes").await;
+    let topology: Topology = toml::from_str(r#"protected_nodes = ["alsa_output.*"]"#).unwrap();
+    let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+    let logger = fr_logging::LoggerFactory::new(sender).new_logger(String::from("test"));
+    let reporter = Reporter::new(logger, Arc::new(Metrics::new().unwrap()));
+
+    let live_state = live::read_live_state(&services.clients, &reporter)
+        .await
+        .unwrap();
+    let desired_links = live_state.desired_links(&topology, &reporter);
+
+    assert!(!desired_links.is_empty());
+    assert!(!desired_links
+        .iter()
+        .any(|link| link.input_node_name == "alsa_output.main"));
+    // Each dropped link is reported once
+    let report = reporter.report_json().unwrap();
+    let protected: Vec<&str> = report["skipped"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|item| item["reason"].as_str())
+        .filter(|reason| reason.contains("alsa_output.main is protected"))
+        .collect();
+    let distinct: std::collections::BTreeSet<&str> = protected.iter().copied().collect();
+    assert!(!protected.is_empty());
+    assert_eq!(distinct.len(), protected.len());
+}
+
+#[tokio::test]
+async fn finds_limiters_of_outputs_that_are_no_longer_limited() {
+    let services = start(studio()).await;
+    build(&services, &Topology::default(), "unused-limiters").await;
+    let limiter = services.state.lock().unwrap().add_plugin("limiter", false);
+    let state_file = std::env::temp_dir().join(format!(
+        "fr-pmx-builder-{}-unused-limiters-gc.state.json",
+        std::process::id()
+    ));
+    let mut checkpoints = Checkpoints::new(&state_file, false).unwrap();
+    checkpoints
+        .record_limiters(BTreeMap::from([(String::from("Main"), limiter)]))
+        .unwrap();
+    let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+    let logger = fr_logging::LoggerFactory::new(sender).new_logger(String::from("test"));
+    let reporter = Reporter::new(logger, Arc::new(Metrics::new().unwrap()));
+
+    // The topology has no [limiter] any more
+    let live_state = live::read_live_state(&services.clients, &reporter)
+        .await
+        .unwrap()
+        .with_checkpoints(&checkpoints);
+    let garbage = gc::find_garbage(&Topology::default(), &live_state);
+    std::fs::remove_file(state_file).unwrap();
+
+    assert_eq!(garbage.limiters, [(String::from("Main"), limiter)]);
+    let plugins: Vec<(u32, &str)> = garbage
+        .plugins
+        .iter()
+        .map(|(plugin, reason)| (plugin.id, *reason))
+        .collect();
+    assert_eq!(plugins, [(limiter, "limiter of an output without one")]);
+}
+
+#[tokio::test]
+async fn registers_created_links_with_the_registry() {
+    let services = start(studio()).await;
+    build(&services, &Topology::default(), "registered-links").await;
+
+    let state = services.state.lock().unwrap();
+    assert!(!state.registered_links.is_empty());
+    assert_eq!(state.registered_links.len(), state.links.len());
+}
+
+#[tokio::test]
+async fn taps_recorded_inputs_into_their_reserved_record_channels() {
+    let mut mock = studio();
+    mock.add_node(
+        "pw-record",
+        &[
+            ("in", "AUX0"),
+            ("in", "AUX1"),
+            ("in", "AUX2"),
+            ("in", "AUX3"),
+            ("in", "AUX4"),
+            ("in", "AUX5"),
+        ],
+    );
+    let services = start(mock).await;
+    let topology: Topology = toml::from_str(
+        r#"
+        record = { node = "pw-record" }
+
+        [[inputs]]
+        name = "Synth"
+        record = true
+
+        [[inputs]]
+        name = "Pad"
+        record = true
+
+        [[inputs]]
+        name = "Kick"
+        record = true
+        "#,
+    )
+    .unwrap();
+    build(&services, &topology, "record-tap").await;
+
+    let state = services.state.lock().unwrap();
+    let mut recorded: Vec<(String, u32)> = state
+        .named_links()
+        .into_iter()
+        .filter(|(_, _, input, _)| input == "pw-record")
+        .map(|(output, _, _, port)| (output, port))
+        .collect();
+    recorded.sort_by_key(|(_, port)| *port);
+    assert_eq!(
+        recorded,
+        vec![
+            (String::from("alsa_input.synth"), 0),
+            (String::from("alsa_input.synth"), 1),
+            (String::from("alsa_input.kick"), 4),
+        ]
+    );
+}
+
+#[test]
+fn assigns_inputs_to_groups_by_tag_and_name_pattern() {
+    let topology: Topology = toml::from_str(
+        r#"
+        [[groups]]
+        name = "Drums"
+        match_tags = "drums|perc"
+
+        [[groups]]
+        name = "Melody"
+        match_inputs = "Synth.*"
+
+        [[inputs]]
+        name = "Shaker"
+        tags = ["perc"]
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(plan::matched_group(&topology, "Shaker"), Some("Drums"));
+    assert_eq!(plan::matched_group(&topology, "Synth 2"), Some("Melody"));
+    assert_eq!(plan::matched_group(&topology, "Kick"), None);
+}
+
+#[tokio::test]
+async fn replays_a_recorded_build_without_services() {
+    let services = start(studio()).await;
+    let recording = Arc::new(Cassette::recording());
+    cassette::scope(
+        Some(recording.clone()),
+        build(&services, &Topology::default(), "record"),
+    )
+    .await;
+    let path = std::env::temp_dir().join(format!(
+        "fr-pmx-builder-{}-replay.cassette.json",
+        std::process::id()
+    ));
+    recording.write(&path).unwrap();
+
+    let cassette = Arc::new(Cassette::read(&path).unwrap());
+    cassette::scope(Some(cassette.clone()), async {
+        // Nothing listens here, every call has to come from the cassette
+        let url = String::from("http://127.0.0.1:1");
+        let offline = MockServices {
+            state: Arc::new(Mutex::new(MockPmx::default())),
+            clients: connect_clients(url.clone(), url.clone(), url).await,
+        };
+        build(&offline, &Topology::default(), "replay").await;
+    })
+    .await;
+    std::fs::remove_file(path).unwrap();
+
+    assert!(cassette.unused().is_empty());
+}
+
+#[tokio::test]
+async fn alternates_the_output_stage_sides_over_a_four_channel_output() {
+    let mut mock = studio();
+    mock.add_node(
+        "alsa_output.quad",
+        &[
+            ("in", "AUX0"),
+            ("in", "AUX1"),
+            ("in", "AUX2"),
+            ("in", "AUX3"),
+        ],
+    );
+    let services = start(mock).await;
+    let topology: Topology = toml::from_str(
+        r#"
+        [[outputs]]
+        name = "Quad"
+        port_paths = [
+            "alsa_output.quad:in_0",
+            "alsa_output.quad:in_1",
+            "alsa_output.quad:in_2",
+            "alsa_output.quad:in_3",
+        ]
+        "#,
+    )
+    .unwrap();
+    build(&services, &topology, "quad-output").await;
+
+    let state = services.state.lock().unwrap();
+    let port_name = |node_name: &str, port_id: u32| {
+        let node = state.nodes.iter().find(|n| n.name == node_name).unwrap();
+        state
+            .ports
+            .iter()
+            .find(|p| p.node_id == node.object_serial && p.id == port_id)
+            .map(|p| p.name.clone())
+            .unwrap()
+    };
+    let mut channels: Vec<(u32, String)> = state
+        .named_links()
+        .into_iter()
+        .filter(|(_, _, input, _)| input == "alsa_output.quad")
+        .map(|(output, output_port, _, channel)| (channel, port_name(&output, output_port)))
+        .collect();
+    channels.sort();
+    assert_eq!(
+        channels,
+        [
+            (0, String::from("out_FL")),
+            (1, String::from("out_FR")),
+            (2, String::from("out_FL")),
+            (3, String::from("out_FR")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn feeds_groups_to_their_crossfader_side_and_centres_the_output_stage() {
+    let mut mock = studio();
+    mock.add_input("Bass DI", PmxInputType::MonoInput, "Bass");
+    let services = start(mock).await;
+    let topology: Topology = toml::from_str(
+        r#"
+        [[groups]]
+        name = "Drums"
+        cross_fader = "a"
+
+        [[groups]]
+        name = "Melody"
+        cross_fader = "b"
+
+        [[groups]]
+        name = "Bass"
+        "#,
+    )
+    .unwrap();
+    build(&services, &topology, "cross-fader-sides").await;
+
+    let state = services.state.lock().unwrap();
+    let strip = |name: &str| {
+        state
+            .channel_strips
+            .iter()
+            .find(|c| c.name == name)
+            .unwrap()
+    };
+    let plugin_name = |id: u32| {
+        state
+            .plugins
+            .iter()
+            .find(|p| p.id == id)
+            .map(|p| p.name.clone())
+            .unwrap()
+    };
+    let saturator_name = |strip_id: u32| {
+        let strip = state
+            .channel_strips
+            .iter()
+            .find(|c| c.id == strip_id)
+            .unwrap();
+        plugin_name(strip.saturator_plugin_id)
+    };
+    let output_stage = &state.output_stages[0];
+    let left = saturator_name(output_stage.left_channel_strip_id);
+    let right = saturator_name(output_stage.right_channel_strip_id);
+    let links = state.named_links();
+    let sides = |group: &str| {
+        let gain = plugin_name(strip(group).gain_plugin_id);
+        let feeds = |side: &str| {
+            links
+                .iter()
+                .any(|(output, _, input, _)| *output == gain && input == side)
+        };
+        (feeds(&left), feeds(&right))
+    };
+
+    assert_eq!(sides("Drums"), (true, false));
+    assert_eq!(sides("Melody"), (false, true));
+    assert_eq!(sides("Bass"), (true, true));
+    assert_eq!(
+        state
+            .parameters
+            .get(&(output_stage.cross_fader_plugin_id, String::from("fade"))),
+        Some(&0.5)
+    );
+}
+
+#[test]
+fn centres_output_stages_playing_sided_groups() {
+    let topology: Topology = toml::from_str(
+        r#"
+        [[groups]]
+        name = "Drums"
+        cross_fader = "a"
+
+        [[groups]]
+        name = "Melody"
+
+        [[output_stages]]
+        name = "Main"
+
+        [[output_stages]]
+        name = "Booth"
+        sources = ["Melody"]
+
+        [[output_stages]]
+        name = "Stream"
+        sources = ["Melody"]
+        cross_fader = 0.25
+        "#,
+    )
+    .unwrap();
+    let output_stages: Vec<OutputStage> = ["Main", "Booth", "Stream"]
+        .into_iter()
+        .zip(1..)
+        .map(|(name, id)| OutputStage {
+            id,
+            name: String::from(name),
+            cross_fader_plugin_id: id * 10,
+            left_channel_strip_id: 0,
+            right_channel_strip_id: 0,
+        })
+        .collect();
+    let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+    let logger = fr_logging::LoggerFactory::new(sender).new_logger(String::from("test"));
+    let reporter = Reporter::new(logger, Arc::new(Metrics::new().unwrap()));
+
+    let settings: Vec<(String, u32, f32)> =
+        plan::output_stage_parameter_settings(&topology, &output_stages, &reporter)
+            .into_iter()
+            .map(|s| (s.channel_strip, s.plugin_id, s.value))
+            .collect();
+
+    assert_eq!(
+        settings,
+        [
+            (String::from("Main"), 10, 0.5),
+            (String::from("Stream"), 30, 0.25),
+        ]
+    );
+}
+
+#[test]
+fn reads_state_files_from_before_the_version() {
+    let path = std::env::temp_dir().join(format!(
+        "fr-pmx-builder-{}-unversioned.state.json",
+        std::process::id()
+    ));
+    std::fs::write(
+        &path,
+        r#"{
+            "completed_stages": ["channel_strips"],
+            "links": [{
+                "output_node_name": "alsa_input.kick",
+                "output_port_id": 1,
+                "input_node_name": "Kick Gain",
+                "input_port_id": 2
+            }]
+        }"#,
+    )
+    .unwrap();
+
+    let checkpoints = Checkpoints::new(&path, true).unwrap();
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert!(checkpoints.is_completed(crate::state::Stage::ChannelStrips));
+    assert_eq!(checkpoints.owned_links().len(), 1);
+    assert!(checkpoints.state().version > 0);
+    assert!(written.contains("\"version\""));
+}
+
+#[test]
+fn keeps_only_the_newest_builds_in_the_history() {
+    let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+    let logger = fr_logging::LoggerFactory::new(sender).new_logger(String::from("test"));
+    let reporter = Reporter::new(logger, Arc::new(Metrics::new().unwrap()));
+    let directory =
+        std::env::temp_dir().join(format!("fr-pmx-builder-{}-history", std::process::id()));
+
+    // Started at 99 seconds, the second run is the oldest
+    for run_id in ["100-1", "99-2", "101-3"] {
+        let record = history::RunRecord::new(
+            run_id,
+            std::time::Duration::from_secs(1),
+            history::config_hash(&[], None),
+            &reporter,
+        )
+        .unwrap();
+        history::record(&directory, 2, &record).unwrap();
+    }
+
+    let mut kept: Vec<String> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    kept.sort();
+    assert_eq!(kept, ["100-1.json", "101-3.json"]);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn reads_nodes_and_ports_from_pw_dump() {
+    let dump = pw_dump();
+
+    let nodes = pw_cli_connector::list_nodes(&dump);
+    let names: Vec<(u32, u32, &str)> = nodes
+        .iter()
+        .map(|n| (n.id, n.object_serial, n.name.as_str()))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            (31, 1031, "alsa_input.usb-Focusrite_Scarlett_2i2"),
+            (45, 1045, "Kick Gain")
+        ]
+    );
+
+    // The port on the missing node 99 is left out instead of landing on node 0
+    let ports = pw_cli_connector::list_ports(&dump);
+    let ports: Vec<(u32, u32, u32, &str, &str)> = ports
+        .iter()
+        .map(|(global_id, p)| {
+            (
+                *global_id,
+                p.node_id,
+                p.id,
+                p.direction.as_str(),
+                p.audio_channel.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        ports,
+        [
+            (60, 1031, 0, "out", "FL"),
+            (61, 1031, 1, "out", "FR"),
+            (70, 1045, 0, "in", "FL"),
+            (71, 1045, 1, "in", "FR"),
+            (72, 1045, 0, "out", "FL"),
+        ]
+    );
+
+    let port = |node_name, port_id, direction| {
+        pw_cli_connector::port_global_id(&dump, node_name, port_id, direction).ok()
+    };
+    assert_eq!(port("Kick Gain", 0, "in"), Some(70));
+    assert_eq!(port("Kick Gain", 0, "out"), Some(72));
+    assert_eq!(
+        port("alsa_input.usb-Focusrite_Scarlett_2i2", 1, "out"),
+        Some(61)
+    );
+    assert_eq!(port("Kick Gain", 2, "in"), None);
+    assert_eq!(port("Snare Gain", 0, "in"), None);
+}
+
+#[test]
+fn maps_pw_dump_links_to_node_serials_and_port_numbers() {
+    let links = pw_cli_connector::list_links(&pw_dump());
+
+    // The link from the missing node 99 is left out
+    assert_eq!(links.len(), 1);
+    let link = &links[0];
+    assert_eq!(
+        (
+            link.id,
+            link.output_node_id,
+            link.output_port_id,
+            link.input_node_id,
+            link.input_port_id
+        ),
+        (90, 1031, 1, 1045, 0)
+    );
+    assert!(crate::ownership::is_builder_link(link));
+}
+
+#[test]
+fn lets_only_one_builder_hold_the_state_file() {
+    let path = std::env::temp_dir().join(format!(
+        "fr-pmx-builder-{}-locked.state.json",
+        std::process::id()
+    ));
+
+    let lock = crate::state::StateLock::acquire(&path).unwrap();
+    let error = crate::state::StateLock::acquire(&path).err().unwrap();
+    assert!(error
+        .to_string()
+        .contains(&format!("locked by running builder {}", std::process::id())));
+    drop(lock);
+    assert!(crate::state::StateLock::acquire(&path).is_ok());
+    std::fs::remove_file(path.with_extension("lock")).unwrap();
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::rpc::Service;
+
+/// Duration of one RPC together with the stage it was made in.
+#[derive(Debug, Clone)]
+pub struct CallTiming {
+    pub stage: String,
+    pub service: Service,
+    pub rpc: &'static str,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Timings {
+    calls: Vec<CallTiming>,
+    /// Stages in the order they started, with their total duration
+    stages: Vec<(String, Duration)>,
+    current_stage: Option<(String, Instant)>,
+}
+
+impl Timings {
+    fn close_stage(&mut self) {
+        if let Some((stage, started)) = self.current_stage.take() {
+            self.stages.push((stage, started.elapsed()));
+        }
+    }
+}
+
+static TIMINGS: OnceLock<Mutex<Timings>> = OnceLock::new();
+
+/// Starts recording stage and call durations. Nothing is recorded unless
+/// this was called.
+pub fn enable() {
+    let _ = TIMINGS.set(Mutex::new(Timings::default()));
+}
+
+pub fn enter_stage(stage: &str) {
+    if let Some(timings) = TIMINGS.get() {
+        let mut timings = timings.lock().unwrap();
+        timings.close_stage();
+        timings.current_stage = Some((String::from(stage), Instant::now()));
+    }
+}
+
+pub fn record_call(service: Service, rpc: &'static str, duration: Duration) {
+    if let Some(timings) = TIMINGS.get() {
+        let mut timings = timings.lock().unwrap();
+        let stage = timings
+            .current_stage
+            .as_ref()
+            .map(|(stage, _)| stage.clone())
+            .unwrap_or_else(|| String::from("Connecting"));
+        timings.calls.push(CallTiming {
+            stage,
+            service,
+            rpc,
+            duration,
+        });
+    }
+}
+
+/// Calls and stage time outside of calls as folded stacks, one
+/// `stage;service rpc microseconds` line each, ready for flamegraph tools.
+fn folded_stacks(timings: &Timings) -> Vec<String> {
+    let mut stacks: BTreeMap<String, u128> = BTreeMap::new();
+    let mut call_time: BTreeMap<&str, Duration> = BTreeMap::new();
+    for call in &timings.calls {
+        let stack = format!("{};{} {}", call.stage, call.service, call.rpc);
+        *stacks.entry(stack).or_default() += call.duration.as_micros();
+        *call_time.entry(call.stage.as_str()).or_default() += call.duration;
+    }
+    let mut stage_time: BTreeMap<&str, Duration> = BTreeMap::new();
+    for (stage, duration) in &timings.stages {
+        *stage_time.entry(stage.as_str()).or_default() += *duration;
+    }
+    for (stage, duration) in stage_time {
+        let outside_calls =
+            duration.saturating_sub(call_time.get(stage).copied().unwrap_or_default());
+        *stacks.entry(String::from(stage)).or_default() += outside_calls.as_micros();
+    }
+    stacks
+        .into_iter()
+        .filter(|(_, micros)| *micros > 0)
+        .map(|(stack, micros)| format!("{stack} {micros}"))
+        .collect()
+}
+
+/// Writes the folded stacks to `path` and prints the `count` slowest calls.
+pub fn write_report(path: &Path, count: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(timings) = TIMINGS.get() else {
+        return Ok(());
+    };
+    let mut timings = timings.lock().unwrap();
+    timings.close_stage();
+
+    let mut file = std::fs::File::create(path)?;
+    for line in folded_stacks(&timings) {
+        writeln!(file, "{line}")?;
+    }
+
+    let mut slowest = timings.calls.clone();
+    slowest.sort_by(|a, b| b.duration.cmp(&a.duration));
+    eprintln!("Slowest calls:");
+    for call in slowest.iter().take(count) {
+        eprintln!(
+            "{:>10.1} ms  {} {} ({})",
+            call.duration.as_secs_f64() * 1000.0,
+            call.service,
+            call.rpc,
+            call.stage
+        );
+    }
+    let total: Duration = timings.stages.iter().map(|(_, duration)| *duration).sum();
+    eprintln!(
+        "{} calls in {} stages, {:.1} s in total, folded stacks written to {}",
+        timings.calls.len(),
+        timings.stages.len(),
+        total.as_secs_f64(),
+        path.display()
+    );
+    Ok(())
+}
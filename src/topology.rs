@@ -0,0 +1,744 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::budget::Budget;
+use crate::calibration::CalibrationConfig;
+use crate::config;
+use crate::connection::Connections;
+use crate::connector::GraphBackend;
+use crate::devices::DeviceConfig;
+use crate::discovery::DiscoveryRule;
+use crate::hooks::HookConfig;
+use crate::latency::LatencyConfig;
+use crate::looper::LooperBackend;
+use crate::midi::MidiMapping;
+use crate::model::Output;
+use crate::naming::Naming;
+use crate::patch::{self, Patch};
+use crate::pmx::factory::channel_strip::PmxChannelStripType;
+use crate::pmx::pipewire::{node::ListNode, port::ListPort};
+use crate::port_map::PortMap;
+use crate::rpc::{Reconnect, Timeouts};
+use crate::templates;
+use crate::validate;
+use crate::venue::{self, Venue};
+
+/// Desired layout of the mixer. Everything that isn't configured falls back
+/// to the layout the builder has always produced.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Topology {
+    pub inputs: Vec<InputConfig>,
+    pub groups: Vec<GroupConfig>,
+    pub aux_buses: Vec<AuxBusConfig>,
+    pub sidechains: Vec<SidechainConfig>,
+    /// External hardware looped into channel strip chains
+    pub inserts: Vec<InsertConfig>,
+    pub cue: Option<CueConfig>,
+    pub talkback: Option<TalkbackConfig>,
+    /// Recording application the inputs with `record` set are tapped into
+    pub record: Option<RecordConfig>,
+    pub output_stages: Vec<OutputStageConfig>,
+    /// Channel layouts of outputs, for interfaces with more than two channels
+    pub outputs: Vec<OutputConfig>,
+    /// Brickwall limiter in front of every output the output stages feed
+    pub limiter: Option<LimiterConfig>,
+    /// Plugin summing both sides for the outputs in `mono_sum` mode
+    pub mono_sum: MonoSumConfig,
+    /// Device profiles set before the inputs are wired
+    pub devices: Vec<DeviceConfig>,
+    /// Limits of the rig checked before building
+    pub budget: Budget,
+    pub looper: LooperBackend,
+    /// Control surfaces wired to the looper and channel strip plugins
+    pub midi: Vec<MidiMapping>,
+    pub latency: LatencyConfig,
+    /// Gain staging of the inputs from measured levels, off unless set
+    pub calibration: Option<CalibrationConfig>,
+    /// Seconds each service gets to answer a call
+    pub timeouts: Timeouts,
+    /// Retries of calls failing because a service went away
+    pub reconnect: Reconnect,
+    /// Channel strips created at once when the factory creates them one by
+    /// one
+    pub concurrency: usize,
+    /// TLS and credentials of the service connections
+    pub connections: Connections,
+    /// Audio graph the mixer is wired in, pipewire unless set to `jack` or
+    /// `pw_cli`
+    pub graph_backend: GraphBackend,
+    /// Names given to the channel strips and output stages the builder creates
+    pub naming: Naming,
+    /// Node name patterns, like `easyeffects_*`, the builder never creates
+    /// or removes links of
+    pub protected_nodes: Vec<String>,
+    /// Initial plugin parameters of channel strips, set after wiring
+    pub presets: Vec<PresetConfig>,
+    /// Rules registering pipewire nodes as inputs before the build
+    pub discovery: Vec<DiscoveryRule>,
+    /// Named variations of the layout, selected with `--profile`
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    /// External commands run before or after build stages
+    pub hooks: Vec<HookConfig>,
+    /// CSV patch list of extra connections made on top of the topology's
+    /// wiring, relative to the topology file
+    pub patch_list: Option<PathBuf>,
+    /// Connections read from `patch_list`
+    #[serde(skip)]
+    pub patches: Vec<Patch>,
+    /// Port mapping of the outputs read from `--venue`, it wins over the
+    /// registry and `outputs`. Only written out by `config dump`, the
+    /// topology file can't set it
+    #[serde(skip_deserializing)]
+    pub venue: Venue,
+}
+
+/// Replaces parts of the topology when the profile is selected.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileConfig {
+    pub groups: Option<Vec<GroupConfig>>,
+    pub aux_buses: Option<Vec<AuxBusConfig>>,
+    pub output_stages: Option<Vec<OutputStageConfig>>,
+    /// Turns looper registration and wiring on or off for every input
+    pub loopers: Option<bool>,
+}
+
+/// Per input settings, matched to registry inputs by name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputConfig {
+    pub name: String,
+    #[serde(default)]
+    pub mono_mode: MonoMode,
+    /// Register a looper for the input and wire it up
+    #[serde(default = "default_looper")]
+    pub looper: bool,
+    /// Fallback for the left port when its registry path doesn't resolve
+    pub left_port: Option<PortMatch>,
+    /// Fallback for the right port when its registry path doesn't resolve
+    pub right_port: Option<PortMatch>,
+    /// Crossfader side the input's channel strip is assigned to
+    pub cross_fader: Option<CrossFaderSide>,
+    #[serde(default)]
+    pub channel_strip_type: ChannelStripType,
+    /// Ports of the channel strip feeding the group
+    #[serde(default)]
+    pub group_ports: PortMap,
+    /// Feed the group before or after the input's gain plugin
+    #[serde(default)]
+    pub group_send: GroupSend,
+    /// Extra plugins spliced in between the saturator and the gain plugin
+    #[serde(default)]
+    pub plugins: Vec<ChainPluginConfig>,
+    /// Also feed the looper playback straight into the group, so it stays
+    /// audible whatever the input strip crossfader does
+    #[serde(default)]
+    pub looper_direct_monitoring: bool,
+    /// Link the input straight into its group without a channel strip or a
+    /// looper, for sources that come mixed already
+    #[serde(default)]
+    pub passthrough: bool,
+    /// Output a passthrough input is linked to instead of its group
+    pub passthrough_output: Option<String>,
+    /// Inputs with a higher priority are wired first, 0 by default
+    #[serde(default)]
+    pub priority: i32,
+    /// Also link the raw input ports into the `record` node
+    #[serde(default)]
+    pub record: bool,
+    /// Labels the `match_tags` of groups select the input by
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Plugin mod-host adds to a channel strip chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainPluginConfig {
+    /// LV2 URI the plugin is instantiated from
+    pub uri: String,
+    /// Ports of the previous plugin in the chain feeding this one
+    #[serde(default)]
+    pub ports: PortMap,
+}
+
+/// Finds an input port without its path, which changes whenever ALSA
+/// renumbers a card or an application restarts. `node` and `port` are tried
+/// first, then `alias`, then `application`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PortMatch {
+    /// Name of the pipewire node owning the port
+    pub node: Option<String>,
+    /// Name of the port on `node`
+    pub port: Option<String>,
+    /// Regular expression matched against the port alias
+    pub alias: Option<String>,
+    /// Application stream the input is fed from, like a browser or Bitwig.
+    /// `*` matches any text and `?` a single character of the node name.
+    pub application: Option<String>,
+    /// Audio channel of the application's output port, like FL, the first
+    /// output port if not set
+    pub channel: Option<String>,
+}
+
+fn default_looper() -> bool {
+    true
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        InputConfig {
+            name: String::new(),
+            mono_mode: MonoMode::default(),
+            looper: default_looper(),
+            left_port: None,
+            right_port: None,
+            cross_fader: None,
+            channel_strip_type: ChannelStripType::default(),
+            group_ports: PortMap::default(),
+            group_send: GroupSend::default(),
+            plugins: Vec::new(),
+            looper_direct_monitoring: false,
+            passthrough: false,
+            passthrough_output: None,
+            priority: 0,
+            record: false,
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// Plugin parameters a channel strip starts with. Parameters that aren't set
+/// keep whatever the factory left.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresetConfig {
+    pub channel_strip: String,
+    /// Gain in dB
+    pub gain: Option<f32>,
+    pub saturator_drive: Option<f32>,
+    pub cross_fader_curve: Option<f32>,
+}
+
+/// Side of the crossfader a channel strip plays on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossFaderSide {
+    A,
+    B,
+}
+
+impl CrossFaderSide {
+    /// Position of the channel strip's cross fader plugin for the side.
+    pub fn value(self) -> f32 {
+        match self {
+            CrossFaderSide::A => 0.0,
+            CrossFaderSide::B => 1.0,
+        }
+    }
+}
+
+/// Point of an input channel strip its group is fed from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupSend {
+    /// Out of the cross fader, before the saturator and the gain plugin.
+    /// Strips without a cross fader send out of the saturator.
+    PreFader,
+    /// Out of the gain plugin
+    #[default]
+    PostFader,
+}
+
+/// How inputs are spread over the left and right plugin ports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MonoMode {
+    /// Mono inputs only feed the left port
+    #[default]
+    LeftOnly,
+    /// Mono inputs feed both the left and the right port
+    DualMono,
+    /// Both ports of stereo inputs are summed into the left and the right port,
+    /// mono inputs are handled like dual mono
+    MonoSum,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupConfig {
+    pub name: String,
+    #[serde(default)]
+    pub channel_strip_type: ChannelStripType,
+    /// Outputs fed from the group besides the output stages, like a stem
+    /// recorder or a streaming encoder defined in outputs
+    #[serde(default)]
+    pub destinations: Vec<String>,
+    /// Side of the output stage crossfader the group plays on, both if not set
+    pub cross_fader: Option<CrossFaderSide>,
+    /// Group this group feeds instead of the output stages
+    pub parent: Option<String>,
+    /// Regex on input names, matching inputs join the group whatever group
+    /// the registry has for them
+    pub match_inputs: Option<String>,
+    /// Regex on input tags, like `drums|perc`, an input with a matching tag
+    /// joins the group
+    pub match_tags: Option<String>,
+}
+
+/// Kind of channel strip the factory creates. Only cross faded strips have
+/// the cross fader plugin the looper plays back into.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelStripType {
+    Basic,
+    #[default]
+    CrossFaded,
+}
+
+impl ChannelStripType {
+    pub fn to_pmx(self) -> PmxChannelStripType {
+        match self {
+            ChannelStripType::Basic => PmxChannelStripType::Basic,
+            ChannelStripType::CrossFaded => PmxChannelStripType::CrossFaded,
+        }
+    }
+}
+
+/// Shared effect bus fed by sends from input channel strips.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuxBusConfig {
+    pub name: String,
+    /// Names of the inputs sending into the bus
+    #[serde(default)]
+    pub sends: Vec<String>,
+    /// Group the bus returns into, the output stage if not set
+    pub destination: Option<String>,
+    /// Ports of each sending channel strip feeding the bus
+    #[serde(default)]
+    pub send_ports: PortMap,
+    /// Ports of the bus feeding its destination group
+    #[serde(default)]
+    pub return_ports: PortMap,
+}
+
+/// Plugin of a channel strip, addressed by the part it plays in the chain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginRole {
+    CrossFader,
+    Saturator,
+    #[default]
+    Gain,
+}
+
+/// Feeds the output of one channel strip into the sidechain input of a
+/// plugin on another channel strip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SidechainConfig {
+    /// Channel strip providing the sidechain signal
+    pub source: String,
+    #[serde(default)]
+    pub source_plugin: PluginRole,
+    /// Channel strip owning the plugin with the sidechain input
+    pub destination: String,
+    pub destination_plugin: PluginRole,
+    /// Sidechain input ports of the destination plugin, left first
+    pub destination_ports: Vec<u32>,
+}
+
+/// Hardware insert splitting a channel strip chain after one of its plugins.
+/// The plugin feeds the `send` output, the `return` input feeds the next
+/// plugin of the chain. The return input gets no channel strip of its own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InsertConfig {
+    pub channel_strip: String,
+    /// Plugin the chain is split after, the cross fader or the saturator
+    #[serde(default = "default_insert_after")]
+    pub after: PluginRole,
+    /// Registry output the hardware is fed from
+    pub send: String,
+    /// Registry input the hardware returns into
+    #[serde(rename = "return")]
+    pub return_input: String,
+}
+
+fn default_insert_after() -> PluginRole {
+    PluginRole::Saturator
+}
+
+/// Pre-listen bus collecting a tap of every input channel strip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CueConfig {
+    #[serde(default = "default_cue_name")]
+    pub name: String,
+    /// Plugin of each input channel strip the cue bus is fed from
+    #[serde(default = "default_cue_tap")]
+    pub tap: PluginRole,
+    /// Name of the headphone output the cue bus is routed to
+    pub output: String,
+    /// Ports of each tapped plugin feeding the cue bus
+    #[serde(default)]
+    pub tap_ports: PortMap,
+}
+
+/// Microphone the engineer talks to the performers through. It gets no
+/// channel strip, it goes straight into the cue bus and, through
+/// `duck_plugin`, into the output stages.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TalkbackConfig {
+    /// Registry input of the microphone
+    pub input: String,
+    /// LV2 URI of the plugin mod-host adds between the microphone and the
+    /// output stages, the microphone stays off the mains if not set
+    pub duck_plugin: Option<String>,
+    /// Ports of the duck plugin feeding the output stage channel strips
+    #[serde(default)]
+    pub duck_ports: PortMap,
+}
+
+/// Multichannel recording node, like `pw-record` or Ardour, capturing the
+/// recorded inputs before any processing. Its input ports take the channels
+/// in id order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordConfig {
+    /// Pipewire node name of the recording application
+    pub node: String,
+}
+
+fn default_cue_name() -> String {
+    String::from("Cue")
+}
+
+fn default_cue_tap() -> PluginRole {
+    PluginRole::Saturator
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputStageConfig {
+    pub name: String,
+    /// Groups and aux buses feeding the stage, every group and every aux bus
+    /// without a destination if not set
+    pub sources: Option<Vec<String>>,
+    /// Names of the outputs the stage is routed to, all outputs if not set
+    pub outputs: Option<Vec<String>>,
+    /// Ports of each source feeding the stage's channel strips
+    pub source_ports: PortMap,
+    /// Position of the stage's crossfader, 0 plays side A and 1 side B.
+    /// Centred if not set and a group of the stage has a side
+    pub cross_fader: Option<f32>,
+}
+
+/// Channel layout of an output. A registered output keeps its left and right
+/// port paths unless `port_paths` is set, an output the registry doesn't know
+/// is added with the configured paths.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputConfig {
+    pub name: String,
+    /// Port path of every channel in order
+    #[serde(default)]
+    pub port_paths: Vec<String>,
+    /// Which plugin port feeds which channel, `in` indices count the channels
+    pub channels: Option<PortMap>,
+    #[serde(default)]
+    pub mode: OutputMode,
+}
+
+/// Limiter mod-host adds between the output stage crossfaders and each
+/// output they feed, so the PA is protected whatever the mix does. Outputs
+/// whose limiter isn't live aren't wired at all.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimiterConfig {
+    /// LV2 URI of the limiter
+    pub plugin: String,
+    /// Ceiling in dB
+    #[serde(default = "default_limiter_ceiling")]
+    pub ceiling: f32,
+    /// Symbol of the limiter's ceiling control
+    #[serde(default = "default_limiter_ceiling_parameter")]
+    pub ceiling_parameter: String,
+}
+
+fn default_limiter_ceiling() -> f32 {
+    -1.0
+}
+
+fn default_limiter_ceiling_parameter() -> String {
+    String::from("ceiling")
+}
+
+/// Mono plugin mod-host adds for each output in `mono_sum` mode. Pipewire
+/// sums both sides into its input and the plugin pads the sum, so a mono
+/// source doesn't come out 6 dB louder than on a stereo output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MonoSumConfig {
+    /// LV2 URI of the plugin, with a single audio input and output
+    pub plugin: String,
+    /// Gain in dB the sum is padded by
+    pub gain: f32,
+    /// Symbol of the plugin's gain control
+    pub gain_parameter: String,
+}
+
+impl Default for MonoSumConfig {
+    fn default() -> Self {
+        MonoSumConfig {
+            plugin: String::from("http://lv2plug.in/plugins/eg-amp"),
+            gain: -6.0,
+            gain_parameter: String::from("gain"),
+        }
+    }
+}
+
+/// How the left and right side reach the channels of an output, on top of
+/// its channel map.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputMode {
+    #[default]
+    Stereo,
+    /// Both sides summed into every channel through the mono sum plugin,
+    /// for a single zone speaker
+    MonoSum,
+    /// Left and right trade places
+    Swap,
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Topology {
+            inputs: Vec::new(),
+            groups: ["Drums", "Bass", "Melody", "Atmos"]
+                .iter()
+                .map(|name| GroupConfig {
+                    name: String::from(*name),
+                    channel_strip_type: ChannelStripType::default(),
+                    destinations: Vec::new(),
+                    cross_fader: None,
+                    parent: None,
+                    match_inputs: None,
+                    match_tags: None,
+                })
+                .collect(),
+            aux_buses: Vec::new(),
+            sidechains: Vec::new(),
+            inserts: Vec::new(),
+            cue: None,
+            talkback: None,
+            record: None,
+            output_stages: vec![OutputStageConfig::default()],
+            outputs: Vec::new(),
+            limiter: None,
+            mono_sum: MonoSumConfig::default(),
+            devices: Vec::new(),
+            budget: Budget::default(),
+            looper: LooperBackend::default(),
+            midi: Vec::new(),
+            latency: LatencyConfig::default(),
+            calibration: None,
+            timeouts: Timeouts::default(),
+            reconnect: Reconnect::default(),
+            concurrency: 1,
+            connections: Connections::default(),
+            graph_backend: GraphBackend::default(),
+            naming: Naming::default(),
+            protected_nodes: Vec::new(),
+            presets: Vec::new(),
+            discovery: Vec::new(),
+            profiles: BTreeMap::new(),
+            hooks: Vec::new(),
+            patch_list: None,
+            patches: Vec::new(),
+            venue: Venue::default(),
+        }
+    }
+}
+
+impl Topology {
+    /// Topology with the profile `name` applied, checked for references the
+    /// profile left dangling.
+    pub fn with_profile(&self, name: &str) -> Result<Topology, String> {
+        let Some(profile) = self.profiles.get(name) else {
+            let available: Vec<&str> = self.profiles.keys().map(|k| k.as_str()).collect();
+            return Err(format!(
+                "Unknown profile {name}, available profiles: {}",
+                available.join(", ")
+            ));
+        };
+
+        let mut topology = self.clone();
+        if let Some(groups) = &profile.groups {
+            topology.groups = groups.clone();
+        }
+        if let Some(aux_buses) = &profile.aux_buses {
+            topology.aux_buses = aux_buses.clone();
+        }
+        if let Some(output_stages) = &profile.output_stages {
+            topology.output_stages = output_stages.clone();
+        }
+        if let Some(loopers) = profile.loopers {
+            topology.looper.enabled = loopers;
+        }
+
+        topology
+            .validate()
+            .map_err(|e| format!("Profile {name}: {e}"))?;
+        Ok(topology)
+    }
+
+    /// Checks the topology on its own, every violation is reported at once.
+    pub fn validate(&self) -> Result<(), String> {
+        validate::into_result(validate::topology_violations(self))
+    }
+
+    /// Whether the input is the return of a hardware insert.
+    pub fn is_insert_return(&self, input_name: &str) -> bool {
+        self.inserts.iter().any(|i| i.return_input == input_name)
+    }
+
+    /// Whether the input is the talkback microphone.
+    pub fn is_talkback(&self, input_name: &str) -> bool {
+        self.talkback
+            .as_ref()
+            .is_some_and(|t| t.input == input_name)
+    }
+
+    /// Whether the input is linked past the channel strips.
+    pub fn is_passthrough(&self, input_name: &str) -> bool {
+        self.inputs
+            .iter()
+            .any(|i| i.name == input_name && i.passthrough)
+    }
+
+    /// Whether the input gets a channel strip, a looper and a group of its
+    /// own. Insert returns, the talkback microphone and passthrough inputs
+    /// are wired elsewhere.
+    pub fn has_chain(&self, input_name: &str) -> bool {
+        !self.is_insert_return(input_name)
+            && !self.is_talkback(input_name)
+            && !self.is_passthrough(input_name)
+    }
+
+    /// Registered outputs with the configured channel layouts applied, plus
+    /// the outputs only the topology or the venue defines. Venue node names
+    /// are resolved against the live `nodes` and `ports`.
+    pub fn outputs(
+        &self,
+        registered: &[Output],
+        nodes: &[ListNode],
+        ports: &[ListPort],
+    ) -> Vec<Output> {
+        let mut outputs = registered.to_vec();
+        for config in &self.outputs {
+            let output = find_or_add_output(&mut outputs, &config.name);
+            if !config.port_paths.is_empty() {
+                output.port_paths = config.port_paths.clone();
+            }
+            output.channel_map = config.channels.clone();
+            output.mode = config.mode;
+        }
+        for (name, venue_output) in &self.venue.outputs {
+            find_or_add_output(&mut outputs, name).port_paths =
+                venue_output.port_paths(nodes, ports);
+        }
+        outputs
+    }
+
+    /// Path of `patch_list`, relative to the topology file or the overlay
+    /// without one.
+    pub fn patch_list_path(&self, path: Option<&Path>, overlay: Option<&Path>) -> Option<PathBuf> {
+        let patch_list = self.patch_list.as_ref()?;
+        let directory = path
+            .or(overlay)
+            .and_then(Path::parent)
+            .unwrap_or(Path::new("."));
+        Some(directory.join(patch_list))
+    }
+
+    pub fn input_config(&self, name: &str) -> InputConfig {
+        self.inputs
+            .iter()
+            .find(|i| i.name == name)
+            .cloned()
+            .unwrap_or_else(|| InputConfig {
+                name: String::from(name),
+                ..InputConfig::default()
+            })
+    }
+}
+
+impl Default for OutputStageConfig {
+    fn default() -> Self {
+        OutputStageConfig {
+            name: String::from("Output Stage"),
+            sources: None,
+            outputs: None,
+            source_ports: PortMap::default(),
+            cross_fader: None,
+        }
+    }
+}
+
+/// Reads the topology file with the overlay merged over it, the default
+/// topology if neither is given.
+pub fn read_topology(
+    path: Option<&Path>,
+    overlay: Option<&Path>,
+) -> Result<Topology, Box<dyn std::error::Error>> {
+    if path.is_none() && overlay.is_none() {
+        return Ok(Topology::default());
+    }
+    let mut layers = config::read_layers(path, overlay)?;
+    let sources: Vec<String> = path
+        .into_iter()
+        .chain(overlay)
+        .map(|p| p.display().to_string())
+        .collect();
+    templates::expand(&mut layers).map_err(|e| format!("{}: {e}", sources.join(" + ")))?;
+    let mut topology: Topology = toml::Value::Table(layers)
+        .try_into()
+        .map_err(|e| format!("{}: {e}", sources.join(" + ")))?;
+    topology
+        .validate()
+        .map_err(|e| format!("{}: {e}", sources.join(" + ")))?;
+    if let Some(patch_list) = topology.patch_list_path(path, overlay) {
+        topology.patches = patch::read_patch_list(&patch_list)?;
+    }
+    Ok(topology)
+}
+
+fn find_or_add_output<'a>(outputs: &'a mut Vec<Output>, name: &str) -> &'a mut Output {
+    match outputs.iter().position(|o| o.name == name) {
+        Some(index) => &mut outputs[index],
+        None => {
+            outputs.push(Output {
+                name: String::from(name),
+                port_paths: Vec::new(),
+                channel_map: None,
+                mode: OutputMode::default(),
+                mono_sum_plugin_id: None,
+            });
+            outputs.last_mut().unwrap()
+        }
+    }
+}
+
+/// Reads the topology file, its overlay and the venue and applies the
+/// `PMX_BUILDER_*` overrides and `profile` to them.
+pub fn load_topology(
+    path: Option<&Path>,
+    overlay: Option<&Path>,
+    venue: Option<&Path>,
+    profile: Option<&str>,
+) -> Result<Topology, Box<dyn std::error::Error>> {
+    let mut topology = read_topology(path, overlay)?;
+    if let Some(venue) = venue {
+        topology.venue = venue::read_venue(venue)?;
+    }
+    config::apply_environment(&mut topology)?;
+    match profile {
+        Some(profile) => Ok(topology.with_profile(profile)?),
+        None => Ok(topology),
+    }
+}
@@ -0,0 +1,389 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::builder;
+use crate::clients::Clients;
+use crate::live::{self, LiveState};
+use crate::plan::{self, ConnectionPlan, Link};
+use crate::report::{Progress, Reporter};
+use crate::state::{Checkpoints, Stage};
+use crate::topology::Topology;
+
+/// Wiring state of one planned link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LinkStatus {
+    Connected,
+    Missing,
+    Failed(String),
+}
+
+impl LinkStatus {
+    fn label(&self) -> &'static str {
+        match self {
+            LinkStatus::Connected => "connected",
+            LinkStatus::Missing => "missing",
+            LinkStatus::Failed(_) => "failed",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            LinkStatus::Connected => Color::Green,
+            LinkStatus::Missing => Color::Yellow,
+            LinkStatus::Failed(_) => Color::Red,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Links,
+    Stages,
+}
+
+/// Everything the console shows. The live state is read again after every
+/// action so the table always reflects pipewire.
+struct App {
+    live_state: LiveState,
+    links: Vec<(Link, LinkStatus)>,
+    link_table: TableState,
+    stage_list: ListState,
+    focus: Focus,
+    status: String,
+}
+
+impl App {
+    fn new(live_state: LiveState) -> App {
+        App {
+            live_state,
+            links: Vec::new(),
+            link_table: TableState::default().with_selected(Some(0)),
+            stage_list: ListState::default().with_selected(Some(0)),
+            focus: Focus::Links,
+            status: String::from("Ready"),
+        }
+    }
+
+    /// Plans the desired links against the live state and marks each one
+    /// connected, missing or failed by the last attempt.
+    fn update_links(&mut self, topology: &Topology, reporter: &Reporter) {
+        let plan = self.live_state.connection_plan(topology, reporter);
+        let existing: BTreeSet<Link> = self
+            .live_state
+            .links
+            .iter()
+            .filter_map(|link| plan::resolve_live_link(link, &self.live_state.nodes))
+            .collect();
+        let failed: BTreeMap<Link, String> = reporter.failed_links().into_iter().collect();
+        self.links = plan
+            .links()
+            .iter()
+            .map(|link| {
+                let status = if existing.contains(link) {
+                    LinkStatus::Connected
+                } else if let Some(error) = failed.get(link) {
+                    LinkStatus::Failed(error.clone())
+                } else {
+                    LinkStatus::Missing
+                };
+                (link.clone(), status)
+            })
+            .collect();
+    }
+
+    async fn refresh(
+        &mut self,
+        topology: &Topology,
+        checkpoints: &Checkpoints,
+        clients: &Clients,
+        reporter: &Reporter,
+    ) {
+        match live::read_live_state(clients, reporter).await {
+            Ok(live_state) => {
+                self.live_state = live_state.with_checkpoints(checkpoints);
+                self.update_links(topology, reporter);
+            }
+            Err(error) => self.status = format!("Couldn't read the live state: {error}"),
+        }
+    }
+
+    fn select(&mut self, offset: isize) {
+        let (state, len) = match self.focus {
+            Focus::Links => (self.link_table.selected_mut(), self.links.len()),
+            Focus::Stages => (self.stage_list.selected_mut(), Stage::ALL.len()),
+        };
+        if len == 0 {
+            return;
+        }
+        let selected = state.unwrap_or(0) as isize + offset;
+        *state = Some(selected.clamp(0, len as isize - 1) as usize);
+    }
+
+    fn selected_link(&self) -> Option<&Link> {
+        self.link_table
+            .selected()
+            .and_then(|index| self.links.get(index))
+            .map(|(link, _)| link)
+    }
+
+    fn selected_stage(&self) -> Option<Stage> {
+        self.stage_list
+            .selected()
+            .and_then(|index| Stage::ALL.get(index).copied())
+    }
+}
+
+fn draw(frame: &mut Frame, app: &mut App, checkpoints: &Checkpoints) {
+    let [main, footer] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(3)]).areas(frame.area());
+    let [left, right] =
+        Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)]).areas(main);
+    let [inputs, channel_strips, loopers, stages] = Layout::vertical([
+        Constraint::Percentage(30),
+        Constraint::Percentage(30),
+        Constraint::Percentage(15),
+        Constraint::Length(Stage::ALL.len() as u16 + 2),
+    ])
+    .areas(left);
+
+    draw_list(
+        frame,
+        inputs,
+        "Inputs",
+        app.live_state.inputs.iter().map(|i| {
+            format!(
+                "{} ({:?}) -> {}",
+                i.name, i.input_type, i.group_channel_strip_name
+            )
+        }),
+    );
+    draw_list(
+        frame,
+        channel_strips,
+        "Channel strips",
+        app.live_state
+            .channel_strips
+            .iter()
+            .map(|c| format!("{} #{}", c.name, c.id)),
+    );
+    draw_list(
+        frame,
+        loopers,
+        "Loopers",
+        app.live_state
+            .loopers
+            .iter()
+            .map(|l| format!("loop {}", l.loop_number)),
+    );
+
+    let highlight = Style::default().add_modifier(Modifier::REVERSED);
+    let stage_items: Vec<ListItem> = Stage::ALL
+        .iter()
+        .map(|stage| {
+            let mark = if checkpoints.is_completed(*stage) {
+                "x"
+            } else {
+                " "
+            };
+            ListItem::new(format!("[{mark}] {stage:?}"))
+        })
+        .collect();
+    let stage_list = List::new(stage_items)
+        .block(focus_block("Stages", app.focus == Focus::Stages))
+        .highlight_style(highlight);
+    frame.render_stateful_widget(stage_list, stages, &mut app.stage_list);
+
+    let connected = app
+        .links
+        .iter()
+        .filter(|(_, status)| *status == LinkStatus::Connected)
+        .count();
+    let rows = app.links.iter().map(|(link, status)| {
+        let error = match status {
+            LinkStatus::Failed(error) => error.as_str(),
+            _ => "",
+        };
+        Row::new([
+            String::from(status.label()),
+            link.to_string(),
+            String::from(error),
+        ])
+        .style(Style::default().fg(status.color()))
+    });
+    let link_table = Table::new(
+        rows,
+        [
+            Constraint::Length(10),
+            Constraint::Percentage(60),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(
+        Row::new(["Status", "Link", "Error"]).style(Style::default().add_modifier(Modifier::BOLD)),
+    )
+    .block(focus_block(
+        &format!("Links ({connected}/{} connected)", app.links.len()),
+        app.focus == Focus::Links,
+    ))
+    .highlight_style(highlight);
+    frame.render_stateful_widget(link_table, right, &mut app.link_table);
+
+    let help = "q quit  tab switch  ↑↓ select  r retry link  a connect missing  enter re-run stage  u refresh";
+    frame.render_widget(
+        Paragraph::new(vec![Line::from(app.status.as_str()), Line::from(help)])
+            .block(Block::bordered()),
+        footer,
+    );
+}
+
+fn draw_list(frame: &mut Frame, area: Rect, title: &str, items: impl Iterator<Item = String>) {
+    let items: Vec<ListItem> = items.map(ListItem::new).collect();
+    let title = format!("{title} ({})", items.len());
+    frame.render_widget(List::new(items).block(Block::bordered().title(title)), area);
+}
+
+fn focus_block(title: &str, focused: bool) -> Block<'static> {
+    let block = Block::bordered().title(String::from(title));
+    if focused {
+        block.border_style(Style::default().fg(Color::Cyan))
+    } else {
+        block
+    }
+}
+
+async fn run_app(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    topology: &Topology,
+    checkpoints: &mut Checkpoints,
+    clients: &Clients,
+    reporter: &Reporter,
+    progress: &mut UnboundedReceiver<Progress>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        while let Ok(progress) = progress.try_recv() {
+            app.status = format!("{}: {}", progress.stage, progress.message);
+        }
+        terminal.draw(|frame| draw(frame, app, checkpoints))?;
+
+        if !event::poll(Duration::from_millis(200))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Tab => {
+                app.focus = match app.focus {
+                    Focus::Links => Focus::Stages,
+                    Focus::Stages => Focus::Links,
+                }
+            }
+            KeyCode::Down | KeyCode::Char('j') => app.select(1),
+            KeyCode::Up | KeyCode::Char('k') => app.select(-1),
+            KeyCode::Char('u') => {
+                app.refresh(topology, checkpoints, clients, reporter).await;
+                app.status = String::from("Refreshed");
+            }
+            KeyCode::Char('r') => {
+                let Some(link) = app.selected_link().cloned() else {
+                    continue;
+                };
+                let result = builder::create_links(
+                    &[link.clone()],
+                    &[],
+                    &topology.protected_nodes,
+                    clients,
+                    reporter,
+                )
+                .await;
+                checkpoints.record_links(reporter.created_links())?;
+                app.refresh(topology, checkpoints, clients, reporter).await;
+                app.status = match result {
+                    Ok(()) => format!("Connected {link}"),
+                    Err(error) => format!("Couldn't connect {link}: {error}"),
+                };
+            }
+            KeyCode::Char('a') => {
+                let missing = ConnectionPlan::new(
+                    app.links
+                        .iter()
+                        .filter(|(_, status)| *status != LinkStatus::Connected)
+                        .map(|(link, _)| link.clone()),
+                );
+                let result = builder::execute_plan(
+                    &missing,
+                    &BTreeSet::new(),
+                    &topology.protected_nodes,
+                    clients,
+                    reporter,
+                )
+                .await;
+                checkpoints.record_links(reporter.created_links())?;
+                app.refresh(topology, checkpoints, clients, reporter).await;
+                app.status = match result {
+                    Ok(()) => format!("Connected {} links", missing.len()),
+                    Err(error) => error.to_string(),
+                };
+            }
+            KeyCode::Enter if app.focus == Focus::Stages => {
+                let Some(stage) = app.selected_stage() else {
+                    continue;
+                };
+                // Wiring runs last, so it has to run again after any stage.
+                checkpoints.reset(stage)?;
+                checkpoints.reset(Stage::OutputStageWired)?;
+                let result =
+                    crate::build_with_clients(topology, checkpoints, clients, reporter).await;
+                app.refresh(topology, checkpoints, clients, reporter).await;
+                app.status = match result {
+                    Ok(()) => format!("Re-ran {stage:?}"),
+                    Err(error) => format!("{stage:?} failed: {error}"),
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Live wiring console: shows the registry and the state of every planned
+/// link, and retries links or re-runs build stages on request.
+pub async fn run(
+    topology: &Topology,
+    checkpoints: &mut Checkpoints,
+    clients: &Clients,
+    reporter: &Reporter,
+    mut progress: UnboundedReceiver<Progress>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let live_state = live::read_live_state(clients, reporter)
+        .await?
+        .with_checkpoints(checkpoints);
+    let mut app = App::new(live_state);
+    app.update_links(topology, reporter);
+
+    let mut terminal = ratatui::init();
+    let result = run_app(
+        &mut terminal,
+        &mut app,
+        topology,
+        checkpoints,
+        clients,
+        reporter,
+        &mut progress,
+    )
+    .await;
+    ratatui::restore();
+    result
+}
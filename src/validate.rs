@@ -0,0 +1,443 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::model::{Input, Output};
+use crate::naming;
+use crate::plan;
+use crate::pmx::pipewire::{node::ListNode, port::ListPort};
+use crate::topology::{ChannelStripType, PluginRole, Topology};
+
+/// Joins the violations into one error listing all of them.
+pub fn into_result(violations: Vec<String>) -> Result<(), String> {
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let mut message = format!("{} problems found:", violations.len());
+    for violation in violations {
+        message.push_str("\n  ");
+        message.push_str(&violation);
+    }
+    Err(message)
+}
+
+fn duplicates<'a>(names: impl Iterator<Item = &'a str>) -> Vec<(usize, &'a str)> {
+    let mut seen = BTreeSet::new();
+    names
+        .enumerate()
+        .filter(|(_, name)| !seen.insert(*name))
+        .collect()
+}
+
+/// Groups feeding each other through their parents in a circle, each cycle
+/// from its first group in topology order back to that group.
+fn group_cycles(topology: &Topology) -> Vec<Vec<&str>> {
+    let parents: BTreeMap<&str, &str> = topology
+        .groups
+        .iter()
+        .filter_map(|g| g.parent.as_deref().map(|p| (g.name.as_str(), p)))
+        .collect();
+    let mut cycles = Vec::new();
+    let mut reported: BTreeSet<&str> = BTreeSet::new();
+    for group in &topology.groups {
+        let start = group.name.as_str();
+        let mut path = vec![start];
+        let mut current = start;
+        while let Some(parent) = parents.get(current).copied() {
+            if parent == start {
+                if path.iter().all(|g| !reported.contains(g)) {
+                    reported.extend(path.iter().copied());
+                    path.push(start);
+                    cycles.push(path);
+                }
+                break;
+            }
+            if path.contains(&parent) {
+                // A cycle further up, reported from one of its own groups
+                break;
+            }
+            path.push(parent);
+            current = parent;
+        }
+    }
+    cycles
+}
+
+/// Problems visible in the topology alone, each prefixed with the field it
+/// was found in.
+pub fn topology_violations(topology: &Topology) -> Vec<String> {
+    let mut violations = Vec::new();
+
+    for (index, name) in duplicates(topology.inputs.iter().map(|i| i.name.as_str())) {
+        violations.push(format!(
+            "inputs[{index}].name: input {name} is configured twice"
+        ));
+    }
+    for (index, name) in duplicates(topology.groups.iter().map(|g| g.name.as_str())) {
+        violations.push(format!(
+            "groups[{index}].name: group {name} is defined twice"
+        ));
+    }
+    for (index, name) in duplicates(topology.aux_buses.iter().map(|a| a.name.as_str())) {
+        violations.push(format!(
+            "aux_buses[{index}].name: aux bus {name} is defined twice"
+        ));
+    }
+    for (index, name) in duplicates(topology.output_stages.iter().map(|o| o.name.as_str())) {
+        violations.push(format!(
+            "output_stages[{index}].name: output stage {name} is defined twice"
+        ));
+    }
+    for (index, input) in topology.inputs.iter().enumerate() {
+        if !input.passthrough && input.passthrough_output.is_some() {
+            violations.push(format!(
+                "inputs[{index}].passthrough_output: input {} isn't a passthrough input",
+                input.name
+            ));
+        }
+        if input.record && topology.record.is_none() {
+            violations.push(format!(
+                "inputs[{index}].record: input {} is recorded but there's no record node",
+                input.name
+            ));
+        }
+        if input.passthrough && !input.plugins.is_empty() {
+            violations.push(format!(
+                "inputs[{index}].plugins: passthrough input {} has no channel strip to \
+                 splice plugins into",
+                input.name
+            ));
+        }
+    }
+    if let Some(talkback) = &topology.talkback {
+        if topology.cue.is_none() && talkback.duck_plugin.is_none() {
+            violations.push(format!(
+                "talkback: {} goes nowhere, it needs a cue bus or a duck_plugin",
+                talkback.input
+            ));
+        }
+    }
+
+    if let Some(calibration) = &topology.calibration {
+        if calibration.seconds >= topology.timeouts.pipewire {
+            violations.push(format!(
+                "calibration.seconds: measuring for {} seconds needs timeouts.pipewire above it, \
+                 it is {}",
+                calibration.seconds, topology.timeouts.pipewire
+            ));
+        }
+    }
+
+    if topology.concurrency == 0 {
+        violations.push(String::from(
+            "concurrency: at least one channel strip has to be created at a time",
+        ));
+    }
+
+    for (index, name) in duplicates(topology.outputs.iter().map(|o| o.name.as_str())) {
+        violations.push(format!(
+            "outputs[{index}].name: output {name} is configured twice"
+        ));
+    }
+
+    let group_names: Vec<&str> = topology.groups.iter().map(|g| g.name.as_str()).collect();
+    for (index, aux_bus) in topology.aux_buses.iter().enumerate() {
+        if let Some(destination) = &aux_bus.destination {
+            if !group_names.contains(&destination.as_str()) {
+                violations.push(format!(
+                    "aux_buses[{index}].destination: aux bus {} returns into unknown group {destination}",
+                    aux_bus.name
+                ));
+            }
+        }
+    }
+
+    for (index, group) in topology.groups.iter().enumerate() {
+        for (field, pattern) in [
+            ("match_inputs", &group.match_inputs),
+            ("match_tags", &group.match_tags),
+        ] {
+            if let Some(Err(error)) = pattern.as_deref().map(regex::Regex::new) {
+                violations.push(format!("groups[{index}].{field}: {error}"));
+            }
+        }
+        if let Some(parent) = &group.parent {
+            if !group_names.contains(&parent.as_str()) {
+                violations.push(format!(
+                    "groups[{index}].parent: group {} feeds unknown group {parent}",
+                    group.name
+                ));
+            }
+        }
+    }
+    for cycle in group_cycles(topology) {
+        violations.push(format!(
+            "groups: groups feed each other in a cycle, {}",
+            cycle.join(" -> ")
+        ));
+    }
+
+    for (index, output_stage) in topology.output_stages.iter().enumerate() {
+        for source in output_stage.sources.iter().flatten() {
+            let known = group_names.contains(&source.as_str())
+                || topology.aux_buses.iter().any(|a| a.name == *source);
+            if !known {
+                violations.push(format!(
+                    "output_stages[{index}].sources: output stage {} is fed from unknown source {source}",
+                    output_stage.name
+                ));
+            }
+        }
+    }
+
+    for (index, input) in topology.inputs.iter().enumerate() {
+        if input.cross_fader.is_some() && input.channel_strip_type == ChannelStripType::Basic {
+            violations.push(format!(
+                "inputs[{index}].cross_fader: input {} has a Basic channel strip without a cross fader",
+                input.name
+            ));
+        }
+    }
+
+    for (index, insert) in topology.inserts.iter().enumerate() {
+        if insert.after == PluginRole::Gain {
+            violations.push(format!(
+                "inserts[{index}].after: insert on channel strip {} can't follow the gain plugin, it ends the chain",
+                insert.channel_strip
+            ));
+        }
+        let basic = topology.inputs.iter().any(|i| {
+            i.name == insert.channel_strip && i.channel_strip_type == ChannelStripType::Basic
+        }) || topology.groups.iter().any(|g| {
+            g.name == insert.channel_strip && g.channel_strip_type == ChannelStripType::Basic
+        });
+        let chained = topology
+            .inputs
+            .iter()
+            .any(|i| i.name == insert.channel_strip && !i.plugins.is_empty());
+        if insert.after == PluginRole::Saturator && chained {
+            violations.push(format!(
+                "inserts[{index}].after: channel strip {} has extra plugins after its saturator",
+                insert.channel_strip
+            ));
+        }
+        if insert.after == PluginRole::CrossFader && basic {
+            violations.push(format!(
+                "inserts[{index}].after: channel strip {} is Basic and has no cross fader",
+                insert.channel_strip
+            ));
+        }
+    }
+
+    for (index, preset) in topology.presets.iter().enumerate() {
+        let basic = topology.inputs.iter().any(|i| {
+            i.name == preset.channel_strip && i.channel_strip_type == ChannelStripType::Basic
+        }) || topology.groups.iter().any(|g| {
+            g.name == preset.channel_strip && g.channel_strip_type == ChannelStripType::Basic
+        });
+        if preset.cross_fader_curve.is_some() && basic {
+            violations.push(format!(
+                "presets[{index}].cross_fader_curve: channel strip {} is Basic and has no cross fader",
+                preset.channel_strip
+            ));
+        }
+    }
+
+    let naming = &topology.naming;
+    for (field, template, placeholder) in [
+        ("input", &naming.input, naming::INPUT_PLACEHOLDER),
+        ("group", &naming.group, naming::GROUP_PLACEHOLDER),
+        ("aux_bus", &naming.aux_bus, naming::AUX_BUS_PLACEHOLDER),
+        ("cue", &naming.cue, naming::CUE_PLACEHOLDER),
+        (
+            "output_stage",
+            &naming.output_stage,
+            naming::OUTPUT_STAGE_PLACEHOLDER,
+        ),
+    ] {
+        if !template.contains(placeholder) {
+            violations.push(format!(
+                "naming.{field}: template {template} doesn't contain {placeholder}"
+            ));
+        }
+    }
+
+    for (index, hook) in topology.hooks.iter().enumerate() {
+        if hook.command.is_empty() {
+            violations.push(format!(
+                "hooks[{index}].command: hook of stage {} has no command",
+                hook.stage.name()
+            ));
+        }
+    }
+
+    violations
+}
+
+/// Problems between the topology and what is registered, found before the
+/// build creates anything.
+pub fn plan_violations(
+    topology: &Topology,
+    inputs: &[Input],
+    outputs: &[Output],
+    nodes: &[ListNode],
+    ports: &[ListPort],
+) -> Vec<String> {
+    let mut violations = Vec::new();
+    let outputs = topology.outputs(outputs, nodes, ports);
+
+    let duplicate_inputs: BTreeSet<&str> = duplicates(inputs.iter().map(|i| i.name.as_str()))
+        .into_iter()
+        .map(|(_, name)| name)
+        .collect();
+    for name in duplicate_inputs {
+        violations.push(format!(
+            "registry: input {name} is registered more than once"
+        ));
+    }
+
+    let mut port_owners: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for input in inputs {
+        for path in [&input.left_port_path, &input.right_port_path]
+            .into_iter()
+            .flatten()
+        {
+            port_owners.entry(path).or_default().push(&input.name);
+        }
+    }
+    for (path, owners) in port_owners.into_iter().filter(|(_, o)| o.len() > 1) {
+        violations.push(format!(
+            "registry: port {path} is used by more than one input channel: {}",
+            owners.join(", ")
+        ));
+    }
+
+    for input in inputs {
+        let group =
+            plan::matched_group(topology, &input.name).unwrap_or(&input.group_channel_strip_name);
+        if !topology.groups.iter().any(|g| g.name == group) {
+            violations.push(format!(
+                "registry: input {} is routed to group {group}, which groups doesn't define",
+                input.name
+            ));
+        }
+    }
+
+    for (index, sync) in topology.looper.sync.iter().enumerate() {
+        if sync
+            .instance
+            .is_some_and(|instance| instance == 0 || instance > topology.looper.instances.max(1))
+        {
+            violations.push(format!(
+                "looper.sync[{index}].instance: there are {} looper instances, counted from 1",
+                topology.looper.instances.max(1)
+            ));
+        }
+    }
+    if topology.looper.instances > 1 && topology.looper.max_loops.is_none() {
+        violations.push(String::from(
+            "looper.instances: several looper instances need max_loops to spread the loops",
+        ));
+    }
+    if let Some(capacity) = topology
+        .looper
+        .capacity()
+        .filter(|_| topology.looper.enabled)
+    {
+        let looped = inputs
+            .iter()
+            .map(|i| topology.input_config(&i.name))
+            .filter(|c| c.looper && c.channel_strip_type != ChannelStripType::Basic)
+            .count();
+        if looped > capacity as usize {
+            violations.push(format!(
+                "looper.max_loops: {looped} inputs want a looper, {} instances of {} support {capacity}",
+                topology.looper.instances.max(1),
+                topology.looper.node
+            ));
+        }
+    }
+
+    // Strips are looked up by the topology name under every template, so a
+    // strip of one kind mustn't be what a name of another kind resolves to
+    let naming = &topology.naming;
+    let mut strips: Vec<(&str, &str, String)> = Vec::new();
+    for input in inputs {
+        if !topology.input_config(&input.name).passthrough {
+            strips.push(("input", &input.name, naming.input_strip(&input.name)));
+        }
+    }
+    for group in &topology.groups {
+        strips.push(("group", &group.name, naming.group_strip(&group.name)));
+    }
+    for aux_bus in &topology.aux_buses {
+        strips.push((
+            "aux bus",
+            &aux_bus.name,
+            naming.aux_bus_strip(&aux_bus.name),
+        ));
+    }
+    if let Some(cue) = &topology.cue {
+        strips.push(("cue", &cue.name, naming.cue_strip(&cue.name)));
+    }
+    for (index, (kind, name, strip)) in strips.iter().enumerate() {
+        for (other_kind, other_name, other_strip) in &strips[index + 1..] {
+            if kind != other_kind
+                && (naming.matches(strip, other_name) || naming.matches(other_strip, name))
+            {
+                violations.push(format!(
+                    "naming: channel strips of {kind} {name} and {other_kind} {other_name} \
+                     can't be told apart, rename one of them"
+                ));
+            }
+        }
+    }
+
+    let mut used_outputs: Vec<(String, &str)> = Vec::new();
+    for (index, output_stage) in topology.output_stages.iter().enumerate() {
+        let field = format!("output_stages[{index}].outputs");
+        match &output_stage.outputs {
+            Some(names) => used_outputs.extend(names.iter().map(|n| (field.clone(), n.as_str()))),
+            None => used_outputs.extend(outputs.iter().map(|o| (field.clone(), o.name.as_str()))),
+        }
+    }
+    for (index, group) in topology.groups.iter().enumerate() {
+        let field = format!("groups[{index}].destinations");
+        used_outputs.extend(
+            group
+                .destinations
+                .iter()
+                .map(|n| (field.clone(), n.as_str())),
+        );
+    }
+    if let Some(cue) = &topology.cue {
+        used_outputs.push((String::from("cue.output"), &cue.output));
+    }
+    for (field, name) in used_outputs {
+        match outputs.iter().find(|o| o.name == name) {
+            None => violations.push(format!(
+                "{field}: no output {name} is registered or defined in outputs"
+            )),
+            Some(output) if output.port_paths.is_empty() => {
+                violations.push(format!("{field}: output {name} has no port paths"))
+            }
+            Some(_) => {}
+        }
+    }
+
+    for (index, config) in topology.outputs.iter().enumerate() {
+        let channels = outputs
+            .iter()
+            .find(|o| o.name == config.name)
+            .map_or(0, |o| o.port_paths.len());
+        let mapped = config.channels.iter().flat_map(|c| c.pairs());
+        if let Some(channel) = mapped.map(|(_, channel)| *channel).max() {
+            if channel as usize >= channels {
+                violations.push(format!(
+                    "outputs[{index}].channels: output {} has {channels} channels, the map feeds channel {channel}",
+                    config.name
+                ));
+            }
+        }
+    }
+
+    violations
+}
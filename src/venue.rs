@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::glob_to_regex;
+use crate::pmx::pipewire::{node::ListNode, port::ListPort};
+
+/// Where the topology's outputs land physically in one venue, read from a
+/// file of its own so a tour swaps the venue and keeps the topology.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Venue {
+    /// Port mapping of each output by its name in the topology
+    pub outputs: BTreeMap<String, VenueOutput>,
+}
+
+/// Ports of one output, either by path or by the node they belong to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VenueOutput {
+    /// Port path of every channel in order
+    pub port_paths: Vec<String>,
+    /// Name of the playback node, `*` matches any text and `?` a single
+    /// character. Its input ports in id order are the output's channels.
+    pub node: Option<String>,
+}
+
+impl VenueOutput {
+    /// Port paths of the output's channels, none if no node matches.
+    pub fn port_paths(&self, nodes: &[ListNode], ports: &[ListPort]) -> Vec<String> {
+        if !self.port_paths.is_empty() {
+            return self.port_paths.clone();
+        }
+        let Some(regex) = self.node.as_deref().and_then(|n| glob_to_regex(n).ok()) else {
+            return Vec::new();
+        };
+        let Some(node) = nodes
+            .iter()
+            .filter(|n| regex.is_match(&n.name))
+            .min_by_key(|n| n.id)
+        else {
+            return Vec::new();
+        };
+        let mut node_ports: Vec<&ListPort> = ports
+            .iter()
+            .filter(|p| p.node_id == node.object_serial && p.direction == "in")
+            .collect();
+        node_ports.sort_by_key(|p| p.id);
+        node_ports.into_iter().map(|p| p.path.clone()).collect()
+    }
+}
+
+pub fn read_venue(path: &Path) -> Result<Venue, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let venue: Venue = toml::from_str(&contents).map_err(|e| format!("{}: {e}", path.display()))?;
+    for (name, output) in &venue.outputs {
+        match &output.node {
+            Some(_) if !output.port_paths.is_empty() => {
+                return Err(format!(
+                    "{}: outputs.{name}: set port_paths or node, not both",
+                    path.display()
+                )
+                .into());
+            }
+            Some(node) => {
+                glob_to_regex(node)
+                    .map_err(|e| format!("{}: outputs.{name}.node: {e}", path.display()))?;
+            }
+            None if output.port_paths.is_empty() => {
+                return Err(format!(
+                    "{}: outputs.{name}: needs port_paths or a node",
+                    path.display()
+                )
+                .into());
+            }
+            None => {}
+        }
+    }
+    Ok(venue)
+}